@@ -0,0 +1,83 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+use restson::Error as RestError;
+
+use rustdag_lib::dag::contract::error::ContractError;
+use rustdag_lib::dag::transaction::error::TransactionError;
+use rustdag_lib::util::types::RejectionReason;
+use rustdag_lib::util::{AddressError, HashId};
+
+#[derive(Debug)]
+pub enum ClientError {
+    IoError(io::Error),
+    ContractError(ContractError),
+    TransactionError(TransactionError),
+    RestError(RestError),
+    /// The server rejected a transaction
+    Rejected(RejectionReason),
+    AddressError(AddressError),
+    /// The command line arguments were not understood
+    Usage(String),
+    /// The server does not store the state root
+    RootNotFound(u64),
+    /// The pinned state root is not the merge of the selected parents' roots
+    RootNotMerged {
+        root: u64,
+        merged: u64,
+    },
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ClientError::IoError(err) => write!(f, "IO Error: {}", err),
+            ClientError::ContractError(err) => write!(f, "Contract Error: {}", err),
+            ClientError::TransactionError(err) => write!(f, "Transaction Error: {}", err),
+            ClientError::RestError(err) => write!(f, "Rest Error: {:?}", err),
+            ClientError::Rejected(reason) => write!(f, "Rejected: {}", reason),
+            ClientError::AddressError(err) => write!(f, "Address Error: {}", err),
+            ClientError::Usage(msg) => write!(f, "{}", msg),
+            ClientError::RootNotFound(root) => write!(f, "State root {} not found", HashId(*root)),
+            ClientError::RootNotMerged { root, merged } => write!(
+                f,
+                "State root {} is not the merge of the parents' roots {}",
+                HashId(*root),
+                HashId(*merged)
+            ),
+        }
+    }
+}
+
+impl Error for ClientError {}
+
+impl From<io::Error> for ClientError {
+    fn from(error: io::Error) -> Self {
+        ClientError::IoError(error)
+    }
+}
+
+impl From<ContractError> for ClientError {
+    fn from(error: ContractError) -> Self {
+        ClientError::ContractError(error)
+    }
+}
+
+impl From<TransactionError> for ClientError {
+    fn from(error: TransactionError) -> Self {
+        ClientError::TransactionError(error)
+    }
+}
+
+impl From<RestError> for ClientError {
+    fn from(error: RestError) -> Self {
+        ClientError::RestError(error)
+    }
+}
+
+impl From<AddressError> for ClientError {
+    fn from(error: AddressError) -> Self {
+        ClientError::AddressError(error)
+    }
+}
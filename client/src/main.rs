@@ -1,69 +1,122 @@
+use std::env;
 use std::fs::File;
 use std::io::Read;
+use std::process;
 
+extern crate restson;
 extern crate rustdag_lib;
 
+mod error;
+
 use rustdag_lib::{dag, security, util};
 
 use dag::contract::source::ContractSource;
 use dag::contract::ContractValue;
 use dag::transaction::{data::TransactionData, Transaction};
 
-use security::hash::proof::proof_of_work;
 use security::keys::PrivateKey;
 use security::ring::digest::SHA512_256;
-use util::peer::Peer;
+use util::peer::{Peer, PeerApi, RemoteBlockDAG};
 use util::types::TransactionStatus;
+use util::{decode_address, encode_address, HashId};
 
-fn main() {
-    let server = Peer::new(String::from("http://localhost:4200"));
-    let blockdag = server.clone().into_remote_blockdag();
-    // Load contract
-    let mut file = File::open("test.wasm").expect("Could not open test file");
-    let mut buf: Vec<u8> = Vec::with_capacity(file.metadata().unwrap().len() as usize);
-    file.read_to_end(&mut buf)
-        .expect("Could not read test file");
-    let contract_src = ContractSource::new(&buf);
-
-    let mut contract_id = 0;
-    let mut trunk_nonce = 0;
-    let mut root = blockdag.get_mpt_default_root();
-    let tip_hashes = server.get_tips();
-    if let Ok(trunk) = server.get_transaction(tip_hashes.trunk_hash) {
-        if let Ok(branch) = server.get_transaction(tip_hashes.branch_hash) {
-            trunk_nonce = proof_of_work(trunk.get_nonce(), branch.get_nonce());
-
-            let mut pk = PrivateKey::new(&SHA512_256);
-
-            let mut transaction = Transaction::create(
-                tip_hashes.branch_hash,
-                tip_hashes.trunk_hash,
-                vec![],
-                0,
-                trunk_nonce,
-                root,
-                TransactionData::GenContract(contract_src.clone()),
-            );
+use error::ClientError;
 
-            transaction.sign(&mut pk);
+/// Maximum number of transactions searched back from the tips for their
+/// merge base
+const MAX_MERGE_BASE_DEPTH: usize = 1000;
 
-            contract_id = transaction.get_hash();
+const USAGE: &str = "Usage: rustdag-client [run] [--contract <address>]
+       rustdag-client empty [--root <hex>]";
 
-            root = blockdag
-                .try_add_transaction(&transaction)
-                .unwrap()
-                .get_storage_root()
-                .unwrap();
+/// A command given on the command line
+#[derive(PartialEq, Debug)]
+enum Command {
+    /// Run the example contract calls, against an existing contract if one
+    /// is given, or else against a newly deployed one
+    Run { contract: Option<u64> },
+    /// Send an empty transaction, checking that it claims the given state
+    /// root if one is given
+    Empty { root: Option<u64> },
+}
 
-            if let Ok(TransactionStatus::Rejected(_)) = server.post_transaction(&transaction) {
-                panic!("Contract rejected");
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let server = Peer::new(String::from("http://localhost:4200")).with_scheme_check();
+    let blockdag = server.clone().into_remote_blockdag();
+    let result = parse_args(&args).and_then(|command| match command {
+        Command::Run { contract } => run(&server, &blockdag, contract),
+        Command::Empty { root } => {
+            let (transaction, status) = empty_transaction(&server, root)?;
+            println!("Transaction {}: {}", HashId(transaction.get_hash()), status);
+            Ok(())
+        }
+    });
+    if let Err(err) = result {
+        eprintln!("{}", err);
+        if let ClientError::Usage(_) = err {
+            eprintln!("{}", USAGE);
+        }
+        process::exit(1);
+    }
+}
+
+/// Parse the command line arguments, not including the program name
+fn parse_args(args: &[String]) -> Result<Command, ClientError> {
+    let mut args = args.iter();
+    match args.next().map(String::as_str) {
+        None | Some("run") => {
+            let mut contract = None;
+            while let Some(arg) = args.next() {
+                match arg.as_str() {
+                    "--contract" => {
+                        let address = args
+                            .next()
+                            .ok_or_else(|| ClientError::Usage("Missing contract address".into()))?;
+                        contract = Some(decode_address(address)?);
+                    }
+                    arg => return Err(ClientError::Usage(format!("Unknown argument {}", arg))),
+                }
+            }
+            Ok(Command::Run { contract })
+        }
+        Some("empty") => {
+            let mut root = None;
+            while let Some(arg) = args.next() {
+                match arg.as_str() {
+                    "--root" => {
+                        let hex = args
+                            .next()
+                            .ok_or_else(|| ClientError::Usage("Missing root".into()))?;
+                        root =
+                            Some(u64::from_str_radix(hex, 16).map_err(|_| {
+                                ClientError::Usage(format!("Invalid root {}", hex))
+                            })?);
+                    }
+                    arg => return Err(ClientError::Usage(format!("Unknown argument {}", arg))),
+                }
             }
+            Ok(Command::Empty { root })
         }
+        Some(command) => Err(ClientError::Usage(format!("Unknown command {}", command))),
     }
+}
+
+fn run<P: PeerApi + Clone>(
+    server: &P,
+    blockdag: &RemoteBlockDAG<P>,
+    contract: Option<u64>,
+) -> Result<(), ClientError> {
+    let (contract_id, mut root) = match contract {
+        Some(contract_id) => {
+            // Start from the state shared by the current tips
+            (contract_id, starting_root(server, blockdag)?)
+        }
+        None => deploy_contract(server, blockdag, load_contract("test.wasm")?)?,
+    };
+    println!("Contract ID: {}", encode_address(contract_id));
 
-    let mut trunk_hash = contract_id;
     // Execute the contract grant function
-    // let mut contract: Contract = Contract::new(contract_src, contract_id).expect("Failed to create contract");
     for data in [
         TransactionData::ExecContract(
             "grant".into(),
@@ -108,33 +161,266 @@ fn main() {
     ]
     .iter()
     {
-        let tip_hashes = server.get_tips();
-        if let Ok(branch) = server.get_transaction(tip_hashes.branch_hash) {
-            trunk_nonce = proof_of_work(trunk_nonce, branch.get_nonce());
-            let mut pk = PrivateKey::new(&SHA512_256);
-            let mut transaction = Transaction::create(
-                tip_hashes.branch_hash,
-                trunk_hash,
-                vec![],
-                contract_id,
-                trunk_nonce,
-                root,
-                data.clone(),
-            );
-            transaction.sign(&mut pk);
-            trunk_hash = transaction.get_hash();
-            root = blockdag
-                .try_add_transaction(&transaction)
-                .unwrap()
-                .get_storage_root()
-                .unwrap();
-            print!("Transaction {}: ", transaction.get_hash());
-
-            match server.post_transaction(&transaction) {
-                Ok(TransactionStatus::Milestone) => println!("Milestone"),
-                Ok(TransactionStatus::Rejected(message)) => println!("Rejected: {:?}", message),
-                data => println!("{:?}", data),
+        let (hash, status, new_root) =
+            run_contract(server, blockdag, contract_id, root, data.clone())?;
+        root = new_root;
+        print!("Transaction {}: ", HashId(hash));
+
+        match status {
+            TransactionStatus::Milestone => println!("Milestone: {}", HashId(hash)),
+            TransactionStatus::Rejected(reason) => println!("Rejected: {}", reason),
+            status => println!("{}", status),
+        }
+    }
+    Ok(())
+}
+
+/// Get the state root shared by the current tips of the server
+///
+/// The merge base of the tips is searched for at most MAX_MERGE_BASE_DEPTH
+/// transactions back. Tips sharing no recent ancestor start from the root of
+/// the head milestone instead
+fn starting_root<P: PeerApi>(server: &P, blockdag: &RemoteBlockDAG<P>) -> Result<u64, ClientError> {
+    let tips = server.get_tips()?;
+    match blockdag.get_merge_base_within(tips.trunk_hash, tips.branch_hash, MAX_MERGE_BASE_DEPTH) {
+        Ok(Some(merge_base)) => return Ok(merge_base.root),
+        Ok(None) => eprintln!("Tips have no merge base, using the head milestone root"),
+        Err(err) => eprintln!("{}, using the head milestone root", err),
+    }
+    let (milestone, _) = server.get_head_milestone()?;
+    Ok(server.get_transaction(milestone)?.get_root())
+}
+
+/// Load contract source from a wasm file
+fn load_contract(filename: &str) -> Result<ContractSource, ClientError> {
+    let mut file = File::open(filename)?;
+    let mut buf: Vec<u8> = Vec::with_capacity(file.metadata()?.len() as usize);
+    file.read_to_end(&mut buf)?;
+    Ok(ContractSource::new(&buf))
+}
+
+/// Deploy a contract in a transaction
+///
+/// Returns the id of the contract and the resulting state root
+fn deploy_contract<P: PeerApi + Clone>(
+    server: &P,
+    blockdag: &RemoteBlockDAG<P>,
+    contract_src: ContractSource,
+) -> Result<(u64, u64), ClientError> {
+    let root = blockdag.get_mpt_default_root();
+    let mut pk = PrivateKey::new(&SHA512_256);
+    let transaction = server.build_signed_transaction(
+        TransactionData::GenContract(contract_src, None),
+        0,
+        Some(root),
+        &mut pk,
+    )?;
+
+    let root = blockdag
+        .try_add_transaction(&transaction)?
+        .get_storage_root()
+        .unwrap_or(root);
+
+    if let TransactionStatus::Rejected(reason) = server.post_transaction(&transaction)? {
+        return Err(ClientError::Rejected(reason));
+    }
+    Ok((transaction.get_hash(), root))
+}
+
+/// Send an empty transaction on the current tips
+///
+/// The transaction claims the merge of its parents' roots. Empty transactions
+/// change no state, so the server rejects any other root with
+/// RejectionReason::MergeRoot. A given root is only checked against the
+/// merge, and the transaction is not sent if they differ
+fn empty_transaction<P: PeerApi + Clone>(
+    server: &P,
+    root: Option<u64>,
+) -> Result<(Transaction, TransactionStatus), ClientError> {
+    let mut pk = PrivateKey::new(&SHA512_256);
+    let transaction = server.build_signed_transaction(TransactionData::Empty, 0, None, &mut pk)?;
+    if let Some(root) = root {
+        let merged = transaction.get_root();
+        if root != merged {
+            if server.get_mpt_node(root).is_err() {
+                return Err(ClientError::RootNotFound(root));
+            }
+            return Err(ClientError::RootNotMerged { root, merged });
+        }
+    }
+    let status = server.post_transaction(&transaction)?;
+    Ok((transaction, status))
+}
+
+/// Execute a contract function in a transaction
+///
+/// Returns the hash of the transaction, its status, and the resulting state
+/// root
+fn run_contract<P: PeerApi + Clone>(
+    server: &P,
+    blockdag: &RemoteBlockDAG<P>,
+    contract_id: u64,
+    root: u64,
+    data: TransactionData,
+) -> Result<(u64, TransactionStatus, u64), ClientError> {
+    let mut pk = PrivateKey::new(&SHA512_256);
+    let transaction = server.build_signed_transaction(data, contract_id, Some(root), &mut pk)?;
+    let root = blockdag
+        .try_add_transaction(&transaction)?
+        .get_storage_root()
+        .unwrap_or(root);
+    let status = server.post_transaction(&transaction)?;
+    Ok((transaction.get_hash(), status, root))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use util::mock_peer::MockPeer;
+    use util::AddressError;
+
+    #[test]
+    fn test_load_missing_contract() {
+        match load_contract("nonexistent.wasm") {
+            Err(ClientError::IoError(_)) => {}
+            result => panic!("Expected IoError, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_parse_args() {
+        let args = |args: &[&str]| args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>();
+        assert_eq!(
+            parse_args(&args(&[])).unwrap(),
+            Command::Run { contract: None }
+        );
+
+        let address = encode_address(6043537212972274484);
+        assert_eq!(
+            parse_args(&args(&["run", "--contract", &address])).unwrap(),
+            Command::Run {
+                contract: Some(6043537212972274484)
             }
+        );
+
+        // A mistyped address is caught by the checksum
+        let mut typo = address.clone();
+        typo.replace_range(3..4, if &address[3..4] == "q" { "p" } else { "q" });
+        match parse_args(&args(&["run", "--contract", &typo])) {
+            Err(ClientError::AddressError(AddressError::InvalidChecksum)) => {}
+            result => panic!("Expected InvalidChecksum, got {:?}", result),
+        }
+        match parse_args(&args(&["run", "--contract"])) {
+            Err(ClientError::Usage(_)) => {}
+            result => panic!("Expected Usage, got {:?}", result),
+        }
+
+        assert_eq!(
+            parse_args(&args(&["empty", "--root", "00000000000000ff"])).unwrap(),
+            Command::Empty { root: Some(0xff) }
+        );
+        match parse_args(&args(&["empty", "--root", "root"])) {
+            Err(ClientError::Usage(_)) => {}
+            result => panic!("Expected Usage, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_empty_transaction_pinned_root() {
+        let server = MockPeer::new();
+        let blockdag = server.clone().into_remote_blockdag();
+        let default_root = blockdag.get_mpt_default_root();
+        let contract_src = load_contract(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/../lib/resources/test/contracts/api_test.wasm"
+        ))
+        .unwrap();
+        let (_, root) = deploy_contract(&server, &blockdag, contract_src).unwrap();
+        assert_ne!(root, default_root);
+
+        // The deploy executed against the default root, so the tips still
+        // merge to it, and it can be pinned
+        let (transaction, status) = empty_transaction(&server, Some(default_root)).unwrap();
+        assert_eq!(transaction.get_root(), default_root);
+        assert_eq!(transaction.get_data(), &TransactionData::Empty);
+        match status {
+            TransactionStatus::Pending | TransactionStatus::Milestone => {}
+            status => panic!("Unexpected status {}", status),
+        }
+
+        // Without a root, the transaction claims the merge of its parents'
+        // roots
+        let (transaction, _) = empty_transaction(&server, None).unwrap();
+        let merged = blockdag
+            .build_transaction(
+                transaction.get_trunk_hash(),
+                transaction.get_branch_hash(),
+                0,
+                TransactionData::Empty,
+            )
+            .unwrap();
+        assert_eq!(transaction.get_root(), merged.get_root());
+
+        // A stored root which is not the merge would be rejected by the
+        // server, so it is not sent
+        match empty_transaction(&server, Some(root)) {
+            Err(ClientError::RootNotMerged {
+                root: pinned,
+                merged,
+            }) => {
+                assert_eq!(pinned, root);
+                assert_eq!(merged, default_root);
+            }
+            result => panic!("Expected RootNotMerged, got {:?}", result.map(|_| ())),
+        }
+
+        match empty_transaction(&server, Some(root ^ 1)) {
+            Err(ClientError::RootNotFound(missing)) => assert_eq!(missing, root ^ 1),
+            result => panic!("Expected RootNotFound, got {:?}", result.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn test_starting_root() {
+        let server = MockPeer::new();
+        let blockdag = server.clone().into_remote_blockdag();
+        let tips = server.get_tips().unwrap();
+        let merge_base = server
+            .get_merge_base(tips.trunk_hash, tips.branch_hash)
+            .unwrap();
+        assert_eq!(starting_root(&server, &blockdag).unwrap(), merge_base.root);
+    }
+
+    #[test]
+    fn test_deploy_and_run_contract() {
+        let server = MockPeer::new();
+        let blockdag = server.clone().into_remote_blockdag();
+        let contract_src = load_contract(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/../lib/resources/test/contracts/api_test.wasm"
+        ))
+        .unwrap();
+
+        let (contract_id, root) = deploy_contract(&server, &blockdag, contract_src).unwrap();
+        assert!(server.get_contract(contract_id).is_ok());
+
+        let (_, status, new_root) = run_contract(
+            &server,
+            &blockdag,
+            contract_id,
+            root,
+            TransactionData::ExecContract(
+                "set_u64".into(),
+                vec![ContractValue::U32(1), ContractValue::U64(10)],
+            ),
+        )
+        .unwrap();
+        match status {
+            TransactionStatus::Pending | TransactionStatus::Milestone => {}
+            status => panic!("Unexpected status {}", status),
         }
+        assert_ne!(root, new_root);
+        // The server computed the same state as the client
+        assert!(server.get_mpt_node(new_root).is_ok());
     }
 }
@@ -1,37 +1,184 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
 
-use rand::{thread_rng, Rng};
+use crossbeam_utils::thread;
+use rand::{thread_rng, Error as RandError, Rng, RngCore};
 
-use dag::contract::{state::ContractStateStorage, Contract, ContractValue};
-use dag::milestone::pending::{MilestoneSignature, MilestoneTracker};
+use dag::contract::{
+    cache::ModuleCache,
+    error::ContractError,
+    scheduler::{execute_rooted, ContractCall, ExecResult},
+    state::ContractStateStorage,
+    Contract, ContractValue,
+};
+use dag::error::BlockDAGError;
+use dag::fee::{FeePolicy, NoFees};
+use dag::genesis::{genesis_contract_id, GenesisConfig};
+use dag::milestone::pending::{
+    AggregateMilestoneSignature, MilestoneError, MilestoneSignature, MilestoneTracker,
+};
 use dag::milestone::Milestone;
-use dag::storage::map::{Map, OOB};
-use dag::storage::mpt::{node::Node, MerklePatriciaTree};
+use dag::storage::keyed::HashKeyMap;
+use dag::storage::map::{ListableMap, Map, MapError, RemovableMap, OOB};
+use dag::storage::mpt::{node::Node, MerklePatriciaTree, NodeUpdates};
 use dag::transaction::{
     data::TransactionData, error::TransactionError, updates::TransactionUpdates, Transaction,
 };
 
 use super::incomplete_chain::IncompleteChain;
+use super::missing_parents::MissingParents;
 
-use security::hash::proof::valid_proof;
+use security::hash::proof::{
+    random_proof_of_work, valid_proof, Difficulty, MILESTONE_INTERVAL_SPAN,
+};
+use security::keys::KeyScheme;
 
-use util::types::{TransactionHashes, TransactionStatus};
+use util::types::{
+    Checkpoint, ContractTrace, MergeBase, RejectionReason, TransactionHashes, TransactionStatus,
+};
+use util::HashId;
 
 const GENESIS_HASH: u64 = 0;
 
+/// Number of milestones which must follow a milestone before its state is
+/// final
+const DEFAULT_FINALITY_DEPTH: usize = 6;
+
 const MILESTONE_NONCE_MIN: u32 = 100_000;
 const MILESTONE_NONCE_MAX: u32 = 200_000;
 
+/// Number of milestone candidates accepted past the pending limit, so
+/// confirming milestones can drain the pending transactions
+const MILESTONE_PENDING_BUDGET: usize = 8;
+
+/// Maximum number of missing parents remembered from rejected transactions
+/// and incomplete milestone chains, past which the oldest are forgotten, so
+/// unknown hashes cannot grow the set without bound
+const MAX_MISSING_PARENTS: usize = 1024;
+
+/// Check if a transaction with nonce is a milestone candidate
+fn is_milestone_nonce(nonce: u32) -> bool {
+    nonce > MILESTONE_NONCE_MIN && nonce < MILESTONE_NONCE_MAX
+}
+
+/// Limits on the size of contract calls, bounding the size of transactions
+/// and the cost of executing them
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct CallLimits {
+    /// Maximum length of the function name, in bytes
+    pub max_name_len: usize,
+    /// Maximum number of arguments
+    pub max_args: usize,
+    /// Maximum total size of the arguments, in bytes
+    pub max_args_size: usize,
+}
+
+impl CallLimits {
+    /// Check if a call to func_name with args is within the limits
+    pub fn check(&self, func_name: &str, args: &[ContractValue]) -> bool {
+        func_name.len() <= self.max_name_len
+            && args.len() <= self.max_args
+            && args.iter().map(ContractValue::size).sum::<usize>() <= self.max_args_size
+    }
+}
+
+/// No limits, so every call is within them
+impl Default for CallLimits {
+    fn default() -> Self {
+        CallLimits {
+            max_name_len: usize::max_value(),
+            max_args: usize::max_value(),
+            max_args_size: usize::max_value(),
+        }
+    }
+}
+
 pub trait TransactionStorage = Map<u64, Transaction>;
 pub trait ContractStorage = Map<u64, Contract>;
 
+/// Draws from the rng of the thread selecting tips
+///
+/// ThreadRng cannot be sent between threads, so the dag stores this instead
+struct ThreadRngSource;
+
+impl RngCore for ThreadRngSource {
+    fn next_u32(&mut self) -> u32 {
+        thread_rng().next_u32()
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        thread_rng().next_u64()
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        thread_rng().fill_bytes(dest)
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), RandError> {
+        thread_rng().try_fill_bytes(dest)
+    }
+}
+
 pub struct BlockDAG<M: ContractStateStorage, T: TransactionStorage, C: ContractStorage> {
     transactions: T,
-    pending_transactions: HashMap<u64, Transaction>,
+    pending_transactions: HashKeyMap<u64, Transaction>,
     contracts: C,
     storage: MerklePatriciaTree<ContractValue, M>,
     milestones: MilestoneTracker,
     tips: Vec<u64>,
+    contract_state_limit: Option<u64>,
+    /// Maximum number of pending transactions before new transactions are
+    /// rejected
+    pending_limit: Option<usize>,
+    /// Last transaction counter committed by each address
+    counters: HashMap<Vec<u8>, u64>,
+    /// Hashes of the transactions signed by each address, by address id
+    address_index: HashKeyMap<u64, Vec<u64>>,
+    /// Data of the events emitted by contracts, with the hash of the
+    /// transaction emitting them, by topic
+    event_index: HashKeyMap<u64, Vec<(u64, Vec<u8>)>>,
+    /// Number of threads verifying signatures when applying a block
+    verify_parallelism: usize,
+    /// State root of the milestone that confirmed each transaction, by
+    /// transaction hash
+    anchor_roots: HashKeyMap<u64, u64>,
+    /// Position of the milestone that confirmed each transaction, by
+    /// transaction hash
+    confirmed_in: HashKeyMap<u64, usize>,
+    /// State root committed by each pending transaction, released if the
+    /// transaction is rolled back
+    committed_roots: HashKeyMap<u64, u64>,
+    /// Parsed contract modules, shared by the threads executing contracts
+    module_cache: ModuleCache,
+    /// Collects the fees of committed transactions
+    fee_policy: Box<dyn FeePolicy>,
+    /// Number of committed transactions referencing each transaction, by
+    /// hash
+    approvers: HashKeyMap<u64, usize>,
+    /// Tips whose parents all have more approvers than this are only
+    /// selected if too few other tips exist
+    lazy_approver_limit: Option<usize>,
+    /// Accept transactions without a signature
+    allow_unsigned: bool,
+    /// Maximum number of tips, beyond which the oldest tips are evicted
+    tip_limit: Option<usize>,
+    /// Evicted tips not yet referenced by any transaction
+    evicted_tips: Vec<u64>,
+    /// Check the proof of work of each transaction
+    require_pow: bool,
+    /// Public keys of the validators allowed to sign transactions when the
+    /// proof of work is not required
+    validators: HashSet<Vec<u8>>,
+    /// Source of randomness for tip selection
+    rng: Mutex<Box<dyn RngCore + Send>>,
+    /// Parents of rejected transactions and milestone chains which were not
+    /// found locally
+    missing_parents: Mutex<MissingParents>,
+    /// Number of milestones which must follow a milestone before it is
+    /// finalized
+    finality_depth: usize,
+    /// Limits on the function name and arguments of contract calls
+    call_limits: CallLimits,
 }
 
 impl<
@@ -46,10 +193,40 @@ impl<
 }
 
 impl<M: ContractStateStorage, T: TransactionStorage, C: ContractStorage> BlockDAG<M, T, C> {
-    #[allow(unused_must_use)]
     pub fn new(transaction_storage: T, contract_storage: C, state_storage: M) -> Self {
-        let storage = MerklePatriciaTree::new(state_storage);
-        let default_root = storage.default_root();
+        Self::with_genesis(
+            transaction_storage,
+            contract_storage,
+            state_storage,
+            GenesisConfig::default(),
+        )
+        .expect("Failed to create default genesis")
+    }
+
+    /// Create a dag with the initial state described by genesis
+    ///
+    /// Any contracts in the genesis configuration are instantiated, and the
+    /// state produced by their init functions is included in the root of the
+    /// genesis transactions.
+    ///
+    /// Returns an error if any of the preloaded contracts fail to initialize
+    #[allow(unused_must_use)]
+    pub fn with_genesis(
+        transaction_storage: T,
+        mut contract_storage: C,
+        state_storage: M,
+        genesis: GenesisConfig,
+    ) -> Result<Self, ContractError> {
+        let mut storage = MerklePatriciaTree::new(state_storage);
+        let mut genesis_root = storage.default_root();
+
+        for (index, src) in genesis.get_contracts().iter().enumerate() {
+            let id = genesis_contract_id(index);
+            let (contract, node_updates) = Contract::new(src.clone(), id, &storage, genesis_root)?;
+            genesis_root = node_updates.get_root_hash();
+            storage.commit_set(node_updates)?;
+            contract_storage.set(id, contract)?;
+        }
 
         let genesis_transaction = Transaction::new(
             GENESIS_HASH,
@@ -58,18 +235,40 @@ impl<M: ContractStateStorage, T: TransactionStorage, C: ContractStorage> BlockDA
             0,
             0,
             0,
-            default_root,
+            genesis_root,
             TransactionData::Genesis,
         );
         let genesis_milestone = Milestone::new(GENESIS_HASH, genesis_transaction.clone());
 
         let mut dag = BlockDAG {
             transactions: transaction_storage,
-            pending_transactions: HashMap::default(),
+            pending_transactions: HashKeyMap::default(),
             contracts: contract_storage,
             storage,
             milestones: MilestoneTracker::new(genesis_milestone),
             tips: Vec::new(),
+            contract_state_limit: None,
+            pending_limit: None,
+            counters: HashMap::new(),
+            address_index: HashKeyMap::default(),
+            event_index: HashKeyMap::default(),
+            verify_parallelism: 1,
+            anchor_roots: HashKeyMap::default(),
+            confirmed_in: HashKeyMap::default(),
+            committed_roots: HashKeyMap::default(),
+            module_cache: ModuleCache::new(),
+            fee_policy: Box::new(NoFees),
+            approvers: HashKeyMap::default(),
+            lazy_approver_limit: None,
+            allow_unsigned: false,
+            tip_limit: None,
+            evicted_tips: Vec::new(),
+            require_pow: true,
+            validators: HashSet::new(),
+            rng: Mutex::new(Box::new(ThreadRngSource)),
+            missing_parents: Mutex::new(MissingParents::new(MAX_MISSING_PARENTS)),
+            finality_depth: DEFAULT_FINALITY_DEPTH,
+            call_limits: CallLimits::default(),
         };
 
         let genesis_transaction_hash = genesis_transaction.get_hash();
@@ -80,7 +279,7 @@ impl<M: ContractStateStorage, T: TransactionStorage, C: ContractStorage> BlockDA
             0,
             0,
             0,
-            default_root,
+            genesis_root,
             TransactionData::Genesis,
         );
         let genesis_branch_hash = genesis_branch.get_hash();
@@ -92,7 +291,115 @@ impl<M: ContractStateStorage, T: TransactionStorage, C: ContractStorage> BlockDA
         dag.tips.push(genesis_transaction_hash);
         dag.tips.push(genesis_branch_hash);
 
-        dag
+        Ok(dag)
+    }
+
+    /// Limit the number of distinct mapping keys each newly created contract
+    /// can set
+    ///
+    /// Contracts keep the limit they were created with
+    pub fn set_contract_state_limit(&mut self, state_limit: Option<u64>) {
+        self.contract_state_limit = state_limit;
+    }
+
+    /// Limit the number of pending transactions
+    ///
+    /// Once the limit is reached, transactions other than milestones are
+    /// rejected until confirmed milestones free space. Only a small number of
+    /// milestone candidates are accepted past the limit
+    pub fn set_pending_limit(&mut self, pending_limit: Option<usize>) {
+        self.pending_limit = pending_limit;
+    }
+
+    /// Set the number of threads used to verify signatures when applying a
+    /// block of transactions
+    ///
+    /// Transactions are still committed serially. A value of 0 is treated
+    /// as 1
+    pub fn set_verify_parallelism(&mut self, parallelism: usize) {
+        self.verify_parallelism = parallelism.max(1);
+    }
+
+    /// Deprioritize tips which only reference heavily approved transactions
+    ///
+    /// A tip whose parents all have more than limit approvers does not help
+    /// confirm the other tips, as spam referencing the same old transactions
+    /// does. Such lazy tips are only selected by get_tips if fewer than two
+    /// other tips exist
+    pub fn set_lazy_approver_limit(&mut self, limit: Option<usize>) {
+        self.lazy_approver_limit = limit;
+    }
+
+    /// Accept unsigned transactions, for test and private networks
+    ///
+    /// Their signatures are not verified, but their nonces and state roots
+    /// are still checked. Unsigned transactions are rejected by default
+    pub fn set_allow_unsigned(&mut self, allow_unsigned: bool) {
+        self.allow_unsigned = allow_unsigned;
+    }
+
+    /// Limit the number of tips selected from
+    ///
+    /// Once the limit is exceeded, the oldest tips are evicted. Evicted
+    /// transactions are no longer selected as trunk or branch by get_tips,
+    /// but are returned as references for new transactions until one
+    /// references them, so they stay connected. A limit below 2 is treated
+    /// as 2
+    pub fn set_tip_limit(&mut self, limit: Option<usize>) {
+        self.tip_limit = limit.map(|limit| limit.max(2));
+        self.evict_tips();
+    }
+
+    /// Require each transaction to carry a valid proof of work
+    ///
+    /// The proof of work is required by default. Permissioned networks can
+    /// disable it, in which case transactions must instead be signed by one
+    /// of the validators set with [set_validators](#method.set_validators)
+    pub fn set_require_pow(&mut self, require_pow: bool) {
+        self.require_pow = require_pow;
+    }
+
+    /// Check if transactions must carry a valid proof of work
+    pub fn requires_pow(&self) -> bool {
+        self.require_pow
+    }
+
+    /// Set the public keys of the validators allowed to sign transactions
+    /// when the proof of work is not required
+    ///
+    /// Lamport keys can only sign once, so validators sign with a reusable
+    /// [ValidatorKey](../../security/keys/struct.ValidatorKey.html), whose
+    /// public key is the address of every transaction it signs
+    pub fn set_validators(&mut self, validators: HashSet<Vec<u8>>) {
+        self.validators = validators;
+    }
+
+    /// Set the policy collecting the fees of committed transactions
+    ///
+    /// Transactions committed before the policy was set are not charged
+    pub fn set_fee_policy(&mut self, fee_policy: Box<dyn FeePolicy>) {
+        self.fee_policy = fee_policy;
+    }
+
+    /// Set the source of randomness used to select tips
+    ///
+    /// Tips are selected with the thread rng by default. A seeded rng makes
+    /// tip selection reproducible
+    pub fn set_rng(&mut self, rng: Box<dyn RngCore + Send>) {
+        self.rng = Mutex::new(rng);
+    }
+
+    /// Set the number of milestones which must follow a milestone before
+    /// its state root is final
+    pub fn set_finality_depth(&mut self, depth: usize) {
+        self.finality_depth = depth;
+    }
+
+    /// Limit the function name and arguments of contract calls
+    ///
+    /// Calls past the limits are rejected with RejectionReason::ArgsTooLarge
+    pub fn set_call_limits(&mut self, call_limits: CallLimits) {
+        self.call_limits = call_limits;
     }
 
     /// Try to add a transaction to the dag
@@ -107,35 +414,231 @@ impl<M: ContractStateStorage, T: TransactionStorage, C: ContractStorage> BlockDA
         &self,
         transaction: &Transaction,
     ) -> Result<TransactionUpdates, TransactionError> {
+        self.check_transaction(transaction, None, None)
+            .map_err(|err| {
+                warn!(
+                    "Rejected transaction {}: {}",
+                    HashId(transaction.get_hash()),
+                    err
+                );
+                self.record_missing_parent(&err);
+                err
+            })
+    }
+
+    /// Remember the parent a transaction was rejected for missing, so it is
+    /// reported as an orphan until it is received
+    fn record_missing_parent(&self, err: &TransactionError) {
+        if let TransactionError::Rejected(RejectionReason::MissingParent(parent)) = err {
+            self.record_missing_parents(&[*parent]);
+        }
+    }
+
+    /// Remember parents which were not found locally
+    fn record_missing_parents(&self, hashes: &[u64]) {
+        let mut missing_parents = self.missing_parents.lock().unwrap();
+        for hash in hashes {
+            missing_parents.insert(*hash);
+        }
+    }
+
+    /// Build an unsigned transaction referencing trunk and branch
+    ///
+    /// The transaction's root is the merge of the parents' contract state
+    /// roots, as the dag checks when it is added, and its nonce is a valid
+    /// proof of work for the parents if one is required.
+    ///
+    /// # Errors
+    ///
+    /// Fails if a parent is not found, if the parents' roots conflict, or if
+    /// no nonce is valid for the parents. Contracts can only be created or
+    /// executed against a stored root, so fails with
+    /// BlockDAGError::MergeNotStored if the merged root is new, until an
+    /// empty transaction merging the parents is committed
+    pub fn build_transaction(
+        &self,
+        trunk: u64,
+        branch: u64,
+        contract: u64,
+        data: TransactionData,
+    ) -> Result<Transaction, BlockDAGError> {
+        let trunk_transaction = self
+            .get_transaction(trunk)
+            .ok_or(BlockDAGError::TransactionNotFound(trunk))?
+            .clone();
+        let branch_transaction = self
+            .get_transaction(branch)
+            .ok_or(BlockDAGError::TransactionNotFound(branch))?
+            .clone();
+
+        let root = self
+            .merge_parent_roots(&trunk_transaction, &branch_transaction)
+            .ok_or(BlockDAGError::MergeConflict)?
+            .get_root_hash();
+        let stored = self.storage.nodes.get(&root).is_ok();
+        if !stored && data != TransactionData::Empty {
+            return Err(BlockDAGError::MergeNotStored(root));
+        }
+
+        let nonce = if self.require_pow {
+            random_proof_of_work(
+                trunk_transaction.get_nonce(),
+                branch_transaction.get_nonce(),
+            )
+            .ok_or(BlockDAGError::NoValidNonce)?
+        } else {
+            0
+        };
+        Ok(Transaction::create(
+            branch,
+            trunk,
+            vec![],
+            contract,
+            nonce,
+            root,
+            data,
+        ))
+    }
+
+    /// Compute the contract state root after a transaction, without checking
+    /// or committing it
+    ///
+    /// Only the transaction's data is executed, so clients can compute the
+    /// root before signing. Empty transactions change no state, so their
+    /// resulting root is their own root
+    ///
+    /// # Errors
+    ///
+    /// Fails with BlockDAGError::Contract if the contract is not found, or
+    /// creating or executing it fails
+    pub fn compute_resulting_root(&self, transaction: &Transaction) -> Result<u64, BlockDAGError> {
+        let root = transaction.get_root();
+        match transaction.get_data() {
+            TransactionData::Genesis | TransactionData::Empty => Ok(root),
+            TransactionData::GenContract(src, _) => {
+                let (_, node_updates) = Contract::with_state_limit(
+                    src.clone(),
+                    transaction.get_hash(),
+                    self.contract_state_limit,
+                    &self.storage,
+                    root,
+                )?;
+                Ok(node_updates.get_root_hash())
+            }
+            TransactionData::ExecContract(func_name, args) => {
+                let contract = self
+                    .contracts
+                    .get(&transaction.get_contract())
+                    .map_err(|_| ContractError::ContractNotFound(transaction.get_contract()))?;
+                let (_, node_updates, _) = contract.exec_cached(
+                    &self.module_cache,
+                    func_name,
+                    args,
+                    &self.storage,
+                    root,
+                )?;
+                Ok(node_updates.get_root_hash())
+            }
+        }
+    }
+
+    /// Check a transaction, using the results of an earlier signature
+    /// verification and contract execution if there are any
+    fn check_transaction(
+        &self,
+        transaction: &Transaction,
+        verified: Option<bool>,
+        executed: Option<Result<ExecResult, ContractError>>,
+    ) -> Result<TransactionUpdates, TransactionError> {
+        // A parent counted twice, or a transaction referencing itself, would
+        // create degenerate cycles in the graph
+        let hash = transaction.get_hash();
+        let refs = transaction.get_all_refs();
+        let parents: HashSet<u64> = refs.iter().cloned().collect();
+        if parents.len() != refs.len() || parents.contains(&hash) {
+            return Err(TransactionError::Rejected(RejectionReason::SelfReference));
+        }
+
+        // Milestones are still accepted within a separate budget, so
+        // confirming them can drain the pending transactions
+        if let Some(pending_limit) = self.pending_limit {
+            let limit = if is_milestone_nonce(transaction.get_nonce()) {
+                pending_limit + MILESTONE_PENDING_BUDGET
+            } else {
+                pending_limit
+            };
+            if self.pending_transactions.len() >= limit {
+                return Err(TransactionError::Rejected(RejectionReason::MempoolFull));
+            }
+        }
+
         let branch_transaction;
         let trunk_transaction;
         if let Some(trunk_handle) = self.get_transaction(transaction.get_trunk_hash()) {
             if let Some(branch_handle) = self.get_transaction(transaction.get_branch_hash()) {
                 let trunk = trunk_handle.borrow();
                 let branch = branch_handle.borrow();
-                if !valid_proof(
-                    trunk.get_nonce(),
-                    branch.get_nonce(),
-                    transaction.get_nonce(),
-                ) {
-                    return Err(TransactionError::Rejected("Invalid nonce".into()));
+                if self.require_pow
+                    && !valid_proof(
+                        trunk.get_nonce(),
+                        branch.get_nonce(),
+                        transaction.get_nonce(),
+                    )
+                {
+                    return Err(TransactionError::Rejected(RejectionReason::BadNonce));
                 }
                 trunk_transaction = trunk.clone();
                 branch_transaction = branch.clone();
             } else {
-                return Err(TransactionError::Rejected(
-                    "Branch transaction not found".into(),
-                ));
+                return Err(TransactionError::Rejected(RejectionReason::MissingParent(
+                    transaction.get_branch_hash(),
+                )));
             }
         } else {
-            return Err(TransactionError::Rejected(
-                "Trunk transaction not found".into(),
-            ));
+            return Err(TransactionError::Rejected(RejectionReason::MissingParent(
+                transaction.get_trunk_hash(),
+            )));
+        }
+
+        // Expiry is checked against the timestamps of the parents rather
+        // than the local clock, so every node agrees on it
+        let parents_timestamp = trunk_transaction
+            .get_timestamp()
+            .max(branch_transaction.get_timestamp());
+        if transaction.is_expired(parents_timestamp) {
+            return Err(TransactionError::Rejected(RejectionReason::Expired));
         }
 
         // Verify the transaction's signature
-        if !transaction.verify() {
-            return Err(TransactionError::Rejected("Invalid signature".into()));
+        let unsigned_allowed = self.allow_unsigned && transaction.is_unsigned();
+        if !unsigned_allowed && !verified.unwrap_or_else(|| transaction.verify()) {
+            return Err(TransactionError::Rejected(RejectionReason::BadSignature));
+        }
+
+        // Without the proof of work, only validators may add transactions,
+        // and only validators may sign with validator keys
+        let validator_signed = transaction.get_scheme() == KeyScheme::Ed25519;
+        if (!self.require_pow || validator_signed)
+            && !self.validators.contains(transaction.get_address())
+        {
+            return Err(TransactionError::Rejected(RejectionReason::NotValidator));
+        }
+
+        // A known transaction with the same contents but a different
+        // signature has been tampered with
+        if let Some(existing) = self.get_transaction(transaction.get_hash()) {
+            if existing.full_hash() != transaction.full_hash() {
+                return Err(TransactionError::Rejected(RejectionReason::BadSignature));
+            }
+        }
+
+        // Reject transactions reusing a counter from the same address
+        if let Some(counter) = transaction.get_counter() {
+            if let Some(last) = self.counters.get(transaction.get_address()) {
+                if counter <= *last {
+                    return Err(TransactionError::Rejected(RejectionReason::Replayed));
+                }
+            }
         }
 
         let ref_hashes = transaction.get_ref_hashes();
@@ -146,62 +649,139 @@ impl<M: ContractStateStorage, T: TransactionStorage, C: ContractStorage> BlockDA
             if let Some(t) = self.get_transaction(hash) {
                 referenced.push(t.get_hash());
             } else {
-                return Err(TransactionError::Rejected(
-                    "Referenced transaction not found".into(),
-                ));
+                return Err(TransactionError::Rejected(RejectionReason::MissingParent(
+                    hash,
+                )));
             }
         }
 
-        let hash = transaction.get_hash();
-
         let mut updates = TransactionUpdates::new(referenced);
 
         // Process the transaction's data
         match transaction.get_data() {
             TransactionData::Genesis => {
-                return Err(TransactionError::Rejected("Genesis transaction".into()))
+                return Err(TransactionError::Rejected(RejectionReason::Other(
+                    "Genesis transaction".into(),
+                )))
             }
-            TransactionData::GenContract(src) => {
+            TransactionData::GenContract(src, abi) => {
                 if transaction.get_contract() != 0 {
-                    return Err(TransactionError::Rejected("Invalid gen contract id".into()));
+                    return Err(TransactionError::Rejected(RejectionReason::Other(
+                        "Invalid gen contract id".into(),
+                    )));
+                }
+                if let Err(err) = src.check_memory_export() {
+                    return Err(TransactionError::Rejected(err.into()));
                 }
                 // Generate a new contract
-                match Contract::new(src.clone(), hash, &self.storage, transaction.get_root()) {
+                match Contract::with_state_limit(
+                    src.clone(),
+                    hash,
+                    self.contract_state_limit,
+                    &self.storage,
+                    transaction.get_root(),
+                ) {
                     Ok((contract, node_updates)) => {
-                        updates.add_contract(contract);
+                        updates.add_contract(contract.with_abi(abi.clone()));
                         updates.add_node_updates(node_updates);
                     }
-                    Err(_) => return Err(TransactionError::Rejected("Invalid contract".into())),
+                    Err(err) => return Err(TransactionError::Rejected(err.into())),
                 }
             }
             TransactionData::ExecContract(func_name, args) => {
+                if !self.call_limits.check(func_name, args) {
+                    return Err(TransactionError::Rejected(RejectionReason::ArgsTooLarge));
+                }
                 if transaction.get_contract() != trunk_transaction.get_contract()
                     && trunk_transaction.get_contract() != 0
                 {
-                    return Err(TransactionError::Rejected("Invalid contract id".into()));
+                    return Err(TransactionError::Rejected(RejectionReason::Other(
+                        "Invalid contract id".into(),
+                    )));
                 }
                 if let Ok(contract) = self.contracts.get(&transaction.get_contract()) {
-                    match contract.exec(func_name, args, &self.storage, transaction.get_root()) {
-                        Ok((_val, node_updates)) => {
+                    // Reject calls not matching the abi before executing them
+                    if let Some(abi) = contract.get_abi() {
+                        if let Err(err) = abi.check_call(func_name, args) {
+                            return Err(TransactionError::Rejected(err.into()));
+                        }
+                    }
+                    let result = executed.unwrap_or_else(|| {
+                        contract.exec_cached(
+                            &self.module_cache,
+                            func_name,
+                            args,
+                            &self.storage,
+                            transaction.get_root(),
+                        )
+                    });
+                    match result {
+                        Ok((_val, node_updates, events)) => {
                             updates.add_node_updates(node_updates);
+                            updates.add_events(events);
                         }
                         Err(err) => {
-                            return Err(TransactionError::Rejected(format!(
-                                "Function failed to execute: {:?}",
-                                err
-                            )));
+                            return Err(TransactionError::Rejected(err.into()));
                         }
                     }
                 } else {
-                    return Err(TransactionError::Rejected("Contract not found".into()));
+                    return Err(TransactionError::Rejected(
+                        ContractError::ContractNotFound(transaction.get_contract()).into(),
+                    ));
+                }
+            }
+            TransactionData::Empty => {
+                // Empty transactions change no state, so a claimed root must
+                // be the merge of the parents' roots. A root of 0 claims no
+                // root, but the parents' roots must still merge
+                if transaction.get_root() != 0 {
+                    match self.merge_parent_roots(&trunk_transaction, &branch_transaction) {
+                        Some(node_updates) => {
+                            if node_updates.get_root_hash() != transaction.get_root() {
+                                return Err(TransactionError::Rejected(RejectionReason::MergeRoot));
+                            }
+                            updates.add_node_updates(node_updates);
+                        }
+                        None => return Err(TransactionError::Rejected(RejectionReason::MergeRoot)),
+                    }
+                } else if trunk_transaction.get_root() != 0
+                    && branch_transaction.get_root() != 0
+                    && self
+                        .merge_parent_roots(&trunk_transaction, &branch_transaction)
+                        .is_none()
+                {
+                    return Err(TransactionError::Rejected(RejectionReason::MergeRoot));
                 }
             }
-            TransactionData::Empty => {}
         };
 
         Ok(updates)
     }
 
+    /// Merge the contract state roots of two parent transactions, relative to
+    /// the root of their merge base
+    ///
+    /// Returns None if the roots conflict, or are not stored locally
+    fn merge_parent_roots(
+        &self,
+        trunk: &Transaction,
+        branch: &Transaction,
+    ) -> Option<NodeUpdates<ContractValue>> {
+        let (trunk_root, branch_root) = (trunk.get_root(), branch.get_root());
+        let base_root = if trunk_root == branch_root {
+            trunk_root
+        } else {
+            self.get_merge_base(trunk.get_hash(), branch.get_hash())?
+                .root
+        };
+        for root in &[trunk_root, branch_root, base_root] {
+            if self.storage.nodes.get(root).is_err() {
+                return None;
+            }
+        }
+        self.storage.try_merge(trunk_root, branch_root, base_root)
+    }
+
     /// inserts the new transaction into the list
     /// of active tips, and moves all transactions it references from
     /// list of active tips to the list of transactions.
@@ -212,6 +792,9 @@ impl<M: ContractStateStorage, T: TransactionStorage, C: ContractStorage> BlockDA
     ) -> Result<TransactionStatus, TransactionError> {
         let hash = transaction.get_hash();
 
+        if let Some(root) = updates.get_storage_root() {
+            self.committed_roots.insert(hash, root);
+        }
         if let Some(updates) = updates.node_updates {
             self.storage.commit_set(updates)?;
         }
@@ -221,11 +804,33 @@ impl<M: ContractStateStorage, T: TransactionStorage, C: ContractStorage> BlockDA
         for t in updates.referenced {
             self.tips.remove_item(&t);
         }
+        for parent in transaction.get_all_refs() {
+            *self.approvers.entry(parent).or_insert(0) += 1;
+            self.evicted_tips.remove_item(&parent);
+        }
+        self.missing_parents.get_mut().unwrap().remove(&hash);
+        if let Some(counter) = transaction.get_counter() {
+            self.counters
+                .insert(transaction.get_address().to_vec(), counter);
+        }
+        if !transaction.get_address().is_empty() {
+            self.address_index
+                .entry(transaction.get_address_id())
+                .or_insert_with(Vec::new)
+                .push(hash);
+        }
+        for event in updates.events {
+            self.event_index
+                .entry(event.topic)
+                .or_insert_with(Vec::new)
+                .push((hash, event.data));
+        }
+        let fee = self.fee_policy.fee(&transaction);
+        self.fee_policy.collect(&transaction, fee);
 
         let mut res = TransactionStatus::Pending;
 
-        if transaction.get_nonce() > MILESTONE_NONCE_MIN
-            && transaction.get_nonce() < MILESTONE_NONCE_MAX
+        if is_milestone_nonce(transaction.get_nonce())
             && self.milestones.new_milestone(transaction.clone())
         {
             res = TransactionStatus::Milestone;
@@ -233,10 +838,23 @@ impl<M: ContractStateStorage, T: TransactionStorage, C: ContractStorage> BlockDA
 
         self.pending_transactions.set(hash, transaction)?;
         self.tips.push(hash);
+        self.evict_tips();
 
         return Ok(res);
     }
 
+    /// Evict the oldest tips past the tip limit, keeping them to be
+    /// referenced by new transactions
+    fn evict_tips(&mut self) {
+        if let Some(limit) = self.tip_limit {
+            if self.tips.len() > limit {
+                let evicted = self.tips.len() - limit;
+                let evicted_tips: Vec<u64> = self.tips.drain(..evicted).collect();
+                self.evicted_tips.extend(evicted_tips);
+            }
+        }
+    }
+
     /// Add a confirmed milestone to the list of milestones
     ///
     /// Walks backward on the graph searching for the previous milestone
@@ -273,6 +891,7 @@ impl<M: ContractStateStorage, T: TransactionStorage, C: ContractStorage> BlockDA
         if transaction_found {
             Ok(transaction_chain)
         } else {
+            self.record_missing_parents(&missing_hashes);
             Err(IncompleteChain::new(missing_hashes))
         }
     }
@@ -282,8 +901,14 @@ impl<M: ContractStateStorage, T: TransactionStorage, C: ContractStorage> BlockDA
     /// confirmation
     pub fn process_chain(&mut self, milestone: u64, chain: Vec<Transaction>) -> bool {
         for transaction in chain.into_iter() {
-            if let Err(_err) = self.milestones.new_chain(milestone, transaction) {
-                // TODO Log error
+            let hash = transaction.get_hash();
+            if let Err(err) = self.milestones.new_chain(milestone, transaction) {
+                error!(
+                    "Could not add transaction {} to the chain of milestone {}: {}",
+                    HashId(hash),
+                    HashId(milestone),
+                    err
+                );
                 return false;
             }
         }
@@ -292,19 +917,244 @@ impl<M: ContractStateStorage, T: TransactionStorage, C: ContractStorage> BlockDA
 
     /// Add a signature to the current pending milestone
     pub fn add_pending_signature(&mut self, signature: MilestoneSignature) -> bool {
-        match self.milestones.sign(signature) {
+        let result = self.milestones.sign(signature);
+        self.process_signature_result(result)
+    }
+
+    /// Add an aggregate signature to the current pending milestone
+    ///
+    /// The aggregate is verified against the validators first, and ignored
+    /// if it is not signed by a quorum of them
+    pub fn add_pending_aggregate_signature(
+        &mut self,
+        signature: AggregateMilestoneSignature,
+    ) -> bool {
+        if !signature.verify(&self.validators) {
+            warn!(
+                "Aggregate signature of milestone {} not signed by a quorum of validators",
+                HashId(signature.get_milestone())
+            );
+            return false;
+        }
+        let result = self.milestones.sign_aggregate(signature);
+        self.process_signature_result(result)
+    }
+
+    /// Confirm the transactions of a milestone approved by a signature
+    fn process_signature_result(
+        &mut self,
+        result: Result<Option<Milestone>, MilestoneError>,
+    ) -> bool {
+        match result {
             Ok(Some(milestone)) => {
-                self.confirm_transactions(milestone.get_transaction());
+                let transaction = milestone.get_transaction();
+                if let Some(index) = self.milestones.get_milestone_index(transaction.get_hash()) {
+                    self.confirm_transactions(transaction, transaction.get_root(), index);
+                }
                 true
             }
             Ok(None) => true,
-            Err(_err) => {
-                // TODO Log error
+            Err(err) => {
+                warn!("Milestone signature not accepted: {}", err);
                 false
             }
         }
     }
 
+    /// Get the most recent confirmed milestone
+    pub fn get_head_milestone(&self) -> &Milestone {
+        self.milestones.get_head_milestone()
+    }
+
+    /// Get the newest milestone deep enough that its state is final
+    ///
+    /// Clients can sync from the checkpoint instead of from genesis. Returns
+    /// None until enough milestones have been confirmed
+    pub fn finalized_checkpoint(&self) -> Option<Checkpoint> {
+        self.milestones
+            .get_finalized_milestone(self.finality_depth)
+            .map(|milestone| {
+                let transaction = milestone.get_transaction();
+                Checkpoint {
+                    hash: transaction.get_hash(),
+                    root: transaction.get_root(),
+                    timestamp: transaction.get_timestamp(),
+                }
+            })
+    }
+
+    /// Suggest the proof of work difficulty from how quickly recent
+    /// milestones were issued
+    pub fn suggested_difficulty(&self) -> Difficulty {
+        // One more milestone than intervals averaged over
+        let timestamps = self
+            .milestones
+            .recent_timestamps(MILESTONE_INTERVAL_SPAN + 1);
+        Difficulty::suggest(&timestamps)
+    }
+
+    /// List the transactions confirmed after the older milestone, up to and
+    /// including the newer milestone
+    ///
+    /// These are the ancestors of the newer milestone which are not
+    /// ancestors of the older milestone. The search stops at transactions
+    /// confirmed by the older milestone or before it, which are all its
+    /// ancestors. The hashes are sorted
+    pub fn transactions_between(&self, older: u64, newer: u64) -> Result<Vec<u64>, BlockDAGError> {
+        let older_index = self
+            .milestones
+            .get_milestone_index(older)
+            .ok_or(BlockDAGError::MilestoneNotFound(older))?;
+        let newer_index = self
+            .milestones
+            .get_milestone_index(newer)
+            .ok_or(BlockDAGError::MilestoneNotFound(newer))?;
+        if newer_index < older_index {
+            return Err(BlockDAGError::NotAncestor { older, newer });
+        }
+
+        let mut confirmed = HashSet::new();
+        let mut stack = vec![newer];
+        while let Some(hash) = stack.pop() {
+            if hash == older || confirmed.contains(&hash) {
+                continue;
+            }
+            let after_older = match self.confirmed_in.get(&hash) {
+                Some(index) => *index > older_index,
+                // Only the newest milestone may still be pending, and the
+                // genesis transactions were never confirmed by a milestone
+                None => self.pending_transactions.contains_key(&hash),
+            };
+            if !after_older {
+                continue;
+            }
+            if let Some(transaction) = self.get_transaction(hash) {
+                confirmed.insert(hash);
+                stack.extend(transaction.get_all_refs());
+            }
+        }
+
+        let mut hashes: Vec<u64> = confirmed.into_iter().collect();
+        hashes.sort();
+        Ok(hashes)
+    }
+
+    /// Find the closest common ancestor of two transactions
+    ///
+    /// The distance of an ancestor is the larger of its distances from the two
+    /// transactions. When several common ancestors are equally close, the one
+    /// with the lowest hash is chosen, so the merge base does not depend on
+    /// the order the ancestors are visited in, or the order of the arguments.
+    ///
+    /// A transaction is its own ancestor. Returns None if the transactions
+    /// have no common ancestor stored locally
+    pub fn find_merge_base(&self, hash_a: u64, hash_b: u64) -> Option<u64> {
+        self.search_merge_base(hash_a, hash_b, None).unwrap_or(None)
+    }
+
+    /// Find the closest common ancestor of two transactions, searching at
+    /// most max_depth transactions back from either of them
+    ///
+    /// Fails with BlockDAGError::MergeBaseTooDeep if no common ancestor is
+    /// found within the depth, so callers reading transactions from a peer
+    /// can fall back to a milestone root instead of walking the whole dag.
+    /// See [find_merge_base](#method.find_merge_base)
+    pub fn find_merge_base_within(
+        &self,
+        hash_a: u64,
+        hash_b: u64,
+        max_depth: usize,
+    ) -> Result<Option<u64>, BlockDAGError> {
+        self.search_merge_base(hash_a, hash_b, Some(max_depth))
+    }
+
+    fn search_merge_base(
+        &self,
+        hash_a: u64,
+        hash_b: u64,
+        max_depth: Option<usize>,
+    ) -> Result<Option<u64>, BlockDAGError> {
+        if self.get_transaction(hash_a).is_none() || self.get_transaction(hash_b).is_none() {
+            return Ok(None);
+        }
+        if hash_a == hash_b {
+            return Ok(Some(hash_a));
+        }
+
+        let mut visited_a = HashSet::new();
+        let mut visited_b = HashSet::new();
+        visited_a.insert(hash_a);
+        visited_b.insert(hash_b);
+        let mut frontier_a = vec![hash_a];
+        let mut frontier_b = vec![hash_b];
+
+        let mut depth = 0;
+        while !frontier_a.is_empty() || !frontier_b.is_empty() {
+            if let Some(max_depth) = max_depth {
+                if depth >= max_depth {
+                    return Err(BlockDAGError::MergeBaseTooDeep(max_depth));
+                }
+            }
+            depth += 1;
+
+            frontier_a = self.expand_ancestors(&frontier_a, &mut visited_a);
+            frontier_b = self.expand_ancestors(&frontier_b, &mut visited_b);
+
+            // Any new common ancestor includes a newly visited transaction
+            let common_a = frontier_a.iter().filter(|hash| visited_b.contains(hash));
+            let common_b = frontier_b.iter().filter(|hash| visited_a.contains(hash));
+            if let Some(base) = common_a.chain(common_b).min() {
+                return Ok(Some(*base));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Find the merge base of two transactions, along with its contract state
+    /// root
+    ///
+    /// See [find_merge_base](#method.find_merge_base)
+    pub fn get_merge_base(&self, hash_a: u64, hash_b: u64) -> Option<MergeBase> {
+        let hash = self.find_merge_base(hash_a, hash_b)?;
+        self.get_transaction(hash)
+            .map(|transaction| MergeBase::new(hash, transaction.get_root()))
+    }
+
+    /// Find the merge base of two transactions within max_depth, along with
+    /// its contract state root
+    ///
+    /// See [find_merge_base_within](#method.find_merge_base_within)
+    pub fn get_merge_base_within(
+        &self,
+        hash_a: u64,
+        hash_b: u64,
+        max_depth: usize,
+    ) -> Result<Option<MergeBase>, BlockDAGError> {
+        Ok(self
+            .find_merge_base_within(hash_a, hash_b, max_depth)?
+            .and_then(|hash| {
+                self.get_transaction(hash)
+                    .map(|transaction| MergeBase::new(hash, transaction.get_root()))
+            }))
+    }
+
+    /// Get the unvisited, locally stored parents of the transactions in
+    /// frontier, and mark them as visited
+    fn expand_ancestors(&self, frontier: &[u64], visited: &mut HashSet<u64>) -> Vec<u64> {
+        let mut next = Vec::new();
+        for hash in frontier {
+            if let Some(transaction) = self.get_transaction(*hash) {
+                for parent in transaction.get_all_refs() {
+                    if !visited.contains(&parent) && self.get_transaction(parent).is_some() {
+                        visited.insert(parent);
+                        next.push(parent);
+                    }
+                }
+            }
+        }
+        next
+    }
+
     /// Walk backwards from transaction, searching for a transaction specified
     /// by hash. Stops at any transaction that occurred before timestamp
     ///
@@ -324,23 +1174,36 @@ impl<M: ContractStateStorage, T: TransactionStorage, C: ContractStorage> BlockDA
         if transaction.get_timestamp() < timestamp {
             return false;
         }
-        for transaction_hash in transaction.get_all_refs() {
+        // Each entry on the stack holds the hash of a transaction on the
+        // current chain, its references, and the index of the next reference
+        // to visit
+        let mut stack = vec![(transaction.get_hash(), transaction.get_all_refs(), 0)];
+        while let Some((_, refs, index)) = stack.last_mut() {
+            let transaction_hash = match refs.get(*index) {
+                Some(transaction_hash) => *transaction_hash,
+                None => {
+                    // Every reference has been searched, backtrack
+                    stack.pop();
+                    continue;
+                }
+            };
+            *index += 1;
+
             if let Some(transaction_handle) = self.get_transaction(transaction_hash) {
-                let transaction = transaction_handle.borrow();
                 if transaction_hash == hash {
-                    // This is the transaction we are looking for, return
+                    // This is the transaction we are looking for, pass the
+                    // chain leading to it to chain_function, nearest first.
+                    // The starting transaction is not part of the chain
+                    for (chain_hash, _, _) in stack.iter().skip(1).rev() {
+                        if let Some(chain_handle) = self.get_transaction(*chain_hash) {
+                            chain_function(&chain_handle.borrow());
+                        }
+                    }
                     return true;
                 }
-                if self.walk_search(
-                    &transaction,
-                    hash,
-                    timestamp,
-                    chain_function,
-                    not_found_function,
-                ) {
-                    // Found the transaction somewhere along this chain
-                    chain_function(&transaction);
-                    return true;
+                let transaction = transaction_handle.borrow();
+                if transaction.get_timestamp() >= timestamp {
+                    stack.push((transaction_hash, transaction.get_all_refs(), 0));
                 }
             } else {
                 not_found_function(transaction_hash);
@@ -350,13 +1213,17 @@ impl<M: ContractStateStorage, T: TransactionStorage, C: ContractStorage> BlockDA
     }
 
     /// Move all transactions referenced by transaction from
-    /// pending_transactions to transactions
+    /// pending_transactions to transactions, anchoring them to the state root
+    /// of the confirming milestone at position index
     #[allow(unused_must_use)]
-    fn confirm_transactions(&mut self, transaction: &Transaction) {
+    fn confirm_transactions(&mut self, transaction: &Transaction, anchor_root: u64, index: usize) {
         for transaction_hash in transaction.get_all_refs() {
             if let Some(pending_transaction) = self.pending_transactions.remove(&transaction_hash) {
-                self.confirm_transactions(&pending_transaction);
+                self.confirm_transactions(&pending_transaction, anchor_root, index);
                 self.transactions.set(transaction_hash, pending_transaction);
+                self.anchor_roots.insert(transaction_hash, anchor_root);
+                self.confirmed_in.insert(transaction_hash, index);
+                self.committed_roots.remove(&transaction_hash);
             }
         }
     }
@@ -370,6 +1237,11 @@ impl<M: ContractStateStorage, T: TransactionStorage, C: ContractStorage> BlockDA
             })
     }
 
+    /// Get every transaction not yet confirmed by a milestone
+    pub fn get_pending_transactions(&self) -> Vec<Transaction> {
+        self.pending_transactions.values().cloned().collect()
+    }
+
     /// Get the confirmation status of a transaction specified by hash
     pub fn get_confirmation_status(&self, hash: u64) -> TransactionStatus {
         if self.pending_transactions.get(&hash).is_some() {
@@ -378,38 +1250,151 @@ impl<M: ContractStateStorage, T: TransactionStorage, C: ContractStorage> BlockDA
         if self.transactions.get(&hash).is_ok() {
             return TransactionStatus::Accepted;
         }
-        TransactionStatus::Rejected("Not accepted".into())
+        TransactionStatus::Rejected(RejectionReason::Other("Not accepted".into()))
+    }
+
+    /// Get the state root of the milestone that confirmed a transaction
+    ///
+    /// Light clients can request merkle proofs against this root without
+    /// replaying the dag. Returns None if the transaction has not been
+    /// confirmed by a milestone
+    pub fn get_anchor_root(&self, hash: u64) -> Option<u64> {
+        self.anchor_roots.get(&hash).cloned()
     }
 
     /// Select tips from the dag
     ///
     /// This function will select 2 tips from the dag to use for a new
     /// transaction. Any transaction with no transactions referencing it is
-    /// considered a tip.
+    /// considered a tip. Evicted tips are returned as references
     pub fn get_tips(&self) -> TransactionHashes {
-        let (trunk_tip, branch_tip) = if self.tips.len() > 1 {
-            // Randomly select two unique transactions from the tips
-            let mut rng = thread_rng();
-            let trunk_tip_idx = rng.gen_range(0, self.tips.len());
-            let mut branch_tip_idx = rng.gen_range(0, self.tips.len());
-            while branch_tip_idx == trunk_tip_idx {
-                branch_tip_idx = rng.gen_range(0, self.tips.len());
-            }
-
-            (self.tips[trunk_tip_idx], self.tips[branch_tip_idx])
-        } else {
-            let trunk_tip = self.tips[0];
-            (
-                trunk_tip,
-                self.get_transaction(trunk_tip).unwrap().get_branch_hash(),
-            )
-        };
-
-        TransactionHashes::new(trunk_tip, branch_tip)
+        self.get_tips_excluding(&[])
     }
 
-    pub fn get_contract<'a>(&'a self, id: u64) -> Option<OOB<Contract>> {
-        self.contracts.get(&id).ok()
+    /// Select tips from the dag, avoiding the transactions in exclude
+    ///
+    /// Lets a client avoid selecting its own in-flight transactions as
+    /// parents. Excluded tips are only selected if no other tips exist
+    pub fn get_tips_excluding(&self, exclude: &[u64]) -> TransactionHashes {
+        let mut tips: Vec<u64> = self
+            .tips
+            .iter()
+            .filter(|tip| !exclude.contains(tip))
+            .cloned()
+            .collect();
+        if tips.is_empty() {
+            tips = self.tips.clone();
+        }
+        if let Some(limit) = self.lazy_approver_limit {
+            let fresh: Vec<u64> = tips
+                .iter()
+                .filter(|tip| !self.is_lazy(**tip, limit))
+                .cloned()
+                .collect();
+            if fresh.len() > 1 {
+                tips = fresh;
+            }
+        }
+
+        let (trunk_tip, branch_tip) = match tips.len() {
+            0 => {
+                // Tips should never run out, but fall back to building on the
+                // head milestone rather than failing to select any
+                warn!("No tips to select, falling back to the head milestone");
+                let milestone = self.get_head_milestone();
+                (
+                    milestone.get_hash(),
+                    milestone.get_transaction().get_branch_hash(),
+                )
+            }
+            1 => {
+                // The branch parent of the only tip is usually confirmed
+                // already, so branch from the head milestone instead, unless
+                // the tip is the head milestone
+                let trunk_tip = tips[0];
+                let milestone = self.get_head_milestone().get_hash();
+                let branch_tip = if milestone != trunk_tip {
+                    milestone
+                } else {
+                    self.get_transaction(trunk_tip).unwrap().get_branch_hash()
+                };
+                (trunk_tip, branch_tip)
+            }
+            _ => {
+                // Randomly select two unique transactions from the tips
+                let mut rng = self.rng.lock().unwrap();
+                let trunk_tip_idx = rng.gen_range(0, tips.len());
+                let mut branch_tip_idx = rng.gen_range(0, tips.len());
+                while branch_tip_idx == trunk_tip_idx {
+                    branch_tip_idx = rng.gen_range(0, tips.len());
+                }
+
+                (tips[trunk_tip_idx], tips[branch_tip_idx])
+            }
+        };
+
+        TransactionHashes::new(trunk_tip, branch_tip).with_ref_hashes(self.evicted_tips.clone())
+    }
+
+    /// Check if every parent of a transaction has more than limit approvers
+    fn is_lazy(&self, hash: u64, limit: usize) -> bool {
+        match self.get_transaction(hash) {
+            Some(transaction) => transaction
+                .get_all_refs()
+                .iter()
+                .all(|parent| self.approvers.get(parent).cloned().unwrap_or(0) > limit),
+            None => false,
+        }
+    }
+
+    /// Get the number of committed transactions referencing a transaction
+    pub fn get_approver_count(&self, hash: u64) -> usize {
+        self.approvers.get(&hash).cloned().unwrap_or(0)
+    }
+
+    pub fn get_contract<'a>(&'a self, id: u64) -> Option<OOB<Contract>> {
+        self.contracts.get(&id).ok()
+    }
+
+    /// Get the uncompressed wasm code of a contract
+    ///
+    /// Returns None if the contract does not exist
+    pub fn get_contract_source(&self, id: u64) -> Option<Vec<u8>> {
+        self.get_contract(id)
+            .map(|contract| contract.get_source().get_code().to_vec())
+    }
+
+    /// Execute a contract function against the state at root, recording the
+    /// api calls it makes
+    ///
+    /// Returns None if the contract does not exist
+    pub fn trace_contract(
+        &self,
+        id: u64,
+        func_name: &str,
+        args: &[ContractValue],
+        root: u64,
+    ) -> Option<Result<ContractTrace, ContractError>> {
+        let contract = self.get_contract(id)?;
+        Some(
+            contract
+                .exec_traced(func_name, args, &self.storage, root)
+                .map(|(return_values, trace)| ContractTrace::new(return_values, trace)),
+        )
+    }
+
+    /// Execute several contract functions read-only against the state at
+    /// root
+    ///
+    /// Returns None if the contract does not exist
+    pub fn multicall_contract(
+        &self,
+        id: u64,
+        calls: &[(String, Vec<ContractValue>)],
+        root: u64,
+    ) -> Option<Result<Vec<Vec<ContractValue>>, ContractError>> {
+        let contract = self.get_contract(id)?;
+        Some(contract.exec_multicall(calls, &self.storage, root))
     }
 
     pub fn get_mpt_node<'a>(&'a self, id: u64) -> Option<OOB<Node<ContractValue>>> {
@@ -419,12 +1404,341 @@ impl<M: ContractStateStorage, T: TransactionStorage, C: ContractStorage> BlockDA
     pub fn get_mpt_default_root(&self) -> u64 {
         self.storage.default_root()
     }
+
+    /// Get the number of nodes stored in the contract state tree
+    pub fn get_mpt_node_count(&self) -> usize {
+        self.storage.node_count()
+    }
+
+    /// Get the hashes of the transactions signed by the address with the id
+    /// address, in the order they were committed
+    pub fn get_transactions_by_address(&self, address: u64) -> Vec<u64> {
+        self.address_index
+            .get(&address)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// Get the events emitted under topic, with the hash of the transaction
+    /// emitting each one, in the order they were committed
+    pub fn get_events(&self, topic: u64) -> Vec<(u64, Vec<u8>)> {
+        self.event_index.get(&topic).cloned().unwrap_or_default()
+    }
+
+    /// Get the hashes of the transactions referenced by committed
+    /// transactions, rejected transactions or milestone chains, but not
+    /// stored locally
+    ///
+    /// Such parents were never received, or were dropped, and must be
+    /// fetched from peers before the transactions referencing them can be
+    /// added or confirmed
+    pub fn orphans(&self) -> Vec<u64> {
+        let missing_parents = self.missing_parents.lock().unwrap();
+        let mut orphans: Vec<u64> = self
+            .approvers
+            .keys()
+            .chain(missing_parents.iter())
+            .filter(|hash| self.get_transaction(**hash).is_none())
+            .cloned()
+            .collect();
+        orphans.sort();
+        orphans.dedup();
+        orphans
+    }
+
+    /// Get the number of transactions not yet referenced by another
+    pub fn get_tip_count(&self) -> usize {
+        self.tips.len()
+    }
+
+    /// Get the hashes of every transaction not yet referenced by another
+    pub fn get_tip_hashes(&self) -> Vec<u64> {
+        self.tips.clone()
+    }
+}
+
+impl<
+        M: ContractStateStorage,
+        T: TransactionStorage,
+        C: ContractStorage + ListableMap<u64, Contract>,
+    > BlockDAG<M, T, C>
+{
+    /// Get a page of the hash id's of the contracts stored on the dag
+    ///
+    /// Contracts are ordered by id, skipping the first offset contracts and
+    /// returning at most limit contracts
+    pub fn list_contracts(&self, offset: usize, limit: usize) -> Vec<u64> {
+        let mut contracts = self.contracts.get_keys();
+        contracts.sort();
+        contracts.into_iter().skip(offset).take(limit).collect()
+    }
+}
+
+impl<
+        M: ContractStateStorage,
+        T: TransactionStorage + ListableMap<u64, Transaction>,
+        C: ContractStorage,
+    > BlockDAG<M, T, C>
+{
+    /// Rebuild the tips from the stored transactions
+    ///
+    /// A transaction is a tip if no other transaction references it. The
+    /// genesis transactions are not counted as references, so both remain
+    /// tips until they are referenced. Tips are sorted by hash
+    pub fn recompute_tips(&mut self) {
+        let mut hashes = self.transactions.get_keys();
+        hashes.extend(self.pending_transactions.keys().cloned());
+
+        let mut referenced = HashSet::new();
+        for hash in hashes.iter() {
+            if let Some(transaction) = self.get_transaction(*hash) {
+                if let TransactionData::Genesis = transaction.get_data() {
+                    continue;
+                }
+                referenced.extend(transaction.get_all_refs());
+            }
+        }
+
+        let mut tips: Vec<u64> = hashes
+            .into_iter()
+            .filter(|hash| !referenced.contains(hash))
+            .collect();
+        tips.sort();
+        self.tips = tips;
+    }
+}
+
+impl<
+        M: ContractStateStorage + Sync,
+        T: TransactionStorage,
+        C: ContractStorage + RemovableMap<u64, Contract>,
+    > BlockDAG<M, T, C>
+{
+    /// Add and commit a batch of transactions
+    ///
+    /// Transactions are added in order, so each transaction may reference
+    /// the transactions before it in the batch, and execute against their
+    /// state. Calls to committed contracts against committed state are
+    /// executed ahead of time, concurrently for different contracts. Returns
+    /// the hash and status of each transaction.
+    ///
+    /// # Errors
+    ///
+    /// If any transaction is rejected, every transaction already committed
+    /// from the batch is rolled back, and fails with BlockDAGError::Rejected.
+    /// Fails with BlockDAGError::Storage if the rollback fails
+    pub fn apply_block(
+        &mut self,
+        transactions: Vec<Transaction>,
+    ) -> Result<Vec<(u64, TransactionStatus)>, BlockDAGError> {
+        let tips = self.tips.clone();
+        let evicted_tips = self.evicted_tips.clone();
+        let milestones = self.milestones.clone();
+        let missing_parents = self.missing_parents.get_mut().unwrap().clone();
+
+        let verified = self.verify_signatures(&transactions);
+        let executed = self.execute_contract_calls(&transactions);
+
+        let mut applied = Vec::with_capacity(transactions.len());
+        let mut statuses = Vec::with_capacity(transactions.len());
+        for ((transaction, verified), executed) in
+            transactions.into_iter().zip(verified).zip(executed)
+        {
+            let hash = transaction.get_hash();
+            let result = self
+                .check_transaction(&transaction, Some(verified), executed)
+                .and_then(|updates| {
+                    let contract = updates.contract.is_some();
+                    let status = self.commit_transaction(transaction, updates)?;
+                    applied.push((hash, contract));
+                    Ok(status)
+                });
+
+            match result {
+                Ok(status) => statuses.push((hash, status)),
+                Err(err) => {
+                    warn!("Rejected block at transaction {}: {}", HashId(hash), err);
+                    self.tips = tips;
+                    self.evicted_tips = evicted_tips;
+                    self.milestones = milestones;
+                    *self.missing_parents.get_mut().unwrap() = missing_parents;
+                    self.record_missing_parent(&err);
+                    self.rollback_transactions(&applied)?;
+                    let TransactionError::Rejected(reason) = err;
+                    return Err(BlockDAGError::Rejected(hash, reason));
+                }
+            }
+        }
+
+        Ok(statuses)
+    }
+
+    /// Drop pending transactions which expired at or before the timestamp of
+    /// the most recent milestone
+    ///
+    /// Only tips, evicted or not, are dropped, so a transaction another
+    /// transaction references is kept until that transaction is dropped too. The parents of a dropped
+    /// transaction become tips again once nothing else references them.
+    /// Returns the hashes of the dropped transactions
+    pub fn sweep_expired(&mut self) -> Result<Vec<u64>, MapError> {
+        let now = self.milestones.get_head_milestone().get_timestamp();
+        let mut swept = Vec::new();
+        loop {
+            let expired: Vec<Transaction> = self
+                .tips
+                .iter()
+                .chain(self.evicted_tips.iter())
+                .filter_map(|hash| self.pending_transactions.get(hash))
+                .filter(|transaction| transaction.is_expired(now))
+                .cloned()
+                .collect();
+            if expired.is_empty() {
+                return Ok(swept);
+            }
+
+            for transaction in expired {
+                let hash = transaction.get_hash();
+                let contract = match transaction.get_data() {
+                    TransactionData::GenContract(_, _) => true,
+                    _ => false,
+                };
+                self.tips.remove_item(&hash);
+                self.evicted_tips.remove_item(&hash);
+                self.rollback_transactions(&[(hash, contract)])?;
+                for parent in transaction.get_all_refs() {
+                    if self.get_approver_count(parent) == 0
+                        && self.get_transaction(parent).is_some()
+                        && !self.tips.contains(&parent)
+                    {
+                        self.tips.push(parent);
+                    }
+                }
+                debug!("Dropped expired transaction {}", HashId(hash));
+                swept.push(hash);
+            }
+        }
+    }
+
+    /// Execute the contract calls of a batch of transactions ahead of time
+    ///
+    /// Only calls to committed contracts, against state roots already in
+    /// storage, are executed. Calls depending on earlier transactions of the
+    /// batch are executed when the transaction is checked
+    fn execute_contract_calls(
+        &self,
+        transactions: &[Transaction],
+    ) -> Vec<Option<Result<ExecResult, ContractError>>> {
+        let mut indices = Vec::new();
+        let mut calls = Vec::new();
+        for (i, transaction) in transactions.iter().enumerate() {
+            if let TransactionData::ExecContract(func_name, args) = transaction.get_data() {
+                let root = transaction.get_root();
+                if !self.call_limits.check(func_name, args)
+                    || self.storage.nodes.get(&root).is_err()
+                {
+                    continue;
+                }
+                if let Ok(contract) = self.contracts.get(&transaction.get_contract()) {
+                    let call = ContractCall::new(contract.clone(), func_name.clone(), args.clone());
+                    indices.push(i);
+                    calls.push((call, root));
+                }
+            }
+        }
+
+        let mut executed: Vec<_> = transactions.iter().map(|_| None).collect();
+        let results = execute_rooted(&self.module_cache, &self.storage, &calls);
+        for (i, result) in indices.into_iter().zip(results) {
+            executed[i] = Some(result);
+        }
+        executed
+    }
+
+    /// Verify the signatures of a batch of transactions, split across
+    /// verify_parallelism threads
+    fn verify_signatures(&self, transactions: &[Transaction]) -> Vec<bool> {
+        if self.verify_parallelism <= 1 || transactions.len() <= 1 {
+            return transactions.iter().map(Transaction::verify).collect();
+        }
+
+        // Signatures checked by a thread which panicked count as invalid
+        let chunk_size = (transactions.len() - 1) / self.verify_parallelism + 1;
+        thread::scope(|scope| {
+            let handles: Vec<_> = transactions
+                .chunks(chunk_size)
+                .map(|chunk| {
+                    let handle = scope
+                        .spawn(move |_| chunk.iter().map(Transaction::verify).collect::<Vec<_>>());
+                    (handle, chunk.len())
+                })
+                .collect();
+            handles
+                .into_iter()
+                .flat_map(|(handle, len)| handle.join().unwrap_or_else(|_| vec![false; len]))
+                .collect()
+        })
+        .unwrap_or_else(|_| vec![false; transactions.len()])
+    }
+
+    /// Remove committed transactions, their contracts and their references to
+    /// the contract state, undo their counters and refund their fees
+    fn rollback_transactions(&mut self, applied: &[(u64, bool)]) -> Result<(), MapError> {
+        for (hash, contract) in applied.iter().rev() {
+            if let Some(transaction) = self.pending_transactions.remove(hash) {
+                if let Some(hashes) = self.address_index.get_mut(&transaction.get_address_id()) {
+                    hashes.retain(|h| h != hash);
+                }
+                if transaction.get_counter().is_some() {
+                    self.restore_counter(&transaction);
+                }
+                for events in self.event_index.values_mut() {
+                    events.retain(|(h, _)| h != hash);
+                }
+                for parent in transaction.get_all_refs() {
+                    if let Some(count) = self.approvers.get_mut(&parent) {
+                        *count -= 1;
+                    }
+                }
+                let fee = self.fee_policy.fee(&transaction);
+                self.fee_policy.refund(&transaction, fee);
+            }
+            if *contract {
+                self.contracts.remove(hash)?;
+            }
+            if let Some(root) = self.committed_roots.remove(hash) {
+                self.storage.release_root(root)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Reset the counter of the address of a rolled back transaction to the
+    /// largest counter of the address's remaining transactions
+    fn restore_counter(&mut self, transaction: &Transaction) {
+        let address = transaction.get_address();
+        let counter = self
+            .address_index
+            .get(&transaction.get_address_id())
+            .into_iter()
+            .flatten()
+            .filter_map(|hash| self.get_transaction(*hash))
+            .filter(|other| other.get_address() == address)
+            .filter_map(|other| other.get_counter())
+            .max();
+        match counter {
+            Some(counter) => self.counters.insert(address.to_vec(), counter),
+            None => self.counters.remove(address),
+        };
+    }
 }
 
 impl<M: ContractStateStorage, T: TransactionStorage> BlockDAG<M, T, HashMap<u64, Contract>> {
-    // Get the hash id's of all the contracts stored on the dag
+    /// Get the hash id's of all the contracts stored on the dag, sorted so
+    /// every node iterates them in the same order
     pub fn get_contracts(&self) -> Vec<u64> {
-        self.contracts.keys().cloned().collect()
+        let mut contracts: Vec<u64> = self.contracts.keys().cloned().collect();
+        contracts.sort();
+        contracts
     }
 }
 
@@ -440,24 +1754,42 @@ impl<M: ContractStateStorage, T: TransactionStorage, C: ContractStorage> BlockDA
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::cell::RefCell;
     use std::fs::File;
     use std::io::Read;
     use std::path::PathBuf;
 
-    use dag::contract::{source::ContractSource, ContractValue};
+    use log::{self, Level, LevelFilter, Log, Metadata, Record};
+
+    use dag::contract::{
+        abi::{ContractAbi, ContractValueType},
+        source::ContractSource,
+        ContractValue,
+    };
     use dag::transaction::Transaction;
 
-    use security::hash::proof::proof_of_work;
-    use security::keys::PrivateKey;
+    use security::hash::proof::{proof_of_work, proof_of_work_from};
+    use security::keys::{PrivateKey, ValidatorKey};
     use security::ring::digest::SHA512_256;
 
     // Hardcoded values for the hashes of the genesis transactions.
-    // If the default genesis transactions change, these values must be updated.
+    // If the default genesis transactions change, these values must be updated,
+    // along with the golden values in the consensus vectors.
     const TRUNK_HASH: u64 = 7994361212180723510;
-    const BRANCH_HASH: u64 = 5285319433948766311;
+    const BRANCH_HASH: u64 = 3935804193461013965;
 
     const BASE_NONCE: u32 = 132;
 
+    fn load_api_test_source() -> ContractSource {
+        let mut d = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        d.push("resources/test/contracts/api_test.wasm");
+        let mut file = File::open(d).expect("Could not open test file");
+        let mut buf: Vec<u8> = Vec::new();
+        file.read_to_end(&mut buf)
+            .expect("Could not read test file");
+        ContractSource::new(&buf)
+    }
+
     fn insert_transaction<M: ContractStateStorage, T: TransactionStorage, C: ContractStorage>(
         dag: &mut BlockDAG<M, T, C>,
         branch: u64,
@@ -507,124 +1839,2281 @@ mod tests {
             Transaction::create(10, BRANCH_HASH, vec![], 0, 0, 0, TransactionData::Genesis);
         assert_eq!(
             dag.try_add_transaction(&bad_transaction),
-            Err(TransactionError::Rejected(
-                "Branch transaction not found".into()
-            ))
+            Err(TransactionError::Rejected(RejectionReason::MissingParent(
+                10
+            )))
         );
     }
 
     #[test]
-    fn test_walk_search() {
-        let dag = BlockDAG::<HashMap<_, _>, HashMap<_, _>, HashMap<_, _>>::default();
-        let prev_milestone = dag.milestones.get_head_milestone();
-
-        let transaction = Transaction::create(
+    fn test_replayed_transaction() {
+        let mut dag = BlockDAG::<HashMap<_, _>, HashMap<_, _>, HashMap<_, _>>::default();
+        let mut key = PrivateKey::new(&SHA512_256);
+        let mut transaction = Transaction::create(
             TRUNK_HASH,
             BRANCH_HASH,
             vec![],
             0,
+            BASE_NONCE,
             0,
-            0,
-            TransactionData::Genesis,
+            TransactionData::Empty,
+        )
+        .with_counter(1);
+        transaction.sign(&mut key);
+        let updates = dag.try_add_transaction(&transaction).unwrap();
+        assert_eq!(
+            Ok(TransactionStatus::Pending),
+            dag.commit_transaction(transaction.clone(), updates)
         );
-        assert!(dag.walk_search(
-            &transaction,
-            prev_milestone.get_hash(),
-            0,
-            &mut |_| {},
-            &mut |_| {}
-        ));
 
-        let transaction =
-            Transaction::create(TRUNK_HASH, 0, vec![], 0, 0, 0, TransactionData::Genesis);
-        assert!(dag.walk_search(
-            &transaction,
-            prev_milestone.get_hash(),
-            0,
-            &mut |_| {},
-            &mut |_| {}
-        ));
+        // Submitting the same transaction again is rejected
+        assert_eq!(
+            dag.try_add_transaction(&transaction),
+            Err(TransactionError::Rejected(RejectionReason::Replayed))
+        );
+    }
 
-        let transaction =
-            Transaction::create(0, BRANCH_HASH, vec![], 0, 0, 0, TransactionData::Genesis);
-        assert!(dag.walk_search(
-            &transaction,
-            prev_milestone.get_hash(),
+    #[test]
+    fn test_expired_transaction() {
+        let mut dag = BlockDAG::<HashMap<_, _>, HashMap<_, _>, HashMap<_, _>>::default();
+        let mut parent = Transaction::create(
+            BRANCH_HASH,
+            TRUNK_HASH,
+            vec![],
             0,
-            &mut |_| {},
-            &mut |_| {}
-        ));
-
-        let transaction = Transaction::create(0, 0, vec![], 0, 0, 0, TransactionData::Genesis);
-        assert!(!dag.walk_search(
-            &transaction,
-            prev_milestone.get_hash(),
+            BASE_NONCE,
             0,
-            &mut |_| {},
-            &mut |_| {}
-        ));
+            TransactionData::Empty,
+        );
+        parent.sign(&mut PrivateKey::new(&SHA512_256));
+        let updates = dag.try_add_transaction(&parent).unwrap();
+        dag.commit_transaction(parent.clone(), updates).unwrap();
+
+        // Expiry is judged by the timestamps of the parents, whatever the
+        // local clock says
+        let nonce = proof_of_work(BASE_NONCE, 0).unwrap();
+        let expiring = |expires_at: u64| {
+            let mut transaction = Transaction::create(
+                BRANCH_HASH,
+                parent.get_hash(),
+                vec![],
+                0,
+                nonce,
+                0,
+                TransactionData::Empty,
+            )
+            .with_expiration(expires_at);
+            transaction.sign(&mut PrivateKey::new(&SHA512_256));
+            transaction
+        };
+        assert_eq!(
+            dag.try_add_transaction(&expiring(parent.get_timestamp())),
+            Err(TransactionError::Rejected(RejectionReason::Expired))
+        );
+        assert!(dag
+            .try_add_transaction(&expiring(parent.get_timestamp() + 1))
+            .is_ok());
     }
 
     #[test]
-    fn test_add_milestone() {
+    fn test_sweep_expired() {
+        let src = load_api_test_source();
+
         let mut dag = BlockDAG::<HashMap<_, _>, HashMap<_, _>, HashMap<_, _>>::default();
-        let data = TransactionData::GenContract(ContractSource::new(&vec![]));
-        let middle_transaction = insert_transaction(&mut dag, 0, TRUNK_HASH, 1, data.clone());
-        let transaction = insert_transaction(&mut dag, 0, middle_transaction.get_hash(), 1, data);
+        let add_milestone = |dag: &mut BlockDAG<HashMap<_, _>, HashMap<_, _>, HashMap<_, _>>,
+                             branch: u64,
+                             trunk: u64,
+                             timestamp: u64| {
+            let transaction = Transaction::new(
+                branch,
+                trunk,
+                vec![],
+                0,
+                timestamp,
+                150_000,
+                0,
+                TransactionData::Empty,
+            );
+            let hash = transaction.get_hash();
+            let updates = TransactionUpdates::new(vec![branch, trunk]);
+            assert_eq!(
+                dag.commit_transaction(transaction.clone(), updates),
+                Ok(TransactionStatus::Milestone)
+            );
+            let chain = dag.verify_milestone(transaction).unwrap();
+            assert!(dag.process_chain(hash, chain));
+            assert!(dag.add_pending_signature(MilestoneSignature::new(hash, 0, 0)));
+            hash
+        };
+        let first_milestone = add_milestone(&mut dag, BRANCH_HASH, TRUNK_HASH, 1);
 
-        match dag.verify_milestone(transaction) {
-            Ok(chain) => {
-                assert_eq!(1, chain.len());
-                assert_eq!(chain[0].get_hash(), middle_transaction.get_hash());
-            }
-            Err(err) => panic!("Unexpected missing transactions: {:?}", err),
-        }
+        // A contract deploy expiring after the first milestone
+        let node_count = dag.get_mpt_node_count();
+        let mut deploy = Transaction::create(
+            BRANCH_HASH,
+            TRUNK_HASH,
+            vec![],
+            0,
+            BASE_NONCE,
+            dag.storage.default_root(),
+            TransactionData::GenContract(src, None),
+        )
+        .with_counter(1)
+        .with_expiration(2);
+        deploy.sign(&mut PrivateKey::new(&SHA512_256));
+        let hash = deploy.get_hash();
+        let updates = dag.try_add_transaction(&deploy).unwrap();
+        dag.commit_transaction(deploy.clone(), updates).unwrap();
+
+        // Nothing is dropped before the transaction expires
+        assert_eq!(dag.sweep_expired(), Ok(vec![]));
+        assert!(dag.get_transaction(hash).is_some());
+
+        // Once a milestone passes the expiry, the transaction is dropped
+        // along with its contract, counter and state
+        let second_milestone = add_milestone(&mut dag, BRANCH_HASH, first_milestone, 2);
+        assert_eq!(dag.sweep_expired(), Ok(vec![hash]));
+        assert!(dag.get_transaction(hash).is_none());
+        assert!(dag.get_contract(hash).is_none());
+        assert!(dag.counters.get(deploy.get_address()).is_none());
+        assert!(dag.storage.prune().is_ok());
+        assert_eq!(node_count, dag.get_mpt_node_count());
+
+        // The parents are still referenced by the first milestone, so are
+        // not tips again
+        assert_eq!(dag.tips, vec![second_milestone]);
+        assert_eq!(dag.get_approver_count(TRUNK_HASH), 1);
     }
 
     #[test]
-    fn test_get_confirmation_status() {
+    fn test_sweep_expired_evicted() {
         let mut dag = BlockDAG::<HashMap<_, _>, HashMap<_, _>, HashMap<_, _>>::default();
-        assert_eq!(
-            dag.get_confirmation_status(TRUNK_HASH),
-            TransactionStatus::Accepted
-        );
-        assert_eq!(
-            dag.get_confirmation_status(BRANCH_HASH),
-            TransactionStatus::Pending
+        dag.set_tip_limit(Some(2));
+        let commit = |dag: &mut BlockDAG<_, _, _>, transaction: Transaction| {
+            let hash = transaction.get_hash();
+            let referenced = transaction.get_all_refs();
+            dag.commit_transaction(transaction, TransactionUpdates::new(referenced))
+                .unwrap();
+            hash
+        };
+        let create = |contract: u64| {
+            Transaction::new(
+                BRANCH_HASH,
+                TRUNK_HASH,
+                vec![],
+                contract,
+                0,
+                0,
+                0,
+                TransactionData::Empty,
+            )
+        };
+
+        // The expiring transaction is evicted by the two after it
+        let expiring = commit(&mut dag, create(0).with_expiration(1));
+        let first = commit(&mut dag, create(1));
+        let second = commit(&mut dag, create(2));
+        assert_eq!(dag.tips, vec![first, second]);
+        assert_eq!(dag.evicted_tips, vec![expiring]);
+
+        // A milestone past the expiry drops it from the evicted tips too
+        let milestone = Transaction::new(
+            first,
+            second,
+            vec![],
+            0,
+            1,
+            150_000,
+            0,
+            TransactionData::Empty,
         );
-        assert_eq!(
-            dag.get_confirmation_status(10),
-            TransactionStatus::Rejected("Not accepted".into())
+        let milestone_hash = milestone.get_hash();
+        dag.commit_transaction(
+            milestone.clone(),
+            TransactionUpdates::new(vec![first, second]),
+        )
+        .unwrap();
+        let chain = dag.verify_milestone(milestone).unwrap();
+        assert!(dag.process_chain(milestone_hash, chain));
+        assert!(dag.add_pending_signature(MilestoneSignature::new(milestone_hash, 0, 0)));
+
+        assert_eq!(dag.sweep_expired(), Ok(vec![expiring]));
+        assert!(dag.get_transaction(expiring).is_none());
+        assert!(dag.evicted_tips.is_empty());
+        assert_eq!(dag.tips, vec![milestone_hash]);
+    }
+
+    /// Build a contract deploy followed by a set_u32 call for each value,
+    /// each transaction referencing the previous one
+    ///
+    /// The state roots of the calls are computed against a separate tree, as
+    /// the transactions are not yet committed to the dag. Returns the
+    /// transactions and the root of the state after the last call
+    fn build_contract_block(values: &[u32]) -> (Vec<Transaction>, u64) {
+        let src = load_api_test_source();
+
+        let mut storage = MerklePatriciaTree::<ContractValue, _>::new(HashMap::new());
+        let root = storage.default_root();
+        let mut deploy = Transaction::create(
+            TRUNK_HASH,
+            BRANCH_HASH,
+            vec![],
+            0,
+            BASE_NONCE,
+            root,
+            TransactionData::GenContract(src.clone(), None),
         );
+        deploy.sign(&mut PrivateKey::new(&SHA512_256));
+        let contract_id = deploy.get_hash();
+        let (contract, updates) = Contract::new(src, contract_id, &storage, root).unwrap();
+        let mut root = updates.get_root_hash();
+        storage.commit_set(updates).unwrap();
 
-        let mut key = PrivateKey::new(&SHA512_256);
-        let data = TransactionData::Empty;
-        let mut transaction =
-            Transaction::create(TRUNK_HASH, BRANCH_HASH, vec![], 0, BASE_NONCE, 0, data);
+        let mut block = vec![deploy];
+        for value in values {
+            let args = vec![ContractValue::U32(0), ContractValue::U32(*value)];
+            let nonce = proof_of_work(block.last().unwrap().get_nonce(), 0).unwrap();
+            let mut transaction = Transaction::create(
+                BRANCH_HASH,
+                block.last().unwrap().get_hash(),
+                vec![],
+                contract_id,
+                nonce,
+                root,
+                TransactionData::ExecContract("set_u32".into(), args.clone()),
+            );
+            transaction.sign(&mut PrivateKey::new(&SHA512_256));
+            let (_, updates) = contract.exec("set_u32", &args, &storage, root).unwrap();
+            root = updates.get_root_hash();
+            storage.commit_set(updates).unwrap();
+            block.push(transaction);
+        }
+        (block, root)
+    }
+
+    #[test]
+    fn test_apply_block() {
+        let mut dag = BlockDAG::<HashMap<_, _>, HashMap<_, _>, HashMap<_, _>>::default();
+        let (block, root) = build_contract_block(&[5, 6, 7]);
+        let contract_id = block[0].get_hash();
+        let last = block.last().unwrap().get_hash();
+
+        let expected: Vec<_> = block
+            .iter()
+            .map(|t| (t.get_hash(), TransactionStatus::Pending))
+            .collect();
+        assert_eq!(Ok(expected), dag.apply_block(block));
+
+        assert_eq!(dag.tips, vec![last]);
+        assert!(dag.get_contract(contract_id).is_some());
+        assert_eq!(
+            Ok(OOB::Borrowed(&ContractValue::U32(7))),
+            dag.storage.get(root, get_key(0, contract_id))
+        );
+    }
+
+    #[test]
+    fn test_apply_block_rollback() {
+        let mut dag = BlockDAG::<HashMap<_, _>, HashMap<_, _>, HashMap<_, _>>::default();
+        let (mut block, _) = build_contract_block(&[5, 6]);
+        let contract_id = block[0].get_hash();
+        let node_count = dag.get_mpt_node_count();
+
+        // The deploy is missing before the block is applied
+        let mut child = Transaction::create(
+            contract_id,
+            TRUNK_HASH,
+            vec![],
+            0,
+            0,
+            0,
+            TransactionData::Empty,
+        );
+        child.sign(&mut PrivateKey::new(&SHA512_256));
+        assert!(dag.try_add_transaction(&child).is_err());
+        assert_eq!(dag.orphans(), vec![contract_id]);
+
+        // The last transaction references a missing parent
+        block.push(Transaction::create(
+            BRANCH_HASH,
+            10,
+            vec![],
+            contract_id,
+            0,
+            0,
+            TransactionData::Empty,
+        ));
+        let missing = block.last().unwrap().get_hash();
+        assert_eq!(
+            Err(BlockDAGError::Rejected(
+                missing,
+                RejectionReason::MissingParent(10)
+            )),
+            dag.apply_block(block.clone())
+        );
+
+        for transaction in block.iter() {
+            assert!(dag.get_transaction(transaction.get_hash()).is_none());
+        }
+        assert!(dag.get_contract(contract_id).is_none());
+        assert_eq!(dag.get_tip_count(), 2);
+        assert!(dag.storage.prune().is_ok());
+        assert_eq!(node_count, dag.get_mpt_node_count());
+
+        // The rolled back deploy is missing again, along with the parent the
+        // block was rejected for
+        let mut expected = vec![contract_id, 10];
+        expected.sort();
+        assert_eq!(dag.orphans(), expected);
+    }
+
+    #[test]
+    fn test_apply_block_executes_ahead() {
+        let src = load_api_test_source();
+
+        let genesis = GenesisConfig::new()
+            .with_contract(src.clone())
+            .with_contract(src.clone());
+        let mut dag = BlockDAG::<HashMap<_, _>, HashMap<_, _>, HashMap<_, _>>::with_genesis(
+            HashMap::new(),
+            HashMap::new(),
+            HashMap::new(),
+            genesis,
+        )
+        .unwrap();
+        let root = dag.get_transaction(TRUNK_HASH).unwrap().get_root();
+
+        // Every call executes against the genesis state, alternating between
+        // the two contracts
+        let block: Vec<Transaction> = (0..4)
+            .map(|i| {
+                let mut transaction = Transaction::create(
+                    TRUNK_HASH,
+                    BRANCH_HASH,
+                    vec![],
+                    genesis_contract_id(i % 2),
+                    BASE_NONCE,
+                    root,
+                    TransactionData::ExecContract(
+                        "set_u64".into(),
+                        vec![ContractValue::U32(1), ContractValue::U64(i as u64 + 10)],
+                    ),
+                );
+                transaction.sign(&mut PrivateKey::new(&SHA512_256));
+                transaction
+            })
+            .collect();
+        let roots: Vec<u64> = block
+            .iter()
+            .map(|t| dag.compute_resulting_root(t).unwrap())
+            .collect();
+
+        let expected: Vec<_> = block
+            .iter()
+            .map(|t| (t.get_hash(), TransactionStatus::Pending))
+            .collect();
+        assert_eq!(Ok(expected), dag.apply_block(block));
+
+        for (i, root) in roots.into_iter().enumerate() {
+            assert_eq!(
+                Ok(OOB::Borrowed(&ContractValue::U64(i as u64 + 10))),
+                dag.storage
+                    .get(root, get_key(1, genesis_contract_id(i % 2)))
+            );
+        }
+    }
+
+    #[test]
+    fn test_apply_block_parallel_verify() {
+        let mut block = Vec::new();
+        let (mut prev, mut prev_nonce) = (TRUNK_HASH, 0);
+        for _ in 0..12 {
+            let nonce = proof_of_work(prev_nonce, 0).unwrap();
+            let mut transaction = Transaction::create(
+                BRANCH_HASH,
+                prev,
+                vec![],
+                0,
+                nonce,
+                0,
+                TransactionData::Empty,
+            );
+            transaction.sign(&mut PrivateKey::new(&SHA512_256));
+            prev = transaction.get_hash();
+            prev_nonce = nonce;
+            block.push(transaction);
+        }
+
+        let mut serial = BlockDAG::<HashMap<_, _>, HashMap<_, _>, HashMap<_, _>>::default();
+        let mut parallel = BlockDAG::<HashMap<_, _>, HashMap<_, _>, HashMap<_, _>>::default();
+        parallel.set_verify_parallelism(4);
+
+        let result = serial.apply_block(block.clone());
+        assert!(result.is_ok());
+        assert_eq!(result, parallel.apply_block(block.clone()));
+        assert_eq!(serial.tips, parallel.tips);
+
+        // An unsigned transaction in the middle of the batch rejects it
+        let mut serial = BlockDAG::<HashMap<_, _>, HashMap<_, _>, HashMap<_, _>>::default();
+        let mut parallel = BlockDAG::<HashMap<_, _>, HashMap<_, _>, HashMap<_, _>>::default();
+        parallel.set_verify_parallelism(4);
+
+        let bad = &block[6];
+        block[6] = Transaction::create(
+            bad.get_branch_hash(),
+            bad.get_trunk_hash(),
+            vec![],
+            0,
+            bad.get_nonce(),
+            0,
+            TransactionData::Empty,
+        );
+        let result = serial.apply_block(block.clone());
+        assert_eq!(
+            Err(TransactionError::Rejected(RejectionReason::BadSignature)),
+            result
+        );
+        assert_eq!(result, parallel.apply_block(block.clone()));
+        assert_eq!(serial.tips, parallel.tips);
+        assert_eq!(parallel.get_tip_count(), 2);
+    }
+
+    #[test]
+    fn test_empty_transaction_root() {
+        let dag = BlockDAG::<HashMap<_, _>, HashMap<_, _>, HashMap<_, _>>::default();
+        let root = dag.get_mpt_default_root();
+
+        let mut transaction = Transaction::create(
+            TRUNK_HASH,
+            BRANCH_HASH,
+            vec![],
+            0,
+            BASE_NONCE,
+            root,
+            TransactionData::Empty,
+        );
+        transaction.sign(&mut PrivateKey::new(&SHA512_256));
+        let updates = dag.try_add_transaction(&transaction).unwrap();
+        assert_eq!(updates.get_storage_root(), Some(root));
+
+        // The claimed root differs from the parents' roots
+        let mut tampered = Transaction::create(
+            TRUNK_HASH,
+            BRANCH_HASH,
+            vec![],
+            0,
+            BASE_NONCE,
+            root ^ 1,
+            TransactionData::Empty,
+        );
+        tampered.sign(&mut PrivateKey::new(&SHA512_256));
+        assert_eq!(
+            Err(TransactionError::Rejected(RejectionReason::MergeRoot)),
+            dag.try_add_transaction(&tampered)
+        );
+    }
+
+    #[test]
+    fn test_empty_transaction_conflicting_parents() {
+        let src = load_api_test_source();
+
+        let genesis = GenesisConfig::new().with_contract(src);
+        let mut dag = BlockDAG::<HashMap<_, _>, HashMap<_, _>, HashMap<_, _>>::with_genesis(
+            HashMap::new(),
+            HashMap::new(),
+            HashMap::new(),
+            genesis,
+        )
+        .unwrap();
+        let genesis_root = dag.get_transaction(TRUNK_HASH).unwrap().get_root();
+
+        // Two chains setting the same value differently, each followed by a
+        // call executing against the state the first call left
+        let exec = |branch: u64, trunk: &Transaction, root: u64, value: u32| {
+            let nonce = if trunk.get_hash() == TRUNK_HASH {
+                BASE_NONCE
+            } else {
+                proof_of_work(trunk.get_nonce(), 0).unwrap()
+            };
+            let mut transaction = Transaction::create(
+                branch,
+                trunk.get_hash(),
+                vec![],
+                genesis_contract_id(0),
+                nonce,
+                root,
+                TransactionData::ExecContract(
+                    "set_u32".into(),
+                    vec![ContractValue::U32(0), ContractValue::U32(value)],
+                ),
+            );
+            transaction.sign(&mut PrivateKey::new(&SHA512_256));
+            transaction
+        };
+        let trunk = dag.get_transaction(TRUNK_HASH).unwrap().clone();
+        let first = exec(BRANCH_HASH, &trunk, genesis_root, 5);
+        let second = exec(BRANCH_HASH, &trunk, genesis_root, 6);
+        let first_child = exec(
+            BRANCH_HASH,
+            &first,
+            dag.compute_resulting_root(&first).unwrap(),
+            7,
+        );
+        let second_child = exec(
+            BRANCH_HASH,
+            &second,
+            dag.compute_resulting_root(&second).unwrap(),
+            8,
+        );
+        let block = vec![first, second, first_child.clone(), second_child.clone()];
+        assert!(dag.apply_block(block).is_ok());
+
+        // The parents' states conflict, so an empty transaction can not
+        // merge them, even claiming no root
+        let mut merge = Transaction::create(
+            second_child.get_hash(),
+            first_child.get_hash(),
+            vec![],
+            0,
+            proof_of_work(first_child.get_nonce(), second_child.get_nonce()).unwrap(),
+            0,
+            TransactionData::Empty,
+        );
+        merge.sign(&mut PrivateKey::new(&SHA512_256));
+        assert_eq!(
+            Err(TransactionError::Rejected(RejectionReason::MergeRoot)),
+            dag.try_add_transaction(&merge)
+        );
+    }
+
+    #[test]
+    fn test_build_transaction() {
+        let mut dag = BlockDAG::<HashMap<_, _>, HashMap<_, _>, HashMap<_, _>>::default();
+        let mut key = PrivateKey::new(&SHA512_256);
+
+        let mut transaction = dag
+            .build_transaction(TRUNK_HASH, BRANCH_HASH, 0, TransactionData::Empty)
+            .unwrap();
+        assert_eq!(transaction.get_trunk_hash(), TRUNK_HASH);
+        assert_eq!(transaction.get_branch_hash(), BRANCH_HASH);
+        assert_eq!(transaction.get_root(), dag.get_mpt_default_root());
+        transaction.sign(&mut key);
+        let updates = dag.try_add_transaction(&transaction).unwrap();
+        assert!(dag.commit_transaction(transaction.clone(), updates).is_ok());
+
+        // Transactions can be built on the new transaction in turn
+        let mut child = dag
+            .build_transaction(
+                transaction.get_hash(),
+                BRANCH_HASH,
+                0,
+                TransactionData::Empty,
+            )
+            .unwrap();
+        child.sign(&mut PrivateKey::new(&SHA512_256));
+        assert!(dag.try_add_transaction(&child).is_ok());
+
+        assert_eq!(
+            dag.build_transaction(10, BRANCH_HASH, 0, TransactionData::Empty),
+            Err(BlockDAGError::TransactionNotFound(10))
+        );
+    }
+
+    #[test]
+    fn test_compute_resulting_root() {
+        let src = load_api_test_source();
+
+        let mut dag = BlockDAG::<HashMap<_, _>, HashMap<_, _>, HashMap<_, _>>::default();
+        let data = TransactionData::GenContract(src, None);
+        let mut deploy = dag
+            .build_transaction(TRUNK_HASH, BRANCH_HASH, 0, data)
+            .unwrap();
+
+        // The root is computed before signing, and matches the committed root
+        let deploy_root = dag.compute_resulting_root(&deploy).unwrap();
+        deploy.sign(&mut PrivateKey::new(&SHA512_256));
+        let updates = dag.try_add_transaction(&deploy).unwrap();
+        assert_eq!(updates.get_storage_root(), Some(deploy_root));
+        dag.commit_transaction(deploy.clone(), updates).unwrap();
+        assert!(dag.storage.nodes.get(&deploy_root).is_ok());
+
+        let contract_id = deploy.get_hash();
+        let nonce = proof_of_work(
+            deploy.get_nonce(),
+            dag.get_transaction(BRANCH_HASH).unwrap().get_nonce(),
+        )
+        .unwrap();
+        let mut exec = Transaction::create(
+            BRANCH_HASH,
+            contract_id,
+            vec![],
+            contract_id,
+            nonce,
+            deploy_root,
+            TransactionData::ExecContract(
+                "set_u32".into(),
+                vec![ContractValue::U32(0), ContractValue::U32(3)],
+            ),
+        );
+        let exec_root = dag.compute_resulting_root(&exec).unwrap();
+        exec.sign(&mut PrivateKey::new(&SHA512_256));
+        let updates = dag.try_add_transaction(&exec).unwrap();
+        assert_eq!(updates.get_storage_root(), Some(exec_root));
+        dag.commit_transaction(exec, updates).unwrap();
+        assert_eq!(
+            Ok(OOB::Borrowed(&ContractValue::U32(3))),
+            dag.storage.get(exec_root, get_key(0, contract_id))
+        );
+
+        let missing = Transaction::create(
+            BRANCH_HASH,
+            TRUNK_HASH,
+            vec![],
+            10,
+            0,
+            deploy_root,
+            TransactionData::ExecContract("set_u32".into(), vec![]),
+        );
+        assert_eq!(
+            dag.compute_resulting_root(&missing),
+            Err(BlockDAGError::Contract(ContractError::ContractNotFound(10)))
+        );
+    }
+
+    #[test]
+    fn test_orphans() {
+        let mut dag = BlockDAG::<HashMap<_, _>, HashMap<_, _>, HashMap<_, _>>::default();
+        assert!(dag.orphans().is_empty());
+
+        // Committing skips the checks, so the missing parent is accepted
+        let missing = 10;
+        let transaction =
+            Transaction::create(missing, TRUNK_HASH, vec![], 0, 0, 0, TransactionData::Empty);
+        dag.commit_transaction(
+            transaction.clone(),
+            TransactionUpdates::new(transaction.get_all_refs()),
+        )
+        .unwrap();
+        assert_eq!(dag.orphans(), vec![missing]);
+    }
+
+    #[test]
+    fn test_orphans_rejected() {
+        let mut dag = BlockDAG::<HashMap<_, _>, HashMap<_, _>, HashMap<_, _>>::default();
+        let mut parent = Transaction::create(
+            TRUNK_HASH,
+            BRANCH_HASH,
+            vec![],
+            0,
+            BASE_NONCE,
+            0,
+            TransactionData::Empty,
+        );
+        parent.sign(&mut PrivateKey::new(&SHA512_256));
+        let parent_hash = parent.get_hash();
+
+        // The parent of a transaction rejected for missing it is an orphan
+        let mut child = Transaction::create(
+            parent_hash,
+            TRUNK_HASH,
+            vec![],
+            0,
+            0,
+            0,
+            TransactionData::Empty,
+        );
+        child.sign(&mut PrivateKey::new(&SHA512_256));
+        assert_eq!(
+            dag.try_add_transaction(&child),
+            Err(TransactionError::Rejected(RejectionReason::MissingParent(
+                parent_hash
+            )))
+        );
+        assert_eq!(dag.orphans(), vec![parent_hash]);
+
+        // So are the parents missing from an incomplete milestone chain
+        let milestone = Transaction::create(11, 12, vec![], 0, 150_000, 0, TransactionData::Empty);
+        assert!(dag.verify_milestone(milestone).is_err());
+        let mut expected = vec![parent_hash, 11, 12];
+        expected.sort();
+        assert_eq!(dag.orphans(), expected);
+
+        // Once received, the parent is no longer an orphan
+        let updates = dag.try_add_transaction(&parent).unwrap();
+        dag.commit_transaction(parent, updates).unwrap();
+        assert_eq!(dag.orphans(), vec![11, 12]);
+    }
+
+    #[test]
+    fn test_orphans_bounded() {
+        let mut dag = BlockDAG::<HashMap<_, _>, HashMap<_, _>, HashMap<_, _>>::default();
+        let hashes: Vec<u64> = (1..=MAX_MISSING_PARENTS as u64).collect();
+        dag.record_missing_parents(&hashes);
+        assert_eq!(dag.orphans(), hashes);
+
+        // Past the limit the oldest missing parents are forgotten, rather than
+        // new ones being ignored
+        let newest = MAX_MISSING_PARENTS as u64 + 1;
+        dag.record_missing_parents(&[newest]);
+        let orphans = dag.orphans();
+        assert_eq!(orphans.len(), MAX_MISSING_PARENTS);
+        assert!(!orphans.contains(&1));
+        assert!(orphans.contains(&newest));
+
+        // Received parents are forgotten too, making room for others
+        let mut parent = Transaction::create(
+            TRUNK_HASH,
+            BRANCH_HASH,
+            vec![],
+            0,
+            BASE_NONCE,
+            0,
+            TransactionData::Empty,
+        );
+        parent.sign(&mut PrivateKey::new(&SHA512_256));
+        let parent_hash = parent.get_hash();
+        dag.record_missing_parents(&[parent_hash]);
+        assert!(dag.orphans().contains(&parent_hash));
+        let updates = dag.try_add_transaction(&parent).unwrap();
+        dag.commit_transaction(parent, updates).unwrap();
+        let missing: Vec<u64> = dag
+            .missing_parents
+            .lock()
+            .unwrap()
+            .iter()
+            .cloned()
+            .collect();
+        assert!(!missing.contains(&parent_hash));
+        assert_eq!(missing.len(), MAX_MISSING_PARENTS - 1);
+    }
+
+    #[test]
+    fn test_recompute_tips() {
+        let mut dag = BlockDAG::<HashMap<_, _>, HashMap<_, _>, HashMap<_, _>>::default();
+        let mut expected = dag.tips.clone();
+        expected.sort();
+        dag.tips.clear();
+        dag.recompute_tips();
+        assert_eq!(dag.tips, expected);
+
+        let mut block = Vec::new();
+        for (branch, trunk, start) in vec![
+            (TRUNK_HASH, BRANCH_HASH, 0),
+            (TRUNK_HASH, BRANCH_HASH, BASE_NONCE + 1),
+        ] {
+            let mut transaction = Transaction::create(
+                branch,
+                trunk,
+                vec![],
+                0,
+                proof_of_work_from(0, 0, start).unwrap(),
+                0,
+                TransactionData::Empty,
+            );
+            transaction.sign(&mut PrivateKey::new(&SHA512_256));
+            block.push(transaction);
+        }
+        let first = block[0].get_hash();
+        let nonce = proof_of_work(block[0].get_nonce(), 0).unwrap();
+        let mut transaction = Transaction::create(
+            BRANCH_HASH,
+            first,
+            vec![],
+            0,
+            nonce,
+            0,
+            TransactionData::Empty,
+        );
+        transaction.sign(&mut PrivateKey::new(&SHA512_256));
+        block.push(transaction);
+        dag.apply_block(block.clone()).unwrap();
+
+        let mut expected = vec![block[1].get_hash(), block[2].get_hash()];
+        expected.sort();
+        let mut tips = dag.tips.clone();
+        tips.sort();
+        assert_eq!(tips, expected);
+
+        // Corrupt the tips
+        dag.tips = vec![TRUNK_HASH, first];
+        dag.recompute_tips();
+        assert_eq!(dag.tips, expected);
+    }
+
+    #[test]
+    fn test_gen_contract_missing_memory_export() {
+        let header: [u8; 8] = [0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00];
+        // Type section: (i32, i32) -> ()
+        let types: [u8; 8] = [0x01, 0x06, 0x01, 0x60, 0x02, 0x7f, 0x7f, 0x00];
+        // Import section: env.__ofc__return_tuple
+        let mut imports = vec![0x02, 0x1b, 0x01, 0x03];
+        imports.extend(b"env");
+        imports.push(0x13);
+        imports.extend(b"__ofc__return_tuple");
+        imports.extend(&[0x00, 0x00]);
+        // Memory section, and an export of the memory
+        let mut memory = vec![0x05, 0x03, 0x01, 0x00, 0x01, 0x07, 0x0a, 0x01, 0x06];
+        memory.extend(b"memory");
+        memory.extend(&[0x02, 0x00]);
+
+        let mut code = [&header[..], &types[..], &imports[..]].concat();
+        let src = ContractSource::new(&code);
+        code.extend(memory);
+        assert!(ContractSource::new(&code).check_memory_export().is_ok());
+
+        let mut dag = BlockDAG::<HashMap<_, _>, HashMap<_, _>, HashMap<_, _>>::default();
+        let mut transaction = Transaction::create(
+            TRUNK_HASH,
+            BRANCH_HASH,
+            vec![],
+            0,
+            BASE_NONCE,
+            dag.get_mpt_default_root(),
+            TransactionData::GenContract(src, None),
+        );
+        transaction.sign(&mut PrivateKey::new(&SHA512_256));
+        assert_eq!(
+            dag.try_add_transaction(&transaction),
+            Err(TransactionError::Rejected(
+                ContractError::MissingMemoryExport.into()
+            ))
+        );
+    }
+
+    #[test]
+    fn test_exec_contract_abi() {
+        let src = load_api_test_source();
+        let abi = ContractAbi::new().with_function(
+            "set_u32",
+            vec![ContractValueType::U32, ContractValueType::U32],
+            None,
+        );
+
+        let mut dag = BlockDAG::<HashMap<_, _>, HashMap<_, _>, HashMap<_, _>>::default();
+        let mut deploy = Transaction::create(
+            TRUNK_HASH,
+            BRANCH_HASH,
+            vec![],
+            0,
+            BASE_NONCE,
+            dag.get_mpt_default_root(),
+            TransactionData::GenContract(src, Some(abi.clone())),
+        );
+        deploy.sign(&mut PrivateKey::new(&SHA512_256));
+        let updates = dag.try_add_transaction(&deploy).unwrap();
+        let root = updates.get_storage_root().unwrap();
+        dag.commit_transaction(deploy.clone(), updates).unwrap();
+        let contract_id = deploy.get_hash();
+        assert_eq!(Some(&abi), dag.get_contract(contract_id).unwrap().get_abi());
+
+        let exec = |func_name: &str, args: Vec<ContractValue>| {
+            let mut transaction = Transaction::create(
+                BRANCH_HASH,
+                deploy.get_hash(),
+                vec![],
+                contract_id,
+                proof_of_work(deploy.get_nonce(), 0).unwrap(),
+                root,
+                TransactionData::ExecContract(func_name.into(), args),
+            );
+            transaction.sign(&mut PrivateKey::new(&SHA512_256));
+            dag.try_add_transaction(&transaction)
+        };
+        assert!(exec(
+            "set_u32",
+            vec![ContractValue::U32(0), ContractValue::U32(5)]
+        )
+        .is_ok());
+        assert_eq!(
+            exec(
+                "set_u32",
+                vec![ContractValue::U32(0), ContractValue::U64(5)]
+            ),
+            Err(TransactionError::Rejected(
+                ContractError::AbiMismatch("set_u32".into()).into()
+            ))
+        );
+        assert_eq!(
+            exec("get_u32", vec![ContractValue::U32(0)]),
+            Err(TransactionError::Rejected(
+                ContractError::AbiMismatch("get_u32".into()).into()
+            ))
+        );
+    }
+
+    #[test]
+    fn test_exec_contract_not_found() {
+        let src = load_api_test_source();
+
+        let mut dag = BlockDAG::<HashMap<_, _>, HashMap<_, _>, HashMap<_, _>>::default();
+        let mut deploy = Transaction::create(
+            TRUNK_HASH,
+            BRANCH_HASH,
+            vec![],
+            0,
+            BASE_NONCE,
+            dag.get_mpt_default_root(),
+            TransactionData::GenContract(src, None),
+        );
+        deploy.sign(&mut PrivateKey::new(&SHA512_256));
+        let updates = dag.try_add_transaction(&deploy).unwrap();
+        let root = updates.get_storage_root().unwrap();
+        dag.commit_transaction(deploy.clone(), updates).unwrap();
+
+        let exec = |contract_id: u64, func_name: &str| {
+            let mut transaction = Transaction::create(
+                BRANCH_HASH,
+                deploy.get_hash(),
+                vec![],
+                contract_id,
+                proof_of_work(deploy.get_nonce(), 0).unwrap(),
+                root,
+                TransactionData::ExecContract(func_name.into(), vec![ContractValue::U32(0)]),
+            );
+            transaction.sign(&mut PrivateKey::new(&SHA512_256));
+            dag.try_add_transaction(&transaction)
+        };
+        assert_eq!(
+            exec(1, "get_u32"),
+            Err(TransactionError::Rejected(
+                ContractError::ContractNotFound(1).into()
+            ))
+        );
+        assert_eq!(
+            exec(deploy.get_hash(), "missing"),
+            Err(TransactionError::Rejected(
+                ContractError::FunctionNotFound("missing".into()).into()
+            ))
+        );
+    }
+
+    #[test]
+    fn test_transactions_by_address() {
+        let mut dag = BlockDAG::<HashMap<_, _>, HashMap<_, _>, HashMap<_, _>>::default();
+        // Lamport keys sign a single message, so the transactions share the
+        // address of a key without being signed by it
+        let address = PrivateKey::new(&SHA512_256).public_key().to_bytes();
+        let mut id = 0;
+        let mut hashes = Vec::new();
+        for nonce in 0..3 {
+            let unsigned = Transaction::create(
+                TRUNK_HASH,
+                BRANCH_HASH,
+                vec![],
+                0,
+                nonce,
+                0,
+                TransactionData::Empty,
+            );
+            let transaction = Transaction::from_parts(
+                unsigned.get_header(),
+                TransactionData::Empty,
+                KeyScheme::LamportSha512_256,
+                address.clone(),
+                vec![],
+            )
+            .unwrap();
+            id = transaction.get_address_id();
+            hashes.push(transaction.get_hash());
+            let updates = TransactionUpdates::new(vec![TRUNK_HASH, BRANCH_HASH]);
+            dag.commit_transaction(transaction, updates).unwrap();
+        }
+
+        let mut transaction = Transaction::create(
+            TRUNK_HASH,
+            BRANCH_HASH,
+            vec![],
+            0,
+            BASE_NONCE,
+            0,
+            TransactionData::Empty,
+        );
+        transaction.sign(&mut PrivateKey::new(&SHA512_256));
+        let other = transaction.get_address_id();
+        let updates = dag.try_add_transaction(&transaction).unwrap();
+        dag.commit_transaction(transaction.clone(), updates)
+            .unwrap();
+
+        assert_eq!(dag.get_transactions_by_address(id), hashes);
+        assert_eq!(
+            dag.get_transactions_by_address(other),
+            vec![transaction.get_hash()]
+        );
+        assert!(dag.get_transactions_by_address(0).is_empty());
+    }
+
+    #[test]
+    fn test_find_merge_base() {
+        let mut dag = BlockDAG::<HashMap<_, _>, HashMap<_, _>, HashMap<_, _>>::default();
+        // Both genesis transactions are common ancestors at the same distance
+        let a = insert_transaction(&mut dag, BRANCH_HASH, TRUNK_HASH, 0, TransactionData::Empty);
+        let b = insert_transaction(&mut dag, TRUNK_HASH, BRANCH_HASH, 1, TransactionData::Empty);
+        let (a, b) = (a.get_hash(), b.get_hash());
+
+        assert_eq!(dag.find_merge_base(a, b), Some(BRANCH_HASH.min(TRUNK_HASH)));
+        assert_eq!(dag.find_merge_base(b, a), Some(BRANCH_HASH.min(TRUNK_HASH)));
+
+        // A closer common ancestor is preferred over a lower hash
+        let c = insert_transaction(&mut dag, a, a, 2, TransactionData::Empty).get_hash();
+        let d = insert_transaction(&mut dag, a, b, 3, TransactionData::Empty).get_hash();
+        assert_eq!(dag.find_merge_base(c, d), Some(a));
+        assert_eq!(dag.find_merge_base(c, a), Some(a));
+        assert_eq!(dag.find_merge_base(c, 10), None);
+    }
+
+    #[test]
+    fn test_find_merge_base_within() {
+        let mut dag = BlockDAG::<HashMap<_, _>, HashMap<_, _>, HashMap<_, _>>::default();
+        let mut a =
+            insert_transaction(&mut dag, BRANCH_HASH, TRUNK_HASH, 0, TransactionData::Empty);
+        let mut b =
+            insert_transaction(&mut dag, TRUNK_HASH, BRANCH_HASH, 1, TransactionData::Empty);
+        // Grow two chains whose only common ancestors are the genesis
+        // transactions, 5 transactions back
+        for i in 0..4 {
+            a = insert_transaction(
+                &mut dag,
+                a.get_hash(),
+                a.get_hash(),
+                2 * i + 2,
+                TransactionData::Empty,
+            );
+            b = insert_transaction(
+                &mut dag,
+                b.get_hash(),
+                b.get_hash(),
+                2 * i + 3,
+                TransactionData::Empty,
+            );
+        }
+        let (a, b) = (a.get_hash(), b.get_hash());
+        let genesis = BRANCH_HASH.min(TRUNK_HASH);
+
+        assert_eq!(
+            dag.find_merge_base_within(a, b, 4),
+            Err(BlockDAGError::MergeBaseTooDeep(4))
+        );
+        assert_eq!(dag.find_merge_base_within(a, b, 5), Ok(Some(genesis)));
+        assert_eq!(dag.find_merge_base(a, b), Some(genesis));
+        assert_eq!(
+            dag.get_merge_base_within(a, b, 5)
+                .unwrap()
+                .map(|base| base.hash),
+            Some(genesis)
+        );
+        // Closer merge bases are still found with a small depth
+        assert_eq!(dag.find_merge_base_within(a, a, 0), Ok(Some(a)));
+    }
+
+    #[test]
+    fn test_walk_search() {
+        let dag = BlockDAG::<HashMap<_, _>, HashMap<_, _>, HashMap<_, _>>::default();
+        let prev_milestone = dag.milestones.get_head_milestone();
+
+        let transaction = Transaction::create(
+            TRUNK_HASH,
+            BRANCH_HASH,
+            vec![],
+            0,
+            0,
+            0,
+            TransactionData::Genesis,
+        );
+        assert!(dag.walk_search(
+            &transaction,
+            prev_milestone.get_hash(),
+            0,
+            &mut |_| {},
+            &mut |_| {}
+        ));
+
+        let transaction =
+            Transaction::create(TRUNK_HASH, 0, vec![], 0, 0, 0, TransactionData::Genesis);
+        assert!(dag.walk_search(
+            &transaction,
+            prev_milestone.get_hash(),
+            0,
+            &mut |_| {},
+            &mut |_| {}
+        ));
+
+        let transaction =
+            Transaction::create(0, BRANCH_HASH, vec![], 0, 0, 0, TransactionData::Genesis);
+        assert!(dag.walk_search(
+            &transaction,
+            prev_milestone.get_hash(),
+            0,
+            &mut |_| {},
+            &mut |_| {}
+        ));
+
+        let transaction = Transaction::create(0, 0, vec![], 0, 0, 0, TransactionData::Genesis);
+        assert!(!dag.walk_search(
+            &transaction,
+            prev_milestone.get_hash(),
+            0,
+            &mut |_| {},
+            &mut |_| {}
+        ));
+    }
+
+    #[test]
+    fn test_verify_deep_milestone() {
+        let mut dag = BlockDAG::<HashMap<_, _>, HashMap<_, _>, HashMap<_, _>>::default();
+        let depth = 20_000;
+
+        let mut prev_hash = TRUNK_HASH;
+        for _ in 0..depth {
+            prev_hash =
+                insert_transaction(&mut dag, prev_hash, prev_hash, 0, TransactionData::Empty)
+                    .get_hash();
+        }
+        let milestone = Transaction::create(
+            prev_hash,
+            prev_hash,
+            vec![],
+            0,
+            0,
+            0,
+            TransactionData::Empty,
+        );
+
+        let chain = dag.verify_milestone(milestone).unwrap();
+        assert_eq!(chain.len(), depth);
+        assert_eq!(chain[0].get_branch_hash(), TRUNK_HASH);
+        assert_eq!(chain[depth - 1].get_hash(), prev_hash);
+    }
+
+    #[test]
+    fn test_add_milestone() {
+        let mut dag = BlockDAG::<HashMap<_, _>, HashMap<_, _>, HashMap<_, _>>::default();
+        let data = TransactionData::GenContract(ContractSource::new(&vec![]), None);
+        let middle_transaction = insert_transaction(&mut dag, 0, TRUNK_HASH, 1, data.clone());
+        let transaction = insert_transaction(&mut dag, 0, middle_transaction.get_hash(), 1, data);
+
+        match dag.verify_milestone(transaction) {
+            Ok(chain) => {
+                assert_eq!(1, chain.len());
+                assert_eq!(chain[0].get_hash(), middle_transaction.get_hash());
+            }
+            Err(err) => panic!("Unexpected missing transactions: {:?}", err),
+        }
+    }
+
+    #[test]
+    fn test_get_confirmation_status() {
+        let mut dag = BlockDAG::<HashMap<_, _>, HashMap<_, _>, HashMap<_, _>>::default();
+        assert_eq!(
+            dag.get_confirmation_status(TRUNK_HASH),
+            TransactionStatus::Accepted
+        );
+        assert_eq!(
+            dag.get_confirmation_status(BRANCH_HASH),
+            TransactionStatus::Pending
+        );
+        assert_eq!(
+            dag.get_confirmation_status(10),
+            TransactionStatus::Rejected(RejectionReason::Other("Not accepted".into()))
+        );
+
+        let mut key = PrivateKey::new(&SHA512_256);
+        let data = TransactionData::Empty;
+        let mut transaction =
+            Transaction::create(TRUNK_HASH, BRANCH_HASH, vec![], 0, BASE_NONCE, 0, data);
         transaction.sign(&mut key);
         let updates = dag.try_add_transaction(&transaction).unwrap();
         assert_eq!(
-            Ok(TransactionStatus::Pending),
-            dag.commit_transaction(transaction.clone(), updates)
+            Ok(TransactionStatus::Pending),
+            dag.commit_transaction(transaction.clone(), updates)
+        );
+        assert_eq!(
+            dag.get_confirmation_status(transaction.get_hash()),
+            TransactionStatus::Pending
+        );
+    }
+
+    #[test]
+    fn test_get_head_milestone() {
+        let mut dag = BlockDAG::<HashMap<_, _>, HashMap<_, _>, HashMap<_, _>>::default();
+        assert_eq!(dag.get_head_milestone().get_hash(), TRUNK_HASH);
+
+        let milestone = Transaction::create(
+            TRUNK_HASH,
+            BRANCH_HASH,
+            vec![],
+            0,
+            150_000,
+            0,
+            TransactionData::Empty,
+        );
+        assert_eq!(
+            Ok(TransactionStatus::Milestone),
+            dag.commit_transaction(milestone.clone(), TransactionUpdates::new(vec![]))
+        );
+        // The head does not change until the milestone is confirmed
+        assert_eq!(dag.get_head_milestone().get_hash(), TRUNK_HASH);
+
+        assert!(dag.add_pending_signature(MilestoneSignature::new(milestone.get_hash(), 0, 0)));
+        assert_eq!(dag.get_head_milestone().get_hash(), milestone.get_hash());
+    }
+
+    #[test]
+    fn test_aggregate_milestone_signature() {
+        let mut dag = BlockDAG::<HashMap<_, _>, HashMap<_, _>, HashMap<_, _>>::default();
+        let keys: Vec<ValidatorKey> = (0..3)
+            .map(|_| ValidatorKey::from_pkcs8(&ValidatorKey::generate_pkcs8().unwrap()).unwrap())
+            .collect();
+        dag.set_validators(keys.iter().map(ValidatorKey::public_key).collect());
+        let genesis = dag.get_head_milestone().get_hash();
+
+        let milestone = Transaction::create(
+            BRANCH_HASH,
+            TRUNK_HASH,
+            vec![],
+            0,
+            150_000,
+            0,
+            TransactionData::Empty,
+        );
+        assert_eq!(
+            Ok(TransactionStatus::Milestone),
+            dag.commit_transaction(milestone.clone(), TransactionUpdates::new(vec![]))
+        );
+        let chain = dag.verify_milestone(milestone.clone()).unwrap();
+        assert!(dag.process_chain(milestone.get_hash(), chain));
+
+        // An aggregate without a quorum of validators is ignored
+        let mut aggregate = AggregateMilestoneSignature::new(milestone.get_hash(), vec![0]);
+        aggregate.sign(&keys[0]);
+        assert!(!dag.add_pending_aggregate_signature(aggregate.clone()));
+        assert_eq!(dag.get_head_milestone().get_hash(), genesis);
+
+        // A quorum approves the milestone in one step
+        aggregate.sign(&keys[1]);
+        assert!(dag.add_pending_aggregate_signature(aggregate));
+        assert_eq!(dag.get_head_milestone().get_hash(), milestone.get_hash());
+    }
+
+    #[test]
+    fn test_anchor_root() {
+        let mut dag = BlockDAG::<HashMap<_, _>, HashMap<_, _>, HashMap<_, _>>::default();
+        let genesis_root = dag.get_transaction(TRUNK_HASH).unwrap().get_root();
+        assert_eq!(dag.get_anchor_root(TRUNK_HASH), None);
+
+        let mut transaction = Transaction::create(
+            TRUNK_HASH,
+            BRANCH_HASH,
+            vec![],
+            0,
+            BASE_NONCE,
+            0,
+            TransactionData::Empty,
+        );
+        transaction.sign(&mut PrivateKey::new(&SHA512_256));
+        let updates = dag.try_add_transaction(&transaction).unwrap();
+        assert_eq!(
+            Ok(TransactionStatus::Pending),
+            dag.commit_transaction(transaction.clone(), updates)
+        );
+        assert_eq!(dag.get_anchor_root(transaction.get_hash()), None);
+
+        let milestone = Transaction::create(
+            BRANCH_HASH,
+            transaction.get_hash(),
+            vec![],
+            0,
+            150_000,
+            genesis_root,
+            TransactionData::Empty,
+        );
+        assert_eq!(
+            Ok(TransactionStatus::Milestone),
+            dag.commit_transaction(milestone.clone(), TransactionUpdates::new(vec![]))
+        );
+        let chain = dag.verify_milestone(milestone.clone()).unwrap();
+        assert!(dag.process_chain(milestone.get_hash(), chain));
+        assert!(dag.add_pending_signature(MilestoneSignature::new(milestone.get_hash(), 0, 0)));
+
+        assert_eq!(
+            dag.get_confirmation_status(transaction.get_hash()),
+            TransactionStatus::Accepted
+        );
+        assert_eq!(
+            dag.get_anchor_root(transaction.get_hash()),
+            Some(milestone.get_root())
+        );
+        assert_eq!(dag.get_anchor_root(BRANCH_HASH), Some(genesis_root));
+    }
+
+    #[test]
+    fn test_transactions_between() {
+        let mut dag = BlockDAG::<HashMap<_, _>, HashMap<_, _>, HashMap<_, _>>::default();
+        let mut add = |branch: u64, trunk: u64, timestamp: u64, nonce: u32| {
+            let transaction = Transaction::new(
+                branch,
+                trunk,
+                vec![],
+                0,
+                timestamp,
+                nonce,
+                0,
+                TransactionData::Empty,
+            );
+            let hash = transaction.get_hash();
+            let status =
+                dag.commit_transaction(transaction.clone(), TransactionUpdates::new(vec![]));
+            if status == Ok(TransactionStatus::Milestone) {
+                let chain = dag.verify_milestone(transaction).unwrap();
+                assert!(dag.process_chain(hash, chain));
+                assert!(dag.add_pending_signature(MilestoneSignature::new(hash, 0, 0)));
+            }
+            hash
+        };
+
+        let first = add(BRANCH_HASH, TRUNK_HASH, 1, 0);
+        let second = add(BRANCH_HASH, first, 2, 0);
+        let first_milestone = add(BRANCH_HASH, second, 3, 150_000);
+        let third = add(second, first_milestone, 4, 0);
+        // Timestamps are set by the issuer, so may predate the older milestone
+        let skewed = add(first_milestone, third, 1, 0);
+        let second_milestone = add(third, skewed, 5, 150_000);
+
+        let mut expected = vec![BRANCH_HASH, first, second, first_milestone];
+        expected.sort();
+        assert_eq!(
+            dag.transactions_between(TRUNK_HASH, first_milestone),
+            Ok(expected)
+        );
+        let mut expected = vec![third, skewed, second_milestone];
+        expected.sort();
+        assert_eq!(
+            dag.transactions_between(first_milestone, second_milestone),
+            Ok(expected)
+        );
+
+        assert_eq!(
+            dag.transactions_between(second_milestone, first_milestone),
+            Err(BlockDAGError::NotAncestor {
+                older: second_milestone,
+                newer: first_milestone
+            })
+        );
+        assert_eq!(
+            dag.transactions_between(TRUNK_HASH, third),
+            Err(BlockDAGError::MilestoneNotFound(third))
+        );
+    }
+
+    #[test]
+    fn test_finalized_checkpoint() {
+        let mut dag = BlockDAG::<HashMap<_, _>, HashMap<_, _>, HashMap<_, _>>::default();
+        dag.set_finality_depth(2);
+        let mut add_milestone = |branch: u64, trunk: u64, timestamp: u64| {
+            let milestone = Transaction::new(
+                branch,
+                trunk,
+                vec![],
+                0,
+                timestamp,
+                150_000,
+                timestamp,
+                TransactionData::Empty,
+            );
+            let hash = milestone.get_hash();
+            assert_eq!(
+                Ok(TransactionStatus::Milestone),
+                dag.commit_transaction(milestone.clone(), TransactionUpdates::new(vec![]))
+            );
+            let chain = dag.verify_milestone(milestone).unwrap();
+            assert!(dag.process_chain(hash, chain));
+            assert!(dag.add_pending_signature(MilestoneSignature::new(hash, 0, 0)));
+            (hash, dag.finalized_checkpoint())
+        };
+
+        let (first, checkpoint) = add_milestone(BRANCH_HASH, TRUNK_HASH, 1);
+        assert_eq!(checkpoint, None);
+
+        // Genesis is followed by two milestones
+        let (second, checkpoint) = add_milestone(BRANCH_HASH, first, 2);
+        assert_eq!(
+            checkpoint.map(|checkpoint| checkpoint.hash),
+            Some(TRUNK_HASH)
+        );
+
+        // The first milestone is final once two milestones follow it
+        let (_, checkpoint) = add_milestone(first, second, 3);
+        assert_eq!(
+            checkpoint,
+            Some(Checkpoint {
+                hash: first,
+                root: 1,
+                timestamp: 1,
+            })
+        );
+    }
+
+    #[test]
+    fn test_self_reference() {
+        let dag = BlockDAG::<HashMap<_, _>, HashMap<_, _>, HashMap<_, _>>::default();
+        for (branch, trunk, refs) in vec![
+            (TRUNK_HASH, TRUNK_HASH, vec![]),
+            (BRANCH_HASH, TRUNK_HASH, vec![BRANCH_HASH]),
+        ] {
+            let mut transaction = Transaction::create(
+                branch,
+                trunk,
+                refs,
+                0,
+                BASE_NONCE,
+                0,
+                TransactionData::Empty,
+            );
+            transaction.sign(&mut PrivateKey::new(&SHA512_256));
+            assert_eq!(
+                Err(TransactionError::Rejected(RejectionReason::SelfReference)),
+                dag.try_add_transaction(&transaction)
+            );
+        }
+    }
+
+    #[test]
+    fn test_pending_limit() {
+        let mut dag = BlockDAG::<HashMap<_, _>, HashMap<_, _>, HashMap<_, _>>::default();
+        // The genesis branch takes one of the pending slots
+        dag.set_pending_limit(Some(3));
+
+        let mut start = 0;
+        let mut next_transaction = || {
+            let nonce = proof_of_work_from(0, 0, start).unwrap();
+            start = nonce + 1;
+            let mut transaction = Transaction::create(
+                TRUNK_HASH,
+                BRANCH_HASH,
+                vec![],
+                0,
+                nonce,
+                0,
+                TransactionData::Empty,
+            );
+            transaction.sign(&mut PrivateKey::new(&SHA512_256));
+            transaction
+        };
+
+        let mut pending = Vec::new();
+        for _ in 0..2 {
+            let transaction = next_transaction();
+            let updates = dag.try_add_transaction(&transaction).unwrap();
+            dag.commit_transaction(transaction.clone(), updates)
+                .unwrap();
+            pending.push(transaction);
+        }
+        let transaction = next_transaction();
+        assert_eq!(
+            Err(TransactionError::Rejected(RejectionReason::MempoolFull)),
+            dag.try_add_transaction(&transaction)
+        );
+
+        // Milestones are accepted over the limit
+        let (branch, trunk) = (&pending[0], &pending[1]);
+        let nonce = proof_of_work_from(
+            trunk.get_nonce(),
+            branch.get_nonce(),
+            MILESTONE_NONCE_MIN + 1,
+        )
+        .unwrap();
+        let mut milestone = Transaction::create(
+            branch.get_hash(),
+            trunk.get_hash(),
+            vec![],
+            0,
+            nonce,
+            0,
+            TransactionData::Empty,
+        );
+        milestone.sign(&mut PrivateKey::new(&SHA512_256));
+        let updates = dag.try_add_transaction(&milestone).unwrap();
+        assert_eq!(
+            Ok(TransactionStatus::Milestone),
+            dag.commit_transaction(milestone.clone(), updates)
+        );
+
+        // Confirming the milestone frees space
+        let chain = dag.verify_milestone(milestone.clone()).unwrap();
+        assert!(dag.process_chain(milestone.get_hash(), chain.into_iter().rev().collect()));
+        assert!(dag.add_pending_signature(MilestoneSignature::new(milestone.get_hash(), 0, 0)));
+        for transaction in pending {
+            assert_eq!(
+                dag.get_confirmation_status(transaction.get_hash()),
+                TransactionStatus::Accepted
+            );
+        }
+        assert!(dag.try_add_transaction(&transaction).is_ok());
+    }
+
+    #[test]
+    fn test_pending_limit_milestone_budget() {
+        let mut dag = BlockDAG::<HashMap<_, _>, HashMap<_, _>, HashMap<_, _>>::default();
+        // The genesis branch fills the only pending slot
+        dag.set_pending_limit(Some(1));
+
+        let mut start = MILESTONE_NONCE_MIN + 1;
+        let mut next_milestone = || {
+            let nonce = proof_of_work_from(0, 0, start).unwrap();
+            start = nonce + 1;
+            let mut transaction = Transaction::create(
+                TRUNK_HASH,
+                BRANCH_HASH,
+                vec![],
+                0,
+                nonce,
+                0,
+                TransactionData::Empty,
+            );
+            transaction.sign(&mut PrivateKey::new(&SHA512_256));
+            transaction
+        };
+
+        for _ in 0..MILESTONE_PENDING_BUDGET {
+            let milestone = next_milestone();
+            let updates = dag.try_add_transaction(&milestone).unwrap();
+            assert_eq!(
+                Ok(TransactionStatus::Milestone),
+                dag.commit_transaction(milestone, updates)
+            );
+        }
+
+        // Milestones past the budget are rejected too
+        assert_eq!(
+            Err(TransactionError::Rejected(RejectionReason::MempoolFull)),
+            dag.try_add_transaction(&next_milestone())
+        );
+    }
+
+    use dag::contract::state::get_key;
+
+    #[test]
+    fn test_list_contracts() {
+        let src = load_api_test_source();
+
+        let mut genesis = GenesisConfig::new();
+        for _ in 0..5 {
+            genesis = genesis.with_contract(src.clone());
+        }
+        let dag = BlockDAG::<HashMap<_, _>, HashMap<_, _>, HashMap<_, _>>::with_genesis(
+            HashMap::new(),
+            HashMap::new(),
+            HashMap::new(),
+            genesis,
+        )
+        .unwrap();
+
+        let mut expected: Vec<u64> = (0..5).map(genesis_contract_id).collect();
+        expected.sort();
+
+        let mut listed = Vec::new();
+        for page in 0..3 {
+            let contracts = dag.list_contracts(page * 2, 2);
+            assert!(contracts.len() <= 2);
+            listed.extend(contracts);
+        }
+        assert_eq!(listed, expected);
+        assert!(dag.list_contracts(5, 2).is_empty());
+    }
+
+    #[test]
+    fn test_get_contract_source() {
+        let src = load_api_test_source();
+
+        let mut dag = BlockDAG::<HashMap<_, _>, HashMap<_, _>, HashMap<_, _>>::default();
+        let root = dag.get_transaction(TRUNK_HASH).unwrap().get_root();
+        let mut transaction = Transaction::create(
+            TRUNK_HASH,
+            BRANCH_HASH,
+            vec![],
+            0,
+            BASE_NONCE,
+            root,
+            TransactionData::GenContract(src.clone(), None),
+        );
+        transaction.sign(&mut PrivateKey::new(&SHA512_256));
+        // Deploy the transaction as received from a peer, with its source
+        // compressed
+        let json = serde_json::to_string(&transaction).unwrap();
+        let transaction: Transaction = serde_json::from_str(&json).unwrap();
+        let updates = dag.try_add_transaction(&transaction).unwrap();
+        assert!(dag.commit_transaction(transaction.clone(), updates).is_ok());
+
+        assert_eq!(
+            dag.get_contract_source(transaction.get_hash()),
+            Some(src.get_code().to_vec())
+        );
+        assert_eq!(dag.get_contract_source(TRUNK_HASH), None);
+    }
+
+    /// Records the log messages of the current thread, so tests running in
+    /// parallel do not see each other's messages
+    struct CaptureLogger;
+
+    thread_local! {
+        static CAPTURED_LOGS: RefCell<Vec<(Level, String)>> = RefCell::new(Vec::new());
+    }
+
+    impl Log for CaptureLogger {
+        fn enabled(&self, _metadata: &Metadata) -> bool {
+            true
+        }
+
+        fn log(&self, record: &Record) {
+            CAPTURED_LOGS.with(|logs| {
+                logs.borrow_mut()
+                    .push((record.level(), record.args().to_string()))
+            });
+        }
+
+        fn flush(&self) {}
+    }
+
+    static CAPTURE_LOGGER: CaptureLogger = CaptureLogger;
+
+    #[test]
+    fn test_rejection_logged() {
+        let _ = log::set_logger(&CAPTURE_LOGGER);
+        log::set_max_level(LevelFilter::Warn);
+
+        let dag = BlockDAG::<HashMap<_, _>, HashMap<_, _>, HashMap<_, _>>::default();
+        let mut transaction = Transaction::create(
+            TRUNK_HASH,
+            TRUNK_HASH,
+            vec![],
+            0,
+            BASE_NONCE,
+            0,
+            TransactionData::Empty,
+        );
+        transaction.sign(&mut PrivateKey::new(&SHA512_256));
+        assert!(dag.try_add_transaction(&transaction).is_err());
+
+        let hash = HashId(transaction.get_hash()).to_string();
+        CAPTURED_LOGS.with(|logs| {
+            assert!(
+                logs.borrow()
+                    .iter()
+                    .any(|(level, message)| *level == Level::Warn && message.contains(&hash)),
+                "No warning logged for {}: {:?}",
+                hash,
+                logs.borrow()
+            );
+        });
+    }
+
+    #[test]
+    fn test_allow_unsigned() {
+        let mut dag = BlockDAG::<HashMap<_, _>, HashMap<_, _>, HashMap<_, _>>::default();
+        let unsigned = |nonce| {
+            Transaction::create(
+                TRUNK_HASH,
+                BRANCH_HASH,
+                vec![],
+                0,
+                nonce,
+                0,
+                TransactionData::Empty,
+            )
+        };
+        let transaction = unsigned(BASE_NONCE);
+        assert!(transaction.is_unsigned());
+
+        // Rejected by default
+        assert_eq!(
+            dag.try_add_transaction(&transaction),
+            Err(TransactionError::Rejected(RejectionReason::BadSignature))
         );
+
+        dag.set_allow_unsigned(true);
+        assert!(dag.try_add_transaction(&transaction).is_ok());
+        // The nonce is still checked
         assert_eq!(
-            dag.get_confirmation_status(transaction.get_hash()),
-            TransactionStatus::Pending
+            dag.try_add_transaction(&unsigned(BASE_NONCE + 1)),
+            Err(TransactionError::Rejected(RejectionReason::BadNonce))
+        );
+        // Signed transactions must still have valid signatures
+        let mut signed = unsigned(BASE_NONCE);
+        signed.sign(&mut PrivateKey::new(&SHA512_256));
+        let forged = Transaction::raw(
+            signed.get_branch_hash(),
+            signed.get_trunk_hash(),
+            vec![],
+            0,
+            signed.get_timestamp(),
+            BASE_NONCE,
+            0,
+            signed.get_address().to_vec(),
+            vec![0; signed.get_signature().len()],
+            TransactionData::Empty,
+        );
+        assert_eq!(
+            dag.try_add_transaction(&forged),
+            Err(TransactionError::Rejected(RejectionReason::BadSignature))
         );
     }
 
-    use dag::contract::state::get_key;
+    #[test]
+    fn test_lazy_tips_deprioritized() {
+        let mut dag = BlockDAG::<HashMap<_, _>, HashMap<_, _>, HashMap<_, _>>::default();
+        let commit = |dag: &mut BlockDAG<_, _, _>, branch: u64, trunk: u64, contract: u64| {
+            let transaction = Transaction::new(
+                branch,
+                trunk,
+                vec![],
+                contract,
+                0,
+                0,
+                0,
+                TransactionData::Empty,
+            );
+            let hash = transaction.get_hash();
+            dag.commit_transaction(transaction, TransactionUpdates::new(vec![branch, trunk]))
+                .unwrap();
+            hash
+        };
+
+        let p = commit(&mut dag, BRANCH_HASH, TRUNK_HASH, 0);
+        let q = commit(&mut dag, BRANCH_HASH, TRUNK_HASH, 1);
+        // Spam referencing the same stale genesis transactions
+        let spam: Vec<u64> = (2..8)
+            .map(|contract| commit(&mut dag, BRANCH_HASH, TRUNK_HASH, contract))
+            .collect();
+        // Fresh tips confirming the earlier tips
+        let f1 = commit(&mut dag, p, q, 8);
+        let f2 = commit(&mut dag, q, p, 9);
+        assert_eq!(dag.get_approver_count(TRUNK_HASH), 8);
+        assert_eq!(dag.get_approver_count(p), 2);
+
+        dag.set_lazy_approver_limit(Some(2));
+        for _ in 0..50 {
+            let tips = dag.get_tips();
+            let mut selected = vec![tips.trunk_hash, tips.branch_hash];
+            selected.sort();
+            let mut fresh = vec![f1, f2];
+            fresh.sort();
+            assert_eq!(selected, fresh);
+        }
+
+        // Without fresh alternatives, lazy tips are still selected
+        dag.set_lazy_approver_limit(Some(1));
+        let tips = dag.get_tips();
+        assert!(dag.tips.contains(&tips.trunk_hash));
+        assert_eq!(dag.tips.len(), spam.len() + 2);
+    }
+
+    #[test]
+    fn test_tip_limit() {
+        let mut dag = BlockDAG::<HashMap<_, _>, HashMap<_, _>, HashMap<_, _>>::default();
+        dag.set_tip_limit(Some(4));
+        let commit = |dag: &mut BlockDAG<_, _, _>, tips: TransactionHashes, contract: u64| {
+            let transaction = Transaction::new(
+                tips.branch_hash,
+                tips.trunk_hash,
+                tips.ref_hashes,
+                contract,
+                0,
+                0,
+                0,
+                TransactionData::Empty,
+            );
+            let hash = transaction.get_hash();
+            let referenced = transaction.get_all_refs();
+            dag.commit_transaction(transaction, TransactionUpdates::new(referenced))
+                .unwrap();
+            hash
+        };
+
+        // Every transaction creates a new tip
+        let hashes: Vec<u64> = (0..10)
+            .map(|contract| {
+                let tips = TransactionHashes::new(TRUNK_HASH, BRANCH_HASH);
+                commit(&mut dag, tips, contract)
+            })
+            .collect();
+        assert_eq!(dag.get_tip_count(), 4);
+        assert_eq!(dag.tips, hashes[6..].to_vec());
+
+        // Evicted tips are not selected, but are returned as references
+        let tips = dag.get_tips();
+        assert!(hashes[6..].contains(&tips.trunk_hash));
+        assert!(hashes[6..].contains(&tips.branch_hash));
+        assert_eq!(tips.ref_hashes, hashes[..6].to_vec());
+        let joined = commit(&mut dag, tips, 10);
+        assert_eq!(dag.get_tip_count(), 3);
+        assert_eq!(dag.tips.last(), Some(&joined));
+        assert!(dag.get_tips().ref_hashes.is_empty());
+
+        // Every transaction is still reachable from the tips
+        let mut reachable = HashSet::new();
+        let mut stack = dag.tips.clone();
+        while let Some(hash) = stack.pop() {
+            if let Some(transaction) = dag.get_transaction(hash) {
+                if reachable.insert(hash) {
+                    stack.extend(transaction.get_all_refs());
+                }
+            }
+        }
+        assert!(hashes.iter().all(|hash| reachable.contains(hash)));
+
+        // Lowering the limit evicts immediately
+        dag.set_tip_limit(Some(2));
+        assert_eq!(dag.get_tip_count(), 2);
+        assert_eq!(dag.tips.last(), Some(&joined));
+        assert_eq!(dag.get_tips().ref_hashes.len(), 1);
+    }
+
+    #[test]
+    fn test_require_pow() {
+        let mut dag = BlockDAG::<HashMap<_, _>, HashMap<_, _>, HashMap<_, _>>::default();
+        let create = |trunk_hash: u64| {
+            Transaction::create(
+                trunk_hash,
+                BRANCH_HASH,
+                vec![],
+                0,
+                0,
+                0,
+                TransactionData::Empty,
+            )
+        };
+        let validator_key =
+            ValidatorKey::from_pkcs8(&ValidatorKey::generate_pkcs8().unwrap()).unwrap();
+        let mut first = create(TRUNK_HASH);
+        first.sign_validator(&validator_key);
+
+        // The zero nonce is rejected while the proof of work is required
+        dag.set_validators(vec![validator_key.public_key()].into_iter().collect());
+        assert_eq!(
+            dag.try_add_transaction(&first),
+            Err(TransactionError::Rejected(RejectionReason::BadNonce))
+        );
+
+        // The same validator key signs any number of transactions
+        dag.set_require_pow(false);
+        let updates = dag.try_add_transaction(&first).unwrap();
+        let first_hash = first.get_hash();
+        dag.commit_transaction(first, updates).unwrap();
+        let mut second = create(first_hash);
+        second.sign_validator(&validator_key);
+        assert!(dag.try_add_transaction(&second).is_ok());
+
+        // Neither lamport keys nor unknown validator keys are accepted
+        let mut from_other = create(TRUNK_HASH);
+        from_other.sign(&mut PrivateKey::new(&SHA512_256));
+        assert_eq!(
+            dag.try_add_transaction(&from_other),
+            Err(TransactionError::Rejected(RejectionReason::NotValidator))
+        );
+        let other_key = ValidatorKey::from_pkcs8(&ValidatorKey::generate_pkcs8().unwrap()).unwrap();
+        let mut from_other = create(TRUNK_HASH);
+        from_other.sign_validator(&other_key);
+        assert_eq!(
+            dag.try_add_transaction(&from_other),
+            Err(TransactionError::Rejected(RejectionReason::NotValidator))
+        );
+    }
+
+    #[test]
+    fn test_get_tips_empty() {
+        let mut dag = BlockDAG::<HashMap<_, _>, HashMap<_, _>, HashMap<_, _>>::default();
+        dag.tips.clear();
+
+        let tips = dag.get_tips();
+        let milestone = dag.get_head_milestone();
+        assert_eq!(tips.trunk_hash, milestone.get_hash());
+        assert_eq!(
+            tips.branch_hash,
+            milestone.get_transaction().get_branch_hash()
+        );
+        assert_eq!(
+            dag.get_tips_excluding(&[TRUNK_HASH]).trunk_hash,
+            tips.trunk_hash
+        );
+    }
+
+    #[test]
+    fn test_get_tips_single() {
+        let mut dag = BlockDAG::<HashMap<_, _>, HashMap<_, _>, HashMap<_, _>>::default();
+
+        // Approve both genesis tips, leaving a single tip whose branch parent
+        // is the pending genesis branch
+        let mut transaction = Transaction::create(
+            BRANCH_HASH,
+            TRUNK_HASH,
+            vec![],
+            0,
+            BASE_NONCE,
+            0,
+            TransactionData::Empty,
+        );
+        transaction.sign(&mut PrivateKey::new(&SHA512_256));
+        let hash = transaction.get_hash();
+        let updates = dag.try_add_transaction(&transaction).unwrap();
+        dag.commit_transaction(transaction, updates).unwrap();
+        assert_eq!(dag.tips, vec![hash]);
+
+        // The branch extends the head milestone instead
+        let tips = dag.get_tips();
+        assert_eq!(tips.trunk_hash, hash);
+        assert_eq!(tips.branch_hash, dag.get_head_milestone().get_hash());
+        assert_ne!(tips.branch_hash, BRANCH_HASH);
+
+        let nonce = proof_of_work(
+            dag.get_transaction(tips.trunk_hash).unwrap().get_nonce(),
+            dag.get_transaction(tips.branch_hash).unwrap().get_nonce(),
+        )
+        .unwrap();
+        let mut transaction = Transaction::create(
+            tips.branch_hash,
+            tips.trunk_hash,
+            vec![],
+            0,
+            nonce,
+            0,
+            TransactionData::Empty,
+        );
+        transaction.sign(&mut PrivateKey::new(&SHA512_256));
+        assert!(dag.try_add_transaction(&transaction).is_ok());
+    }
+
+    #[test]
+    fn test_get_tips_excluding() {
+        let mut dag = BlockDAG::<HashMap<_, _>, HashMap<_, _>, HashMap<_, _>>::default();
+        dag.tips.clear();
+        dag.tips.extend_from_slice(&[1, 2, 3, 4]);
+
+        for _ in 0..50 {
+            let tips = dag.get_tips_excluding(&[1, 2]);
+            let mut selected = vec![tips.trunk_hash, tips.branch_hash];
+            selected.sort();
+            assert_eq!(selected, vec![3, 4]);
+
+            let tips = dag.get_tips_excluding(&[4]);
+            assert_ne!(tips.trunk_hash, 4);
+            assert_ne!(tips.branch_hash, 4);
+            assert_ne!(tips.trunk_hash, tips.branch_hash);
+        }
+
+        // With no other tips, excluded tips are still selected
+        let tips = dag.get_tips_excluding(&[1, 2, 3, 4]);
+        assert!(dag.tips.contains(&tips.trunk_hash));
+        assert!(dag.tips.contains(&tips.branch_hash));
+    }
+
+    #[test]
+    fn test_set_rng() {
+        /// Draws values selecting the given tip indices from tips of len
+        struct FixedIndices(Vec<u64>);
+
+        impl FixedIndices {
+            fn new(indices: &[u64], len: u64) -> Self {
+                // gen_range scales the drawn value by the range, and takes
+                // the high bits as the index
+                let values = indices
+                    .iter()
+                    .rev()
+                    .map(|index| {
+                        let len = u128::from(len);
+                        ((u128::from(*index) << 64) + len - 1) / len
+                    })
+                    .map(|value| value as u64)
+                    .collect();
+                FixedIndices(values)
+            }
+        }
+
+        impl RngCore for FixedIndices {
+            fn next_u32(&mut self) -> u32 {
+                self.next_u64() as u32
+            }
+
+            fn next_u64(&mut self) -> u64 {
+                self.0.pop().expect("No more indices")
+            }
+
+            fn fill_bytes(&mut self, dest: &mut [u8]) {
+                for byte in dest {
+                    *byte = self.next_u64() as u8;
+                }
+            }
+
+            fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), RandError> {
+                self.fill_bytes(dest);
+                Ok(())
+            }
+        }
+
+        let mut dag = BlockDAG::<HashMap<_, _>, HashMap<_, _>, HashMap<_, _>>::default();
+        dag.tips.clear();
+        dag.tips.extend_from_slice(&[10, 11, 12, 13]);
+
+        dag.set_rng(Box::new(FixedIndices::new(&[3, 1], 4)));
+        let tips = dag.get_tips();
+        assert_eq!((tips.trunk_hash, tips.branch_hash), (13, 11));
+
+        // The branch is drawn again until it differs from the trunk
+        dag.set_rng(Box::new(FixedIndices::new(&[2, 2, 2, 0], 4)));
+        let tips = dag.get_tips();
+        assert_eq!((tips.trunk_hash, tips.branch_hash), (12, 10));
+    }
+
+    #[test]
+    fn test_fee_policy() {
+        use std::sync::{Arc, Mutex};
+
+        /// Charges each transaction its nonce, and counts the total
+        struct CountFees(Arc<Mutex<u64>>);
+
+        impl FeePolicy for CountFees {
+            fn fee(&self, transaction: &Transaction) -> u64 {
+                u64::from(transaction.get_nonce())
+            }
+
+            fn collect(&mut self, _transaction: &Transaction, fee: u64) {
+                *self.0.lock().unwrap() += fee;
+            }
+
+            fn refund(&mut self, _transaction: &Transaction, fee: u64) {
+                *self.0.lock().unwrap() -= fee;
+            }
+        }
+
+        let mut dag = BlockDAG::<HashMap<_, _>, HashMap<_, _>, HashMap<_, _>>::default();
+        let total = Arc::new(Mutex::new(0));
+        dag.set_fee_policy(Box::new(CountFees(total.clone())));
+
+        let mut expected = 0;
+        for _ in 0..4 {
+            let tips = dag.get_tips();
+            let trunk = dag.get_transaction(tips.trunk_hash).unwrap().clone();
+            let branch = dag.get_transaction(tips.branch_hash).unwrap().clone();
+            let nonce = proof_of_work(trunk.get_nonce(), branch.get_nonce()).unwrap();
+            let mut transaction = Transaction::create(
+                branch.get_hash(),
+                trunk.get_hash(),
+                vec![],
+                0,
+                nonce,
+                0,
+                TransactionData::Empty,
+            );
+            transaction.sign(&mut PrivateKey::new(&SHA512_256));
+            let updates = dag.try_add_transaction(&transaction).unwrap();
+            dag.commit_transaction(transaction, updates).unwrap();
+            expected += u64::from(nonce);
+        }
+        assert_eq!(*total.lock().unwrap(), expected);
+
+        // Fees of a rejected block are refunded
+        let tips = dag.get_tips();
+        let trunk = dag.get_transaction(tips.trunk_hash).unwrap().clone();
+        let branch = dag.get_transaction(tips.branch_hash).unwrap().clone();
+        let nonce = proof_of_work(trunk.get_nonce(), branch.get_nonce()).unwrap();
+        let mut transaction = Transaction::create(
+            branch.get_hash(),
+            trunk.get_hash(),
+            vec![],
+            0,
+            nonce,
+            0,
+            TransactionData::Empty,
+        );
+        transaction.sign(&mut PrivateKey::new(&SHA512_256));
+        let missing_parent =
+            Transaction::create(BRANCH_HASH, 10, vec![], 0, 0, 0, TransactionData::Empty);
+        assert!(dag.apply_block(vec![transaction, missing_parent]).is_err());
+        assert_eq!(*total.lock().unwrap(), expected);
+    }
+
+    #[test]
+    fn test_get_contracts_sorted() {
+        let src = load_api_test_source();
+
+        let mpt = MerklePatriciaTree::new(HashMap::new());
+        let ids: Vec<u64> = vec![u64::max_value(), 3, 1 << 40, 0, 17];
+        let contracts: Vec<Contract> = ids
+            .iter()
+            .map(|id| {
+                Contract::new(src.clone(), *id, &mpt, mpt.default_root())
+                    .unwrap()
+                    .0
+            })
+            .collect();
+
+        let mut forward = BlockDAG::<HashMap<_, _>, HashMap<_, _>, HashMap<_, _>>::default();
+        let mut backward = BlockDAG::<HashMap<_, _>, HashMap<_, _>, HashMap<_, _>>::default();
+        for (id, contract) in ids.iter().zip(contracts.iter()) {
+            forward.contracts.insert(*id, contract.clone());
+        }
+        for (id, contract) in ids.iter().zip(contracts.iter()).rev() {
+            backward.contracts.insert(*id, contract.clone());
+        }
+
+        let mut expected = ids.clone();
+        expected.sort();
+        assert_eq!(forward.get_contracts(), expected);
+        assert_eq!(backward.get_contracts(), expected);
+    }
+
+    #[test]
+    fn test_genesis_preloaded_contract() {
+        let src = load_api_test_source();
+
+        let genesis = GenesisConfig::new().with_contract(src);
+        let dag = BlockDAG::<HashMap<_, _>, HashMap<_, _>, HashMap<_, _>>::with_genesis(
+            HashMap::new(),
+            HashMap::new(),
+            HashMap::new(),
+            genesis,
+        )
+        .unwrap();
+
+        // The genesis hashes do not depend on the preloaded state
+        assert!(dag.get_transaction(TRUNK_HASH).is_some());
+        assert!(dag.get_transaction(BRANCH_HASH).is_some());
+
+        let contract_id = genesis_contract_id(0);
+        let genesis_root = dag.get_transaction(TRUNK_HASH).unwrap().get_root();
+        assert_ne!(genesis_root, dag.get_mpt_default_root());
+        assert!(dag.get_contract(contract_id).is_some());
+        assert_eq!(
+            Ok(OOB::Borrowed(&ContractValue::U32(1))),
+            dag.storage.get(genesis_root, get_key(0, contract_id))
+        );
+        assert_eq!(
+            vec![ContractValue::U64(2)],
+            dag.get_contract(contract_id)
+                .unwrap()
+                .exec_const(
+                    "get_u64",
+                    &[ContractValue::U32(1)],
+                    &dag.storage,
+                    genesis_root
+                )
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn test_call_limits() {
+        let src = load_api_test_source();
+
+        let genesis = GenesisConfig::new().with_contract(src);
+        let mut dag = BlockDAG::<HashMap<_, _>, HashMap<_, _>, HashMap<_, _>>::with_genesis(
+            HashMap::new(),
+            HashMap::new(),
+            HashMap::new(),
+            genesis,
+        )
+        .unwrap();
+        dag.set_call_limits(CallLimits {
+            max_name_len: 7,
+            max_args: 2,
+            max_args_size: 8,
+        });
+        let root = dag.get_transaction(TRUNK_HASH).unwrap().get_root();
+        let call = |func_name: &str, args: Vec<ContractValue>| {
+            let mut transaction = Transaction::create(
+                TRUNK_HASH,
+                BRANCH_HASH,
+                vec![],
+                genesis_contract_id(0),
+                BASE_NONCE,
+                root,
+                TransactionData::ExecContract(func_name.into(), args),
+            );
+            transaction.sign(&mut PrivateKey::new(&SHA512_256));
+            dag.try_add_transaction(&transaction)
+        };
+
+        // A call at every limit is accepted
+        let args = vec![ContractValue::U32(0), ContractValue::U32(5)];
+        assert!(call("set_u32", args.clone()).is_ok());
+
+        let too_large = Err(TransactionError::Rejected(RejectionReason::ArgsTooLarge));
+        let mut too_many = args.clone();
+        too_many.push(ContractValue::U32(6));
+        assert_eq!(call("set_u32", too_many), too_large);
+        let too_wide = vec![ContractValue::U32(0), ContractValue::U64(5)];
+        assert_eq!(call("set_u64", too_wide), too_large);
+        assert_eq!(call("set_u32_", args), too_large);
+
+        // Calls are not limited by default
+        let args = vec![ContractValue::U64(0); 100];
+        assert!(CallLimits::default().check(&"f".repeat(1000), &args));
+    }
+
+    /// Module exporting emit(topic, byte), which emits an event holding only
+    /// byte under topic
+    #[rustfmt::skip]
+    const EMIT_EVENT_WASM: [u8; 98] = [
+        // Header
+        0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00,
+        // Type section: (i64, i32, i32) -> (), (i64, i32) -> ()
+        0x01, 0x0c, 0x02, 0x60, 0x03, 0x7e, 0x7f, 0x7f, 0x00, 0x60, 0x02, 0x7e, 0x7f, 0x00,
+        // Import section: env.__ofc__emit_event
+        0x02, 0x19, 0x01, 0x03, b'e', b'n', b'v', 0x11, b'_', b'_', b'o', b'f', b'c', b'_', b'_',
+        b'e', b'm', b'i', b't', b'_', b'e', b'v', b'e', b'n', b't', 0x00, 0x00,
+        // Function section
+        0x03, 0x02, 0x01, 0x01,
+        // Memory section: one page
+        0x05, 0x03, 0x01, 0x00, 0x01,
+        // Export section: memory, emit
+        0x07, 0x11, 0x02, 0x06, b'm', b'e', b'm', b'o', b'r', b'y', 0x02, 0x00, 0x04, b'e', b'm',
+        b'i', b't', 0x00, 0x01,
+        // Code section: store the byte at 0, then emit it under the topic
+        0x0a, 0x13, 0x01, 0x11, 0x00, 0x41, 0x00, 0x20, 0x01, 0x3a, 0x00, 0x00, 0x20, 0x00, 0x41,
+        0x00, 0x41, 0x01, 0x10, 0x00, 0x0b,
+    ];
+
+    #[test]
+    fn test_event_index() {
+        let genesis = GenesisConfig::new().with_contract(ContractSource::new(&EMIT_EVENT_WASM));
+        let mut dag = BlockDAG::<HashMap<_, _>, HashMap<_, _>, HashMap<_, _>>::with_genesis(
+            HashMap::new(),
+            HashMap::new(),
+            HashMap::new(),
+            genesis,
+        )
+        .unwrap();
+        let root = dag.get_transaction(TRUNK_HASH).unwrap().get_root();
+
+        // Emit one byte under topic 1, then topic 2, then topic 1 again
+        let mut hashes = vec![];
+        for &(topic, byte) in &[(1, 10), (2, 20), (1, 30)] {
+            let args = vec![ContractValue::U64(topic), ContractValue::U32(byte)];
+            let mut transaction = Transaction::create(
+                TRUNK_HASH,
+                BRANCH_HASH,
+                vec![],
+                genesis_contract_id(0),
+                BASE_NONCE,
+                root,
+                TransactionData::ExecContract("emit".into(), args),
+            );
+            transaction.sign(&mut PrivateKey::new(&SHA512_256));
+            let updates = dag.try_add_transaction(&transaction).unwrap();
+            hashes.push(transaction.get_hash());
+            dag.commit_transaction(transaction, updates).unwrap();
+        }
+
+        assert_eq!(
+            dag.get_events(1),
+            vec![(hashes[0], vec![10]), (hashes[2], vec![30])]
+        );
+        assert_eq!(dag.get_events(2), vec![(hashes[1], vec![20])]);
+        assert!(dag.get_events(3).is_empty());
+    }
 
     #[test]
     fn test_gen_exec_contract_transaction() {
         // Load example contract file
-        let mut d = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
-        d.push("resources/test/contracts/api_test.wasm");
-        let filename = d.to_str().unwrap().to_string();
-        let mut file = File::open(filename).expect("Could not open test file");
-        let mut buf: Vec<u8> = Vec::with_capacity(file.metadata().unwrap().len() as usize);
-        file.read_to_end(&mut buf)
-            .expect("Could not read test file");
+        let src = load_api_test_source();
 
         let mut dag = BlockDAG::<HashMap<_, _>, HashMap<_, _>, HashMap<_, _>>::default();
         let mpt_root = dag.get_transaction(TRUNK_HASH).unwrap().get_root();
@@ -633,7 +4122,7 @@ mod tests {
         let branch_hash;
         {
             let mut key = PrivateKey::new(&SHA512_256);
-            let data = TransactionData::GenContract(ContractSource::new(&buf));
+            let data = TransactionData::GenContract(src, None);
             let mut transaction = Transaction::create(
                 TRUNK_HASH,
                 BRANCH_HASH,
@@ -660,7 +4149,7 @@ mod tests {
             let new_value = 2;
             let branch_nonce = dag.get_transaction(branch_hash).unwrap().get_nonce();
             let trunk_nonce = dag.get_transaction(trunk_hash).unwrap().get_nonce();
-            let nonce = proof_of_work(trunk_nonce, branch_nonce);
+            let nonce = proof_of_work(trunk_nonce, branch_nonce).unwrap();
             let mut key = PrivateKey::new(&SHA512_256);
             let data = TransactionData::ExecContract(
                 "set_u32".into(),
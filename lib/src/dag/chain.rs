@@ -0,0 +1,149 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+
+use dag::transaction::Transaction;
+
+use security::hash::proof::Difficulty;
+
+use util::HashId;
+
+#[derive(Debug, PartialEq)]
+pub enum ChainError {
+    /// The transaction at index does not reference the next transaction in
+    /// the chain
+    BrokenLink { index: usize, hash: u64 },
+    /// The nonce of the transaction is not a valid proof of work
+    BadNonce(u64),
+    /// The signature of the transaction does not match its contents
+    BadSignature(u64),
+}
+
+impl fmt::Display for ChainError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ChainError::BrokenLink { index, hash } => write!(
+                f,
+                "Transaction {} at {} does not reference the next transaction",
+                HashId(*hash),
+                index
+            ),
+            ChainError::BadNonce(hash) => {
+                write!(f, "Transaction {} has a bad nonce", HashId(*hash))
+            }
+            ChainError::BadSignature(hash) => {
+                write!(f, "Transaction {} has a bad signature", HashId(*hash))
+            }
+        }
+    }
+}
+
+impl Error for ChainError {}
+
+/// Verify a chain of transactions without the rest of the dag
+///
+/// The chain is ordered newest first, so each transaction must reference
+/// the one after it, as in the reversed chain returned by
+/// [verify_milestone](blockdag/struct.BlockDAG.html#method.verify_milestone).
+/// Every signature is checked. The proof of work is checked for each
+/// transaction whose trunk and branch are both in the chain, as the nonces
+/// of other parents are not known
+pub fn verify_chain(chain: &[Transaction], difficulty: Difficulty) -> Result<(), ChainError> {
+    let nonces: HashMap<u64, u32> = chain
+        .iter()
+        .map(|transaction| (transaction.get_hash(), transaction.get_nonce()))
+        .collect();
+
+    for (index, transaction) in chain.iter().enumerate() {
+        let hash = transaction.get_hash();
+        if let Some(next) = chain.get(index + 1) {
+            if !transaction.get_all_refs().contains(&next.get_hash()) {
+                return Err(ChainError::BrokenLink { index, hash });
+            }
+        }
+        let trunk_nonce = nonces.get(&transaction.get_trunk_hash());
+        let branch_nonce = nonces.get(&transaction.get_branch_hash());
+        if let (Some(trunk_nonce), Some(branch_nonce)) = (trunk_nonce, branch_nonce) {
+            if !difficulty.valid_proof(*trunk_nonce, *branch_nonce, transaction.get_nonce()) {
+                return Err(ChainError::BadNonce(hash));
+            }
+        }
+        if !transaction.verify() {
+            return Err(ChainError::BadSignature(hash));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use dag::transaction::data::TransactionData;
+    use security::hash::proof::proof_of_work;
+    use security::keys::PrivateKey;
+    use security::ring::digest::SHA512_256;
+
+    /// Create a signed transaction with both parents set to parent
+    fn child(parent: &Transaction, contract: u64) -> Transaction {
+        let nonce = proof_of_work(parent.get_nonce(), parent.get_nonce()).unwrap();
+        let mut transaction = Transaction::create(
+            parent.get_hash(),
+            parent.get_hash(),
+            vec![],
+            contract,
+            nonce,
+            0,
+            TransactionData::Empty,
+        );
+        transaction.sign(&mut PrivateKey::new(&SHA512_256));
+        transaction
+    }
+
+    #[test]
+    fn test_verify_chain() {
+        let mut first = Transaction::create(1, 2, vec![], 0, 0, 0, TransactionData::Empty);
+        first.sign(&mut PrivateKey::new(&SHA512_256));
+        let second = child(&first, 0);
+        let third = child(&second, 0);
+
+        let chain = vec![third.clone(), second.clone(), first.clone()];
+        assert_eq!(verify_chain(&chain, Difficulty::default()), Ok(()));
+        assert_eq!(verify_chain(&[], Difficulty::default()), Ok(()));
+
+        let broken = vec![third.clone(), first.clone()];
+        assert_eq!(
+            verify_chain(&broken, Difficulty::default()),
+            Err(ChainError::BrokenLink {
+                index: 0,
+                hash: third.get_hash()
+            })
+        );
+
+        // Signed by a different key than the one in the transaction
+        let signed = child(&second, 1);
+        let forged = Transaction::raw(
+            signed.get_branch_hash(),
+            signed.get_trunk_hash(),
+            vec![],
+            signed.get_contract(),
+            signed.get_timestamp(),
+            signed.get_nonce(),
+            signed.get_root(),
+            signed.get_address().to_vec(),
+            third.get_signature().to_vec(),
+            TransactionData::Empty,
+        );
+        let chain = vec![forged.clone(), second.clone(), first.clone()];
+        assert_eq!(
+            verify_chain(&chain, Difficulty::default()),
+            Err(ChainError::BadSignature(forged.get_hash()))
+        );
+
+        // No nonce meets a difficulty of the whole hash
+        assert_eq!(
+            verify_chain(&[third.clone(), second.clone()], Difficulty(64)),
+            Err(ChainError::BadNonce(third.get_hash()))
+        );
+    }
+}
@@ -0,0 +1,144 @@
+//! Golden hashes of a canonical set of transactions and state roots
+//!
+//! Every node must agree on these values, so any change to how transactions,
+//! headers or MPT nodes are hashed fails here, instead of silently changing
+//! the hashes other tests rely on. If a hashing change is intended, update
+//! the golden values, and the constants copied from them in other tests.
+
+use std::collections::HashMap;
+
+use dag::contract::ContractValue;
+use dag::genesis::genesis_contract_id;
+use dag::storage::mpt::MerklePatriciaTree;
+use dag::transaction::{data::TransactionData, Transaction};
+
+/// Golden hash of each vector produced by expected_hashes
+const GOLDEN: &[(&str, u64)] = &[
+    ("genesis", 7994361212180723510),
+    ("genesis full", 13936260801684390098),
+    ("genesis branch", 3935804193461013965),
+    ("exec contract", 467538748722771703),
+    ("exec contract full", 5168288047988137254),
+    ("exec contract header", 467538748722771703),
+    ("counter and expiration", 12897702789593425653),
+    ("signed", 6544112306689153390),
+    ("signed full", 2482884545870507629),
+    ("default root", 2206623258364750227),
+    ("root one value", 1239561065659286598),
+    ("root two values", 6601188226522927840),
+    ("root updated value", 4886937043137710117),
+    ("genesis contract id", 783096069046489837),
+];
+
+/// Compute the hash of each canonical vector, named as in GOLDEN
+fn expected_hashes() -> Vec<(&'static str, u64)> {
+    let mut storage = MerklePatriciaTree::<ContractValue, _>::new(HashMap::new());
+    let default_root = storage.default_root();
+
+    // The genesis transactions, as built by BlockDAG::with_genesis
+    let genesis = Transaction::new(
+        0,
+        0,
+        vec![],
+        0,
+        0,
+        0,
+        default_root,
+        TransactionData::Genesis,
+    );
+    let genesis_hash = genesis.get_hash();
+    let genesis_branch = Transaction::new(
+        genesis_hash,
+        genesis_hash,
+        vec![],
+        0,
+        0,
+        0,
+        default_root,
+        TransactionData::Genesis,
+    );
+    let branch_hash = genesis_branch.get_hash();
+
+    let exec = Transaction::new(
+        branch_hash,
+        genesis_hash,
+        vec![1, 2],
+        3,
+        1000,
+        4,
+        default_root,
+        TransactionData::ExecContract(
+            "transfer".into(),
+            vec![ContractValue::U64(7), ContractValue::U32(8)],
+        ),
+    );
+    let counted = Transaction::new(
+        branch_hash,
+        genesis_hash,
+        vec![],
+        0,
+        1000,
+        4,
+        default_root,
+        TransactionData::Empty,
+    )
+    .with_counter(5)
+    .with_expiration(2000);
+    let signed = Transaction::raw(
+        branch_hash,
+        genesis_hash,
+        vec![],
+        0,
+        1000,
+        4,
+        default_root,
+        vec![1, 2, 3],
+        vec![4; 16],
+        TransactionData::Empty,
+    );
+
+    let root_one = storage
+        .set(default_root, 0x0123_4567_89ab_cdef, ContractValue::U64(42))
+        .unwrap();
+    let root_two = storage
+        .set(root_one, 0xfedc_ba98_7654_3210, ContractValue::U32(7))
+        .unwrap();
+    let root_updated = storage
+        .set(root_two, 0x0123_4567_89ab_cdef, ContractValue::U64(43))
+        .unwrap();
+
+    vec![
+        ("genesis", genesis_hash),
+        ("genesis full", genesis.full_hash()),
+        ("genesis branch", branch_hash),
+        ("exec contract", exec.get_hash()),
+        ("exec contract full", exec.full_hash()),
+        ("exec contract header", exec.get_header().hash),
+        ("counter and expiration", counted.get_hash()),
+        ("signed", signed.get_hash()),
+        ("signed full", signed.full_hash()),
+        ("default root", default_root),
+        ("root one value", root_one),
+        ("root two values", root_two),
+        ("root updated value", root_updated),
+        ("genesis contract id", genesis_contract_id(0)),
+    ]
+}
+
+#[test]
+fn test_consensus_vectors() {
+    let hashes = expected_hashes();
+    assert_eq!(
+        hashes.iter().map(|(name, _)| *name).collect::<Vec<_>>(),
+        GOLDEN.iter().map(|(name, _)| *name).collect::<Vec<_>>(),
+        "Consensus vectors and golden values are out of sync"
+    );
+    for ((name, hash), (_, golden)) in hashes.iter().zip(GOLDEN) {
+        assert_eq!(
+            hash, golden,
+            "Consensus vector {:?} hashed to {}, expected {}. Hashing has changed, \
+             so nodes built from this tree disagree with existing nodes",
+            name, hash, golden
+        );
+    }
+}
@@ -0,0 +1,118 @@
+use std::collections::BTreeMap;
+
+use super::error::ContractError;
+use super::ContractValue;
+
+/// Type of a value passed to or returned from a contract function
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum ContractValueType {
+    U32,
+    U64,
+    F32,
+    F64,
+    U128,
+}
+
+impl<'a> From<&'a ContractValue> for ContractValueType {
+    fn from(value: &'a ContractValue) -> Self {
+        match value {
+            ContractValue::U32(_) => ContractValueType::U32,
+            ContractValue::U64(_) => ContractValueType::U64,
+            ContractValue::F32(_) => ContractValueType::F32,
+            ContractValue::F64(_) => ContractValueType::F64,
+            ContractValue::U128(_) => ContractValueType::U128,
+        }
+    }
+}
+
+/// Argument and return types of a contract function
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Hash, Debug)]
+pub struct FunctionAbi {
+    pub args: Vec<ContractValueType>,
+    /// Type of the returned value, if the function returns one
+    #[serde(default)]
+    pub returns: Option<ContractValueType>,
+}
+
+impl FunctionAbi {
+    pub fn new(args: Vec<ContractValueType>, returns: Option<ContractValueType>) -> Self {
+        FunctionAbi { args, returns }
+    }
+
+    /// Check if the number and types of args match the function's arguments
+    pub fn matches(&self, args: &[ContractValue]) -> bool {
+        self.args.len() == args.len()
+            && self
+                .args
+                .iter()
+                .zip(args)
+                .all(|(expected, arg)| *expected == ContractValueType::from(arg))
+    }
+}
+
+/// Describes the functions a contract can be called with, by name
+///
+/// Serialized as a map from function name to its argument and return types,
+/// so clients can build calls without knowing the types out of band
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Hash, Default, Debug)]
+pub struct ContractAbi(BTreeMap<String, FunctionAbi>);
+
+impl ContractAbi {
+    pub fn new() -> Self {
+        ContractAbi::default()
+    }
+
+    /// Add a function to the abi
+    pub fn with_function(
+        mut self,
+        name: &str,
+        args: Vec<ContractValueType>,
+        returns: Option<ContractValueType>,
+    ) -> Self {
+        self.0.insert(name.into(), FunctionAbi::new(args, returns));
+        self
+    }
+
+    pub fn get_function(&self, name: &str) -> Option<&FunctionAbi> {
+        self.0.get(name)
+    }
+
+    /// Check that a call matches the abi
+    ///
+    /// Fails with ContractError::AbiMismatch if the function is not in the
+    /// abi, or if the number or types of the arguments differ
+    pub fn check_call(&self, func_name: &str, args: &[ContractValue]) -> Result<(), ContractError> {
+        match self.get_function(func_name) {
+            Some(function) if function.matches(args) => Ok(()),
+            _ => Err(ContractError::AbiMismatch(func_name.into())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_call() {
+        let abi = ContractAbi::new().with_function(
+            "set_u32",
+            vec![ContractValueType::U32, ContractValueType::U32],
+            None,
+        );
+        let args = vec![ContractValue::U32(0), ContractValue::U32(1)];
+        assert!(abi.check_call("set_u32", &args).is_ok());
+        assert!(abi.check_call("set_u32", &args[..1]).is_err());
+        assert!(abi
+            .check_call("set_u32", &[ContractValue::U32(0), ContractValue::U64(1)])
+            .is_err());
+        assert!(abi.check_call("get_u32", &args[..1]).is_err());
+
+        let json = serde_json::to_value(&abi).unwrap();
+        assert_eq!(
+            json,
+            json!({"set_u32": {"args": ["U32", "U32"], "returns": null}})
+        );
+        assert_eq!(abi, serde_json::from_value(json).unwrap());
+    }
+}
@@ -0,0 +1,112 @@
+use std::sync::{Arc, RwLock};
+
+use wasmi::Module;
+
+use dag::storage::keyed::HashKeyMap;
+
+use super::error::ContractError;
+use super::source::ContractSource;
+
+/// Parsed wasm modules, by the checksum of their source
+///
+/// The cache can be shared between threads. wasmi module instances can not
+/// be sent between threads, so the parsed modules are cached and each
+/// execution instantiates its own module from them
+#[derive(Default)]
+pub struct ModuleCache {
+    modules: RwLock<HashKeyMap<u64, Arc<Module>>>,
+}
+
+impl ModuleCache {
+    pub fn new() -> Self {
+        ModuleCache::default()
+    }
+
+    /// Get the parsed module of src, parsing it if it is not cached
+    pub fn get_module(&self, src: &ContractSource) -> Result<Arc<Module>, ContractError> {
+        let checksum = src.get_checksum();
+        if let Some(module) = self.modules.read().unwrap().get(&checksum) {
+            return Ok(Arc::clone(module));
+        }
+        // Another thread may parse the same source meanwhile, either module
+        // can be kept
+        let module = Arc::new(src.get_wasm_module()?);
+        Ok(Arc::clone(
+            self.modules
+                .write()
+                .unwrap()
+                .entry(checksum)
+                .or_insert(module),
+        ))
+    }
+
+    /// Number of cached modules
+    pub fn len(&self) -> usize {
+        self.modules.read().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::HashMap;
+    use std::fs;
+    use std::path::PathBuf;
+
+    use crossbeam_utils::thread;
+
+    use dag::contract::{Contract, ContractValue};
+    use dag::storage::mpt::MerklePatriciaTree;
+
+    #[test]
+    fn test_concurrent_cached_exec() {
+        let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        path.push("resources/test/contracts/api_test.wasm");
+        let src = ContractSource::new(&fs::read(path).expect("Could not read test file"));
+
+        let mut storage = MerklePatriciaTree::<ContractValue, _>::new(HashMap::new());
+        let root = storage.default_root();
+        let (contract, updates) = Contract::new(src, 0, &storage, root).unwrap();
+        let root = updates.get_root_hash();
+        storage.commit_set(updates).unwrap();
+
+        // Roots computed without the cache, for each value set
+        let expected: Vec<u64> = (0..2)
+            .map(|index| {
+                let args = [ContractValue::U32(0), ContractValue::U32(10 + index)];
+                let (_, updates, _) = contract.exec("set_u32", &args, &storage, root).unwrap();
+                updates.get_root_hash()
+            })
+            .collect();
+
+        let cache = ModuleCache::new();
+        let (contract, storage, cache) = (&contract, &storage, &cache);
+        thread::scope(|scope| {
+            let threads: Vec<_> = expected
+                .iter()
+                .enumerate()
+                .map(|(index, expected_root)| {
+                    scope.spawn(move |_| {
+                        let args = [ContractValue::U32(0), ContractValue::U32(10 + index as u32)];
+                        for _ in 0..10 {
+                            let (_, updates, _) = contract
+                                .exec_cached(cache, "set_u32", &args, storage, root)
+                                .unwrap();
+                            assert_eq!(updates.get_root_hash(), *expected_root);
+                        }
+                    })
+                })
+                .collect();
+            for thread in threads {
+                thread.join().unwrap();
+            }
+        })
+        .unwrap();
+        assert_eq!(cache.len(), 1);
+    }
+}
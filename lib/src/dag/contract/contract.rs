@@ -1,25 +1,146 @@
-#![allow(clippy::derive_hash_xor_eq)]
-
+use std::convert::TryFrom;
 use std::hash::{Hash, Hasher};
 
 use ordered_float::OrderedFloat;
 
-use wasmi::{ModuleInstance, ModuleRef, RuntimeValue};
+use serde::de::{self, Deserialize, Deserializer};
+use serde::ser::Serializer;
+
+use wasmi::{Module, ModuleInstance, ModuleRef, RuntimeValue};
 
 use dag::storage::mpt::{temp_map::MPTTempMap, MerklePatriciaTree, NodeUpdates};
 
+use super::abi::ContractAbi;
+use super::cache::ModuleCache;
 use super::error::ContractError;
 use super::resolver::get_imports_builder;
 use super::source::ContractSource;
-use super::state::{ContractState, ContractStateStorage};
+use super::state::{AccessList, ContractEvent, ContractState, ContractStateStorage, TraceEntry};
+
+/// Contracts exporting this name read unset state as zero instead of trapping
+pub const DEFAULT_READS_EXPORT: &str = "__default_reads";
+
+/// Function run by migrate, if the contract exports it
+pub const MIGRATE_EXPORT: &str = "migrate";
 
 /// Represents the values that can be passed to a contract
-#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+///
+/// Floats are compared and hashed as OrderedFloats, so NaN equals itself and
+/// values can key maps and sets
+#[derive(Serialize, Deserialize, Clone, Debug)]
 pub enum ContractValue {
     U32(u32),
     U64(u64),
     F32(f32),
     F64(f64),
+    /// Stored by contracts as two u64 limbs, as wasm has no 128 bit integers
+    ///
+    /// Serialized as a decimal string, as JSON numbers cannot hold every u128
+    U128(
+        #[serde(serialize_with = "serialize_u128")]
+        #[serde(deserialize_with = "deserialize_u128")]
+        u128,
+    ),
+}
+
+fn serialize_u128<S: Serializer>(value: &u128, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(value)
+}
+
+fn deserialize_u128<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u128, D::Error> {
+    let value = String::deserialize(deserializer)?;
+    value.parse().map_err(de::Error::custom)
+}
+
+impl ContractValue {
+    /// Get the value if it is a u32
+    pub fn as_u32(&self) -> Option<u32> {
+        match self {
+            ContractValue::U32(val) => Some(*val),
+            _ => None,
+        }
+    }
+
+    /// Get the value if it is a u64
+    pub fn as_u64(&self) -> Option<u64> {
+        match self {
+            ContractValue::U64(val) => Some(*val),
+            _ => None,
+        }
+    }
+
+    /// Get the value if it is an f32
+    pub fn as_f32(&self) -> Option<f32> {
+        match self {
+            ContractValue::F32(val) => Some(*val),
+            _ => None,
+        }
+    }
+
+    /// Get the value if it is an f64
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            ContractValue::F64(val) => Some(*val),
+            _ => None,
+        }
+    }
+
+    /// Get the value if it is a u128
+    pub fn as_u128(&self) -> Option<u128> {
+        match self {
+            ContractValue::U128(val) => Some(*val),
+            _ => None,
+        }
+    }
+
+    /// Get the number of bytes the value occupies
+    pub fn size(&self) -> usize {
+        match self {
+            ContractValue::U32(_) | ContractValue::F32(_) => 4,
+            ContractValue::U64(_) | ContractValue::F64(_) => 8,
+            ContractValue::U128(_) => 16,
+        }
+    }
+}
+
+impl TryFrom<ContractValue> for u32 {
+    type Error = ContractError;
+
+    fn try_from(value: ContractValue) -> Result<Self, ContractError> {
+        value.as_u32().ok_or(ContractError::TypeMismatch)
+    }
+}
+
+impl TryFrom<ContractValue> for u64 {
+    type Error = ContractError;
+
+    fn try_from(value: ContractValue) -> Result<Self, ContractError> {
+        value.as_u64().ok_or(ContractError::TypeMismatch)
+    }
+}
+
+impl TryFrom<ContractValue> for f32 {
+    type Error = ContractError;
+
+    fn try_from(value: ContractValue) -> Result<Self, ContractError> {
+        value.as_f32().ok_or(ContractError::TypeMismatch)
+    }
+}
+
+impl TryFrom<ContractValue> for f64 {
+    type Error = ContractError;
+
+    fn try_from(value: ContractValue) -> Result<Self, ContractError> {
+        value.as_f64().ok_or(ContractError::TypeMismatch)
+    }
+}
+
+impl TryFrom<ContractValue> for u128 {
+    type Error = ContractError;
+
+    fn try_from(value: ContractValue) -> Result<Self, ContractError> {
+        value.as_u128().ok_or(ContractError::TypeMismatch)
+    }
 }
 
 impl Hash for ContractValue {
@@ -29,17 +150,42 @@ impl Hash for ContractValue {
             ContractValue::U64(val) => val.hash(state),
             ContractValue::F32(val) => OrderedFloat::from(*val).hash(state),
             ContractValue::F64(val) => OrderedFloat::from(*val).hash(state),
+            ContractValue::U128(val) => val.hash(state),
         }
     }
 }
 
-impl From<ContractValue> for RuntimeValue {
-    fn from(val: ContractValue) -> Self {
+impl PartialEq for ContractValue {
+    fn eq(&self, other: &ContractValue) -> bool {
+        match (self, other) {
+            (ContractValue::U32(a), ContractValue::U32(b)) => a == b,
+            (ContractValue::U64(a), ContractValue::U64(b)) => a == b,
+            (ContractValue::F32(a), ContractValue::F32(b)) => {
+                OrderedFloat::from(*a) == OrderedFloat::from(*b)
+            }
+            (ContractValue::F64(a), ContractValue::F64(b)) => {
+                OrderedFloat::from(*a) == OrderedFloat::from(*b)
+            }
+            (ContractValue::U128(a), ContractValue::U128(b)) => a == b,
+            _ => false,
+        }
+    }
+}
+
+impl Eq for ContractValue {}
+
+/// Fails with ContractError::TypeMismatch for u128 values, which wasm
+/// functions cannot take
+impl TryFrom<ContractValue> for RuntimeValue {
+    type Error = ContractError;
+
+    fn try_from(val: ContractValue) -> Result<Self, ContractError> {
         match val {
-            ContractValue::U32(val) => RuntimeValue::I32(val as i32),
-            ContractValue::U64(val) => RuntimeValue::I64(val as i64),
-            ContractValue::F32(val) => RuntimeValue::F32(val.into()),
-            ContractValue::F64(val) => RuntimeValue::F64(val.into()),
+            ContractValue::U32(val) => Ok(RuntimeValue::I32(val as i32)),
+            ContractValue::U64(val) => Ok(RuntimeValue::I64(val as i64)),
+            ContractValue::F32(val) => Ok(RuntimeValue::F32(val.into())),
+            ContractValue::F64(val) => Ok(RuntimeValue::F64(val.into())),
+            ContractValue::U128(_) => Err(ContractError::TypeMismatch),
         }
     }
 }
@@ -67,6 +213,15 @@ pub struct Contract {
     /// Source of the contract
     src: ContractSource,
     id: u64,
+    /// Maximum number of distinct mapping keys the contract can set
+    #[serde(default)]
+    state_limit: Option<u64>,
+    /// Types of the functions the contract can be called with
+    #[serde(default)]
+    abi: Option<ContractAbi>,
+    /// Version of the state layout the contract expects
+    #[serde(default)]
+    schema_version: u32,
 }
 
 impl Contract {
@@ -76,107 +231,437 @@ impl Contract {
         storage: &'a MerklePatriciaTree<ContractValue, M>,
         root: u64,
     ) -> Result<(Self, NodeUpdates<ContractValue>), ContractError> {
-        let contract = Contract { src, id };
+        Contract::with_state_limit(src, id, None, storage, root)
+    }
+
+    /// Create a contract limited to setting state_limit distinct mapping keys
+    pub fn with_state_limit<'a, M: ContractStateStorage>(
+        src: ContractSource,
+        id: u64,
+        state_limit: Option<u64>,
+        storage: &'a MerklePatriciaTree<ContractValue, M>,
+        root: u64,
+    ) -> Result<(Self, NodeUpdates<ContractValue>), ContractError> {
+        let contract = Contract {
+            src,
+            id,
+            state_limit,
+            abi: None,
+            schema_version: 0,
+        };
 
-        let (_, updates) = contract.exec("init", &Vec::new(), storage, root)?;
+        // The init function is optional, contracts without one start with
+        // empty state
+        let module = contract.get_module()?;
+        let updates = if module.export_by_name("init").is_some() {
+            let mut temp_state = contract.build_state(&module, storage, root)?;
+            contract.exec_from_state("init", &[], &mut temp_state)?;
+            temp_state.updates()?
+        } else {
+            NodeUpdates::new(storage.nodes.get(&root)?.clone(), Vec::new())
+        };
 
         Ok((contract, updates))
     }
 
+    /// Attach the abi describing the contract's functions
+    pub fn with_abi(mut self, abi: Option<ContractAbi>) -> Self {
+        self.abi = abi;
+        self
+    }
+
+    /// Replace the source of the contract, keeping its id and state
+    ///
+    /// The upgraded contract expects its state in the layout of
+    /// schema_version. If that differs from the current schema version, the
+    /// state must be migrated before the upgraded contract can execute. The
+    /// abi is cleared, attach the abi of the new source with
+    /// [with_abi](#method.with_abi)
+    pub fn upgrade(&self, src: ContractSource, schema_version: u32) -> Self {
+        Contract {
+            src,
+            id: self.id,
+            state_limit: self.state_limit,
+            abi: None,
+            schema_version,
+        }
+    }
+
+    /// Migrate the state at root to the contract's schema version
+    ///
+    /// Runs the contract's migrate function if it exports one, then records
+    /// the new schema version in the state. State already at the contract's
+    /// schema version is left unchanged
+    pub fn migrate<'a, M: ContractStateStorage>(
+        &self,
+        storage: &'a MerklePatriciaTree<ContractValue, M>,
+        root: u64,
+    ) -> Result<NodeUpdates<ContractValue>, ContractError> {
+        let module = self.get_module()?;
+        let mut temp_state = self.new_state(&module, storage, root);
+        if temp_state.schema_version()? == self.schema_version {
+            return Ok(NodeUpdates::new(
+                storage.nodes.get(&root)?.clone(),
+                Vec::new(),
+            ));
+        }
+        if temp_state.has_function(MIGRATE_EXPORT) {
+            self.exec_from_state(MIGRATE_EXPORT, &[], &mut temp_state)?;
+        }
+        temp_state.set_schema_version(self.schema_version)?;
+        Ok(temp_state.updates()?)
+    }
+
+    pub fn get_id(&self) -> u64 {
+        self.id
+    }
+
+    /// Get the version of the state layout the contract expects
+    pub fn schema_version(&self) -> u32 {
+        self.schema_version
+    }
+
+    /// Get the source the contract was deployed or last upgraded with
+    pub fn get_source(&self) -> &ContractSource {
+        &self.src
+    }
+
+    pub fn get_abi(&self) -> Option<&ContractAbi> {
+        self.abi.as_ref()
+    }
+
     fn get_module(&self) -> Result<ModuleRef, ContractError> {
+        self.instantiate(&self.src.get_wasm_module()?)
+    }
+
+    fn instantiate(&self, module: &Module) -> Result<ModuleRef, ContractError> {
         let imports = get_imports_builder();
-        Ok(ModuleInstance::new(&self.src.get_wasm_module()?, &imports)?.assert_no_start())
+        Ok(ModuleInstance::new(module, &imports)?.assert_no_start())
     }
 
+    /// Build the state to execute against, checking it is laid out for the
+    /// contract's schema version
     fn build_state<'a, M: ContractStateStorage>(
         &self,
         module: &'a ModuleRef,
         storage: &'a MerklePatriciaTree<ContractValue, M>,
         root: u64,
     ) -> Result<ContractState<'a, M>, ContractError> {
-        Ok(ContractState::new(
+        let state = self.new_state(module, storage, root);
+        let state_version = state.schema_version()?;
+        if state_version != self.schema_version {
+            return Err(ContractError::SchemaMismatch {
+                contract: self.schema_version,
+                state: state_version,
+            });
+        }
+        Ok(state)
+    }
+
+    fn new_state<'a, M: ContractStateStorage>(
+        &self,
+        module: &'a ModuleRef,
+        storage: &'a MerklePatriciaTree<ContractValue, M>,
+        root: u64,
+    ) -> ContractState<'a, M> {
+        let mut state = ContractState::new(
             module,
             MerklePatriciaTree::new(MPTTempMap::new(storage)),
             self.id,
             root,
-        ))
+        );
+        state.set_state_limit(self.state_limit);
+        state.set_default_reads(module.export_by_name(DEFAULT_READS_EXPORT).is_some());
+        state
     }
 
     /// Execute the contract function
+    ///
+    /// Returns the values the function declared with `__ofc__return_tuple`,
+    /// or else the value it returned, if any
     pub fn exec<'a, M: ContractStateStorage>(
         &self,
         func_name: &str,
         args: &[ContractValue],
         storage: &'a MerklePatriciaTree<ContractValue, M>,
         root: u64,
-    ) -> Result<(Option<ContractValue>, NodeUpdates<ContractValue>), ContractError> {
+    ) -> Result<(Vec<ContractValue>, NodeUpdates<ContractValue>), ContractError> {
+        let module = self.get_module()?;
+        let mut temp_state = self.build_state(&module, storage, root)?;
+        let return_values = self.exec_from_state(func_name, args, &mut temp_state)?;
+        let updates = temp_state.updates()?;
+        return Ok((return_values, updates));
+    }
+
+    /// Execute the contract function, recording the state keys it accesses
+    ///
+    /// Returns the values returned by the function call and its node
+    /// updates, along with the state keys the function read and wrote
+    pub fn exec_recorded<'a, M: ContractStateStorage>(
+        &self,
+        func_name: &str,
+        args: &[ContractValue],
+        storage: &'a MerklePatriciaTree<ContractValue, M>,
+        root: u64,
+    ) -> Result<(Vec<ContractValue>, NodeUpdates<ContractValue>, AccessList), ContractError> {
         let module = self.get_module()?;
         let mut temp_state = self.build_state(&module, storage, root)?;
-        let return_value = self.exec_from_state(func_name, args, &mut temp_state)?;
+        temp_state.set_access_recording(true);
+        let return_values = self.exec_from_state(func_name, args, &mut temp_state)?;
+        let access = temp_state.take_access_list();
+        let updates = temp_state.updates()?;
+        Ok((return_values, updates, access))
+    }
+
+    /// Execute the contract function, taking its parsed module from cache
+    ///
+    /// Returns the values returned by the function, and the events emitted by
+    /// the call along with its node updates
+    pub fn exec_cached<'a, M: ContractStateStorage>(
+        &self,
+        cache: &ModuleCache,
+        func_name: &str,
+        args: &[ContractValue],
+        storage: &'a MerklePatriciaTree<ContractValue, M>,
+        root: u64,
+    ) -> Result<
+        (
+            Vec<ContractValue>,
+            NodeUpdates<ContractValue>,
+            Vec<ContractEvent>,
+        ),
+        ContractError,
+    > {
+        let module = self.instantiate(&*cache.get_module(&self.src)?)?;
+        let mut temp_state = self.build_state(&module, storage, root)?;
+        let return_values = self.exec_from_state(func_name, args, &mut temp_state)?;
+        let events = temp_state.take_events();
         let updates = temp_state.updates()?;
-        return Ok((return_value, updates));
+        Ok((return_values, updates, events))
+    }
+
+    /// Execute the contract function, recording the api calls it makes
+    ///
+    /// Ignores node updates and returns the values returned by the function
+    /// call, along with the api calls in the order they were made
+    pub fn exec_traced<'a, M: ContractStateStorage>(
+        &self,
+        func_name: &str,
+        args: &[ContractValue],
+        storage: &'a MerklePatriciaTree<ContractValue, M>,
+        root: u64,
+    ) -> Result<(Vec<ContractValue>, Vec<TraceEntry>), ContractError> {
+        let module = self.get_module()?;
+        let mut temp_state = self.build_state(&module, storage, root)?;
+        temp_state.set_tracing(true);
+        let return_values = self.exec_from_state(func_name, args, &mut temp_state)?;
+        Ok((return_values, temp_state.take_trace()))
     }
 
     /// Execute the contract function
     ///
-    /// Ignores node updates and only returns the value returned by the function call
+    /// Ignores node updates and only returns the values returned by the function call
     pub fn exec_const<'a, M: ContractStateStorage>(
         &self,
         func_name: &str,
         args: &[ContractValue],
         storage: &'a MerklePatriciaTree<ContractValue, M>,
         root: u64,
-    ) -> Result<Option<ContractValue>, ContractError> {
+    ) -> Result<Vec<ContractValue>, ContractError> {
         let module = self.get_module()?;
         let mut temp_state = self.build_state(&module, storage, root)?;
         self.exec_from_state(func_name, args, &mut temp_state)
     }
 
+    /// Execute the contract function without allowing it to modify state
+    ///
+    /// Fails with ContractError::ReadonlyViolation if the function sets any value
+    pub fn exec_readonly<'a, M: ContractStateStorage>(
+        &self,
+        func_name: &str,
+        args: &[ContractValue],
+        storage: &'a MerklePatriciaTree<ContractValue, M>,
+        root: u64,
+    ) -> Result<Vec<ContractValue>, ContractError> {
+        let module = self.get_module()?;
+        self.exec_readonly_from_module(&module, func_name, args, storage, root)
+    }
+
+    /// Execute several contract functions without allowing them to modify
+    /// state
+    ///
+    /// Every function is executed against the state at root, so the results
+    /// are consistent with each other. Fails if any of the calls fail
+    pub fn exec_multicall<'a, M: ContractStateStorage>(
+        &self,
+        calls: &[(String, Vec<ContractValue>)],
+        storage: &'a MerklePatriciaTree<ContractValue, M>,
+        root: u64,
+    ) -> Result<Vec<Vec<ContractValue>>, ContractError> {
+        let module = self.get_module()?;
+        calls
+            .iter()
+            .map(|(func_name, args)| {
+                self.exec_readonly_from_module(&module, func_name, args, storage, root)
+            })
+            .collect()
+    }
+
+    fn exec_readonly_from_module<'a, M: ContractStateStorage>(
+        &self,
+        module: &ModuleRef,
+        func_name: &str,
+        args: &[ContractValue],
+        storage: &'a MerklePatriciaTree<ContractValue, M>,
+        root: u64,
+    ) -> Result<Vec<ContractValue>, ContractError> {
+        let mut temp_state = self.build_state(module, storage, root)?;
+        temp_state.set_readonly(true);
+        self.exec_from_state(func_name, args, &mut temp_state)
+    }
+
+    /// Execute the contract function against state
+    ///
+    /// Returns the values the function declared with `__ofc__return_tuple`,
+    /// or else the value it returned, if any
     fn exec_from_state<M: ContractStateStorage>(
         &self,
         func_name: &str,
         args: &[ContractValue],
         state: &mut ContractState<M>,
-    ) -> Result<Option<ContractValue>, ContractError> {
+    ) -> Result<Vec<ContractValue>, ContractError> {
+        if !state.has_function(func_name) {
+            return Err(ContractError::FunctionNotFound(func_name.into()));
+        }
+        let args = args
+            .iter()
+            .map(|x| RuntimeValue::try_from(x.clone()))
+            .collect::<Result<Vec<_>, _>>()?;
         let return_value = state
-            .exec(
-                func_name,
-                &args
-                    .iter()
-                    .map(|x| RuntimeValue::from(x.clone()))
-                    .collect::<Vec<_>>(),
-            )?
+            .exec(func_name, &args)?
             .map(|value| ContractValue::from(value));
-        Ok(return_value)
+        Ok(match state.take_returned() {
+            Some(values) => values,
+            None => return_value.into_iter().collect(),
+        })
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::collections::HashMap;
+    use std::collections::{HashMap, HashSet};
     use std::fs::File;
     use std::io::Read;
     use std::path::PathBuf;
 
+    use dag::contract::resolver::{GET_INT32_INDEX, SET_INT32_INDEX};
     use dag::contract::state::{get_key, get_mapping_key};
     use dag::storage::map::OOB;
 
-    #[test]
-    fn test_exec_contract() {
-        // Load the example contract file
+    /// A contract without an init function, exporting only
+    /// `set_mapping(index: u32, key: u64, value: u64)`
+    #[rustfmt::skip]
+    const NO_INIT_WASM: [u8; 77] = [
+        // Header
+        0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00,
+        // Type section: (i32, i64, i64) -> ()
+        0x01, 0x07, 0x01, 0x60, 0x03, 0x7f, 0x7e, 0x7e, 0x00,
+        // Import section: env.api_set_mapping
+        0x02, 0x17, 0x01, 0x03, b'e', b'n', b'v', 0x0f, b'a', b'p', b'i', b'_', b's', b'e', b't',
+        b'_', b'm', b'a', b'p', b'p', b'i', b'n', b'g', 0x00, 0x00,
+        // Function section
+        0x03, 0x02, 0x01, 0x00,
+        // Export section: set_mapping
+        0x07, 0x0f, 0x01, 0x0b, b's', b'e', b't', b'_', b'm', b'a', b'p', b'p', b'i', b'n', b'g',
+        0x00, 0x01,
+        // Code section: call api_set_mapping with the arguments
+        0x0a, 0x0c, 0x01, 0x0a, 0x00, 0x20, 0x00, 0x20, 0x01, 0x20, 0x02, 0x10, 0x00, 0x0b,
+    ];
+
+    /// A contract without an init function, exporting its memory and
+    /// `divmod(a: u32, b: u32)`, which returns the quotient and remainder with
+    /// `__ofc__return_tuple`
+    #[rustfmt::skip]
+    const DIVMOD_WASM: [u8; 107] = [
+        // Header
+        0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00,
+        // Type section: (i32, i32) -> ()
+        0x01, 0x06, 0x01, 0x60, 0x02, 0x7f, 0x7f, 0x00,
+        // Import section: env.__ofc__return_tuple
+        0x02, 0x1b, 0x01, 0x03, b'e', b'n', b'v', 0x13, b'_', b'_', b'o', b'f', b'c', b'_', b'_',
+        b'r', b'e', b't', b'u', b'r', b'n', b'_', b't', b'u', b'p', b'l', b'e', 0x00, 0x00,
+        // Function section
+        0x03, 0x02, 0x01, 0x00,
+        // Memory section: one page
+        0x05, 0x03, 0x01, 0x00, 0x01,
+        // Export section: divmod and memory
+        0x07, 0x13, 0x02, 0x06, b'd', b'i', b'v', b'm', b'o', b'd', 0x00, 0x01, 0x06, b'm', b'e',
+        b'm', b'o', b'r', b'y', 0x02, 0x00,
+        // Code section: store a / b and a % b as u32 return values at 0 and 16,
+        // leaving their zeroed type tags, then return both
+        0x0a, 0x1e, 0x01, 0x1c, 0x00, 0x41, 0x08, 0x20, 0x00, 0x20, 0x01, 0x6e, 0x36, 0x02, 0x00,
+        0x41, 0x18, 0x20, 0x00, 0x20, 0x01, 0x70, 0x36, 0x02, 0x00, 0x41, 0x00, 0x41, 0x02, 0x10,
+        0x00, 0x0b,
+    ];
+
+    fn load_api_test_source() -> ContractSource {
         let mut d = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
         d.push("resources/test/contracts/api_test.wasm");
-        let filename = d.to_str().unwrap().to_string();
-        let mut file = File::open(filename).expect("Could not open test file");
-        let mut buf: Vec<u8> = Vec::with_capacity(file.metadata().unwrap().len() as usize);
+        let mut file = File::open(d).expect("Could not open test file");
+        let mut buf: Vec<u8> = Vec::new();
         file.read_to_end(&mut buf)
             .expect("Could not read test file");
+        ContractSource::new(&buf)
+    }
+
+    #[test]
+    fn test_contract_value_conversion() {
+        assert_eq!(u32::try_from(ContractValue::U32(1)).unwrap(), 1);
+        assert_eq!(u64::try_from(ContractValue::U64(2)).unwrap(), 2);
+        assert_eq!(f32::try_from(ContractValue::F32(3f32)).unwrap(), 3f32);
+        assert_eq!(f64::try_from(ContractValue::F64(4f64)).unwrap(), 4f64);
+        assert_eq!(ContractValue::U64(5).as_u64(), Some(5));
+
+        match u64::try_from(ContractValue::U32(1)) {
+            Err(ContractError::TypeMismatch) => {}
+            res => panic!("Expected TypeMismatch, got {:?}", res),
+        }
+        match f32::try_from(ContractValue::F64(1f64)) {
+            Err(ContractError::TypeMismatch) => {}
+            res => panic!("Expected TypeMismatch, got {:?}", res),
+        }
+        assert_eq!(ContractValue::U32(1).as_u64(), None);
+        assert_eq!(ContractValue::F32(1f32).as_f64(), None);
+    }
+
+    #[test]
+    fn test_contract_value_set() {
+        let mut set = HashSet::new();
+        assert!(set.insert(ContractValue::F64(f64::NAN)));
+        assert!(!set.insert(ContractValue::F64(f64::NAN)));
+        assert!(set.insert(ContractValue::F32(f32::NAN)));
+        assert!(set.insert(ContractValue::F32(1.5)));
+        assert!(!set.insert(ContractValue::F32(1.5)));
+        assert!(set.insert(ContractValue::U32(1)));
+        assert!(set.insert(ContractValue::U64(1)));
+        assert_eq!(set.len(), 5);
+
+        assert!(set.contains(&ContractValue::F64(f64::NAN)));
+        assert!(!set.contains(&ContractValue::F64(1.5)));
+        assert_eq!(ContractValue::F32(f32::NAN), ContractValue::F32(f32::NAN));
+        assert_ne!(ContractValue::F32(f32::NAN), ContractValue::F64(f64::NAN));
+    }
+
+    #[test]
+    fn test_exec_contract() {
+        // Load the example contract file
+        let src = load_api_test_source();
 
         let mut storage = MerklePatriciaTree::<ContractValue, _>::new(HashMap::new());
         let mut root = storage.default_root();
-        let (contract, updates) = Contract::new(ContractSource::new(&buf), 0, &storage, root)
-            .expect("Failed to create contract");
+        let (contract, updates) =
+            Contract::new(src, 0, &storage, root).expect("Failed to create contract");
         root = updates.get_root_hash();
         assert!(storage.commit_set(updates).is_ok());
 
@@ -203,31 +688,31 @@ mod tests {
 
         // Now, assert the correct values also come out of WASM
         assert_eq!(
-            Some(ContractValue::U32(1)),
+            vec![ContractValue::U32(1)],
             contract
                 .exec_const("get_u32", &[ContractValue::U32(0)], &storage, root)
                 .unwrap()
         );
         assert_eq!(
-            Some(ContractValue::U64(2)),
+            vec![ContractValue::U64(2)],
             contract
                 .exec_const("get_u64", &[ContractValue::U32(1)], &storage, root)
                 .unwrap()
         );
         assert_eq!(
-            Some(ContractValue::F32(3f32)),
+            vec![ContractValue::F32(3f32)],
             contract
                 .exec_const("get_f32", &[ContractValue::U32(2)], &storage, root)
                 .unwrap()
         );
         assert_eq!(
-            Some(ContractValue::F64(4f64)),
+            vec![ContractValue::F64(4f64)],
             contract
                 .exec_const("get_f64", &[ContractValue::U32(3)], &storage, root)
                 .unwrap()
         );
         assert_eq!(
-            Some(ContractValue::U64(5)),
+            vec![ContractValue::U64(5)],
             contract
                 .exec_const(
                     "get_mapping",
@@ -238,4 +723,305 @@ mod tests {
                 .unwrap()
         );
     }
+
+    #[test]
+    fn test_schema_version() {
+        let src = load_api_test_source();
+
+        let mut storage = MerklePatriciaTree::<ContractValue, _>::new(HashMap::new());
+        let root = storage.default_root();
+        let (contract, updates) =
+            Contract::new(src.clone(), 0, &storage, root).expect("Failed to create contract");
+        assert_eq!(contract.schema_version(), 0);
+        let root = updates.get_root_hash();
+        assert!(storage.commit_set(updates).is_ok());
+
+        let upgraded = contract.upgrade(src, 1);
+        assert_eq!(upgraded.schema_version(), 1);
+        assert_eq!(upgraded.get_id(), contract.get_id());
+
+        // The old state must be migrated before the upgraded contract runs
+        let get_u32 = |contract: &Contract, root: u64| {
+            contract.exec_const("get_u32", &[ContractValue::U32(0)], &storage, root)
+        };
+        match get_u32(&upgraded, root) {
+            Err(ContractError::SchemaMismatch {
+                contract: 1,
+                state: 0,
+            }) => {}
+            res => panic!("Expected SchemaMismatch, got {:?}", res),
+        }
+
+        let updates = upgraded.migrate(&storage, root).unwrap();
+        let migrated_root = updates.get_root_hash();
+        assert_ne!(migrated_root, root);
+        assert!(storage.commit_set(updates).is_ok());
+        let get_u32 = |contract: &Contract, root: u64| {
+            contract.exec_const("get_u32", &[ContractValue::U32(0)], &storage, root)
+        };
+        assert_eq!(
+            get_u32(&upgraded, migrated_root).unwrap(),
+            vec![ContractValue::U32(1)]
+        );
+
+        // The old contract no longer matches the migrated state
+        match get_u32(&contract, migrated_root) {
+            Err(ContractError::SchemaMismatch {
+                contract: 0,
+                state: 1,
+            }) => {}
+            res => panic!("Expected SchemaMismatch, got {:?}", res),
+        }
+        // Migrating again changes nothing
+        assert_eq!(
+            upgraded
+                .migrate(&storage, migrated_root)
+                .unwrap()
+                .get_root_hash(),
+            migrated_root
+        );
+    }
+
+    #[test]
+    fn test_exec_readonly() {
+        let src = load_api_test_source();
+
+        let mut storage = MerklePatriciaTree::<ContractValue, _>::new(HashMap::new());
+        let root = storage.default_root();
+        let (contract, updates) =
+            Contract::new(src, 0, &storage, root).expect("Failed to create contract");
+        let root = updates.get_root_hash();
+        assert!(storage.commit_set(updates).is_ok());
+
+        // Getters run normally
+        assert_eq!(
+            vec![ContractValue::U32(1)],
+            contract
+                .exec_readonly("get_u32", &[ContractValue::U32(0)], &storage, root)
+                .unwrap()
+        );
+
+        // Setters are rejected
+        match contract.exec_readonly(
+            "set_u32",
+            &[ContractValue::U32(0), ContractValue::U32(5)],
+            &storage,
+            root,
+        ) {
+            Err(ContractError::ReadonlyViolation) => {}
+            res => panic!("Expected ReadonlyViolation, got {:?}", res),
+        }
+    }
+
+    #[test]
+    fn test_exec_multicall() {
+        let src = load_api_test_source();
+
+        let mut storage = MerklePatriciaTree::<ContractValue, _>::new(HashMap::new());
+        let root = storage.default_root();
+        let (contract, updates) =
+            Contract::new(src, 0, &storage, root).expect("Failed to create contract");
+        let root = updates.get_root_hash();
+        assert!(storage.commit_set(updates).is_ok());
+
+        let calls = vec![
+            ("get_u32".to_string(), vec![ContractValue::U32(0)]),
+            ("get_u64".to_string(), vec![ContractValue::U32(1)]),
+            (
+                "get_mapping".to_string(),
+                vec![ContractValue::U32(4), ContractValue::U64(0)],
+            ),
+        ];
+        let results = contract.exec_multicall(&calls, &storage, root).unwrap();
+        let expected: Vec<_> = calls
+            .iter()
+            .map(|(func_name, args)| {
+                contract
+                    .exec_readonly(func_name, args, &storage, root)
+                    .unwrap()
+            })
+            .collect();
+        assert_eq!(results, expected);
+        assert_eq!(
+            results,
+            vec![
+                vec![ContractValue::U32(1)],
+                vec![ContractValue::U64(2)],
+                vec![ContractValue::U64(5)],
+            ]
+        );
+
+        // A setter fails the whole batch
+        let mut calls = calls;
+        calls.push((
+            "set_u32".to_string(),
+            vec![ContractValue::U32(0), ContractValue::U32(5)],
+        ));
+        match contract.exec_multicall(&calls, &storage, root) {
+            Err(ContractError::ReadonlyViolation) => {}
+            res => panic!("Expected ReadonlyViolation, got {:?}", res),
+        }
+    }
+
+    #[test]
+    fn test_contract_without_init() {
+        let mut storage = MerklePatriciaTree::<ContractValue, _>::new(HashMap::new());
+        let root = storage.default_root();
+        let (contract, updates) =
+            Contract::new(ContractSource::new(&NO_INIT_WASM), 1, &storage, root)
+                .expect("Failed to create contract");
+        // No state is set when the contract is created
+        assert_eq!(updates.get_root_hash(), root);
+
+        let (_, updates) = contract
+            .exec(
+                "set_mapping",
+                &[
+                    ContractValue::U32(0),
+                    ContractValue::U64(7),
+                    ContractValue::U64(70),
+                ],
+                &storage,
+                root,
+            )
+            .unwrap();
+        let root = updates.get_root_hash();
+        assert!(storage.commit_set(updates).is_ok());
+        assert_eq!(
+            Ok(OOB::Borrowed(&ContractValue::U64(70))),
+            storage.get(root, get_mapping_key(0, 7, 1))
+        );
+    }
+
+    #[test]
+    fn test_exec_return_tuple() {
+        let storage = MerklePatriciaTree::<ContractValue, _>::new(HashMap::new());
+        let root = storage.default_root();
+        let source = ContractSource::new(&DIVMOD_WASM);
+        assert!(source.check_memory_export().is_ok());
+        let (contract, _) =
+            Contract::new(source, 1, &storage, root).expect("Failed to create contract");
+
+        let (values, updates) = contract
+            .exec(
+                "divmod",
+                &[ContractValue::U32(17), ContractValue::U32(5)],
+                &storage,
+                root,
+            )
+            .unwrap();
+        assert_eq!(values, vec![ContractValue::U32(3), ContractValue::U32(2)]);
+        assert_eq!(updates.get_root_hash(), root);
+
+        // Every way of executing the contract returns the tuple
+        let args = [ContractValue::U32(17), ContractValue::U32(5)];
+        assert_eq!(
+            contract
+                .exec_const("divmod", &args, &storage, root)
+                .unwrap(),
+            values
+        );
+        assert_eq!(
+            contract
+                .exec_cached(&ModuleCache::new(), "divmod", &args, &storage, root)
+                .unwrap()
+                .0,
+            values
+        );
+        let calls = vec![("divmod".to_string(), args.to_vec())];
+        assert_eq!(
+            contract.exec_multicall(&calls, &storage, root).unwrap(),
+            vec![values]
+        );
+    }
+
+    #[test]
+    fn test_exec_access_list() {
+        let src = load_api_test_source();
+
+        let mut storage = MerklePatriciaTree::<ContractValue, _>::new(HashMap::new());
+        let root = storage.default_root();
+        let (contract, updates) =
+            Contract::new(src, 0, &storage, root).expect("Failed to create contract");
+        let root = updates.get_root_hash();
+        assert!(storage.commit_set(updates).is_ok());
+
+        let (_, _, access) = contract
+            .exec_recorded(
+                "set_u32",
+                &[ContractValue::U32(0), ContractValue::U32(5)],
+                &storage,
+                root,
+            )
+            .unwrap();
+        assert_eq!(
+            access.writes,
+            vec![get_key(0, 0)].into_iter().collect::<HashSet<_>>()
+        );
+        assert!(access.reads.is_empty());
+
+        // A getter of the same key conflicts with the setter
+        let (_, _, read_access) = contract
+            .exec_recorded("get_u32", &[ContractValue::U32(0)], &storage, root)
+            .unwrap();
+        assert_eq!(read_access.reads, access.writes);
+        assert!(read_access.writes.is_empty());
+        assert!(access.conflicts_with(&read_access));
+    }
+
+    #[test]
+    fn test_exec_traced() {
+        let src = load_api_test_source();
+
+        let mut storage = MerklePatriciaTree::<ContractValue, _>::new(HashMap::new());
+        let root = storage.default_root();
+        let (contract, updates) =
+            Contract::new(src, 0, &storage, root).expect("Failed to create contract");
+        let root = updates.get_root_hash();
+        assert!(storage.commit_set(updates).is_ok());
+
+        // Run a setter then a getter against the same traced state
+        let module = contract.get_module().unwrap();
+        let mut state = contract.build_state(&module, &storage, root).unwrap();
+        state.set_tracing(true);
+        contract
+            .exec_from_state(
+                "set_u32",
+                &[ContractValue::U32(0), ContractValue::U32(5)],
+                &mut state,
+            )
+            .unwrap();
+        assert_eq!(
+            vec![ContractValue::U32(5)],
+            contract
+                .exec_from_state("get_u32", &[ContractValue::U32(0)], &mut state)
+                .unwrap()
+        );
+        assert_eq!(
+            state.take_trace(),
+            vec![
+                TraceEntry {
+                    index: SET_INT32_INDEX,
+                    args: vec![ContractValue::U32(0), ContractValue::U32(5)],
+                },
+                TraceEntry {
+                    index: GET_INT32_INDEX,
+                    args: vec![ContractValue::U32(0)],
+                },
+            ]
+        );
+
+        // The trace is returned from exec_traced
+        let (values, trace) = contract
+            .exec_traced("get_u32", &[ContractValue::U32(0)], &storage, root)
+            .unwrap();
+        assert_eq!(vec![ContractValue::U32(1)], values);
+        assert_eq!(
+            trace,
+            vec![TraceEntry {
+                index: GET_INT32_INDEX,
+                args: vec![ContractValue::U32(0)],
+            }]
+        );
+    }
 }
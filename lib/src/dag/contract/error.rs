@@ -2,25 +2,116 @@ use std::error::Error;
 use std::fmt;
 
 use dag::storage::map::MapError;
-use wasmi::Error as WasmError;
+use util::HashId;
 use wasmi::HostError;
 
-#[derive(Debug)]
+use super::resolver::{
+    MAX_EVENTS_PER_CALL, MAX_EVENT_SIZE, MAX_MAPPING_KEY_SIZE, MAX_SHA3_INPUT_SIZE,
+};
+use super::wasm_error::{InterpreterErrorKind, TrapCode};
+
+/// Error creating or executing a contract
+///
+/// Errors raised by the wasm interpreter are converted by the
+/// [wasm_error](../wasm_error/index.html) module
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub enum ContractError {
-    //TODO: Consider refactoring so you can't have nested ContractError(WasmError(ContractError(WasmError(...))))
-    WasmError(WasmError),
+    /// The contract trapped while executing
+    Trap(TrapCode),
+    /// The interpreter failed to load or run the contract, contains the
+    /// interpreter's message
+    Interpreter(InterpreterErrorKind, String),
     MapError(MapError),
     RequiredFnNotFound,
     TypeMismatch,
+    StateLimitExceeded,
+    ReadonlyViolation,
+    CorruptSource,
+    MissingMemoryExport,
+    /// A call does not match the contract's abi, contains the function name
+    AbiMismatch(String),
+    /// No contract exists with the id
+    ContractNotFound(u64),
+    /// The contract does not export a function with the name
+    FunctionNotFound(String),
+    /// A contract accessed state with a negative index
+    NegativeIndex(i32),
+    /// A contract used a wide mapping key longer than MAX_MAPPING_KEY_SIZE,
+    /// contains the length of the key
+    KeyTooLong(u32),
+    /// A contract emitted an event with more than MAX_EVENT_SIZE bytes of
+    /// data, contains the length of the data
+    EventTooLarge(u32),
+    /// A contract call emitted more than MAX_EVENTS_PER_CALL events
+    TooManyEvents,
+    /// A contract hashed more than MAX_SHA3_INPUT_SIZE bytes, contains the
+    /// length of the input
+    HashInputTooLarge(u32),
+    /// The contract's schema version differs from the schema version of its
+    /// state, so the state must be migrated before executing
+    SchemaMismatch {
+        contract: u32,
+        state: u32,
+    },
+    /// A thread executing contract calls concurrently panicked
+    ExecutionPanicked,
 }
 
 impl fmt::Display for ContractError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
-            ContractError::WasmError(err) => write!(f, "Wasm Error: {}", err),
+            ContractError::Trap(code) => write!(f, "Trap: {}", code),
+            ContractError::Interpreter(kind, message) => {
+                write!(f, "{} Error: {}", kind, message)
+            }
             ContractError::MapError(err) => write!(f, "Map Error: {}", err),
             ContractError::RequiredFnNotFound => write!(f, "Required function not found"),
             ContractError::TypeMismatch => write!(f, "Type mismatch"),
+            ContractError::StateLimitExceeded => write!(f, "State limit exceeded"),
+            ContractError::ReadonlyViolation => write!(f, "State modified in readonly execution"),
+            ContractError::CorruptSource => write!(f, "Contract source does not match checksum"),
+            ContractError::MissingMemoryExport => {
+                write!(
+                    f,
+                    "Contract imports memory functions without exporting memory"
+                )
+            }
+            ContractError::AbiMismatch(func_name) => {
+                write!(f, "Call to {} does not match the contract abi", func_name)
+            }
+            ContractError::ContractNotFound(id) => write!(f, "Contract {} not found", HashId(*id)),
+            ContractError::FunctionNotFound(func_name) => {
+                write!(f, "Function {} not found", func_name)
+            }
+            ContractError::NegativeIndex(index) => {
+                write!(f, "State index {} is negative", index)
+            }
+            ContractError::KeyTooLong(len) => write!(
+                f,
+                "Mapping key of {} bytes is longer than {} bytes",
+                len, MAX_MAPPING_KEY_SIZE
+            ),
+            ContractError::EventTooLarge(len) => write!(
+                f,
+                "Event of {} bytes is larger than {} bytes",
+                len, MAX_EVENT_SIZE
+            ),
+            ContractError::TooManyEvents => write!(
+                f,
+                "Call emitted more than {} events",
+                MAX_EVENTS_PER_CALL
+            ),
+            ContractError::HashInputTooLarge(len) => write!(
+                f,
+                "Hash input of {} bytes is larger than {} bytes",
+                len, MAX_SHA3_INPUT_SIZE
+            ),
+            ContractError::SchemaMismatch { contract, state } => write!(
+                f,
+                "Contract schema version {} does not match state schema version {}, migrate the state first",
+                contract, state
+            ),
+            ContractError::ExecutionPanicked => write!(f, "Contract execution thread panicked"),
         }
     }
 }
@@ -28,12 +119,6 @@ impl fmt::Display for ContractError {
 impl Error for ContractError {}
 impl HostError for ContractError {}
 
-impl From<WasmError> for ContractError {
-    fn from(error: WasmError) -> Self {
-        ContractError::WasmError(error)
-    }
-}
-
 impl From<MapError> for ContractError {
     fn from(error: MapError) -> Self {
         ContractError::MapError(error)
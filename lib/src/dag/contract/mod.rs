@@ -1,6 +1,10 @@
+pub mod abi;
+pub mod cache;
 pub mod error;
+pub mod scheduler;
 pub mod source;
 pub mod state;
+pub mod wasm_error;
 
 #[allow(clippy::module_inception)]
 mod contract;
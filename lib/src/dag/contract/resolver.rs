@@ -15,6 +15,47 @@ pub const SET_FLOAT32_INDEX: usize = 7;
 pub const SET_FLOAT64_INDEX: usize = 8;
 pub const SET_MAPPING_INDEX: usize = 9;
 
+pub const RETURN_TUPLE_INDEX: usize = 10;
+
+pub const GET_MAPPING_WIDE_INDEX: usize = 11;
+pub const SET_MAPPING_WIDE_INDEX: usize = 12;
+
+pub const GET_U128_INDEX: usize = 13;
+pub const SET_U128_INDEX: usize = 14;
+
+pub const EMIT_EVENT_INDEX: usize = 15;
+pub const SHA3_INDEX: usize = 16;
+
+/// Largest key accepted by the wide mapping functions, in bytes
+pub const MAX_MAPPING_KEY_SIZE: usize = 32;
+
+/// Largest event data accepted by `__ofc__emit_event`, in bytes
+pub const MAX_EVENT_SIZE: usize = 1024;
+
+/// Largest number of events a single call can emit with `__ofc__emit_event`
+pub const MAX_EVENTS_PER_CALL: usize = 64;
+
+/// Largest input accepted by `__ofc__sha3`, in bytes
+pub const MAX_SHA3_INPUT_SIZE: usize = 4096;
+
+/// Size of each value passed to `__ofc__return_tuple`
+///
+/// A value is a little endian u32 type tag (0: u32, 1: u64, 2: f32, 3: f64),
+/// 4 bytes of padding, then the little endian value, padded to 8 bytes
+pub const RETURN_VALUE_SIZE: usize = 16;
+
+/// Host functions passing data through the contract's linear memory
+///
+/// Contracts importing any of these must export their memory as `memory`
+pub const MEMORY_IMPORTS: &[&str] = &[
+    "__ofc__return_tuple",
+    "__ofc__get_mapping_wide",
+    "__ofc__set_mapping_wide",
+    "__ofc__get_u128",
+    "__ofc__emit_event",
+    "__ofc__sha3",
+];
+
 pub struct Resolver;
 
 pub fn get_imports_builder<'a>() -> ImportsBuilder<'a> {
@@ -71,6 +112,46 @@ impl ModuleImportResolver for Resolver {
                 Signature::new(&[ValueType::I32, ValueType::I64, ValueType::I64][..], None),
                 SET_MAPPING_INDEX,
             ),
+
+            "__ofc__return_tuple" => FuncInstance::alloc_host(
+                Signature::new(&[ValueType::I32, ValueType::I32][..], None),
+                RETURN_TUPLE_INDEX,
+            ),
+            "__ofc__get_mapping_wide" => FuncInstance::alloc_host(
+                Signature::new(
+                    &[ValueType::I32, ValueType::I32, ValueType::I32][..],
+                    Some(ValueType::I64),
+                ),
+                GET_MAPPING_WIDE_INDEX,
+            ),
+            "__ofc__set_mapping_wide" => FuncInstance::alloc_host(
+                Signature::new(
+                    &[
+                        ValueType::I32,
+                        ValueType::I32,
+                        ValueType::I32,
+                        ValueType::I64,
+                    ][..],
+                    None,
+                ),
+                SET_MAPPING_WIDE_INDEX,
+            ),
+            "__ofc__get_u128" => FuncInstance::alloc_host(
+                Signature::new(&[ValueType::I32, ValueType::I32][..], None),
+                GET_U128_INDEX,
+            ),
+            "__ofc__set_u128" => FuncInstance::alloc_host(
+                Signature::new(&[ValueType::I32, ValueType::I64, ValueType::I64][..], None),
+                SET_U128_INDEX,
+            ),
+            "__ofc__emit_event" => FuncInstance::alloc_host(
+                Signature::new(&[ValueType::I64, ValueType::I32, ValueType::I32][..], None),
+                EMIT_EVENT_INDEX,
+            ),
+            "__ofc__sha3" => FuncInstance::alloc_host(
+                Signature::new(&[ValueType::I32, ValueType::I32, ValueType::I32][..], None),
+                SHA3_INDEX,
+            ),
             _ => {
                 return Err(InterpreterError::Function(format!(
                     "host module doesn't export function with name {}",
@@ -0,0 +1,172 @@
+use crossbeam_utils::thread;
+
+use dag::storage::mpt::{MerklePatriciaTree, NodeUpdates};
+
+use super::cache::ModuleCache;
+use super::error::ContractError;
+use super::state::{ContractEvent, ContractStateStorage};
+use super::{Contract, ContractValue};
+
+/// The values returned by a contract call, its node updates, and the events
+/// it emitted
+pub type ExecResult = (
+    Vec<ContractValue>,
+    NodeUpdates<ContractValue>,
+    Vec<ContractEvent>,
+);
+
+/// A call to a contract function
+#[derive(Clone, Debug)]
+pub struct ContractCall {
+    contract: Contract,
+    func_name: String,
+    args: Vec<ContractValue>,
+}
+
+impl ContractCall {
+    pub fn new(contract: Contract, func_name: String, args: Vec<ContractValue>) -> Self {
+        ContractCall {
+            contract,
+            func_name,
+            args,
+        }
+    }
+}
+
+/// Group the indices of calls by contract, keeping the order calls were made
+/// in
+fn group_indices<'a, I: Iterator<Item = &'a ContractCall>>(calls: I) -> Vec<Vec<usize>> {
+    let mut groups: Vec<(u64, Vec<usize>)> = Vec::new();
+    for (i, call) in calls.enumerate() {
+        let id = call.contract.get_id();
+        match groups.iter_mut().find(|(group_id, _)| *group_id == id) {
+            Some((_, indices)) => indices.push(i),
+            None => groups.push((id, vec![i])),
+        }
+    }
+    groups.into_iter().map(|(_, indices)| indices).collect()
+}
+
+/// Execute contract calls which each run against their own root, taking the
+/// parsed modules from cache
+///
+/// Calls to the same contract are executed in order on one thread, and calls
+/// to different contracts concurrently. Nothing is committed to storage.
+/// Returns the result of each call, in the order of calls
+pub fn execute_rooted<M: ContractStateStorage + Sync>(
+    cache: &ModuleCache,
+    storage: &MerklePatriciaTree<ContractValue, M>,
+    calls: &[(ContractCall, u64)],
+) -> Vec<Result<ExecResult, ContractError>> {
+    let groups = group_indices(calls.iter().map(|(call, _)| call));
+
+    let mut results: Vec<Result<ExecResult, ContractError>> = calls
+        .iter()
+        .map(|_| Err(ContractError::ExecutionPanicked))
+        .collect();
+    let group_results = thread::scope(|scope| {
+        let handles: Vec<_> = groups
+            .iter()
+            .map(|indices| {
+                scope.spawn(move |_| {
+                    indices
+                        .iter()
+                        .map(|&i| {
+                            let (call, root) = &calls[i];
+                            call.contract.exec_cached(
+                                cache,
+                                &call.func_name,
+                                &call.args,
+                                storage,
+                                *root,
+                            )
+                        })
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .map(|handle| handle.join())
+            .collect::<Vec<_>>()
+    });
+
+    // Calls in a group whose thread panicked keep the error
+    if let Ok(group_results) = group_results {
+        for (indices, group_result) in groups.iter().zip(group_results) {
+            if let Ok(group_result) = group_result {
+                for (&i, result) in indices.iter().zip(group_result) {
+                    results[i] = result;
+                }
+            }
+        }
+    }
+    results
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::fs::File;
+    use std::io::Read;
+    use std::path::PathBuf;
+
+    use dag::contract::source::ContractSource;
+
+    fn load_api_test_source() -> ContractSource {
+        let mut d = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        d.push("resources/test/contracts/api_test.wasm");
+        let mut file = File::open(d).expect("Could not open test file");
+        let mut buf: Vec<u8> = Vec::new();
+        file.read_to_end(&mut buf)
+            .expect("Could not read test file");
+        ContractSource::new(&buf)
+    }
+
+    #[test]
+    fn test_execute_rooted() {
+        let src = load_api_test_source();
+        let mut storage = MerklePatriciaTree::<ContractValue, _>::new(HashMap::new());
+        let mut root = storage.default_root();
+
+        let mut contracts = Vec::new();
+        for id in 1..3 {
+            let (contract, updates) = Contract::new(src.clone(), id, &storage, root).unwrap();
+            root = updates.get_root_hash();
+            storage.commit_set(updates).unwrap();
+            contracts.push(contract);
+        }
+
+        let calls: Vec<(ContractCall, u64)> = (0..4)
+            .map(|i| {
+                let call = ContractCall::new(
+                    contracts[i as usize % 2].clone(),
+                    "set_u64".into(),
+                    vec![ContractValue::U32(1), ContractValue::U64(i)],
+                );
+                (call, root)
+            })
+            .chain(Some((
+                ContractCall::new(contracts[0].clone(), "missing".into(), vec![]),
+                root,
+            )))
+            .collect();
+
+        let cache = ModuleCache::new();
+        let results = execute_rooted(&cache, &storage, &calls);
+        assert_eq!(results.len(), calls.len());
+        for ((call, root), result) in calls.iter().zip(results.iter()).take(4) {
+            let (_, updates) = call
+                .contract
+                .exec(&call.func_name, &call.args, &storage, *root)
+                .unwrap();
+            let (_, rooted_updates, _) = result.as_ref().unwrap();
+            assert_eq!(rooted_updates.get_root_hash(), updates.get_root_hash());
+        }
+        assert_eq!(
+            results[4].as_ref().err(),
+            Some(&ContractError::FunctionNotFound("missing".into()))
+        );
+    }
+}
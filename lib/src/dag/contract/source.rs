@@ -1,32 +1,237 @@
 use std::fmt;
-use std::io::Write;
+use std::hash::{Hash, Hasher};
+use std::io::{self, Read, Write};
 
-use flate2::write::{GzDecoder, GzEncoder};
+use flate2::read::{DeflateDecoder, GzDecoder, ZlibDecoder};
+use flate2::write::{DeflateEncoder, GzEncoder, ZlibEncoder};
 use flate2::Compression;
 
-use wasmi::{Error as WasmError, Module};
+use parity_wasm::elements::{self, Internal};
+use wasmi::Module;
 
 use serde::{
     de::{self, Deserialize, Deserializer, MapAccess, SeqAccess, Unexpected, Visitor},
     ser::{self, Serialize, SerializeStruct, Serializer},
 };
 
-#[derive(Clone, PartialEq, Hash, Debug)]
+use security::hash::hasher::Sha3Hasher;
+
+use super::error::ContractError;
+use super::resolver::MEMORY_IMPORTS;
+use super::wasm_error::InterpreterErrorKind;
+
+/// Largest contract code accepted when deserializing, in bytes
+///
+/// Bounds the memory used decompressing untrusted source
+pub const MAX_CODE_SIZE: usize = 1 << 20;
+
+/// Algorithm used to compress contract source when it is serialized
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum CompressionCodec {
+    Gzip,
+    Zlib,
+    Deflate,
+    /// The source is not compressed
+    None,
+}
+
+/// Compression applied to contract source when it is serialized
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct CompressionConfig {
+    codec: CompressionCodec,
+    /// Compression level, from 0 (fastest) to 9 (smallest)
+    level: u32,
+}
+
+impl CompressionConfig {
+    pub fn new(codec: CompressionCodec, level: u32) -> Self {
+        CompressionConfig { codec, level }
+    }
+
+    pub fn get_codec(&self) -> CompressionCodec {
+        self.codec
+    }
+}
+
+impl Default for CompressionConfig {
+    /// Gzip at the best compression level
+    fn default() -> Self {
+        CompressionConfig::new(CompressionCodec::Gzip, Compression::best().level())
+    }
+}
+
+#[derive(Clone, Debug)]
 pub struct ContractSource {
     code: Vec<u8>,
+    /// Hash of the code, used to detect corruption
+    checksum: u64,
+    /// Compression used when serializing the code
+    compression: CompressionConfig,
 }
 
 impl ContractSource {
     /// Create contract from raw wasm source
     pub fn new(code: &[u8]) -> Self {
+        ContractSource::with_compression(code, CompressionConfig::default())
+    }
+
+    /// Create contract from raw wasm source, which is compressed with
+    /// compression when serialized
+    pub fn with_compression(code: &[u8], compression: CompressionConfig) -> Self {
         ContractSource {
             code: code.to_vec(),
+            checksum: checksum(code),
+            compression,
         }
     }
 
+    /// Get the uncompressed wasm code
+    pub fn get_code(&self) -> &[u8] {
+        &self.code
+    }
+
+    /// Hash of the code
+    pub fn get_checksum(&self) -> u64 {
+        self.checksum
+    }
+
+    pub fn get_compression(&self) -> CompressionConfig {
+        self.compression
+    }
+
     /// Create a wasm module from the contract source
-    pub fn get_wasm_module(&self) -> Result<Module, WasmError> {
-        Module::from_buffer(&self.code)
+    ///
+    /// Returns ContractError::CorruptSource if the code does not match its
+    /// checksum
+    pub fn get_wasm_module(&self) -> Result<Module, ContractError> {
+        if checksum(&self.code) != self.checksum {
+            return Err(ContractError::CorruptSource);
+        }
+        Ok(Module::from_buffer(&self.code)?)
+    }
+
+    /// Check the contract exports its memory if it imports any host function
+    /// using memory
+    ///
+    /// Returns ContractError::MissingMemoryExport if the memory is not
+    /// exported
+    pub fn check_memory_export(&self) -> Result<(), ContractError> {
+        let module = parity_wasm::deserialize_buffer::<elements::Module>(&self.code)
+            .map_err(|err| {
+                ContractError::Interpreter(InterpreterErrorKind::Validation, err.to_string())
+            })?;
+
+        let uses_memory = module
+            .import_section()
+            .into_iter()
+            .flat_map(|imports| imports.entries())
+            .any(|entry| entry.module() == "env" && MEMORY_IMPORTS.contains(&entry.field()));
+        let exports_memory = module
+            .export_section()
+            .into_iter()
+            .flat_map(|exports| exports.entries())
+            .any(|entry| match entry.internal() {
+                Internal::Memory(_) => entry.field() == "memory",
+                _ => false,
+            });
+
+        if uses_memory && !exports_memory {
+            Err(ContractError::MissingMemoryExport)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+// The compression only affects how the source is serialized, so it is not
+// compared or hashed
+impl PartialEq for ContractSource {
+    fn eq(&self, other: &ContractSource) -> bool {
+        self.code == other.code && self.checksum == other.checksum
+    }
+}
+
+impl Hash for ContractSource {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.code.hash(state);
+        self.checksum.hash(state);
+    }
+}
+
+fn checksum(code: &[u8]) -> u64 {
+    let mut hasher = Sha3Hasher::new();
+    hasher.write(code);
+    hasher.finish()
+}
+
+fn compress_code(code: &[u8], compression: CompressionConfig) -> io::Result<Vec<u8>> {
+    let level = Compression::new(compression.level);
+    match compression.codec {
+        CompressionCodec::Gzip => {
+            let mut e = GzEncoder::new(Vec::new(), level);
+            e.write_all(code)?;
+            e.finish()
+        }
+        CompressionCodec::Zlib => {
+            let mut e = ZlibEncoder::new(Vec::new(), level);
+            e.write_all(code)?;
+            e.finish()
+        }
+        CompressionCodec::Deflate => {
+            let mut e = DeflateEncoder::new(Vec::new(), level);
+            e.write_all(code)?;
+            e.finish()
+        }
+        CompressionCodec::None => Ok(code.to_vec()),
+    }
+}
+
+/// Decompress code, failing if it is larger than MAX_CODE_SIZE
+fn decompress_code(bytes: &[u8], codec: CompressionCodec) -> io::Result<Vec<u8>> {
+    let decoder: Box<dyn Read> = match codec {
+        CompressionCodec::Gzip => Box::new(GzDecoder::new(bytes)),
+        CompressionCodec::Zlib => Box::new(ZlibDecoder::new(bytes)),
+        CompressionCodec::Deflate => Box::new(DeflateDecoder::new(bytes)),
+        CompressionCodec::None => Box::new(bytes),
+    };
+    // Read one byte past the limit to detect oversized code without
+    // decompressing all of it
+    let mut code = Vec::new();
+    decoder
+        .take(MAX_CODE_SIZE as u64 + 1)
+        .read_to_end(&mut code)?;
+    if code.len() > MAX_CODE_SIZE {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("code is larger than {} bytes", MAX_CODE_SIZE),
+        ));
+    }
+    Ok(code)
+}
+
+/// Create a contract from its serialized code, compressed with codec
+///
+/// Fails if the decompressed code does not match the serialized checksum
+fn decode_source<E: de::Error>(
+    bytes: &[u8],
+    codec: CompressionCodec,
+    expected_checksum: Option<u64>,
+) -> Result<ContractSource, E> {
+    let code = decompress_code(bytes, codec)
+        .map_err(|err| E::custom(format!("Failed to decompress code: {}", err)))?;
+    let source = ContractSource::with_compression(
+        &code,
+        CompressionConfig {
+            codec,
+            ..CompressionConfig::default()
+        },
+    );
+    match expected_checksum {
+        Some(expected) if expected != source.checksum => {
+            Err(E::custom(ContractError::CorruptSource))
+        }
+        _ => Ok(source),
     }
 }
 
@@ -35,15 +240,16 @@ impl Serialize for ContractSource {
     where
         S: Serializer,
     {
-        let mut state = serializer.serialize_struct("ContractSource", 1)?;
+        let mut state = serializer.serialize_struct("ContractSource", 3)?;
         // Compress and serialize code
-        let mut e = GzEncoder::new(Vec::new(), Compression::best());
-        e.write_all(&self.code)
-            .map_err(|_| ser::Error::custom("Failed to compress code"))?;
-        let bytes = e
-            .finish()
+        let bytes = compress_code(&self.code, self.compression)
             .map_err(|_| ser::Error::custom("Failed to compress code"))?;
         state.serialize_field("code", &base64::encode_config(&bytes, base64::URL_SAFE))?;
+        // The checksum of the uncompressed code, verified when deserializing
+        state.serialize_field("checksum", &self.checksum)?;
+        // Every field is written, so fields keep their position in formats
+        // without field names
+        state.serialize_field("codec", &self.compression.codec)?;
         state.end()
     }
 }
@@ -58,6 +264,8 @@ impl<'de> Deserialize<'de> for ContractSource {
         #[serde(field_identifier, rename_all = "lowercase")]
         enum Field {
             Code,
+            Checksum,
+            Codec,
         }
 
         struct ContractSourceVisitor;
@@ -81,16 +289,12 @@ impl<'de> Deserialize<'de> for ContractSource {
                 .map_err(|_| {
                     de::Error::invalid_value(Unexpected::Str(&"code"), &"valid base64 string")
                 })?;
+                let checksum = seq
+                    .next_element()?
+                    .ok_or_else(|| de::Error::invalid_length(1, &self))?;
+                let codec = seq.next_element()?.unwrap_or(CompressionCodec::Gzip);
 
-                let mut decoder = GzDecoder::new(Vec::new());
-                decoder
-                    .write_all(&bytes[..])
-                    .map_err(|_| de::Error::custom("Failed to decompress code"))?;
-                let code = decoder
-                    .finish()
-                    .map_err(|_| de::Error::custom("Failed to decompress code"))?;
-
-                Ok(ContractSource::new(&code))
+                decode_source(&bytes, codec, Some(checksum))
             }
 
             fn visit_map<V>(self, mut map: V) -> Result<ContractSource, V::Error>
@@ -98,6 +302,8 @@ impl<'de> Deserialize<'de> for ContractSource {
                 V: MapAccess<'de>,
             {
                 let mut code = None;
+                let mut checksum = None;
+                let mut codec = None;
 
                 while let Some(key) = map.next_key()? {
                     match key {
@@ -105,37 +311,43 @@ impl<'de> Deserialize<'de> for ContractSource {
                             if code.is_some() {
                                 return Err(de::Error::duplicate_field("code"));
                             }
-                            let bytes = base64::decode_config(
-                                &map.next_value::<String>()?,
-                                base64::URL_SAFE,
-                            )
-                            .map_err(|_| {
-                                de::Error::invalid_value(
-                                    Unexpected::Str(&"code"),
-                                    &"valid base64 string",
-                                )
-                            })?;
-
-                            let mut decoder = GzDecoder::new(Vec::new());
-                            decoder
-                                .write_all(&bytes[..])
-                                .map_err(|_| de::Error::custom("Failed to decompress code"))?;
                             code = Some(
-                                decoder
-                                    .finish()
-                                    .map_err(|_| de::Error::custom("Failed to decompress code"))?,
+                                base64::decode_config(
+                                    &map.next_value::<String>()?,
+                                    base64::URL_SAFE,
+                                )
+                                .map_err(|_| {
+                                    de::Error::invalid_value(
+                                        Unexpected::Str(&"code"),
+                                        &"valid base64 string",
+                                    )
+                                })?,
                             );
                         }
+                        Field::Checksum => {
+                            if checksum.is_some() {
+                                return Err(de::Error::duplicate_field("checksum"));
+                            }
+                            checksum = Some(map.next_value()?);
+                        }
+                        Field::Codec => {
+                            if codec.is_some() {
+                                return Err(de::Error::duplicate_field("codec"));
+                            }
+                            codec = Some(map.next_value()?);
+                        }
                     }
                 }
 
                 let code = code.ok_or_else(|| de::Error::missing_field("code"))?;
 
-                Ok(ContractSource::new(&code))
+                // For compatibility, gzip is implied when no codec is given,
+                // and sources serialized without a checksum are not verified
+                decode_source(&code, codec.unwrap_or(CompressionCodec::Gzip), checksum)
             }
         }
 
-        const FIELDS: &[&str] = &["code"];
+        const FIELDS: &[&str] = &["code", "checksum", "codec"];
         deserializer.deserialize_struct("ContractSource", FIELDS, ContractSourceVisitor)
     }
 }
@@ -150,12 +362,27 @@ mod tests {
         e.finish().expect("Failed to compress bytes")
     }
 
+    #[test]
+    fn test_corrupt_contract_source() {
+        let code = vec![0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00];
+        let mut source = ContractSource::new(&code);
+        assert!(source.get_wasm_module().is_ok());
+
+        source.code[4] = 0x02;
+        match source.get_wasm_module() {
+            Err(ContractError::CorruptSource) => {}
+            res => panic!("Expected CorruptSource, got {:?}", res.map(|_| ())),
+        }
+    }
+
     #[test]
     fn test_contract_source_serialize() {
         let code = vec![0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07];
         let source = ContractSource::new(&code);
         let json_value = json!({
             "code": base64::encode_config(&compress(&code), base64::URL_SAFE),
+            "checksum": checksum(&code),
+            "codec": "gzip",
         });
         println!("{:?}", json_value);
         assert_eq!(json_value, serde_json::to_value(source).unwrap());
@@ -180,4 +407,53 @@ mod tests {
         let json_value = serde_json::to_value(source.clone()).unwrap();
         assert_eq!(source, serde_json::from_value(json_value).unwrap());
     }
+
+    #[test]
+    fn test_contract_source_deserialize_checksum() {
+        let code = vec![0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07];
+        let source = ContractSource::new(&code);
+        let mut json_value = serde_json::to_value(source.clone()).unwrap();
+        assert_eq!(source, serde_json::from_value(json_value.clone()).unwrap());
+
+        // Code not matching its checksum is rejected
+        json_value["checksum"] = json!(checksum(&code) ^ 1);
+        let err = serde_json::from_value::<ContractSource>(json_value).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains(&ContractError::CorruptSource.to_string()));
+    }
+
+    #[test]
+    fn test_contract_source_oversized() {
+        // Compresses to a few kilobytes, but decompresses past the limit
+        let code = vec![0; MAX_CODE_SIZE + 1];
+        let json_value = json!({
+            "code": base64::encode_config(&compress(&code), base64::URL_SAFE),
+        });
+        let err = serde_json::from_value::<ContractSource>(json_value).unwrap_err();
+        assert!(err.to_string().contains("larger than"));
+
+        let code = vec![0; MAX_CODE_SIZE];
+        let json_value = json!({
+            "code": base64::encode_config(&compress(&code), base64::URL_SAFE),
+        });
+        assert!(serde_json::from_value::<ContractSource>(json_value).is_ok());
+    }
+
+    #[test]
+    fn test_contract_source_compression_codecs() {
+        let code: Vec<u8> = (0..255).chain(0..255).collect();
+        for codec in &[
+            CompressionCodec::Gzip,
+            CompressionCodec::Zlib,
+            CompressionCodec::Deflate,
+            CompressionCodec::None,
+        ] {
+            let source = ContractSource::with_compression(&code, CompressionConfig::new(*codec, 1));
+            let json_value = serde_json::to_value(source.clone()).unwrap();
+            let deserialized: ContractSource = serde_json::from_value(json_value).unwrap();
+            assert_eq!(source, deserialized);
+            assert_eq!(deserialized.get_compression().get_codec(), *codec);
+        }
+    }
 }
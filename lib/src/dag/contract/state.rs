@@ -1,16 +1,22 @@
+use std::collections::HashSet;
 use std::hash::Hasher;
+use std::mem;
 
 use wasmi::{
     nan_preserving_float::{F32, F64},
-    Error as InterpreterError, Externals, ModuleRef, RuntimeArgs, RuntimeValue, Trap, TrapKind,
+    Error as InterpreterError, Externals, MemoryRef, ModuleRef, RuntimeArgs, RuntimeValue, Trap,
+    TrapKind,
 };
 
 use dag::contract::resolver::*;
 use dag::contract::{error::ContractError, ContractValue};
+use dag::storage::keyed::HashKeyMap;
 use dag::storage::map::MapResult;
-use dag::storage::mpt::{temp_map::MPTTempMap, MPTStorageMap, MerklePatriciaTree, NodeUpdates};
+use dag::storage::mpt::{
+    node::Node, temp_map::MPTTempMap, MPTStorageMap, MerklePatriciaTree, NodeUpdates,
+};
 
-use security::hash::hasher::Sha3Hasher;
+use security::hash::hasher::{sha3_256, Sha3Hasher};
 
 pub trait ContractStateStorage = MPTStorageMap<ContractValue>;
 
@@ -28,6 +34,91 @@ pub fn get_mapping_key(index: u32, key: u64, contract: u64) -> u64 {
     hasher.write_u64(contract);
     hasher.finish()
 }
+
+/// Get the key of a mapping entry with a key of arbitrary bytes
+///
+/// The length of the key is hashed before it, so wide keys do not collide
+/// with the keys of get_mapping_key
+pub fn get_wide_mapping_key(index: u32, key: &[u8], contract: u64) -> u64 {
+    let mut hasher = Sha3Hasher::new();
+    hasher.write_u32(index);
+    hasher.write_usize(key.len());
+    hasher.write(key);
+    hasher.write_u64(contract);
+    hasher.finish()
+}
+
+/// Get the key storing the number of mapping entries set by a contract
+pub fn get_mapping_count_key(contract: u64) -> u64 {
+    let mut hasher = Sha3Hasher::new();
+    hasher.write_u64(contract);
+    hasher.finish()
+}
+
+/// Get the key storing the schema version of a contract's state
+pub fn get_schema_version_key(contract: u64) -> u64 {
+    let mut hasher = Sha3Hasher::new();
+    hasher.write(b"schema_version");
+    hasher.write_u64(contract);
+    hasher.finish()
+}
+
+/// A call made by a contract to the host api, recorded while tracing
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct TraceEntry {
+    /// Index of the api function, see the resolver
+    pub index: usize,
+    pub args: Vec<ContractValue>,
+}
+
+/// Data emitted by a contract under a topic, for clients to query
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct ContractEvent {
+    pub topic: u64,
+    pub data: Vec<u8>,
+}
+
+/// The state keys read and written by a contract call
+///
+/// Calls whose access lists do not overlap can be executed in parallel
+#[derive(Serialize, Deserialize, Clone, Default, PartialEq, Debug)]
+pub struct AccessList {
+    /// Keys read from the state before the call, excluding keys the call
+    /// read after writing them
+    pub reads: HashSet<u64>,
+    pub writes: HashSet<u64>,
+}
+
+impl AccessList {
+    /// Check if executing either call could change the result of the other
+    pub fn conflicts_with(&self, other: &AccessList) -> bool {
+        !self.writes.is_disjoint(&other.writes)
+            || !self.writes.is_disjoint(&other.reads)
+            || !self.reads.is_disjoint(&other.writes)
+    }
+}
+
+/// The modified state of a contract, moved out of a ContractState so it no
+/// longer borrows the contract's module or the state tree
+///
+/// Created with [ContractState::persist](struct.ContractState.html#method.persist)
+pub struct PersistedState {
+    contract: u64,
+    root: u64,
+    state_limit: Option<u64>,
+    readonly: bool,
+    default_reads: bool,
+    /// Nodes of the modified state, not yet written out
+    nodes: HashKeyMap<u64, Node<ContractValue>>,
+}
+
+impl PersistedState {
+    /// Get the state root the modifications lead to
+    pub fn get_root(&self) -> u64 {
+        self.root
+    }
+}
+
 /// Cached state of a contract
 ///
 /// Uses copy on write to only store updated state, and holds a reference to the
@@ -37,6 +128,22 @@ pub struct ContractState<'a, M: ContractStateStorage> {
     state: MerklePatriciaTree<ContractValue, MPTTempMap<'a, ContractValue, M>>,
     contract: u64,
     root: u64,
+    state_limit: Option<u64>,
+    readonly: bool,
+    trace: Option<Vec<TraceEntry>>,
+    access: Option<AccessList>,
+    default_reads: bool,
+    /// Values declared with `__ofc__return_tuple` by the last call
+    returned: Option<Vec<ContractValue>>,
+    /// Values read by the current call, by key, or None if unset
+    reads: HashKeyMap<u64, Option<ContractValue>>,
+    /// Number of times the state tree was searched for a value read by the
+    /// contract
+    traversals: usize,
+    /// Events emitted by the contract, in order
+    events: Vec<ContractEvent>,
+    /// Number of events emitted by the current call
+    call_events: usize,
 }
 
 impl<'a, M: ContractStateStorage> ContractState<'a, M> {
@@ -52,13 +159,117 @@ impl<'a, M: ContractStateStorage> ContractState<'a, M> {
             state,
             contract,
             root,
+            state_limit: None,
+            readonly: false,
+            trace: None,
+            access: None,
+            default_reads: false,
+            returned: None,
+            reads: HashKeyMap::default(),
+            traversals: 0,
+            events: Vec::new(),
+            call_events: 0,
         }
     }
 
+    /// Resume a state moved out with [persist](#method.persist)
+    ///
+    /// storage must still hold the nodes the state was built on, so must not
+    /// have been pruned since the state was persisted
+    pub fn resume(
+        module: &'a ModuleRef,
+        storage: &'a MerklePatriciaTree<ContractValue, M>,
+        persisted: PersistedState,
+    ) -> Self {
+        let temp_map = MPTTempMap::with_staged(storage, persisted.nodes);
+        let mut state = ContractState::new(
+            module,
+            MerklePatriciaTree::new(temp_map),
+            persisted.contract,
+            persisted.root,
+        );
+        state.state_limit = persisted.state_limit;
+        state.readonly = persisted.readonly;
+        state.default_reads = persisted.default_reads;
+        state
+    }
+
+    /// Move the modified state out, releasing the borrows of the module and
+    /// state tree
+    ///
+    /// Lets long running calls be paused, for instance while another
+    /// contract is called, and continued with [resume](#method.resume).
+    /// Traces, access lists and cached reads are not kept
+    pub fn persist(self) -> PersistedState {
+        PersistedState {
+            contract: self.contract,
+            root: self.root,
+            state_limit: self.state_limit,
+            readonly: self.readonly,
+            default_reads: self.default_reads,
+            nodes: self.state.inner_map().into_staged(),
+        }
+    }
+
+    /// Limit the number of distinct mapping keys the contract can set
+    ///
+    /// Setting a new mapping key past the limit traps with
+    /// ContractError::StateLimitExceeded
+    pub fn set_state_limit(&mut self, state_limit: Option<u64>) {
+        self.state_limit = state_limit;
+    }
+
+    /// Prevent the contract from modifying its state
+    ///
+    /// Setting any value traps with ContractError::ReadonlyViolation
+    pub fn set_readonly(&mut self, readonly: bool) {
+        self.readonly = readonly;
+    }
+
+    /// Read unset values as zero instead of trapping
+    ///
+    /// Reading a value of the wrong type still traps
+    pub fn set_default_reads(&mut self, default_reads: bool) {
+        self.default_reads = default_reads;
+    }
+
+    /// Record every api call made by the contract
+    ///
+    /// The recorded calls are returned by
+    /// [take_trace](#method.take_trace)
+    pub fn set_tracing(&mut self, tracing: bool) {
+        self.trace = if tracing { Some(Vec::new()) } else { None };
+    }
+
+    /// Take the api calls recorded since tracing was enabled
+    pub fn take_trace(&mut self) -> Vec<TraceEntry> {
+        self.trace.as_mut().map(mem::take).unwrap_or_default()
+    }
+
+    /// Record the state keys read and written by the contract
+    ///
+    /// The recorded keys are returned by
+    /// [take_access_list](#method.take_access_list)
+    pub fn set_access_recording(&mut self, recording: bool) {
+        self.access = if recording {
+            Some(AccessList::default())
+        } else {
+            None
+        };
+    }
+
+    /// Take the keys accessed since recording was enabled
+    pub fn take_access_list(&mut self) -> AccessList {
+        self.access.as_mut().map(mem::take).unwrap_or_default()
+    }
+
     /// Execute a contract function
     ///
     /// Executes the contract function with the name func_name with args as arguments
     ///
+    /// If the call fails, the state it set and the events it emitted are
+    /// reverted
+    ///
     /// #Errors
     ///
     /// Returns an error if:
@@ -70,7 +281,74 @@ impl<'a, M: ContractStateStorage> ContractState<'a, M> {
         func_name: &str,
         args: &[RuntimeValue],
     ) -> Result<Option<RuntimeValue>, InterpreterError> {
-        self.module.invoke_export(func_name, args, self)
+        self.returned = None;
+        self.reads.clear();
+        self.call_events = 0;
+        let root = self.root;
+        let event_count = self.events.len();
+        let result = self.module.invoke_export(func_name, args, self);
+        if result.is_err() {
+            // Revert the state set and events emitted by the failed call, and
+            // release the nodes staged for it
+            self.root = root;
+            self.events.truncate(event_count);
+            self.reads.clear();
+            self.state.nodes.release_unreachable(root);
+        }
+        result
+    }
+
+    /// Take the events emitted since the state was created
+    pub fn take_events(&mut self) -> Vec<ContractEvent> {
+        mem::take(&mut self.events)
+    }
+
+    /// Take the values the contract declared with `__ofc__return_tuple`
+    ///
+    /// Returns None if the contract did not declare multiple return values
+    pub fn take_returned(&mut self) -> Option<Vec<ContractValue>> {
+        self.returned.take()
+    }
+
+    /// Get the number of times the state tree was searched for a value read
+    /// by the contract
+    ///
+    /// Values are only searched for once per call, until they are set
+    pub fn traversal_count(&self) -> usize {
+        self.traversals
+    }
+
+    /// Get the schema version the state is laid out for
+    ///
+    /// State which was never migrated has schema version 0
+    pub fn schema_version(&self) -> MapResult<u32> {
+        Ok(self
+            .state
+            .get_opt(self.root, get_schema_version_key(self.contract))?
+            .and_then(|version| version.as_u32())
+            .unwrap_or(0))
+    }
+
+    /// Record the schema version the state is laid out for
+    pub fn set_schema_version(&mut self, version: u32) -> Result<(), ContractError> {
+        self.set(
+            get_schema_version_key(self.contract),
+            ContractValue::U32(version),
+        )
+    }
+
+    /// Check if the contract exports a function named func_name
+    pub fn has_function(&self, func_name: &str) -> bool {
+        self.module
+            .export_by_name(func_name)
+            .and_then(|export| export.as_func().cloned())
+            .is_some()
+    }
+
+    /// Get the number of state tree nodes staged, and not yet written out
+    /// with [updates](#method.updates)
+    pub fn staged_node_count(&self) -> usize {
+        self.state.nodes.staged_count()
     }
 
     pub fn updates(self) -> MapResult<NodeUpdates<ContractValue>> {
@@ -85,68 +363,177 @@ impl<'a, M: ContractStateStorage> ContractState<'a, M> {
         get_mapping_key(index, key, self.contract)
     }
 
-    fn get_u32(&self, index: u32) -> Result<Option<RuntimeValue>, Trap> {
-        match self
-            .state
-            .get(self.root, self.get_key(index))
-            .map(|v| v.clone())
-        {
-            Ok(ContractValue::U32(val)) => Ok(Some(RuntimeValue::I32(val as i32))),
-            Ok(_) => Err(Trap::new(TrapKind::Unreachable)),
-            Err(_) => Err(Trap::new(TrapKind::MemoryAccessOutOfBounds)),
+    /// Read the value stored at key
+    ///
+    /// Returns None if the value is not set and the contract reads unset
+    /// values as zero, otherwise traps. Values are cached until the end of
+    /// the call, or until they are set
+    fn read(&mut self, key: u64) -> Result<Option<ContractValue>, Trap> {
+        self.record_read(key);
+        let value = match self.reads.get(&key) {
+            Some(value) => value.clone(),
+            None => {
+                self.traversals += 1;
+                let value = self
+                    .state
+                    .get_opt(self.root, key)
+                    .map_err(ContractError::from)?
+                    .map(|value| value.clone());
+                self.reads.insert(key, value.clone());
+                value
+            }
+        };
+        match value {
+            Some(value) => Ok(Some(value)),
+            None if self.default_reads => Ok(None),
+            None => Err(Trap::new(TrapKind::MemoryAccessOutOfBounds)),
         }
     }
 
-    fn get_u64(&self, index: u32) -> Result<Option<RuntimeValue>, Trap> {
-        match self
-            .state
-            .get(self.root, self.get_key(index))
-            .map(|v| v.clone())
-        {
-            Ok(ContractValue::U64(val)) => Ok(Some(RuntimeValue::I64(val as i64))),
-            Ok(_) => Err(Trap::new(TrapKind::Unreachable)),
-            Err(_) => Err(Trap::new(TrapKind::MemoryAccessOutOfBounds)),
+    fn get_u32(&mut self, index: u32) -> Result<Option<RuntimeValue>, Trap> {
+        match self.read(self.get_key(index))? {
+            Some(ContractValue::U32(val)) => Ok(Some(RuntimeValue::I32(val as i32))),
+            None => Ok(Some(RuntimeValue::I32(0))),
+            Some(_) => Err(Trap::new(TrapKind::Unreachable)),
         }
     }
 
-    fn get_f32(&self, index: u32) -> Result<Option<RuntimeValue>, Trap> {
-        match self
-            .state
-            .get(self.root, self.get_key(index))
-            .map(|v| v.clone())
-        {
-            Ok(ContractValue::F32(val)) => Ok(Some(RuntimeValue::F32(F32::from(val)))),
-            Ok(_) => Err(Trap::new(TrapKind::Unreachable)),
-            Err(_) => Err(Trap::new(TrapKind::MemoryAccessOutOfBounds)),
+    fn get_u64(&mut self, index: u32) -> Result<Option<RuntimeValue>, Trap> {
+        match self.read(self.get_key(index))? {
+            Some(ContractValue::U64(val)) => Ok(Some(RuntimeValue::I64(val as i64))),
+            None => Ok(Some(RuntimeValue::I64(0))),
+            Some(_) => Err(Trap::new(TrapKind::Unreachable)),
         }
     }
 
-    fn get_f64(&self, index: u32) -> Result<Option<RuntimeValue>, Trap> {
-        match self
-            .state
-            .get(self.root, self.get_key(index))
-            .map(|v| v.clone())
-        {
-            Ok(ContractValue::F64(val)) => Ok(Some(RuntimeValue::F64(F64::from(val)))),
-            Ok(_) => Err(Trap::new(TrapKind::Unreachable)),
-            Err(_) => Err(Trap::new(TrapKind::MemoryAccessOutOfBounds)),
+    fn get_f32(&mut self, index: u32) -> Result<Option<RuntimeValue>, Trap> {
+        match self.read(self.get_key(index))? {
+            Some(ContractValue::F32(val)) => Ok(Some(RuntimeValue::F32(F32::from(val)))),
+            None => Ok(Some(RuntimeValue::F32(F32::from(0f32)))),
+            Some(_) => Err(Trap::new(TrapKind::Unreachable)),
         }
     }
 
-    fn get_mapping(&self, index: u32, key: u64) -> Result<Option<RuntimeValue>, Trap> {
-        match self
-            .state
-            .get(self.root, self.get_mapping_key(index, key))
-            .map(|v| v.clone())
-        {
-            Ok(ContractValue::U64(val)) => Ok(Some(RuntimeValue::I64(val as i64))),
-            Ok(_) => Err(Trap::new(TrapKind::Unreachable)),
-            Err(_) => Err(Trap::new(TrapKind::MemoryAccessOutOfBounds)),
+    fn get_f64(&mut self, index: u32) -> Result<Option<RuntimeValue>, Trap> {
+        match self.read(self.get_key(index))? {
+            Some(ContractValue::F64(val)) => Ok(Some(RuntimeValue::F64(F64::from(val)))),
+            None => Ok(Some(RuntimeValue::F64(F64::from(0f64)))),
+            Some(_) => Err(Trap::new(TrapKind::Unreachable)),
+        }
+    }
+
+    /// Write the u128 at index to the contract's memory at ptr, as its low
+    /// then high little endian u64 limbs
+    fn get_u128(&mut self, index: u32, ptr: u32) -> Result<(), Trap> {
+        let value = match self.read(self.get_key(index))? {
+            Some(ContractValue::U128(val)) => val,
+            None => 0,
+            Some(_) => return Err(Trap::new(TrapKind::Unreachable)),
+        };
+        self.write_memory(ptr, &value.to_le_bytes())
+    }
+
+    fn get_mapping(&mut self, index: u32, key: u64) -> Result<Option<RuntimeValue>, Trap> {
+        let key = self.get_mapping_key(index, key);
+        self.read_mapping(key)
+    }
+
+    fn record_read(&mut self, key: u64) {
+        if let Some(access) = &mut self.access {
+            if !access.writes.contains(&key) {
+                access.reads.insert(key);
+            }
+        }
+    }
+
+    /// Read the mapping entry stored at key
+    fn read_mapping(&mut self, key: u64) -> Result<Option<RuntimeValue>, Trap> {
+        match self.read(key)? {
+            Some(ContractValue::U64(val)) => Ok(Some(RuntimeValue::I64(val as i64))),
+            None => Ok(Some(RuntimeValue::I64(0))),
+            Some(_) => Err(Trap::new(TrapKind::Unreachable)),
+        }
+    }
+
+    fn memory(&self) -> Result<MemoryRef, ContractError> {
+        self.module
+            .export_by_name("memory")
+            .and_then(|export| export.as_memory().cloned())
+            .ok_or(ContractError::MissingMemoryExport)
+    }
+
+    /// Read len bytes from the contract's memory, starting at ptr
+    fn read_memory(&self, ptr: u32, len: usize) -> Result<Vec<u8>, Trap> {
+        self.memory()?
+            .get(ptr, len)
+            .map_err(|_| Trap::new(TrapKind::MemoryAccessOutOfBounds))
+    }
+
+    /// Write bytes to the contract's memory, starting at ptr
+    fn write_memory(&self, ptr: u32, bytes: &[u8]) -> Result<(), Trap> {
+        self.memory()?
+            .set(ptr, bytes)
+            .map_err(|_| Trap::new(TrapKind::MemoryAccessOutOfBounds))
+    }
+
+    /// Get the state key of the wide mapping entry whose key is the len bytes
+    /// of the contract's memory starting at ptr
+    fn wide_mapping_key(&self, index: u32, ptr: u32, len: u32) -> Result<u64, Trap> {
+        if len as usize > MAX_MAPPING_KEY_SIZE {
+            return Err(ContractError::KeyTooLong(len).into());
+        }
+        let key = self.read_memory(ptr, len as usize)?;
+        Ok(get_wide_mapping_key(index, &key, self.contract))
+    }
+
+    /// Emit the len bytes of the contract's memory starting at ptr as an
+    /// event under topic
+    fn emit_event(&mut self, topic: u64, ptr: u32, len: u32) -> Result<(), Trap> {
+        if self.readonly {
+            return Err(ContractError::ReadonlyViolation.into());
+        }
+        if len as usize > MAX_EVENT_SIZE {
+            return Err(ContractError::EventTooLarge(len).into());
+        }
+        if self.call_events >= MAX_EVENTS_PER_CALL {
+            return Err(ContractError::TooManyEvents.into());
         }
+        let data = self.read_memory(ptr, len as usize)?;
+        self.events.push(ContractEvent { topic, data });
+        self.call_events += 1;
+        Ok(())
+    }
+
+    /// Write the SHA3-256 digest of the data_len bytes of the contract's
+    /// memory starting at data_ptr to the 32 bytes starting at out_ptr
+    fn sha3(&self, data_ptr: u32, data_len: u32, out_ptr: u32) -> Result<(), Trap> {
+        if data_len as usize > MAX_SHA3_INPUT_SIZE {
+            return Err(ContractError::HashInputTooLarge(data_len).into());
+        }
+        let data = self.read_memory(data_ptr, data_len as usize)?;
+        self.write_memory(out_ptr, &sha3_256(&data))
+    }
+
+    /// Read count return values from the contract's memory, starting at ptr
+    fn return_tuple(&mut self, ptr: u32, count: u32) -> Result<(), Trap> {
+        let bytes = self.read_memory(ptr, count as usize * RETURN_VALUE_SIZE)?;
+        let values = bytes
+            .chunks(RETURN_VALUE_SIZE)
+            .map(decode_return_value)
+            .collect::<Result<Vec<_>, _>>()?;
+        self.returned = Some(values);
+        Ok(())
     }
 
     fn set(&mut self, index: u64, value: ContractValue) -> Result<(), ContractError> {
+        if self.readonly {
+            return Err(ContractError::ReadonlyViolation);
+        }
         self.root = self.state.set(self.root, index, value)?;
+        self.reads.remove(&index);
+        if let Some(access) = &mut self.access {
+            access.writes.insert(index);
+        }
         Ok(())
     }
 
@@ -174,73 +561,191 @@ impl<'a, M: ContractStateStorage> ContractState<'a, M> {
         Ok(())
     }
 
+    fn set_u128(&mut self, index: u32, value: u128) -> Result<(), ContractError> {
+        let idx = self.get_key(index);
+        self.set(idx, ContractValue::U128(value))?;
+        Ok(())
+    }
+
     fn set_mapping(&mut self, index: u32, key: u64, value: u64) -> Result<(), ContractError> {
         let idx = self.get_mapping_key(index, key);
+        self.set_mapping_entry(idx, value)
+    }
+
+    /// Set the mapping entry stored at idx, counting new entries against the
+    /// state limit
+    fn set_mapping_entry(&mut self, idx: u64, value: u64) -> Result<(), ContractError> {
+        if let Some(limit) = self.state_limit {
+            self.record_read(idx);
+            if self.state.get_opt(self.root, idx)?.is_none() {
+                // Setting a new key, count it against the limit
+                let count_key = get_mapping_count_key(self.contract);
+                self.record_read(count_key);
+                let count = self
+                    .state
+                    .get_or(self.root, count_key, &ContractValue::U64(0))?
+                    .as_u64()
+                    .unwrap_or(0);
+                if count >= limit {
+                    return Err(ContractError::StateLimitExceeded);
+                }
+                self.set(count_key, ContractValue::U64(count + 1))?;
+            }
+        }
         self.set(idx, ContractValue::U64(value))?;
         Ok(())
     }
 }
 
+/// Read the state index passed as the first argument of a host call
+///
+/// Wasm passes indices as i32, so a negative index traps rather than wrapping
+/// around to a large u32 index
+fn index_arg(args: &RuntimeArgs) -> Result<u32, Trap> {
+    let index: i32 = args.nth(0);
+    if index < 0 {
+        return Err(ContractError::NegativeIndex(index).into());
+    }
+    Ok(index as u32)
+}
+
+/// Decode a value passed to `__ofc__return_tuple`, laid out as described by
+/// RETURN_VALUE_SIZE
+fn decode_return_value(entry: &[u8]) -> Result<ContractValue, ContractError> {
+    let mut tag = [0; 4];
+    tag.copy_from_slice(&entry[0..4]);
+    let mut value = [0; 8];
+    value.copy_from_slice(&entry[8..16]);
+    let value = u64::from_le_bytes(value);
+    match u32::from_le_bytes(tag) {
+        0 => Ok(ContractValue::U32(value as u32)),
+        1 => Ok(ContractValue::U64(value)),
+        2 => Ok(ContractValue::F32(f32::from_bits(value as u32))),
+        3 => Ok(ContractValue::F64(f64::from_bits(value))),
+        _ => Err(ContractError::TypeMismatch),
+    }
+}
+
 impl<'a, M: ContractStateStorage> Externals for ContractState<'a, M> {
     fn invoke_index(
         &mut self,
         index: usize,
         args: RuntimeArgs,
     ) -> Result<Option<RuntimeValue>, Trap> {
+        if let Some(trace) = self.trace.as_mut() {
+            trace.push(TraceEntry {
+                index,
+                args: args
+                    .as_ref()
+                    .iter()
+                    .map(|arg| ContractValue::from(*arg))
+                    .collect(),
+            });
+        }
+
         match index {
             GET_INT32_INDEX => {
-                let index: u32 = args.nth(0);
+                let index = index_arg(&args)?;
                 self.get_u32(index)
             }
             GET_INT64_INDEX => {
-                let index: u32 = args.nth(0);
+                let index = index_arg(&args)?;
                 self.get_u64(index)
             }
             GET_FLOAT32_INDEX => {
-                let index: u32 = args.nth(0);
+                let index = index_arg(&args)?;
                 self.get_f32(index)
             }
             GET_FLOAT64_INDEX => {
-                let index: u32 = args.nth(0);
+                let index = index_arg(&args)?;
                 self.get_f64(index)
             }
             GET_MAPPING_INDEX => {
-                let index: u32 = args.nth(0);
+                let index = index_arg(&args)?;
                 let key: u64 = args.nth(1);
                 self.get_mapping(index, key)
             }
 
             SET_INT32_INDEX => {
-                let index: u32 = args.nth(0);
+                let index = index_arg(&args)?;
                 let value: u32 = args.nth(1);
                 self.set_u32(index, value)?;
                 Ok(None)
             }
             SET_INT64_INDEX => {
-                let index: u32 = args.nth(0);
+                let index = index_arg(&args)?;
                 let value: u64 = args.nth(1);
                 self.set_u64(index, value)?;
                 Ok(None)
             }
             SET_FLOAT32_INDEX => {
-                let index: u32 = args.nth(0);
+                let index = index_arg(&args)?;
                 let value: F32 = args.nth(1);
                 self.set_f32(index, value.to_float())?;
                 Ok(None)
             }
             SET_FLOAT64_INDEX => {
-                let index: u32 = args.nth(0);
+                let index = index_arg(&args)?;
                 let value: F64 = args.nth(1);
                 self.set_f64(index, value.to_float())?;
                 Ok(None)
             }
             SET_MAPPING_INDEX => {
-                let index: u32 = args.nth(0);
+                let index = index_arg(&args)?;
                 let key: u64 = args.nth(1);
                 let value: u64 = args.nth(2);
                 self.set_mapping(index, key, value)?;
                 Ok(None)
             }
+            GET_MAPPING_WIDE_INDEX => {
+                let index = index_arg(&args)?;
+                let key = self.wide_mapping_key(index, args.nth(1), args.nth(2))?;
+                self.read_mapping(key)
+            }
+            SET_MAPPING_WIDE_INDEX => {
+                let index = index_arg(&args)?;
+                let key = self.wide_mapping_key(index, args.nth(1), args.nth(2))?;
+                let value: u64 = args.nth(3);
+                self.set_mapping_entry(key, value)?;
+                Ok(None)
+            }
+
+            GET_U128_INDEX => {
+                let index = index_arg(&args)?;
+                let ptr: u32 = args.nth(1);
+                self.get_u128(index, ptr)?;
+                Ok(None)
+            }
+            SET_U128_INDEX => {
+                let index = index_arg(&args)?;
+                let lo: u64 = args.nth(1);
+                let hi: u64 = args.nth(2);
+                self.set_u128(index, u128::from(hi) << 64 | u128::from(lo))?;
+                Ok(None)
+            }
+
+            EMIT_EVENT_INDEX => {
+                let topic: u64 = args.nth(0);
+                let ptr: u32 = args.nth(1);
+                let len: u32 = args.nth(2);
+                self.emit_event(topic, ptr, len)?;
+                Ok(None)
+            }
+
+            SHA3_INDEX => {
+                let data_ptr: u32 = args.nth(0);
+                let data_len: u32 = args.nth(1);
+                let out_ptr: u32 = args.nth(2);
+                self.sha3(data_ptr, data_len, out_ptr)?;
+                Ok(None)
+            }
+
+            RETURN_TUPLE_INDEX => {
+                let ptr: u32 = args.nth(0);
+                let count: u32 = args.nth(1);
+                self.return_tuple(ptr, count)?;
+                Ok(None)
+            }
 
             _ => Err(Trap::new(TrapKind::Unreachable)),
         }
@@ -257,6 +762,7 @@ mod tests {
 
     use wasmi::{ImportsBuilder, Module, ModuleInstance, ModuleRef};
 
+    use dag::contract::wasm_error::TrapCode;
     use dag::storage::map::OOB;
     use dag::storage::mpt::temp_map::MPTTempMap;
 
@@ -280,6 +786,477 @@ mod tests {
             .assert_no_start()
     }
 
+    /// Module exporting sum_u32(index, count), which sums the u32 at index,
+    /// read count times
+    #[rustfmt::skip]
+    const SUM_U32_WASM: [u8; 99] = [
+        // Header
+        0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00,
+        // Type section: (i32) -> i32, (i32, i32) -> i32
+        0x01, 0x0c, 0x02, 0x60, 0x01, 0x7f, 0x01, 0x7f, 0x60, 0x02, 0x7f, 0x7f, 0x01, 0x7f,
+        // Import section: env.api_get_u32
+        0x02, 0x13, 0x01, 0x03, b'e', b'n', b'v', 0x0b, b'a', b'p', b'i', b'_', b'g', b'e', b't',
+        b'_', b'u', b'3', b'2', 0x00, 0x00,
+        // Function section
+        0x03, 0x02, 0x01, 0x01,
+        // Export section: sum_u32
+        0x07, 0x0b, 0x01, 0x07, b's', b'u', b'm', b'_', b'u', b'3', b'2', 0x00, 0x01,
+        // Code section: loop until count is zero, adding api_get_u32(index) to
+        // the sum each time
+        0x0a, 0x25, 0x01, 0x23, 0x01, 0x01, 0x7f, 0x02, 0x40, 0x03, 0x40, 0x20, 0x01, 0x45, 0x0d,
+        0x01, 0x20, 0x02, 0x20, 0x00, 0x10, 0x00, 0x6a, 0x21, 0x02, 0x20, 0x01, 0x41, 0x01, 0x6b,
+        0x21, 0x01, 0x0c, 0x00, 0x0b, 0x0b, 0x20, 0x02, 0x0b,
+    ];
+
+    /// Module exporting set_value(value) and get_value(), which set and get
+    /// the wide mapping entry at index 0 with a 32 byte key
+    #[rustfmt::skip]
+    const WIDE_MAPPING_WASM: [u8; 209] = [
+        // Header
+        0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00,
+        // Type section: (i32, i32, i32) -> i64, (i32, i32, i32, i64) -> (), (i64) -> (),
+        // () -> i64
+        0x01, 0x17, 0x04, 0x60, 0x03, 0x7f, 0x7f, 0x7f, 0x01, 0x7e, 0x60, 0x04, 0x7f, 0x7f, 0x7f,
+        0x7e, 0x00, 0x60, 0x01, 0x7e, 0x00, 0x60, 0x00, 0x01, 0x7e,
+        // Import section: env.__ofc__get_mapping_wide and env.__ofc__set_mapping_wide
+        0x02, 0x3d, 0x02, 0x03, b'e', b'n', b'v', 0x17, b'_', b'_', b'o', b'f', b'c', b'_', b'_',
+        b'g', b'e', b't', b'_', b'm', b'a', b'p', b'p', b'i', b'n', b'g', b'_', b'w', b'i', b'd',
+        b'e', 0x00, 0x00, 0x03, b'e', b'n', b'v', 0x17, b'_', b'_', b'o', b'f', b'c', b'_', b'_',
+        b's', b'e', b't', b'_', b'm', b'a', b'p', b'p', b'i', b'n', b'g', b'_', b'w', b'i', b'd',
+        b'e', 0x00, 0x01,
+        // Function section
+        0x03, 0x03, 0x02, 0x02, 0x03,
+        // Memory section: one page
+        0x05, 0x03, 0x01, 0x00, 0x01,
+        // Export section: set_value, get_value and memory
+        0x07, 0x22, 0x03, 0x09, b's', b'e', b't', b'_', b'v', b'a', b'l', b'u', b'e', 0x00, 0x02,
+        0x09, b'g', b'e', b't', b'_', b'v', b'a', b'l', b'u', b'e', 0x00, 0x03, 0x06, b'm', b'e',
+        b'm', b'o', b'r', b'y', 0x02, 0x00,
+        // Code section: set and get the mapping at index 0, with the 32 byte key at 0
+        0x0a, 0x19, 0x02, 0x0c, 0x00, 0x41, 0x00, 0x41, 0x00, 0x41, 0x20, 0x20, 0x00, 0x10, 0x01,
+        0x0b, 0x0a, 0x00, 0x41, 0x00, 0x41, 0x00, 0x41, 0x20, 0x10, 0x00, 0x0b,
+        // Data section: the key, bytes 1 to 32
+        0x0b, 0x26, 0x01, 0x00, 0x41, 0x00, 0x0b, 0x20, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07,
+        0x08, 0x09, 0x0a, 0x0b, 0x0c, 0x0d, 0x0e, 0x0f, 0x10, 0x11, 0x12, 0x13, 0x14, 0x15, 0x16,
+        0x17, 0x18, 0x19, 0x1a, 0x1b, 0x1c, 0x1d, 0x1e, 0x1f, 0x20,
+    ];
+
+    /// Module exporting set_and_trap(value), which sets the u32 at index 0 to
+    /// value, then traps
+    #[rustfmt::skip]
+    const SET_AND_TRAP_WASM: [u8; 76] = [
+        // Header
+        0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00,
+        // Type section: (i32, i32) -> (), (i32) -> ()
+        0x01, 0x0a, 0x02, 0x60, 0x02, 0x7f, 0x7f, 0x00, 0x60, 0x01, 0x7f, 0x00,
+        // Import section: env.api_set_u32
+        0x02, 0x13, 0x01, 0x03, b'e', b'n', b'v', 0x0b, b'a', b'p', b'i', b'_', b's', b'e', b't',
+        b'_', b'u', b'3', b'2', 0x00, 0x00,
+        // Function section
+        0x03, 0x02, 0x01, 0x01,
+        // Export section: set_and_trap
+        0x07, 0x10, 0x01, 0x0c, b's', b'e', b't', b'_', b'a', b'n', b'd', b'_', b't', b'r', b'a',
+        b'p', 0x00, 0x01,
+        // Code section: set the u32 at index 0, then trap
+        0x0a, 0x0b, 0x01, 0x09, 0x00, 0x41, 0x00, 0x20, 0x00, 0x10, 0x00, 0x00, 0x0b,
+    ];
+
+    /// Module exporting hash(), which writes the SHA3-256 digest of "abc" to
+    /// the 32 bytes at 32
+    #[rustfmt::skip]
+    const SHA3_WASM: [u8; 94] = [
+        // Header
+        0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00,
+        // Type section: (i32, i32, i32) -> (), () -> ()
+        0x01, 0x0a, 0x02, 0x60, 0x03, 0x7f, 0x7f, 0x7f, 0x00, 0x60, 0x00, 0x00,
+        // Import section: env.__ofc__sha3
+        0x02, 0x13, 0x01, 0x03, b'e', b'n', b'v', 0x0b, b'_', b'_', b'o', b'f', b'c', b'_', b'_',
+        b's', b'h', b'a', b'3', 0x00, 0x00,
+        // Function section
+        0x03, 0x02, 0x01, 0x01,
+        // Memory section: one page
+        0x05, 0x03, 0x01, 0x00, 0x01,
+        // Export section: hash and memory
+        0x07, 0x11, 0x02, 0x04, b'h', b'a', b's', b'h', 0x00, 0x01, 0x06, b'm', b'e', b'm', b'o',
+        b'r', b'y', 0x02, 0x00,
+        // Code section: hash the 3 bytes at 0 into the 32 bytes at 32
+        0x0a, 0x0c, 0x01, 0x0a, 0x00, 0x41, 0x00, 0x41, 0x03, 0x41, 0x20, 0x10, 0x00, 0x0b,
+        // Data section: the input, "abc"
+        0x0b, 0x09, 0x01, 0x00, 0x41, 0x00, 0x0b, 0x03, b'a', b'b', b'c',
+    ];
+
+    #[test]
+    fn test_sha3() {
+        let module = Module::from_buffer(&SHA3_WASM[..]).expect("Could not parse module");
+        let mut imports = ImportsBuilder::new();
+        imports.push_resolver("env", &Resolver);
+        let module = ModuleInstance::new(&module, &imports)
+            .expect("Failed to instantiate module")
+            .assert_no_start();
+
+        let mpt = MerklePatriciaTree::new(HashMap::new());
+        let root = mpt.default_root();
+        let mut temp_state = ContractState::new(
+            &module,
+            MerklePatriciaTree::new(MPTTempMap::new(&mpt)),
+            0,
+            root,
+        );
+        assert_eq!(temp_state.exec("hash", &[]).unwrap(), None);
+
+        // SHA3-256("abc") from the FIPS 202 examples
+        let expected = [
+            0x3a, 0x98, 0x5d, 0xa7, 0x4f, 0xe2, 0x25, 0xb2, 0x04, 0x5c, 0x17, 0x2d, 0x6b, 0xd3,
+            0x90, 0xbd, 0x85, 0x5f, 0x08, 0x6e, 0x3e, 0x9d, 0x52, 0x5b, 0x46, 0xbf, 0xe2, 0x45,
+            0x11, 0x43, 0x15, 0x32,
+        ];
+        assert_eq!(temp_state.read_memory(32, 32).unwrap(), expected.to_vec());
+        assert_eq!(sha3_256(b"abc"), expected);
+
+        // Inputs past the limit are rejected, even within the memory
+        let too_large = MAX_SHA3_INPUT_SIZE as u32 + 1;
+        let err = temp_state.sha3(0, too_large, 32).unwrap_err();
+        assert_eq!(
+            ContractError::from(err),
+            ContractError::HashInputTooLarge(too_large)
+        );
+    }
+
+    #[test]
+    fn test_events_per_call() {
+        let module = Module::from_buffer(&SHA3_WASM[..]).expect("Could not parse module");
+        let mut imports = ImportsBuilder::new();
+        imports.push_resolver("env", &Resolver);
+        let module = ModuleInstance::new(&module, &imports)
+            .expect("Failed to instantiate module")
+            .assert_no_start();
+
+        let mpt = MerklePatriciaTree::new(HashMap::new());
+        let root = mpt.default_root();
+        let mut temp_state = ContractState::new(
+            &module,
+            MerklePatriciaTree::new(MPTTempMap::new(&mpt)),
+            0,
+            root,
+        );
+        for _ in 0..MAX_EVENTS_PER_CALL {
+            assert!(temp_state.emit_event(1, 0, 3).is_ok());
+        }
+        let err = temp_state.emit_event(1, 0, 3).unwrap_err();
+        assert_eq!(ContractError::from(err), ContractError::TooManyEvents);
+
+        // The limit applies to each call separately
+        assert_eq!(temp_state.exec("hash", &[]).unwrap(), None);
+        assert!(temp_state.emit_event(1, 0, 3).is_ok());
+        assert_eq!(temp_state.take_events().len(), MAX_EVENTS_PER_CALL + 1);
+    }
+
+    #[test]
+    fn test_reverted_call_releases_nodes() {
+        let module = Module::from_buffer(&SET_AND_TRAP_WASM[..]).expect("Could not parse module");
+        let mut imports = ImportsBuilder::new();
+        imports.push_resolver("env", &Resolver);
+        let module = ModuleInstance::new(&module, &imports)
+            .expect("Failed to instantiate module")
+            .assert_no_start();
+
+        let contract_id = 0;
+        let mut mpt = MerklePatriciaTree::new(HashMap::new());
+        let default_root = mpt.default_root();
+        let root = mpt
+            .set(default_root, get_key(1, contract_id), ContractValue::U32(7))
+            .unwrap();
+
+        let mut temp_state = ContractState::new(
+            &module,
+            MerklePatriciaTree::new(MPTTempMap::new(&mpt)),
+            contract_id,
+            root,
+        );
+        let err = temp_state
+            .exec("set_and_trap", &[RuntimeValue::I32(5)])
+            .unwrap_err();
+        assert_eq!(
+            ContractError::from(err),
+            ContractError::Trap(TrapCode::Unreachable)
+        );
+
+        // The value set before the trap is reverted, and no nodes are left
+        // staged for it
+        assert_eq!(temp_state.staged_node_count(), 0);
+        assert_eq!(temp_state.root, root);
+        assert!(temp_state.get_u32(0).is_err());
+        assert_eq!(temp_state.get_u32(1).unwrap(), Some(RuntimeValue::I32(7)));
+    }
+
+    #[test]
+    fn test_wide_mapping() {
+        let module = Module::from_buffer(&WIDE_MAPPING_WASM[..]).expect("Could not parse module");
+        let mut imports = ImportsBuilder::new();
+        imports.push_resolver("env", &Resolver);
+        let module = ModuleInstance::new(&module, &imports)
+            .expect("Failed to instantiate module")
+            .assert_no_start();
+
+        let mut mpt = MerklePatriciaTree::new(HashMap::new());
+        let root = mpt.default_root();
+        let contract_id = 3;
+        let updates = {
+            let mut temp_state = ContractState::new(
+                &module,
+                MerklePatriciaTree::new(MPTTempMap::new(&mpt)),
+                contract_id,
+                root,
+            );
+            assert!(temp_state
+                .exec("set_value", &[RuntimeValue::I64(42)])
+                .is_ok());
+            assert_eq!(
+                Some(RuntimeValue::I64(42)),
+                temp_state.exec("get_value", &[]).unwrap()
+            );
+
+            // Keys longer than 32 bytes are rejected
+            assert!(temp_state.wide_mapping_key(0, 0, 33).is_err());
+            temp_state.updates().unwrap()
+        };
+        let root = updates.get_root_hash();
+        mpt.commit_set(updates).unwrap();
+
+        // The value is stored under the hash of the full key
+        let key: Vec<u8> = (1..=32).collect();
+        assert_eq!(
+            mpt.get(root, get_wide_mapping_key(0, &key, contract_id)),
+            Ok(OOB::Borrowed(&ContractValue::U64(42)))
+        );
+        assert!(mpt
+            .get(root, get_wide_mapping_key(0, &key[..8], contract_id))
+            .is_err());
+    }
+
+    /// Module exporting set_value(lo, hi), which sets the u128 at index 0
+    /// from its limbs, and get_lo() and get_hi(), which read it back
+    #[rustfmt::skip]
+    const U128_WASM: [u8; 173] = [
+        // Header
+        0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00,
+        // Type section: (i32, i32) -> (), (i32, i64, i64) -> (), (i64, i64) -> (), () -> i64
+        0x01, 0x15, 0x04, 0x60, 0x02, 0x7f, 0x7f, 0x00, 0x60, 0x03, 0x7f, 0x7e, 0x7e, 0x00, 0x60,
+        0x02, 0x7e, 0x7e, 0x00, 0x60, 0x00, 0x01, 0x7e,
+        // Import section: env.__ofc__get_u128 and env.__ofc__set_u128
+        0x02, 0x2d, 0x02, 0x03, b'e', b'n', b'v', 0x0f, b'_', b'_', b'o', b'f', b'c', b'_', b'_',
+        b'g', b'e', b't', b'_', b'u', b'1', b'2', b'8', 0x00, 0x00, 0x03, b'e', b'n', b'v', 0x0f,
+        b'_', b'_', b'o', b'f', b'c', b'_', b'_', b's', b'e', b't', b'_', b'u', b'1', b'2', b'8',
+        0x00, 0x01,
+        // Function section
+        0x03, 0x04, 0x03, 0x02, 0x03, 0x03,
+        // Memory section: one page
+        0x05, 0x03, 0x01, 0x00, 0x01,
+        // Export section: set_value, get_lo, get_hi and memory
+        0x07, 0x28, 0x04, 0x09, b's', b'e', b't', b'_', b'v', b'a', b'l', b'u', b'e', 0x00, 0x02,
+        0x06, b'g', b'e', b't', b'_', b'l', b'o', 0x00, 0x03, 0x06, b'g', b'e', b't', b'_', b'h',
+        b'i', 0x00, 0x04, 0x06, b'm', b'e', b'm', b'o', b'r', b'y', 0x02, 0x00,
+        // Code section: set the u128 at index 0, or read it to 0 and load one of its limbs
+        0x0a, 0x28, 0x03, 0x0a, 0x00, 0x41, 0x00, 0x20, 0x00, 0x20, 0x01, 0x10, 0x01, 0x0b, 0x0d,
+        0x00, 0x41, 0x00, 0x41, 0x00, 0x10, 0x00, 0x41, 0x00, 0x29, 0x03, 0x00, 0x0b, 0x0d, 0x00,
+        0x41, 0x00, 0x41, 0x00, 0x10, 0x00, 0x41, 0x00, 0x29, 0x03, 0x08, 0x0b,
+    ];
+
+    #[test]
+    fn test_u128() {
+        let module = Module::from_buffer(&U128_WASM[..]).expect("Could not parse module");
+        let mut imports = ImportsBuilder::new();
+        imports.push_resolver("env", &Resolver);
+        let module = ModuleInstance::new(&module, &imports)
+            .expect("Failed to instantiate module")
+            .assert_no_start();
+
+        let value = u128::from(u64::max_value()) * 3 + 5;
+        let (lo, hi) = (value as u64, (value >> 64) as u64);
+
+        let mut mpt = MerklePatriciaTree::new(HashMap::new());
+        let root = mpt.default_root();
+        let updates = {
+            let mut temp_state = ContractState::new(
+                &module,
+                MerklePatriciaTree::new(MPTTempMap::new(&mpt)),
+                0,
+                root,
+            );
+            assert!(temp_state
+                .exec(
+                    "set_value",
+                    &[RuntimeValue::I64(lo as i64), RuntimeValue::I64(hi as i64)]
+                )
+                .is_ok());
+            assert_eq!(
+                Some(RuntimeValue::I64(lo as i64)),
+                temp_state.exec("get_lo", &[]).unwrap()
+            );
+            assert_eq!(
+                Some(RuntimeValue::I64(hi as i64)),
+                temp_state.exec("get_hi", &[]).unwrap()
+            );
+            temp_state.updates().unwrap()
+        };
+        let root = updates.get_root_hash();
+        mpt.commit_set(updates).unwrap();
+        assert_eq!(
+            mpt.get(root, get_key(0, 0)),
+            Ok(OOB::Borrowed(&ContractValue::U128(value)))
+        );
+
+        // Values above u64::MAX survive serialization intact
+        let json = serde_json::to_string(&ContractValue::U128(value)).unwrap();
+        assert_eq!(
+            serde_json::from_str::<ContractValue>(&json).unwrap(),
+            ContractValue::U128(value)
+        );
+    }
+
+    #[test]
+    fn test_repeated_reads_cached() {
+        let module = Module::from_buffer(&SUM_U32_WASM[..]).expect("Could not parse module");
+        let mut imports = ImportsBuilder::new();
+        imports.push_resolver("env", &Resolver);
+        let module = ModuleInstance::new(&module, &imports)
+            .expect("Failed to instantiate module")
+            .assert_no_start();
+
+        let mpt = MerklePatriciaTree::new(HashMap::new());
+        let mut temp_state = ContractState::new(
+            &module,
+            MerklePatriciaTree::new(MPTTempMap::new(&mpt)),
+            0,
+            mpt.default_root(),
+        );
+        temp_state.set_u32(0, 3).unwrap();
+
+        // The tree is only searched by the first read
+        assert_eq!(
+            Some(RuntimeValue::I32(30)),
+            temp_state
+                .exec("sum_u32", &[RuntimeValue::I32(0), RuntimeValue::I32(10)])
+                .unwrap()
+        );
+        assert_eq!(temp_state.traversal_count(), 1);
+
+        // Setting a value invalidates the cached read
+        let key = get_key(0, 0);
+        assert_eq!(temp_state.read(key).unwrap(), Some(ContractValue::U32(3)));
+        temp_state.set_u32(0, 4).unwrap();
+        assert_eq!(temp_state.read(key).unwrap(), Some(ContractValue::U32(4)));
+        assert_eq!(temp_state.traversal_count(), 2);
+
+        // Each call starts with an empty cache
+        assert_eq!(
+            Some(RuntimeValue::I32(40)),
+            temp_state
+                .exec("sum_u32", &[RuntimeValue::I32(0), RuntimeValue::I32(10)])
+                .unwrap()
+        );
+        assert_eq!(temp_state.traversal_count(), 3);
+    }
+
+    #[test]
+    fn test_default_reads() {
+        let module = load_api_test_module_instance();
+        let mpt = MerklePatriciaTree::new(HashMap::new());
+        let mut temp_state = ContractState::new(
+            &module,
+            MerklePatriciaTree::new(MPTTempMap::new(&mpt)),
+            0,
+            mpt.default_root(),
+        );
+        assert!(temp_state
+            .exec("set_u32", &[RuntimeValue::I32(0), RuntimeValue::I32(10)])
+            .is_ok());
+
+        // Unset values trap by default
+        assert!(temp_state.exec("get_u32", &[RuntimeValue::I32(1)]).is_err());
+
+        temp_state.set_default_reads(true);
+        // Present
+        assert_eq!(
+            Some(RuntimeValue::I32(10)),
+            temp_state.exec("get_u32", &[RuntimeValue::I32(0)]).unwrap()
+        );
+        // Absent, read as zero
+        assert_eq!(
+            Some(RuntimeValue::I32(0)),
+            temp_state.exec("get_u32", &[RuntimeValue::I32(1)]).unwrap()
+        );
+        assert_eq!(
+            Some(RuntimeValue::I64(0)),
+            temp_state
+                .exec("get_mapping", &[RuntimeValue::I32(4), RuntimeValue::I64(7)])
+                .unwrap()
+        );
+        // Type mismatch still traps
+        assert!(temp_state.exec("get_u64", &[RuntimeValue::I32(0)]).is_err());
+    }
+
+    #[test]
+    fn test_persist_resume() {
+        let module = load_api_test_module_instance();
+        let mut mpt = MerklePatriciaTree::new(HashMap::new());
+        let root = mpt.default_root();
+        let contract_id = 0;
+
+        let mut temp_state = ContractState::new(
+            &module,
+            MerklePatriciaTree::new(MPTTempMap::new(&mpt)),
+            contract_id,
+            root,
+        );
+        temp_state.set_state_limit(Some(1));
+        assert!(temp_state
+            .exec("set_u32", &[RuntimeValue::I32(0), RuntimeValue::I32(10)])
+            .is_ok());
+        let persisted = temp_state.persist();
+
+        // The tree is no longer borrowed, so can be modified while the state
+        // is persisted
+        let other_root = mpt
+            .set(root, get_key(0, contract_id + 1), ContractValue::U32(1))
+            .unwrap();
+        assert_ne!(other_root, persisted.get_root());
+
+        let mut temp_state = ContractState::resume(&module, &mpt, persisted);
+        assert_eq!(
+            Some(RuntimeValue::I32(10)),
+            temp_state.exec("get_u32", &[RuntimeValue::I32(0)]).unwrap()
+        );
+        assert!(temp_state
+            .exec("set_u64", &[RuntimeValue::I32(1), RuntimeValue::I64(20)])
+            .is_ok());
+        // The state limit is kept across the pause
+        let set_mapping = |state: &mut ContractState<_>, key: i64| {
+            state.exec(
+                "set_mapping",
+                &[
+                    RuntimeValue::I32(2),
+                    RuntimeValue::I64(key),
+                    RuntimeValue::I64(1),
+                ],
+            )
+        };
+        assert!(set_mapping(&mut temp_state, 0).is_ok());
+        assert!(set_mapping(&mut temp_state, 1).is_err());
+
+        let updates = temp_state.updates().unwrap();
+        let root = updates.get_root_hash();
+        assert!(mpt.commit_set(updates).is_ok());
+        assert_eq!(
+            mpt.get(root, get_key(0, contract_id)),
+            Ok(OOB::Borrowed(&ContractValue::U32(10)))
+        );
+        assert_eq!(
+            mpt.get(root, get_key(1, contract_id)),
+            Ok(OOB::Borrowed(&ContractValue::U64(20)))
+        );
+    }
+
     #[test]
     fn test_api_resolver_u32() {
         let module = load_api_test_module_instance();
@@ -595,4 +1572,71 @@ mod tests {
                 .is_err());
         };
     }
+
+    #[test]
+    fn test_mapping_state_limit() {
+        let module = load_api_test_module_instance();
+        let mpt = MerklePatriciaTree::new(HashMap::new());
+        let root = mpt.default_root();
+        let contract_id = 0;
+
+        let mut temp_state = ContractState::new(
+            &module,
+            MerklePatriciaTree::new(MPTTempMap::new(&mpt)),
+            contract_id,
+            root,
+        );
+        temp_state.set_state_limit(Some(2));
+
+        let set_mapping = |state: &mut ContractState<_>, key: i64| {
+            state.exec(
+                "set_mapping",
+                &[
+                    RuntimeValue::I32(0),
+                    RuntimeValue::I64(key),
+                    RuntimeValue::I64(1),
+                ],
+            )
+        };
+
+        // Set entries up to the limit
+        assert!(set_mapping(&mut temp_state, 0).is_ok());
+        assert!(set_mapping(&mut temp_state, 1).is_ok());
+        // Overwriting an existing entry does not count against the limit
+        assert!(set_mapping(&mut temp_state, 0).is_ok());
+
+        // One entry past the limit
+        let err = set_mapping(&mut temp_state, 2).unwrap_err();
+        assert_eq!(ContractError::from(err), ContractError::StateLimitExceeded);
+    }
+
+    #[test]
+    fn test_negative_index() {
+        let module = load_api_test_module_instance();
+        let mpt = MerklePatriciaTree::new(HashMap::new());
+        let mut temp_state = ContractState::new(
+            &module,
+            MerklePatriciaTree::new(MPTTempMap::new(&mpt)),
+            0,
+            mpt.default_root(),
+        );
+        temp_state.set_default_reads(true);
+
+        let calls: &[(&str, &[RuntimeValue])] = &[
+            ("get_u32", &[RuntimeValue::I32(-1)]),
+            ("set_u32", &[RuntimeValue::I32(-1), RuntimeValue::I32(1)]),
+            (
+                "get_mapping",
+                &[RuntimeValue::I32(-2), RuntimeValue::I64(0)],
+            ),
+        ];
+        for (func_name, args) in calls {
+            match ContractError::from(temp_state.exec(func_name, args).unwrap_err()) {
+                ContractError::NegativeIndex(index) => {
+                    assert_eq!(RuntimeValue::I32(index), args[0])
+                }
+                err => panic!("Expected NegativeIndex, got {:?}", err),
+            }
+        }
+    }
 }
@@ -0,0 +1,158 @@
+use std::fmt;
+
+use wasmi::{Error as InterpreterError, HostError, Trap, TrapKind};
+
+use super::error::ContractError;
+
+/// Kind of trap raised while executing a contract
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum TrapCode {
+    Unreachable,
+    MemoryAccessOutOfBounds,
+    TableAccessOutOfBounds,
+    ElemUninitialized,
+    DivisionByZero,
+    InvalidConversionToInt,
+    StackOverflow,
+    UnexpectedSignature,
+    /// A host function failed with an error other than a ContractError
+    Host,
+}
+
+impl fmt::Display for TrapCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TrapCode::Unreachable => write!(f, "unreachable executed"),
+            TrapCode::MemoryAccessOutOfBounds => write!(f, "memory access out of bounds"),
+            TrapCode::TableAccessOutOfBounds => write!(f, "table access out of bounds"),
+            TrapCode::ElemUninitialized => write!(f, "uninitialized table element"),
+            TrapCode::DivisionByZero => write!(f, "division by zero"),
+            TrapCode::InvalidConversionToInt => write!(f, "invalid conversion to integer"),
+            TrapCode::StackOverflow => write!(f, "stack overflow"),
+            TrapCode::UnexpectedSignature => write!(f, "unexpected function signature"),
+            TrapCode::Host => write!(f, "host function failed"),
+        }
+    }
+}
+
+/// Stage of loading or running a contract the interpreter failed at
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum InterpreterErrorKind {
+    Validation,
+    Instantiation,
+    Function,
+    Table,
+    Memory,
+    Global,
+    Value,
+    /// A host error other than a ContractError
+    Host,
+}
+
+impl fmt::Display for InterpreterErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            InterpreterErrorKind::Validation => write!(f, "Validation"),
+            InterpreterErrorKind::Instantiation => write!(f, "Instantiation"),
+            InterpreterErrorKind::Function => write!(f, "Function"),
+            InterpreterErrorKind::Table => write!(f, "Table"),
+            InterpreterErrorKind::Memory => write!(f, "Memory"),
+            InterpreterErrorKind::Global => write!(f, "Global"),
+            InterpreterErrorKind::Value => write!(f, "Value"),
+            InterpreterErrorKind::Host => write!(f, "Host"),
+        }
+    }
+}
+
+/// Unwrap a ContractError raised by a host function
+///
+/// Host functions fail with ContractErrors, which the interpreter wraps in
+/// traps. Returns None for any other host error
+fn host_contract_error(error: &dyn HostError) -> Option<ContractError> {
+    error.downcast_ref::<ContractError>().cloned()
+}
+
+impl From<Trap> for ContractError {
+    fn from(trap: Trap) -> Self {
+        let code = match trap.kind() {
+            TrapKind::Unreachable => TrapCode::Unreachable,
+            TrapKind::MemoryAccessOutOfBounds => TrapCode::MemoryAccessOutOfBounds,
+            TrapKind::TableAccessOutOfBounds => TrapCode::TableAccessOutOfBounds,
+            TrapKind::ElemUninitialized => TrapCode::ElemUninitialized,
+            TrapKind::DivisionByZero => TrapCode::DivisionByZero,
+            TrapKind::InvalidConversionToInt => TrapCode::InvalidConversionToInt,
+            TrapKind::StackOverflow => TrapCode::StackOverflow,
+            TrapKind::UnexpectedSignature => TrapCode::UnexpectedSignature,
+            TrapKind::Host(error) => match host_contract_error(&**error) {
+                Some(error) => return error,
+                None => TrapCode::Host,
+            },
+        };
+        ContractError::Trap(code)
+    }
+}
+
+impl From<InterpreterError> for ContractError {
+    fn from(error: InterpreterError) -> Self {
+        let (kind, message) = match error {
+            InterpreterError::Validation(message) => (InterpreterErrorKind::Validation, message),
+            InterpreterError::Instantiation(message) => {
+                (InterpreterErrorKind::Instantiation, message)
+            }
+            InterpreterError::Function(message) => (InterpreterErrorKind::Function, message),
+            InterpreterError::Table(message) => (InterpreterErrorKind::Table, message),
+            InterpreterError::Memory(message) => (InterpreterErrorKind::Memory, message),
+            InterpreterError::Global(message) => (InterpreterErrorKind::Global, message),
+            InterpreterError::Value(message) => (InterpreterErrorKind::Value, message),
+            InterpreterError::Trap(trap) => return trap.into(),
+            InterpreterError::Host(error) => match host_contract_error(&*error) {
+                Some(error) => return error,
+                None => (InterpreterErrorKind::Host, error.to_string()),
+            },
+        };
+        ContractError::Interpreter(kind, message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_trap_conversion() {
+        assert_eq!(
+            ContractError::from(Trap::new(TrapKind::MemoryAccessOutOfBounds)),
+            ContractError::Trap(TrapCode::MemoryAccessOutOfBounds)
+        );
+        assert_eq!(
+            ContractError::from(InterpreterError::Trap(Trap::new(TrapKind::DivisionByZero))),
+            ContractError::Trap(TrapCode::DivisionByZero)
+        );
+
+        // Contract errors raised by host functions are unwrapped
+        let trap: Trap = ContractError::ReadonlyViolation.into();
+        assert_eq!(ContractError::from(trap), ContractError::ReadonlyViolation);
+    }
+
+    #[test]
+    fn test_interpreter_error_conversion() {
+        let message = "Module doesn't have export get_u128".to_string();
+        let error = ContractError::from(InterpreterError::Function(message.clone()));
+        assert_eq!(
+            error,
+            ContractError::Interpreter(InterpreterErrorKind::Function, message)
+        );
+        assert_ne!(
+            error,
+            ContractError::from(Trap::new(TrapKind::MemoryAccessOutOfBounds))
+        );
+        assert!(error.to_string().contains("get_u128"));
+
+        assert_eq!(
+            ContractError::from(InterpreterError::Host(Box::new(
+                ContractError::StateLimitExceeded
+            ))),
+            ContractError::StateLimitExceeded
+        );
+    }
+}
@@ -0,0 +1,79 @@
+use std::error::Error;
+use std::fmt;
+
+use dag::contract::error::ContractError;
+use dag::storage::map::MapError;
+use util::types::RejectionReason;
+use util::HashId;
+
+#[derive(Debug, PartialEq)]
+pub enum BlockDAGError {
+    /// The hash is not a confirmed milestone
+    MilestoneNotFound(u64),
+    /// The older milestone is not an ancestor of the newer milestone
+    NotAncestor { older: u64, newer: u64 },
+    /// No merge base was found within the maximum search depth
+    MergeBaseTooDeep(usize),
+    /// The transaction, specified by hash, was not found
+    TransactionNotFound(u64),
+    /// The contract state roots of the parents could not be merged
+    MergeConflict,
+    /// The merged state root, specified by hash, is not stored, so contracts
+    /// cannot be executed against it until a transaction merging the
+    /// parents is committed
+    MergeNotStored(u64),
+    /// No nonce is a valid proof of work for the parents
+    NoValidNonce,
+    /// Creating or executing a contract failed
+    Contract(ContractError),
+    /// A transaction of a block, specified by hash, was rejected
+    Rejected(u64, RejectionReason),
+    /// Reading or writing storage failed
+    Storage(MapError),
+}
+
+impl fmt::Display for BlockDAGError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BlockDAGError::MilestoneNotFound(hash) => {
+                write!(f, "{} is not a confirmed milestone", HashId(*hash))
+            }
+            BlockDAGError::NotAncestor { older, newer } => write!(
+                f,
+                "Milestone {} is not an ancestor of milestone {}",
+                HashId(*older),
+                HashId(*newer)
+            ),
+            BlockDAGError::MergeBaseTooDeep(depth) => {
+                write!(f, "No merge base found within depth {}", depth)
+            }
+            BlockDAGError::TransactionNotFound(hash) => {
+                write!(f, "Transaction not found: {}", HashId(*hash))
+            }
+            BlockDAGError::MergeConflict => write!(f, "Failed to merge parent roots"),
+            BlockDAGError::MergeNotStored(root) => {
+                write!(f, "Merged root {} is not stored", HashId(*root))
+            }
+            BlockDAGError::NoValidNonce => write!(f, "No valid nonce for the parents"),
+            BlockDAGError::Contract(err) => write!(f, "Contract failed: {}", err),
+            BlockDAGError::Rejected(hash, reason) => {
+                write!(f, "Transaction {} rejected: {}", HashId(*hash), reason)
+            }
+            BlockDAGError::Storage(err) => write!(f, "Storage failed: {}", err),
+        }
+    }
+}
+
+impl Error for BlockDAGError {}
+
+impl From<ContractError> for BlockDAGError {
+    fn from(err: ContractError) -> Self {
+        BlockDAGError::Contract(err)
+    }
+}
+
+impl From<MapError> for BlockDAGError {
+    fn from(err: MapError) -> Self {
+        BlockDAGError::Storage(err)
+    }
+}
@@ -0,0 +1,33 @@
+use dag::transaction::Transaction;
+
+/// Decides the fee paid by each committed transaction, and what happens to it
+///
+/// The policy is called as transactions are committed to the dag, so it can
+/// credit the fees to a system contract's balance, or simply count the total
+/// burned
+pub trait FeePolicy: Send + Sync {
+    /// Get the fee paid by a transaction
+    ///
+    /// Transactions do not carry an explicit fee, so the policy prices them.
+    /// Charges nothing by default
+    fn fee(&self, _transaction: &Transaction) -> u64 {
+        0
+    }
+
+    /// Called when a transaction is committed to the dag, with its fee
+    fn collect(&mut self, transaction: &Transaction, fee: u64);
+
+    /// Called when a committed transaction is rolled back, with the fee
+    /// collected for it
+    fn refund(&mut self, transaction: &Transaction, fee: u64);
+}
+
+/// Fee policy which charges and collects nothing
+#[derive(Default, Debug)]
+pub struct NoFees;
+
+impl FeePolicy for NoFees {
+    fn collect(&mut self, _transaction: &Transaction, _fee: u64) {}
+
+    fn refund(&mut self, _transaction: &Transaction, _fee: u64) {}
+}
@@ -0,0 +1,40 @@
+use std::hash::Hasher;
+
+use dag::contract::source::ContractSource;
+
+use security::hash::hasher::Sha3Hasher;
+
+/// Configuration of the initial state of a BlockDAG
+///
+/// Contracts added to the configuration are instantiated when the dag is
+/// created, so they are available without being deployed in a transaction.
+#[derive(Clone, Default, Debug)]
+pub struct GenesisConfig {
+    contracts: Vec<ContractSource>,
+}
+
+impl GenesisConfig {
+    pub fn new() -> Self {
+        GenesisConfig::default()
+    }
+
+    /// Add a contract to be instantiated at genesis
+    ///
+    /// The contract id is derived from the order contracts are added in, see
+    /// [genesis_contract_id](fn.genesis_contract_id.html)
+    pub fn with_contract(mut self, src: ContractSource) -> Self {
+        self.contracts.push(src);
+        self
+    }
+
+    pub fn get_contracts(&self) -> &[ContractSource] {
+        &self.contracts
+    }
+}
+
+/// Get the id of the contract preloaded at index in the genesis configuration
+pub fn genesis_contract_id(index: usize) -> u64 {
+    let mut hasher = Sha3Hasher::new();
+    hasher.write_u64(index as u64);
+    hasher.finish()
+}
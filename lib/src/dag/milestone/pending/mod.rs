@@ -6,5 +6,5 @@ mod tracker;
 
 pub use self::error::{MilestoneError, _MilestoneErrorTag};
 pub use self::pending_milestone::PendingMilestone;
-pub use self::signing::MilestoneSignature;
+pub use self::signing::{AggregateMilestoneSignature, MilestoneSignature};
 pub use self::tracker::MilestoneTracker;
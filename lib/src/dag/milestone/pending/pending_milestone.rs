@@ -48,7 +48,9 @@ impl PendingMilestone {
                 PendingMilestone::Signing(signing) => signing.next(&event),
                 PendingMilestone::Approved(_) => match event {
                     StateUpdate::Chain(_) => Err(_MilestoneErrorTag::StaleChain(_self)),
-                    StateUpdate::Sign(_) => Err(_MilestoneErrorTag::StaleSignature(_self)),
+                    StateUpdate::Sign(_) | StateUpdate::SignAggregate(_) => {
+                        Err(_MilestoneErrorTag::StaleSignature(_self))
+                    }
                 },
             };
             match out {
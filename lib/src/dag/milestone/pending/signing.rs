@@ -1,3 +1,7 @@
+use std::collections::HashSet;
+
+use security::keys::{verify_validator_signature, ValidatorKey};
+
 /// Temporary structure pending proper cryptography
 #[derive(Clone)]
 pub struct MilestonePubKey {}
@@ -27,3 +31,122 @@ impl MilestoneSignature {
         self.contract
     }
 }
+
+/// Signatures of a quorum of validators over a milestone, on behalf of
+/// several contracts
+///
+/// Each validator signs the milestone hash and the contracts covered with its
+/// ed25519 key. The signatures are checked together against the validator
+/// keys before the pending milestone changes, so one aggregate confirms
+/// every contract it covers at once
+#[derive(Clone)]
+pub struct AggregateMilestoneSignature {
+    milestone: u64,
+    contracts: Vec<u64>,
+    /// Public key of each signer, and its signature of the signing bytes
+    signatures: Vec<(Vec<u8>, Vec<u8>)>,
+}
+
+impl AggregateMilestoneSignature {
+    /// Create an aggregate without any signatures
+    pub fn new(milestone: u64, contracts: Vec<u64>) -> Self {
+        AggregateMilestoneSignature {
+            milestone,
+            contracts,
+            signatures: Vec::new(),
+        }
+    }
+
+    pub fn get_milestone(&self) -> u64 {
+        self.milestone
+    }
+    pub fn get_contracts(&self) -> &[u64] {
+        &self.contracts
+    }
+
+    /// Get the bytes each validator signs, the milestone hash followed by the
+    /// contracts covered
+    pub fn signing_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(8 * (self.contracts.len() + 1));
+        bytes.extend_from_slice(&self.milestone.to_be_bytes());
+        for contract in &self.contracts {
+            bytes.extend_from_slice(&contract.to_be_bytes());
+        }
+        bytes
+    }
+
+    /// Sign the aggregate with a validator key
+    pub fn sign(&mut self, key: &ValidatorKey) {
+        let signature = key.sign(&self.signing_bytes());
+        self.signatures.push((key.public_key(), signature));
+    }
+
+    /// Add a signature made by an external signer over the signing bytes
+    pub fn add_signature(&mut self, public_key: Vec<u8>, signature: Vec<u8>) {
+        self.signatures.push((public_key, signature));
+    }
+
+    /// Check that more than two thirds of the validators signed the
+    /// aggregate
+    ///
+    /// Fails if any signer is not a validator, signs more than once, or its
+    /// signature does not verify
+    pub fn verify(&self, validators: &HashSet<Vec<u8>>) -> bool {
+        let bytes = self.signing_bytes();
+        let mut signers = HashSet::with_capacity(self.signatures.len());
+        for (public_key, signature) in &self.signatures {
+            if !validators.contains(public_key)
+                || !signers.insert(public_key)
+                || !verify_validator_signature(public_key, &bytes, signature)
+            {
+                return false;
+            }
+        }
+        signers.len() * 3 > validators.len() * 2
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn validator_keys(count: usize) -> Vec<ValidatorKey> {
+        (0..count)
+            .map(|_| ValidatorKey::from_pkcs8(&ValidatorKey::generate_pkcs8().unwrap()).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_verify_aggregate() {
+        let keys = validator_keys(4);
+        let validators: HashSet<Vec<u8>> = keys.iter().map(ValidatorKey::public_key).collect();
+
+        // Two thirds of the validators are not a quorum
+        let mut aggregate = AggregateMilestoneSignature::new(1, vec![2, 3]);
+        aggregate.sign(&keys[0]);
+        aggregate.sign(&keys[1]);
+        assert!(!aggregate.verify(&validators));
+        aggregate.sign(&keys[2]);
+        assert!(aggregate.verify(&validators));
+
+        // Without validators, no aggregate is valid
+        assert!(!aggregate.verify(&HashSet::new()));
+
+        // Signers must be distinct validators
+        let mut repeated = AggregateMilestoneSignature::new(1, vec![2, 3]);
+        for _ in 0..3 {
+            repeated.sign(&keys[0]);
+        }
+        assert!(!repeated.verify(&validators));
+        let mut outsider = aggregate.clone();
+        outsider.sign(&validator_keys(1)[0]);
+        assert!(!outsider.verify(&validators));
+
+        // The signatures cover the milestone and its contracts
+        let mut tampered = AggregateMilestoneSignature::new(1, vec![2, 3, 4]);
+        for (public_key, signature) in aggregate.signatures.iter().cloned() {
+            tampered.add_signature(public_key, signature);
+        }
+        assert!(!tampered.verify(&validators));
+    }
+}
@@ -198,9 +198,9 @@ impl PendingMilestoneState for PendingState {
                     Err(err) => Err(err.convert(PendingMilestone::Pending(self))),
                 }
             }
-            StateUpdate::Sign(_) => Err(_MilestoneErrorTag::StaleSignature(
-                PendingMilestone::Pending(self),
-            )),
+            StateUpdate::Sign(_) | StateUpdate::SignAggregate(_) => Err(
+                _MilestoneErrorTag::StaleSignature(PendingMilestone::Pending(self)),
+            ),
         }
     }
 }
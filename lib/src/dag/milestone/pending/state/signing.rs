@@ -2,7 +2,9 @@ use std::collections::HashMap;
 
 use dag::{
     milestone::{
-        pending::{MilestoneSignature, PendingMilestone, _MilestoneErrorTag},
+        pending::{
+            AggregateMilestoneSignature, MilestoneSignature, PendingMilestone, _MilestoneErrorTag,
+        },
         Milestone,
     },
     transaction::Transaction,
@@ -45,6 +47,21 @@ impl SigningState {
     fn sign(&mut self, signature: &MilestoneSignature) {
         self.signatures.insert(signature.get_contract(), true);
     }
+
+    fn sign_aggregate(&mut self, signature: &AggregateMilestoneSignature) {
+        for contract in signature.get_contracts() {
+            self.signatures.insert(*contract, true);
+        }
+    }
+
+    /// Approve the milestone once every contract has signed it
+    fn try_approve(self) -> PendingMilestone {
+        if self.signatures.values().all(|value| *value) {
+            PendingMilestone::Approved(Milestone::new(self.previous_milestone, self.transaction))
+        } else {
+            PendingMilestone::Signing(self)
+        }
+    }
 }
 
 impl PendingMilestoneState for SigningState {
@@ -55,14 +72,11 @@ impl PendingMilestoneState for SigningState {
             )),
             StateUpdate::Sign(signature) => {
                 self.sign(signature);
-                if self.signatures.values().all(|value| *value) {
-                    Ok(PendingMilestone::Approved(Milestone::new(
-                        self.previous_milestone,
-                        self.transaction,
-                    )))
-                } else {
-                    Ok(PendingMilestone::Signing(self))
-                }
+                Ok(self.try_approve())
+            }
+            StateUpdate::SignAggregate(signature) => {
+                self.sign_aggregate(signature);
+                Ok(self.try_approve())
             }
         }
     }
@@ -78,7 +92,6 @@ impl SigningState {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use dag::milestone::pending::MilestoneSignature;
     use dag::transaction::data::TransactionData;
 
     fn create_transaction(branch: u64, trunk: u64, contract: u64) -> Transaction {
@@ -152,4 +165,44 @@ mod tests {
             Err(err) => panic!("Unexpected error while signing: {:?}", err),
         }
     }
+
+    #[test]
+    fn test_aggregate_signature() {
+        let previous_milestone_transaction = create_transaction(0, 0, 0);
+        let hash = previous_milestone_transaction.get_hash();
+        let trunk_transaction = create_transaction(0, hash, 1);
+        let transaction = create_transaction(0, trunk_transaction.get_hash(), 2);
+
+        let pending = SigningState::new(
+            transaction.clone(),
+            hash,
+            vec![
+                (
+                    trunk_transaction.get_hash(),
+                    trunk_transaction.get_contract(),
+                ),
+                (transaction.get_hash(), transaction.get_contract()),
+            ],
+        );
+
+        // An aggregate missing a contract does not approve the milestone
+        match pending.clone().next(&StateUpdate::SignAggregate(
+            AggregateMilestoneSignature::new(hash, vec![1]),
+        )) {
+            Ok(PendingMilestone::Signing(_)) => {}
+            Ok(_) => panic!("Pending milestone unexpectedly transitioned away from signing state"),
+            Err(err) => panic!("Unexpected error while signing: {:?}", err),
+        }
+
+        // An aggregate over every contract approves it in one step
+        match pending.next(&StateUpdate::SignAggregate(
+            AggregateMilestoneSignature::new(hash, vec![1, 2]),
+        )) {
+            Ok(PendingMilestone::Approved(milestone)) => {
+                assert_eq!(milestone.get_hash(), transaction.get_hash());
+            }
+            Ok(_) => panic!("Pending milestone did not transition to approved state"),
+            Err(err) => panic!("Unexpected error while signing: {:?}", err),
+        }
+    }
 }
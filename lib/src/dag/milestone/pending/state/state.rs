@@ -1,5 +1,7 @@
 use dag::{
-    milestone::pending::{MilestoneSignature, PendingMilestone, _MilestoneErrorTag},
+    milestone::pending::{
+        AggregateMilestoneSignature, MilestoneSignature, PendingMilestone, _MilestoneErrorTag,
+    },
     transaction::Transaction,
 };
 
@@ -7,6 +9,7 @@ use dag::{
 pub enum StateUpdate {
     Chain(Transaction),
     Sign(MilestoneSignature),
+    SignAggregate(AggregateMilestoneSignature),
 }
 
 pub trait PendingMilestoneState {
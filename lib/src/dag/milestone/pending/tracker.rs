@@ -1,19 +1,21 @@
-use std::collections::HashMap;
-
 use dag::{
     milestone::{
-        pending::{state::StateUpdate, MilestoneError, MilestoneSignature},
+        pending::{
+            state::StateUpdate, AggregateMilestoneSignature, MilestoneError, MilestoneSignature,
+        },
         Milestone,
     },
+    storage::keyed::HashKeyMap,
     transaction::Transaction,
 };
 
 use super::PendingMilestone;
 
 ///
+#[derive(Clone)]
 pub struct MilestoneTracker {
     milestones: Vec<Milestone>,
-    pending_milestones: HashMap<u64, PendingMilestone>,
+    pending_milestones: HashKeyMap<u64, PendingMilestone>,
 }
 
 impl MilestoneTracker {
@@ -21,7 +23,7 @@ impl MilestoneTracker {
     pub fn new(milestone: Milestone) -> Self {
         MilestoneTracker {
             milestones: vec![milestone],
-            pending_milestones: HashMap::new(),
+            pending_milestones: HashKeyMap::default(),
         }
     }
 
@@ -53,11 +55,33 @@ impl MilestoneTracker {
         signature: MilestoneSignature,
     ) -> Result<Option<Milestone>, MilestoneError> {
         let hash = signature.get_milestone();
+        self.add_signature(hash, StateUpdate::Sign(signature))
+    }
+
+    /// Add an aggregate signature to a pending milestone
+    pub fn sign_aggregate(
+        &mut self,
+        signature: AggregateMilestoneSignature,
+    ) -> Result<Option<Milestone>, MilestoneError> {
+        let hash = signature.get_milestone();
+        self.add_signature(hash, StateUpdate::SignAggregate(signature))
+    }
+
+    /// Pass a signature event to the pending milestone specified by hash, and
+    /// confirm the milestone if it is approved
+    fn add_signature(
+        &mut self,
+        hash: u64,
+        event: StateUpdate,
+    ) -> Result<Option<Milestone>, MilestoneError> {
         if let Some(pending_milestone) = self.pending_milestones.get_mut(&hash) {
-            if let Err(err) = pending_milestone.next(StateUpdate::Sign(signature)) {
+            if let Err(err) = pending_milestone.next(event) {
                 Err(err)
             } else if let PendingMilestone::Approved(milestone) = pending_milestone {
-                Ok(Some(milestone.clone()))
+                let milestone = milestone.clone();
+                self.pending_milestones.remove(&hash);
+                self.milestones.push(milestone.clone());
+                Ok(Some(milestone))
             } else {
                 Ok(None)
             }
@@ -66,8 +90,48 @@ impl MilestoneTracker {
         }
     }
 
+    /// Get a confirmed milestone by the hash of its transaction
+    pub fn get_milestone(&self, hash: u64) -> Option<&Milestone> {
+        self.milestones
+            .iter()
+            .rev()
+            .find(|milestone| milestone.get_hash() == hash)
+    }
+
+    /// Get the position of a confirmed milestone in confirmation order, the
+    /// initial milestone being at position 0
+    pub fn get_milestone_index(&self, hash: u64) -> Option<usize> {
+        self.milestones
+            .iter()
+            .rposition(|milestone| milestone.get_hash() == hash)
+    }
+
     /// Get the most recent milestone
     pub fn get_head_milestone(&self) -> &Milestone {
         &self.milestones[self.milestones.len() - 1]
     }
+
+    /// Get the newest milestone followed by at least depth milestones
+    ///
+    /// Returns None until depth milestones follow the initial milestone
+    pub fn get_finalized_milestone(&self, depth: usize) -> Option<&Milestone> {
+        let count = self.milestones.len();
+        if count > depth {
+            Some(&self.milestones[count - 1 - depth])
+        } else {
+            None
+        }
+    }
+
+    /// Get the timestamps of up to count of the most recent milestones,
+    /// oldest first
+    ///
+    /// The initial milestone is not issued at a real time, so is excluded
+    pub fn recent_timestamps(&self, count: usize) -> Vec<u64> {
+        let confirmed = &self.milestones[1..];
+        confirmed[confirmed.len().saturating_sub(count)..]
+            .iter()
+            .map(Milestone::get_timestamp)
+            .collect()
+    }
 }
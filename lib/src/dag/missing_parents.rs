@@ -0,0 +1,46 @@
+use std::collections::{HashSet, VecDeque};
+
+/// Parents which were not found locally, remembered up to a capacity
+///
+/// Recording a new parent past the capacity forgets the oldest one, so the
+/// set stays bounded without locking out parents recorded later
+#[derive(Clone, Debug)]
+pub struct MissingParents {
+    capacity: usize,
+    order: VecDeque<u64>,
+    hashes: HashSet<u64>,
+}
+
+impl MissingParents {
+    pub fn new(capacity: usize) -> Self {
+        MissingParents {
+            capacity,
+            order: VecDeque::with_capacity(capacity),
+            hashes: HashSet::with_capacity(capacity),
+        }
+    }
+
+    /// Remember a missing parent, forgetting the oldest if full
+    pub fn insert(&mut self, hash: u64) {
+        if self.capacity == 0 || !self.hashes.insert(hash) {
+            return;
+        }
+        self.order.push_back(hash);
+        if self.order.len() > self.capacity {
+            if let Some(oldest) = self.order.pop_front() {
+                self.hashes.remove(&oldest);
+            }
+        }
+    }
+
+    /// Forget a parent, once it has been received
+    pub fn remove(&mut self, hash: &u64) {
+        if self.hashes.remove(hash) {
+            self.order.retain(|h| h != hash);
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &u64> {
+        self.order.iter()
+    }
+}
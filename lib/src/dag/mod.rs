@@ -1,7 +1,15 @@
 pub mod blockdag;
+pub mod chain;
 pub mod contract;
+pub mod error;
+pub mod fee;
+pub mod genesis;
 pub mod milestone;
 pub mod storage;
 pub mod transaction;
 
 mod incomplete_chain;
+mod missing_parents;
+
+#[cfg(test)]
+mod consensus_vectors;
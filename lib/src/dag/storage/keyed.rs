@@ -0,0 +1,161 @@
+use std::collections::HashMap;
+use std::hash::{BuildHasher, Hasher};
+
+use rand::{thread_rng, Rng};
+
+/// Odd multiplier with well distributed bits, from FxHash
+const MULTIPLIER: u64 = 0x517c_c1b7_2722_0a95;
+
+/// HashMap keyed by hashes, using a KeyedState hasher
+pub type HashKeyMap<K, V> = HashMap<K, V, KeyedState>;
+
+/// Multiply as 128 bit integers, and fold the high half of the product into
+/// the low half
+fn folded_multiply(a: u64, b: u64) -> u64 {
+    let product = u128::from(a) * u128::from(b);
+    (product as u64) ^ ((product >> 64) as u64)
+}
+
+/// Fast, non-cryptographic hasher mixing in a secret seed
+///
+/// Keys of the internal maps are already uniformly distributed hashes, so a
+/// single folded multiply per word is enough to spread them. The seed is
+/// chosen per map and mixed in before the multiply, whose fold makes which
+/// keys collide depend non-linearly on the seed. This makes collisions hard
+/// to grind for without knowing the seed, but the hasher is not a
+/// cryptographic MAC.
+pub struct KeyedHasher {
+    seed: u64,
+    hash: u64,
+}
+
+impl Hasher for KeyedHasher {
+    fn finish(&self) -> u64 {
+        self.hash
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.write_u64(u64::from(*byte));
+        }
+    }
+
+    fn write_u8(&mut self, i: u8) {
+        self.write_u64(u64::from(i));
+    }
+
+    fn write_u32(&mut self, i: u32) {
+        self.write_u64(u64::from(i));
+    }
+
+    fn write_u64(&mut self, i: u64) {
+        self.hash = folded_multiply(self.hash ^ i ^ self.seed, MULTIPLIER);
+    }
+
+    fn write_usize(&mut self, i: usize) {
+        self.write_u64(i as u64);
+    }
+}
+
+/// Builds KeyedHashers sharing a seed chosen when the state is created
+#[derive(Clone, Debug)]
+pub struct KeyedState {
+    seed: u64,
+}
+
+impl KeyedState {
+    pub fn with_seed(seed: u64) -> Self {
+        KeyedState { seed }
+    }
+}
+
+impl Default for KeyedState {
+    fn default() -> Self {
+        KeyedState::with_seed(thread_rng().gen())
+    }
+}
+
+impl BuildHasher for KeyedState {
+    type Hasher = KeyedHasher;
+
+    fn build_hasher(&self) -> KeyedHasher {
+        KeyedHasher {
+            seed: self.seed,
+            hash: 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate test;
+    use self::test::Bencher;
+    use super::*;
+
+    use std::hash::Hash;
+
+    fn hash_with<T: Hash>(state: &KeyedState, value: T) -> u64 {
+        let mut hasher = state.build_hasher();
+        value.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    #[test]
+    fn test_keyed_hasher() {
+        let state = KeyedState::with_seed(1);
+        assert_eq!(hash_with(&state, 42u64), hash_with(&state.clone(), 42u64));
+        assert_ne!(hash_with(&state, 42u64), hash_with(&state, 43u64));
+        assert_ne!(
+            hash_with(&state, 42u64),
+            hash_with(&KeyedState::with_seed(2), 42u64)
+        );
+
+        let mut map: HashKeyMap<u64, u64> = HashKeyMap::default();
+        for key in 0..1000 {
+            map.insert(key, key * 2);
+        }
+        assert!((0..1000).all(|key| map.get(&key) == Some(&(key * 2))));
+    }
+
+    #[test]
+    fn test_collisions_depend_on_seed() {
+        // Find keys sharing the low bits of their hash under one seed
+        let (seed_a, seed_b) = (KeyedState::with_seed(1), KeyedState::with_seed(2));
+        let bucket = |state: &KeyedState, key: u64| hash_with(state, key) & 0xff;
+        let target = bucket(&seed_a, 0);
+        let colliding: Vec<u64> = (0..)
+            .filter(|key| bucket(&seed_a, *key) == target)
+            .take(8)
+            .collect();
+
+        // The same keys are spread out under another seed
+        let target = bucket(&seed_b, colliding[0]);
+        assert!(colliding.iter().any(|key| bucket(&seed_b, *key) != target));
+    }
+
+    fn insert_lookup<S: BuildHasher + Default>(keys: &[u64]) -> usize {
+        let mut map: HashMap<u64, u64, S> = HashMap::default();
+        for key in keys {
+            map.insert(*key, *key);
+        }
+        keys.iter().filter(|key| map.contains_key(key)).count()
+    }
+
+    fn random_keys() -> Vec<u64> {
+        let mut rng = thread_rng();
+        (0..10_000).map(|_| rng.gen()).collect()
+    }
+
+    #[bench]
+    fn bench_insert_lookup_sip(b: &mut Bencher) {
+        use std::collections::hash_map::RandomState;
+        let keys = random_keys();
+        b.iter(|| insert_lookup::<RandomState>(&keys));
+    }
+
+    #[bench]
+    fn bench_insert_lookup_keyed(b: &mut Bencher) {
+        let keys = random_keys();
+        b.iter(|| insert_lookup::<KeyedState>(&keys));
+    }
+}
@@ -1,12 +1,12 @@
 use std::cmp::Eq;
 use std::collections::HashMap;
-use std::hash::Hash;
+use std::hash::{BuildHasher, Hash};
 
 use std::error::Error;
 use std::fmt;
 use std::ops::Deref;
 
-#[derive(PartialEq, Debug)]
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
 pub enum MapError {
     NotFound,
     LookupError,
@@ -30,7 +30,7 @@ pub trait Map<K: Eq + Hash, V> {
     fn set(&mut self, k: K, v: V) -> MapResult<()>;
 }
 
-impl<K: Eq + Hash, V> Map<K, V> for HashMap<K, V> {
+impl<K: Eq + Hash, V, S: BuildHasher> Map<K, V> for HashMap<K, V, S> {
     fn get<'a>(&'a self, k: &K) -> MapResult<OOB<'a, V>> {
         HashMap::get(self, k).map_or(Err(MapError::NotFound), |v| Ok(OOB::Borrowed(v)))
     }
@@ -40,6 +40,28 @@ impl<K: Eq + Hash, V> Map<K, V> for HashMap<K, V> {
     }
 }
 
+/// A map which can list the keys it contains
+pub trait ListableMap<K: Eq + Hash, V>: Map<K, V> {
+    fn get_keys(&self) -> Vec<K>;
+}
+
+impl<K: Eq + Hash + Clone, V, S: BuildHasher> ListableMap<K, V> for HashMap<K, V, S> {
+    fn get_keys(&self) -> Vec<K> {
+        self.keys().cloned().collect()
+    }
+}
+
+/// A map which values can be removed from
+pub trait RemovableMap<K: Eq + Hash, V>: Map<K, V> {
+    fn remove(&mut self, k: &K) -> MapResult<()>;
+}
+
+impl<K: Eq + Hash, V, S: BuildHasher> RemovableMap<K, V> for HashMap<K, V, S> {
+    fn remove(&mut self, k: &K) -> MapResult<()> {
+        HashMap::remove(self, k).map_or(Err(MapError::NotFound), |_| Ok(()))
+    }
+}
+
 #[derive(PartialEq, Hash, Debug)]
 pub enum OOB<'a, T> {
     Owned(T),
@@ -1,2 +1,3 @@
+pub mod keyed;
 pub mod map;
 pub mod mpt;
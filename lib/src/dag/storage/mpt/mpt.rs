@@ -2,9 +2,10 @@ use std::fmt::Debug;
 use std::hash::Hash;
 use std::marker::PhantomData;
 
-use dag::storage::map::{Map, MapError, OOB};
+use dag::storage::keyed::HashKeyMap;
+use dag::storage::map::{Map, MapError, RemovableMap, OOB};
 
-use super::node::{Node, PointerNode};
+use super::node::{Node, PointerNode, POINTER_COUNT};
 use super::node_updates::NodeUpdates;
 
 pub trait MPTStorageMap<T: MPTData> = Map<u64, Node<T>>;
@@ -12,6 +13,8 @@ pub trait MPTData = Hash + PartialEq + Clone + Debug;
 
 pub struct MerklePatriciaTree<T: MPTData, M: MPTStorageMap<T>> {
     pub(crate) nodes: M,
+    /// Number of references to each node, from parent nodes and committed roots
+    refs: HashKeyMap<u64, u64>,
     phantom: PhantomData<T>,
 }
 
@@ -27,8 +30,11 @@ impl<T: MPTData, M: MPTStorageMap<T>> MerklePatriciaTree<T, M> {
         let root = Node::BranchNode(PointerNode::default());
         let hash = root.get_hash();
         nodes.set(hash, root);
+        let mut refs = HashKeyMap::default();
+        refs.insert(hash, 1);
         MerklePatriciaTree {
             nodes,
+            refs,
             phantom: PhantomData,
         }
     }
@@ -71,6 +77,23 @@ impl<T: MPTData, M: MPTStorageMap<T>> MerklePatriciaTree<T, M> {
         Err(MapError::LookupError)
     }
 
+    /// Get the value of k, or None if it is not set
+    ///
+    /// Fails with any error other than MapError::NotFound raised looking up
+    /// the value
+    pub fn get_opt<'a>(&'a self, root: u64, k: u64) -> Result<Option<OOB<'a, T>>, MapError> {
+        match self.get(root, k) {
+            Ok(value) => Ok(Some(value)),
+            Err(MapError::NotFound) => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Get the value of k, or default if it is not set
+    pub fn get_or<'a>(&'a self, root: u64, k: u64, default: &'a T) -> Result<OOB<'a, T>, MapError> {
+        Ok(self.get_opt(root, k)?.unwrap_or(OOB::Borrowed(default)))
+    }
+
     pub fn try_set(&self, root: u64, k: u64, v: T) -> NodeUpdates<T> {
         let mut new_nodes = Vec::new();
         let root_node = self.nodes.get(&root).expect("Root node does not exist");
@@ -136,13 +159,119 @@ impl<T: MPTData, M: MPTStorageMap<T>> MerklePatriciaTree<T, M> {
         }
     }
 
+    /// Set every key in kvs, starting from root
+    ///
+    /// Results in the same root as calling try_set for each key in order, but
+    /// each branch on the paths of several keys is cloned once. If a key
+    /// appears more than once, its last value is set
+    pub fn set_many(&self, root: u64, kvs: &[(u64, T)]) -> NodeUpdates<T> {
+        let root_node = self
+            .nodes
+            .get(&root)
+            .expect("Root node does not exist")
+            .borrow()
+            .clone();
+        let kvs: Vec<&(u64, T)> = kvs.iter().collect();
+        let mut new_nodes = Vec::new();
+        let new_root = self.set_many_recurse(root_node, 0, &kvs, &mut new_nodes);
+        NodeUpdates::new(new_root, new_nodes)
+    }
+
+    /// Set kvs below node, which is at depth in the tree
+    ///
+    /// Returns the updated node, after pushing its updated descendants to
+    /// new_nodes
+    fn set_many_recurse(
+        &self,
+        node: Node<T>,
+        depth: u32,
+        kvs: &[&(u64, T)],
+        new_nodes: &mut Vec<Node<T>>,
+    ) -> Node<T> {
+        let mut pointers = match node {
+            Node::BranchNode(pointers) => pointers,
+            Node::LeafNode(_) => PointerNode::default(),
+        };
+        let shift = 60 - 4 * depth;
+        for nibble in 0..POINTER_COUNT {
+            let group: Vec<&(u64, T)> = kvs
+                .iter()
+                .filter(|kv| (kv.0 >> shift) & 0xF == u64::from(nibble))
+                .cloned()
+                .collect();
+            let last = match group.last() {
+                Some(last) => last,
+                None => continue,
+            };
+            let child = if depth == 15 {
+                Node::LeafNode(last.1.clone())
+            } else {
+                let existing = match pointers.get(nibble) {
+                    Some(hash) => self
+                        .nodes
+                        .get(&hash)
+                        .expect("Node does not exist")
+                        .borrow()
+                        .clone(),
+                    None => Node::BranchNode(PointerNode::default()),
+                };
+                self.set_many_recurse(existing, depth + 1, &group, new_nodes)
+            };
+            pointers.set_hash(nibble, child.get_hash());
+            new_nodes.push(child);
+        }
+        Node::BranchNode(pointers)
+    }
+
+    /// Store the nodes in updates
+    ///
+    /// The root of the updates is referenced until it is released with
+    /// [release_root](#method.release_root)
     pub fn commit_set(&mut self, updates: NodeUpdates<T>) -> Result<(), MapError> {
+        let root = updates.get_root_hash();
         for node in updates.into_iter() {
-            self.nodes.set(node.get_hash(), node)?;
+            let hash = node.get_hash();
+            if self.nodes.get(&hash).is_ok() {
+                // The node is already stored, and references its children
+                continue;
+            }
+            if let Node::BranchNode(ref pointers) = node {
+                for child in pointers.iter().filter_map(|ptr| ptr) {
+                    *self.refs.entry(child).or_insert(0) += 1;
+                }
+            }
+            self.refs.entry(hash).or_insert(0);
+            self.nodes.set(hash, node)?;
         }
+        *self.refs.entry(root).or_insert(0) += 1;
         Ok(())
     }
 
+    /// Release a reference to a root committed with
+    /// [commit_set](#method.commit_set)
+    ///
+    /// Nodes are not removed until the tree is pruned
+    pub fn release_root(&mut self, root: u64) -> Result<(), MapError> {
+        match self.refs.get_mut(&root) {
+            Some(count) if *count > 0 => {
+                *count -= 1;
+                Ok(())
+            }
+            _ => Err(MapError::NotFound),
+        }
+    }
+
+    /// Get the number of nodes committed to the tree, which have not been
+    /// pruned
+    pub fn node_count(&self) -> usize {
+        self.refs.len()
+    }
+
+    /// Get the number of references to the node with hash
+    pub fn ref_count(&self, hash: u64) -> u64 {
+        self.refs.get(&hash).cloned().unwrap_or(0)
+    }
+
     pub fn set(&mut self, root: u64, k: u64, v: T) -> Result<u64, MapError> {
         let updates = { self.try_set(root, k, v) };
         let new_root = updates.get_root_hash();
@@ -200,7 +329,9 @@ impl<T: MPTData, M: MPTStorageMap<T>> MerklePatriciaTree<T, M> {
                             let mut res = self.try_merge(a, b, r);
                             if let Some(child_updates) = res {
                                 // Insert child data into new_ptr and new_nodes
-                                new_ptr.set_hash(i as u8, child_updates.get_root_hash());
+                                if !new_ptr.set_hash(i as u8, child_updates.get_root_hash()) {
+                                    return None;
+                                }
                                 new_nodes.extend(child_updates.into_iter());
                             } else {
                                 // The merge is invalid
@@ -214,7 +345,9 @@ impl<T: MPTData, M: MPTStorageMap<T>> MerklePatriciaTree<T, M> {
                         }
                         (Some(child_ptr), None, None) | (None, Some(child_ptr), None) => {
                             // Insert updated node
-                            new_ptr.set_hash(i as u8, child_ptr);
+                            if !new_ptr.set_hash(i as u8, child_ptr) {
+                                return None;
+                            }
                         }
                         (None, _, Some(_)) | (_, None, Some(_)) => {
                             // This is a special invalid merge, because the
@@ -237,6 +370,43 @@ impl<T: MPTData, M: MPTStorageMap<T>> MerklePatriciaTree<T, M> {
     }
 }
 
+impl<T: MPTData, M: MPTStorageMap<T> + RemovableMap<u64, Node<T>>> MerklePatriciaTree<T, M> {
+    /// Remove every node which is no longer referenced by a root or another
+    /// node
+    ///
+    /// Returns the number of nodes removed
+    pub fn prune(&mut self) -> Result<usize, MapError> {
+        let mut unreferenced: Vec<u64> = self
+            .refs
+            .iter()
+            .filter(|(_, count)| **count == 0)
+            .map(|(hash, _)| *hash)
+            .collect();
+        let mut removed = 0;
+        while let Some(hash) = unreferenced.pop() {
+            if self.refs.get(&hash) != Some(&0) {
+                continue;
+            }
+            self.refs.remove(&hash);
+            let children: Vec<u64> = match *self.nodes.get(&hash)? {
+                Node::BranchNode(ref pointers) => pointers.iter().filter_map(|ptr| ptr).collect(),
+                Node::LeafNode(_) => Vec::new(),
+            };
+            self.nodes.remove(&hash)?;
+            removed += 1;
+            for child in children {
+                if let Some(count) = self.refs.get_mut(&child) {
+                    *count -= 1;
+                    if *count == 0 {
+                        unreferenced.push(child);
+                    }
+                }
+            }
+        }
+        Ok(removed)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -254,6 +424,45 @@ mod tests {
         assert_eq!(mpt.get(mpt.default_root(), 0), Err(MapError::NotFound));
     }
 
+    /// Node storage failing to look up every node but the root
+    struct FailingMap {
+        root: u64,
+        nodes: HashMap<u64, Node<u64>>,
+    }
+
+    impl Map<u64, Node<u64>> for FailingMap {
+        fn get<'a>(&'a self, k: &u64) -> Result<OOB<'a, Node<u64>>, MapError> {
+            if *k == self.root {
+                Map::get(&self.nodes, k)
+            } else {
+                Err(MapError::LookupError)
+            }
+        }
+
+        fn set(&mut self, k: u64, v: Node<u64>) -> Result<(), MapError> {
+            self.nodes.set(k, v)
+        }
+    }
+
+    #[test]
+    fn test_mpt_get_opt() {
+        let mut mpt: MerklePatriciaTree<u64, _> = MerklePatriciaTree::new(HashMap::new());
+        let root = mpt.set(mpt.default_root(), 1, 10).unwrap();
+        assert_eq!(mpt.get_opt(root, 1), Ok(Some(OOB::Borrowed(&10))));
+        assert_eq!(mpt.get_opt(root, 2), Ok(None));
+        assert_eq!(mpt.get_or(root, 1, &5), Ok(OOB::Borrowed(&10)));
+        assert_eq!(mpt.get_or(root, 2, &5), Ok(OOB::Borrowed(&5)));
+
+        // Errors other than a missing value are not mistaken for unset values
+        let failing = MerklePatriciaTree::new(FailingMap {
+            root,
+            nodes: mpt.inner_map(),
+        });
+        assert_eq!(failing.get_opt(root, 1), Err(MapError::LookupError));
+        assert_eq!(failing.get_opt(root, 0xF000_0000_0000_0000), Ok(None));
+        assert_eq!(failing.get_or(root, 1, &5), Err(MapError::LookupError));
+    }
+
     #[test]
     fn test_mpt_get_set() {
         let mut mpt: MerklePatriciaTree<u64, _> = MerklePatriciaTree::new(HashMap::new());
@@ -280,6 +489,45 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_mpt_set_many() {
+        let mut mpt: MerklePatriciaTree<u64, _> = MerklePatriciaTree::new(HashMap::new());
+        let root = mpt
+            .set(mpt.default_root(), 0xAB00_0000_0000_0000, 1)
+            .unwrap();
+
+        // Keys sharing their top nibbles, including one already set
+        let kvs: Vec<(u64, u64)> = (0..100)
+            .map(|i| (0xAB00_0000_0000_0000 | (i * 0x0001_0000_0001), i))
+            .collect();
+
+        let mut one_by_one = root;
+        let mut clones = 0;
+        for (k, v) in &kvs {
+            let updates = mpt.try_set(one_by_one, *k, *v);
+            one_by_one = updates.get_root_hash();
+            clones += updates.clone().into_iter().count();
+            mpt.commit_set(updates).unwrap();
+        }
+
+        let updates = mpt.set_many(root, &kvs);
+        assert_eq!(updates.get_root_hash(), one_by_one);
+        assert!(updates.clone().into_iter().count() < clones);
+
+        let batched = updates.get_root_hash();
+        mpt.commit_set(updates).unwrap();
+        for (k, v) in &kvs {
+            assert_eq!(mpt.get(batched, *k), Ok(OOB::Borrowed(v)));
+        }
+
+        // The last value of a repeated key is set
+        let updates = mpt.set_many(batched, &[(5, 1), (5, 2)]);
+        assert_eq!(
+            updates.get_root_hash(),
+            mpt.try_set(batched, 5, 2).get_root_hash()
+        );
+    }
+
     #[test]
     fn test_mpt_merge() {
         let mut mpt: MerklePatriciaTree<u64, _> = MerklePatriciaTree::new(HashMap::new());
@@ -307,4 +555,86 @@ mod tests {
             assert_eq!(mpt.get(new_root, i), Ok(OOB::Borrowed(&i)));
         }
     }
+
+    #[test]
+    fn test_mpt_merge_last_pointer() {
+        let mut mpt: MerklePatriciaTree<u64, _> = MerklePatriciaTree::new(HashMap::new());
+        let root = mpt.default_root();
+
+        // A key added below pointer 0xF, the last one try_merge visits
+        let root_a = mpt.set(root, 0xF000_0000_0000_0001, 1).unwrap();
+        let root_b = mpt.set(root, 0x1000_0000_0000_0002, 2).unwrap();
+        let updates = mpt.try_merge(root_a, root_b, root).unwrap();
+        let merged = updates.get_root_hash();
+        mpt.commit_set(updates).unwrap();
+        assert_eq!(
+            mpt.get(merged, 0xF000_0000_0000_0001),
+            Ok(OOB::Borrowed(&1))
+        );
+        assert_eq!(
+            mpt.get(merged, 0x1000_0000_0000_0002),
+            Ok(OOB::Borrowed(&2))
+        );
+
+        // Keys added below pointer 0xF on both sides, merged recursively
+        let root_a = mpt.set(merged, 0xF100_0000_0000_0003, 3).unwrap();
+        let root_b = mpt.set(merged, 0xF200_0000_0000_0004, 4).unwrap();
+        let updates = mpt.try_merge(root_a, root_b, merged).unwrap();
+        let merged = updates.get_root_hash();
+        mpt.commit_set(updates).unwrap();
+        assert_eq!(
+            mpt.get(merged, 0xF100_0000_0000_0003),
+            Ok(OOB::Borrowed(&3))
+        );
+        assert_eq!(
+            mpt.get(merged, 0xF200_0000_0000_0004),
+            Ok(OOB::Borrowed(&4))
+        );
+    }
+
+    #[test]
+    fn test_mpt_prune_shared_nodes() {
+        let mut mpt: MerklePatriciaTree<u64, _> = MerklePatriciaTree::new(HashMap::new());
+        let mut root = mpt.default_root();
+        for i in 0..8 {
+            root = mpt.set(root, i, i).unwrap();
+        }
+        // Two roots sharing every node except the path to the changed key
+        let root_a = mpt.set(root, 0x1000_0000_0000_0000, 1).unwrap();
+        let root_b = mpt.set(root, 0x2000_0000_0000_0000, 2).unwrap();
+
+        // Release every root except root_b
+        let mut released = vec![mpt.default_root()];
+        let mut root = mpt.default_root();
+        for i in 0..8 {
+            root = mpt.try_set(root, i, i).get_root_hash();
+            released.push(root);
+        }
+        released.push(root_a);
+        for hash in released {
+            mpt.release_root(hash).unwrap();
+        }
+
+        let unique_a = match *mpt.nodes.get(&root_a).unwrap() {
+            Node::BranchNode(ref pointers) => pointers.get(1).unwrap(),
+            _ => panic!("Root is not a branch node"),
+        };
+        assert!(mpt.prune().unwrap() > 0);
+
+        // Nodes only referenced by released roots are removed
+        assert!(!mpt.nodes.contains_key(&root_a));
+        assert!(!mpt.nodes.contains_key(&unique_a));
+        assert_eq!(
+            mpt.get(root_b, 0x2000_0000_0000_0000),
+            Ok(OOB::Borrowed(&2))
+        );
+        for i in 0..8 {
+            assert_eq!(mpt.get(root_b, i), Ok(OOB::Borrowed(&i)));
+        }
+
+        // Everything left is reachable from root_b
+        mpt.release_root(root_b).unwrap();
+        mpt.prune().unwrap();
+        assert!(mpt.nodes.is_empty());
+    }
 }
@@ -9,6 +9,9 @@ use security::hash::hasher::Sha3Hasher;
 
 use super::MPTData;
 
+/// Number of pointers in a branch node, one per nibble value
+pub const POINTER_COUNT: u8 = 16;
+
 #[inline]
 fn get_top_nibble(val: u64) -> u8 {
     ((val & 0xF000_0000_0000_0000) >> 60) as u8
@@ -67,7 +70,11 @@ impl PointerNode {
         self.get(get_top_nibble(k))
     }
 
+    /// Get the pointer at index
+    ///
+    /// Returns None if index is not a nibble value
     pub fn get(&self, index: u8) -> Option<u64> {
+        debug_assert!(index < POINTER_COUNT, "Invalid pointer index {}", index);
         match index {
             0x0 => self.x_0,
             0x1 => self.x_1,
@@ -85,11 +92,15 @@ impl PointerNode {
             0xD => self.x_d,
             0xE => self.x_e,
             0xF => self.x_f,
-            _ => panic!("Invalid Hex Bit?"),
+            _ => None,
         }
     }
 
-    pub fn set_hash(&mut self, k: u8, v: u64) {
+    /// Set the pointer at index k to v
+    ///
+    /// Returns false, leaving the node unchanged, if k is not a nibble value
+    pub fn set_hash(&mut self, k: u8, v: u64) -> bool {
+        debug_assert!(k < POINTER_COUNT, "Invalid pointer index {}", k);
         match k {
             0x0 => self.x_0 = Some(v),
             0x1 => self.x_1 = Some(v),
@@ -107,11 +118,13 @@ impl PointerNode {
             0xD => self.x_d = Some(v),
             0xE => self.x_e = Some(v),
             0xF => self.x_f = Some(v),
-            _ => panic!("Invalid Hex Bit?"),
+            _ => return false,
         }
+        true
     }
 
     pub fn set_from(&mut self, key: u64, v: u64) {
+        // The bottom nibble is always a valid index
         self.set_hash(get_bottom_nibble(key), v);
     }
 
@@ -135,7 +148,7 @@ impl<'a> Iterator for PointerNodeIterator<'a> {
     type Item = Option<u64>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        if self.index == 16 {
+        if self.index == POINTER_COUNT {
             return None;
         }
 
@@ -280,6 +293,27 @@ mod tests {
         assert_eq!(0xF, get_bottom_nibble(0xFFFF_FFFF_FFFF_FFFF));
     }
 
+    #[test]
+    fn test_pointer_indices() {
+        let mut ptr = PointerNode::default();
+        for i in 0..POINTER_COUNT {
+            assert!(ptr.set_hash(i, u64::from(i)));
+        }
+
+        // Enumerating the pointers, as try_merge does, yields every valid
+        // index and stops before 16
+        let indices: Vec<usize> = ptr
+            .iter()
+            .enumerate()
+            .map(|(i, hash)| {
+                assert_eq!(hash, Some(i as u64));
+                i
+            })
+            .collect();
+        assert_eq!(indices.len(), POINTER_COUNT as usize);
+        assert_eq!(indices.last(), Some(&0xF));
+    }
+
     #[test]
     fn test_serialize() {
         let branch_node = Node::BranchNode::<u64>(PointerNode::default());
@@ -1,29 +1,72 @@
-use std::collections::HashMap;
-
+use dag::storage::keyed::HashKeyMap;
 use dag::storage::map::{Map, MapError, MapResult, OOB};
 
 use super::mpt::{MPTData, MPTStorageMap};
 use super::{node::Node, MerklePatriciaTree, NodeUpdates};
 
 /// Temporary map to store updates to a MerklePatriciaTree
+///
+/// Nodes are staged in the map until written out. The map owns its staged
+/// nodes, so dropping it without writing them out releases them
 pub struct MPTTempMap<'a, T: MPTData, M: MPTStorageMap<T>> {
     mpt: &'a MerklePatriciaTree<T, M>,
-    new_nodes: HashMap<u64, Node<T>>,
+    new_nodes: HashKeyMap<u64, Node<T>>,
 }
 
 impl<'a, T: MPTData, M: MPTStorageMap<T>> MPTTempMap<'a, T, M> {
     pub fn new(mpt: &'a MerklePatriciaTree<T, M>) -> Self {
         MPTTempMap {
             mpt,
-            new_nodes: HashMap::new(),
+            new_nodes: HashKeyMap::default(),
+        }
+    }
+
+    /// Create a map from nodes staged by another map over mpt, taken with
+    /// [into_staged](#method.into_staged)
+    pub fn with_staged(mpt: &'a MerklePatriciaTree<T, M>, nodes: HashKeyMap<u64, Node<T>>) -> Self {
+        MPTTempMap {
+            mpt,
+            new_nodes: nodes,
+        }
+    }
+
+    /// Take the staged nodes, releasing the borrow of the tree
+    pub fn into_staged(self) -> HashKeyMap<u64, Node<T>> {
+        self.new_nodes
+    }
+
+    /// Get the number of nodes staged in the map
+    pub fn staged_count(&self) -> usize {
+        self.new_nodes.len()
+    }
+
+    /// Release the staged nodes which are not reachable from root
+    ///
+    /// Used to discard the nodes staged by abandoned updates. Returns the
+    /// number of nodes released
+    pub fn release_unreachable(&mut self, root: u64) -> usize {
+        let mut reachable = HashKeyMap::default();
+        let mut stack = vec![root];
+        while let Some(hash) = stack.pop() {
+            // Nodes which are not staged are stored in the tree, along with
+            // all their children
+            if let Some(node) = self.new_nodes.remove(&hash) {
+                if let Node::BranchNode(pointers) = &node {
+                    stack.extend(pointers.iter().filter_map(|pointer| *pointer));
+                }
+                reachable.insert(hash, node);
+            }
         }
+        let released = self.new_nodes.len();
+        self.new_nodes = reachable;
+        released
     }
 
     pub fn write_out(mut self, root: u64) -> MapResult<NodeUpdates<T>> {
         /// Move root and all its children from nodes_in to nodes out
         fn move_nodes<T: MPTData>(
             root: Node<T>,
-            nodes_in: &mut HashMap<u64, Node<T>>,
+            nodes_in: &mut HashKeyMap<u64, Node<T>>,
             nodes_out: &mut Vec<Node<T>>,
         ) {
             move_nodes_recurse(&root, nodes_in, nodes_out);
@@ -33,7 +76,7 @@ impl<'a, T: MPTData, M: MPTStorageMap<T>> MPTTempMap<'a, T, M> {
         /// Move the children of root from nodes_in to nodes out
         fn move_nodes_recurse<T: MPTData>(
             root: &Node<T>,
-            nodes_in: &mut HashMap<u64, Node<T>>,
+            nodes_in: &mut HashKeyMap<u64, Node<T>>,
             nodes_out: &mut Vec<Node<T>>,
         ) {
             if let Node::BranchNode(root_ptr) = root {
@@ -75,6 +118,7 @@ impl<'a, T: MPTData, M: MPTStorageMap<T>> Map<u64, Node<T>> for MPTTempMap<'a, T
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::collections::HashMap;
 
     #[test]
     fn test_mpt_temp_map() {
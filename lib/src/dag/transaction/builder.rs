@@ -0,0 +1,164 @@
+use util::epoch_time;
+
+use super::data::TransactionData;
+use super::error::TransactionBuildError;
+use super::Transaction;
+
+/// Builds a transaction from named fields
+///
+/// The branch, trunk, nonce and root must be set. The other fields default to
+/// no extra references, contract 0, the current time and empty data.
+#[derive(Clone, Default, Debug)]
+pub struct TransactionBuilder {
+    branch_transaction: Option<u64>,
+    trunk_transaction: Option<u64>,
+    ref_transactions: Vec<u64>,
+    contract: u64,
+    timestamp: Option<u64>,
+    nonce: Option<u32>,
+    root: Option<u64>,
+    data: Option<TransactionData>,
+    counter: Option<u64>,
+    expires_at: Option<u64>,
+}
+
+impl TransactionBuilder {
+    pub fn new() -> Self {
+        TransactionBuilder::default()
+    }
+
+    pub fn branch(mut self, branch_transaction: u64) -> Self {
+        self.branch_transaction = Some(branch_transaction);
+        self
+    }
+
+    pub fn trunk(mut self, trunk_transaction: u64) -> Self {
+        self.trunk_transaction = Some(trunk_transaction);
+        self
+    }
+
+    pub fn refs(mut self, ref_transactions: Vec<u64>) -> Self {
+        self.ref_transactions = ref_transactions;
+        self
+    }
+
+    pub fn contract(mut self, contract: u64) -> Self {
+        self.contract = contract;
+        self
+    }
+
+    pub fn timestamp(mut self, timestamp: u64) -> Self {
+        self.timestamp = Some(timestamp);
+        self
+    }
+
+    pub fn nonce(mut self, nonce: u32) -> Self {
+        self.nonce = Some(nonce);
+        self
+    }
+
+    pub fn root(mut self, root: u64) -> Self {
+        self.root = Some(root);
+        self
+    }
+
+    pub fn data(mut self, data: TransactionData) -> Self {
+        self.data = Some(data);
+        self
+    }
+
+    /// Set the replay protection counter, see
+    /// [Transaction::with_counter](../struct.Transaction.html#method.with_counter)
+    pub fn counter(mut self, counter: u64) -> Self {
+        self.counter = Some(counter);
+        self
+    }
+
+    /// Set the expiration time, see
+    /// [Transaction::with_expiration](../struct.Transaction.html#method.with_expiration)
+    pub fn expires_at(mut self, expires_at: u64) -> Self {
+        self.expires_at = Some(expires_at);
+        self
+    }
+
+    /// Build the transaction
+    ///
+    /// # Errors
+    ///
+    /// Returns TransactionBuildError::MissingField if the branch, trunk, nonce
+    /// or root was not set
+    pub fn build(self) -> Result<Transaction, TransactionBuildError> {
+        let transaction = Transaction::new(
+            self.branch_transaction
+                .ok_or(TransactionBuildError::MissingField("branch"))?,
+            self.trunk_transaction
+                .ok_or(TransactionBuildError::MissingField("trunk"))?,
+            self.ref_transactions,
+            self.contract,
+            self.timestamp.unwrap_or_else(epoch_time),
+            self.nonce
+                .ok_or(TransactionBuildError::MissingField("nonce"))?,
+            self.root
+                .ok_or(TransactionBuildError::MissingField("root"))?,
+            self.data.unwrap_or(TransactionData::Empty),
+        );
+        let transaction = match self.counter {
+            Some(counter) => transaction.with_counter(counter),
+            None => transaction,
+        };
+        Ok(match self.expires_at {
+            Some(expires_at) => transaction.with_expiration(expires_at),
+            None => transaction,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_builder_matches_new() {
+        let expected = Transaction::new(
+            1,
+            2,
+            vec![3],
+            4,
+            5,
+            6,
+            7,
+            TransactionData::ExecContract("get_u32".into(), vec![]),
+        );
+        let transaction = TransactionBuilder::new()
+            .branch(1)
+            .trunk(2)
+            .refs(vec![3])
+            .contract(4)
+            .timestamp(5)
+            .nonce(6)
+            .root(7)
+            .data(TransactionData::ExecContract("get_u32".into(), vec![]))
+            .build()
+            .unwrap();
+
+        assert_eq!(expected, transaction);
+        assert_eq!(expected.get_hash(), transaction.get_hash());
+        assert_eq!(expected.get_root(), transaction.get_root());
+    }
+
+    #[test]
+    fn test_builder_missing_field() {
+        assert_eq!(
+            Err(TransactionBuildError::MissingField("trunk")),
+            TransactionBuilder::new().branch(1).nonce(0).root(0).build()
+        );
+        assert_eq!(
+            Err(TransactionBuildError::MissingField("root")),
+            TransactionBuilder::new()
+                .branch(1)
+                .trunk(2)
+                .nonce(0)
+                .build()
+        );
+    }
+}
@@ -1,9 +1,10 @@
-use dag::contract::{source::ContractSource, ContractValue};
+use dag::contract::{abi::ContractAbi, source::ContractSource, ContractValue};
 
 #[derive(Serialize, Deserialize, Clone, PartialEq, Hash, Debug)]
 pub enum TransactionData {
     Genesis,
-    GenContract(ContractSource),
+    /// Deploy a contract, optionally describing its functions with an abi
+    GenContract(ContractSource, Option<ContractAbi>),
     ExecContract(String, Vec<ContractValue>),
     Empty,
 }
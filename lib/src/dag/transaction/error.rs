@@ -3,15 +3,17 @@ use std::fmt;
 
 use dag::storage::map::MapError;
 
+use util::types::RejectionReason;
+
 #[derive(Debug, PartialEq)]
 pub enum TransactionError {
-    Rejected(String),
+    Rejected(RejectionReason),
 }
 
 impl fmt::Display for TransactionError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
-            TransactionError::Rejected(reason) => write!(f, "Rejected: {:?}", reason),
+            TransactionError::Rejected(reason) => write!(f, "Rejected: {}", reason),
         }
     }
 }
@@ -20,6 +22,24 @@ impl Error for TransactionError {}
 
 impl From<MapError> for TransactionError {
     fn from(error: MapError) -> Self {
-        TransactionError::Rejected(format!("{:?}", error))
+        TransactionError::Rejected(RejectionReason::Other(format!("{:?}", error)))
     }
 }
+
+/// Error building a transaction with a
+/// [TransactionBuilder](../builder/struct.TransactionBuilder.html)
+#[derive(Debug, PartialEq)]
+pub enum TransactionBuildError {
+    /// A required field was not set
+    MissingField(&'static str),
+}
+
+impl fmt::Display for TransactionBuildError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TransactionBuildError::MissingField(field) => write!(f, "Missing field: {}", field),
+        }
+    }
+}
+
+impl Error for TransactionBuildError {}
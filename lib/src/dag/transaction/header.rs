@@ -0,0 +1,39 @@
+use dag::transaction::Transaction;
+
+/// The fields of a transaction needed to verify its proof of work and state
+/// root, without its data, address or signature
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct TransactionHeader {
+    /// Hash identifying the full transaction
+    pub hash: u64,
+    pub branch_transaction: u64,
+    pub trunk_transaction: u64,
+    pub ref_transactions: Vec<u64>,
+    pub contract: u64,
+    pub timestamp: u64,
+    pub nonce: u32,
+    pub root: u64,
+    /// Replay protection counter, if the transaction has one
+    #[serde(default)]
+    pub counter: Option<u64>,
+    /// Expiration time, if the transaction has one
+    #[serde(default)]
+    pub expires_at: Option<u64>,
+}
+
+impl<'a> From<&'a Transaction> for TransactionHeader {
+    fn from(transaction: &'a Transaction) -> Self {
+        TransactionHeader {
+            hash: transaction.get_hash(),
+            branch_transaction: transaction.get_branch_hash(),
+            trunk_transaction: transaction.get_trunk_hash(),
+            ref_transactions: transaction.get_ref_hashes(),
+            contract: transaction.get_contract(),
+            timestamp: transaction.get_timestamp(),
+            nonce: transaction.get_nonce(),
+            root: transaction.get_root(),
+            counter: transaction.get_counter(),
+            expires_at: transaction.get_expiration(),
+        }
+    }
+}
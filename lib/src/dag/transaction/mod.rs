@@ -1,5 +1,7 @@
+pub mod builder;
 pub mod data;
 pub mod error;
+pub mod header;
 pub mod updates;
 
 #[allow(clippy::module_inception)]
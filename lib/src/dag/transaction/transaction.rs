@@ -7,12 +7,19 @@ use serde::{
 };
 
 use security::hash::hasher::Sha3Hasher;
-use security::keys::{PrivateKey, PublicKey};
+use security::keys::{
+    verify_validator_signature, KeyScheme, PrivateKey, PublicKey, ValidatorKey, VALIDATOR_KEY_LEN,
+};
 use security::ring::digest::SHA512_256;
 
 use util::epoch_time;
 
 use dag::transaction::data::TransactionData;
+use dag::transaction::header::TransactionHeader;
+
+/// Tags hashed ahead of the optional counter and expiration
+const COUNTER_TAG: u8 = 1;
+const EXPIRATION_TAG: u8 = 2;
 
 #[derive(Clone, Debug)]
 pub struct Transaction {
@@ -26,6 +33,11 @@ pub struct Transaction {
     address: Vec<u8>,
     signature: Vec<u8>,
     data: TransactionData,
+    /// Optional counter, which must increase with each transaction from the
+    /// same address
+    counter: Option<u64>,
+    /// Optional time after which the transaction is dropped if still pending
+    expires_at: Option<u64>,
 }
 
 impl Transaction {
@@ -51,6 +63,8 @@ impl Transaction {
             address: Vec::new(),
             signature: vec![0; 8192],
             data,
+            counter: None,
+            expires_at: None,
         }
     }
 
@@ -99,9 +113,44 @@ impl Transaction {
             address,
             signature,
             data,
+            counter: None,
+            expires_at: None,
         }
     }
 
+    /// Set the replay protection counter of the transaction
+    ///
+    /// The counter is part of the signed contents, so it must be set before
+    /// the transaction is signed
+    pub fn with_counter(mut self, counter: u64) -> Self {
+        self.counter = Some(counter);
+        self
+    }
+
+    pub fn get_counter(&self) -> Option<u64> {
+        self.counter
+    }
+
+    /// Set the time, in milliseconds since the epoch, after which the
+    /// transaction expires
+    ///
+    /// Expired transactions are rejected, and dropped from the pending
+    /// transactions. The expiration is part of the signed contents, so it must
+    /// be set before the transaction is signed
+    pub fn with_expiration(mut self, expires_at: u64) -> Self {
+        self.expires_at = Some(expires_at);
+        self
+    }
+
+    pub fn get_expiration(&self) -> Option<u64> {
+        self.expires_at
+    }
+
+    /// Check if the transaction has expired at the time now
+    pub fn is_expired(&self, now: u64) -> bool {
+        self.expires_at.map_or(false, |expires_at| expires_at <= now)
+    }
+
     pub fn get_trunk_hash(&self) -> u64 {
         self.trunk_transaction
     }
@@ -134,12 +183,46 @@ impl Transaction {
         self.timestamp
     }
 
+    /// Get the hash identifying the transaction
+    ///
+    /// Equivalent to [content_hash](#method.content_hash)
     pub fn get_hash(&self) -> u64 {
+        self.content_hash()
+    }
+
+    /// Hash of the transaction's contents, excluding the root, address and
+    /// signature
+    ///
+    /// Used to identify and reference the transaction
+    pub fn content_hash(&self) -> u64 {
+        let mut s = Sha3Hasher::new();
+        self.hash(&mut s);
+        s.finish()
+    }
+
+    /// Hash of the entire transaction, including the root, address and
+    /// signature
+    ///
+    /// Two transactions with the same content hash but different full hashes
+    /// have had their signatures altered. Transactions are still stored and
+    /// referenced by content hash, since their children reference parents by
+    /// it; the first correctly signed copy is kept and later copies with a
+    /// different full hash are rejected
+    pub fn full_hash(&self) -> u64 {
         let mut s = Sha3Hasher::new();
         self.hash(&mut s);
+        self.root.hash(&mut s);
+        self.address.hash(&mut s);
+        self.signature.hash(&mut s);
         s.finish()
     }
 
+    /// Get the header of the transaction, without its data, address or
+    /// signature
+    pub fn get_header(&self) -> TransactionHeader {
+        self.into()
+    }
+
     pub fn get_contract(&self) -> u64 {
         self.contract
     }
@@ -152,14 +235,73 @@ impl Transaction {
         &self.address
     }
 
+    /// Get a short id of the address which signed the transaction
+    pub fn get_address_id(&self) -> u64 {
+        let mut s = Sha3Hasher::new();
+        s.write(&self.address);
+        s.finish()
+    }
+
     pub fn get_signature(&self) -> &[u8] {
         &self.signature
     }
 
-    pub fn sign(&mut self, key: &mut PrivateKey) {
+    /// Get the scheme the transaction is signed with
+    ///
+    /// The scheme is not stored in, or hashed with, the transaction. Lamport
+    /// and validator addresses differ in length, so the address tells them
+    /// apart
+    pub fn get_scheme(&self) -> KeyScheme {
+        if self.address.len() == VALIDATOR_KEY_LEN {
+            KeyScheme::Ed25519
+        } else {
+            KeyScheme::LamportSha512_256
+        }
+    }
+
+    /// Create a signed transaction from its header and data, and a public key
+    /// and signature produced by an external signer
+    ///
+    /// The signature must be the signature of
+    /// [signing_bytes](#method.signing_bytes), with the fragments of lamport
+    /// signatures concatenated. Returns None if the public key is not a key of
+    /// the scheme, or if the header's hash does not match the transaction
+    pub fn from_parts(
+        header: TransactionHeader,
+        data: TransactionData,
+        scheme: KeyScheme,
+        public_key: Vec<u8>,
+        signature: Vec<u8>,
+    ) -> Option<Self> {
+        let transaction = Transaction {
+            branch_transaction: header.branch_transaction,
+            trunk_transaction: header.trunk_transaction,
+            ref_transactions: header.ref_transactions,
+            contract: header.contract,
+            timestamp: header.timestamp,
+            nonce: header.nonce,
+            root: header.root,
+            address: public_key,
+            signature,
+            data,
+            counter: header.counter,
+            expires_at: header.expires_at,
+        };
+        if transaction.get_scheme() != scheme || transaction.get_hash() != header.hash {
+            return None;
+        }
+        Some(transaction)
+    }
+
+    /// Get the bytes that are signed when signing the transaction
+    pub fn signing_bytes(&self) -> Vec<u8> {
         let mut s = Sha3Hasher::new();
         self.hash(&mut s);
-        let bytes = &s.finish_bytes();
+        s.finish_bytes()
+    }
+
+    pub fn sign(&mut self, key: &mut PrivateKey) {
+        let bytes = &self.signing_bytes();
         if let Ok(signature) = key.sign(bytes) {
             // The signature is composed of 256 fragments, which are each arrays of 32 bytes
             for (sig_frag, i) in signature.iter().zip(0..) {
@@ -169,12 +311,32 @@ impl Transaction {
         }
     }
 
+    /// Sign the transaction with a validator key, which unlike a lamport key
+    /// can sign any number of transactions
+    pub fn sign_validator(&mut self, key: &ValidatorKey) {
+        self.signature = key.sign(&self.signing_bytes());
+        self.address = key.public_key();
+    }
+
+    /// Check if the transaction was never signed, so has no address
+    pub fn is_unsigned(&self) -> bool {
+        self.address.is_empty()
+    }
+
     pub fn verify(&self) -> bool {
+        if self.get_scheme() == KeyScheme::Ed25519 {
+            return verify_validator_signature(
+                &self.address,
+                &self.signing_bytes(),
+                &self.signature,
+            );
+        }
         if let Some(key) = PublicKey::from_vec(self.address.clone(), &SHA512_256) {
-            let mut s = Sha3Hasher::new();
-            self.hash(&mut s);
-            let bytes = &s.finish_bytes();
+            let bytes = &self.signing_bytes();
             const SIGNATURE_FRAGMENTS: usize = 256;
+            if self.signature.len() != SIGNATURE_FRAGMENTS * 32 {
+                return false;
+            }
             let mut signature = vec![vec![0; 32]; SIGNATURE_FRAGMENTS];
             for (i, sig_frag) in signature.iter_mut().enumerate().take(SIGNATURE_FRAGMENTS) {
                 sig_frag.copy_from_slice(&self.signature[i * 32..(i + 1) * 32]);
@@ -194,6 +356,18 @@ impl Hash for Transaction {
         self.nonce.hash(state);
         self.contract.hash(state);
         self.data.hash(state);
+        // Each set field is prefixed with its own tag, so a counter cannot be
+        // passed off as an expiration or the reverse. Unset fields are not
+        // hashed at all, leaving the hashes of existing transactions as they
+        // were
+        if let Some(counter) = self.counter {
+            COUNTER_TAG.hash(state);
+            counter.hash(state);
+        }
+        if let Some(expires_at) = self.expires_at {
+            EXPIRATION_TAG.hash(state);
+            expires_at.hash(state);
+        }
     }
 }
 
@@ -206,6 +380,8 @@ impl PartialEq<Transaction> for Transaction {
             && self.nonce == other.nonce
             && self.contract == other.contract
             && self.data == other.data
+            && self.counter == other.counter
+            && self.expires_at == other.expires_at
     }
 }
 
@@ -214,8 +390,8 @@ impl Serialize for Transaction {
     where
         S: Serializer,
     {
-        // 9 fields in the struct
-        let mut state = serializer.serialize_struct("Transaction", 9)?;
+        // 12 fields in the struct
+        let mut state = serializer.serialize_struct("Transaction", 12)?;
         // Serialize fields
         state.serialize_field("branch_transaction", &self.branch_transaction)?;
         state.serialize_field("trunk_transaction", &self.trunk_transaction)?;
@@ -236,6 +412,10 @@ impl Serialize for Transaction {
         )?;
 
         state.serialize_field("data", &self.data)?;
+        // Unset optional fields are written as null, so every field keeps its
+        // position in formats without field names
+        state.serialize_field("counter", &self.counter)?;
+        state.serialize_field("expires_at", &self.expires_at)?;
         state.end()
     }
 }
@@ -259,6 +439,8 @@ impl<'de> Deserialize<'de> for Transaction {
             Address,
             Signature,
             Data,
+            Counter,
+            Expires_At,
         }
 
         struct TransactionVisitor;
@@ -314,8 +496,10 @@ impl<'de> Deserialize<'de> for Transaction {
                 let data = seq
                     .next_element()?
                     .ok_or_else(|| de::Error::invalid_length(9, &self))?;
+                let counter: Option<u64> = seq.next_element()?.unwrap_or(None);
+                let expires_at: Option<u64> = seq.next_element()?.unwrap_or(None);
 
-                Ok(Transaction::raw(
+                let transaction = Transaction::raw(
                     branch_transaction,
                     trunk_transaction,
                     ref_transactions,
@@ -326,7 +510,15 @@ impl<'de> Deserialize<'de> for Transaction {
                     address,
                     signature,
                     data,
-                ))
+                );
+                let transaction = match counter {
+                    Some(counter) => transaction.with_counter(counter),
+                    None => transaction,
+                };
+                Ok(match expires_at {
+                    Some(expires_at) => transaction.with_expiration(expires_at),
+                    None => transaction,
+                })
             }
 
             fn visit_map<V>(self, mut map: V) -> Result<Transaction, V::Error>
@@ -343,6 +535,8 @@ impl<'de> Deserialize<'de> for Transaction {
                 let mut address = None;
                 let mut signature = None;
                 let mut data = None;
+                let mut counter = None;
+                let mut expires_at = None;
 
                 while let Some(key) = map.next_key()? {
                     match key {
@@ -428,6 +622,18 @@ impl<'de> Deserialize<'de> for Transaction {
                             }
                             data = Some(map.next_value()?);
                         }
+                        Field::Counter => {
+                            if counter.is_some() {
+                                return Err(de::Error::duplicate_field("counter"));
+                            }
+                            counter = Some(map.next_value()?);
+                        }
+                        Field::Expires_At => {
+                            if expires_at.is_some() {
+                                return Err(de::Error::duplicate_field("expires_at"));
+                            }
+                            expires_at = Some(map.next_value()?);
+                        }
                     }
                 }
 
@@ -445,7 +651,7 @@ impl<'de> Deserialize<'de> for Transaction {
                 let signature = signature.ok_or_else(|| de::Error::missing_field("signature"))?;
                 let data = data.ok_or_else(|| de::Error::missing_field("data"))?;
 
-                Ok(Transaction::raw(
+                let transaction = Transaction::raw(
                     branch_transaction,
                     trunk_transaction,
                     ref_transactions,
@@ -456,7 +662,15 @@ impl<'de> Deserialize<'de> for Transaction {
                     address,
                     signature,
                     data,
-                ))
+                );
+                let transaction = match counter.unwrap_or(None) {
+                    Some(counter) => transaction.with_counter(counter),
+                    None => transaction,
+                };
+                Ok(match expires_at.unwrap_or(None) {
+                    Some(expires_at) => transaction.with_expiration(expires_at),
+                    None => transaction,
+                })
             }
         }
 
@@ -470,6 +684,8 @@ impl<'de> Deserialize<'de> for Transaction {
             "address",
             "signature",
             "data",
+            "counter",
+            "expires_at",
         ];
         deserializer.deserialize_struct("Transaction", FIELDS, TransactionVisitor)
     }
@@ -503,7 +719,7 @@ mod tests {
             transaction.get_all_refs()
         );
         assert_eq!(0, transaction.get_nonce());
-        assert_eq!(2763323875860498692, transaction.get_hash());
+        assert_eq!(2615639855376453699, transaction.get_hash());
     }
 
     #[test]
@@ -539,6 +755,120 @@ mod tests {
         assert!(transaction.verify());
     }
 
+    #[test]
+    fn test_sign_from_parts() {
+        let unsigned = Transaction::create(0, 0, vec![], 0, 0, 0, TransactionData::Genesis)
+            .with_counter(1)
+            .with_expiration(2);
+
+        // Sign the transaction outside of the transaction
+        let mut key = PrivateKey::new(&SHA512_256);
+        let signature: Vec<u8> = key
+            .sign(&unsigned.signing_bytes())
+            .unwrap()
+            .into_iter()
+            .flatten()
+            .collect();
+        let address = key.public_key().to_bytes();
+
+        let transaction = Transaction::from_parts(
+            unsigned.get_header(),
+            unsigned.get_data().clone(),
+            KeyScheme::LamportSha512_256,
+            address.clone(),
+            signature.clone(),
+        )
+        .unwrap();
+        assert!(transaction.verify());
+        assert_eq!(transaction.get_hash(), unsigned.get_hash());
+        assert!(!unsigned.verify());
+
+        // The key must be a key of the scheme
+        assert!(Transaction::from_parts(
+            unsigned.get_header(),
+            unsigned.get_data().clone(),
+            KeyScheme::Ed25519,
+            address.clone(),
+            signature.clone(),
+        )
+        .is_none());
+
+        // The header must match the data
+        assert!(Transaction::from_parts(
+            unsigned.get_header(),
+            TransactionData::Empty,
+            KeyScheme::LamportSha512_256,
+            address,
+            signature,
+        )
+        .is_none());
+    }
+
+    #[test]
+    fn test_sign_from_parts_ed25519() {
+        extern crate untrusted;
+        use security::ring::rand::SystemRandom;
+        use security::ring::signature::Ed25519KeyPair;
+
+        let unsigned = Transaction::create(0, 0, vec![], 0, 0, 0, TransactionData::Genesis);
+
+        // Sign with a key pair from ring, as an external signer would
+        let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new()).unwrap();
+        let key_pair = Ed25519KeyPair::from_pkcs8(untrusted::Input::from(pkcs8.as_ref())).unwrap();
+        let signature = key_pair.sign(&unsigned.signing_bytes()).as_ref().to_vec();
+
+        let transaction = Transaction::from_parts(
+            unsigned.get_header(),
+            unsigned.get_data().clone(),
+            KeyScheme::Ed25519,
+            key_pair.public_key_bytes().to_vec(),
+            signature,
+        )
+        .unwrap();
+        assert_eq!(transaction.get_scheme(), KeyScheme::Ed25519);
+        assert!(transaction.verify());
+        assert_eq!(transaction.get_hash(), unsigned.get_hash());
+    }
+
+    #[test]
+    fn test_content_and_full_hash() {
+        let mut transaction = Transaction::create(0, 0, vec![], 0, 0, 0, TransactionData::Genesis);
+        let mut tampered = transaction.clone();
+        transaction.sign(&mut PrivateKey::new(&SHA512_256));
+        tampered.sign(&mut PrivateKey::new(&SHA512_256));
+
+        // Swap in a signature from a different key
+        let tampered = Transaction::raw(
+            transaction.get_branch_hash(),
+            transaction.get_trunk_hash(),
+            transaction.get_ref_hashes(),
+            transaction.get_contract(),
+            transaction.get_timestamp(),
+            transaction.get_nonce(),
+            transaction.get_root(),
+            transaction.get_address().to_vec(),
+            tampered.get_signature().to_vec(),
+            transaction.get_data().clone(),
+        );
+        assert!(!tampered.verify());
+        assert_eq!(transaction.content_hash(), tampered.content_hash());
+        assert_eq!(transaction.get_hash(), tampered.get_hash());
+        assert_ne!(transaction.full_hash(), tampered.full_hash());
+    }
+
+    #[test]
+    fn test_counter_and_expiration_signed_apart() {
+        let base = Transaction::create(0, 0, vec![], 0, 0, 0, TransactionData::Empty);
+        let counted = base.clone().with_counter(100);
+        let expiring = base.clone().with_expiration(100);
+
+        // Moving a value between the counter and the expiration changes the
+        // signed bytes, so the signature no longer verifies
+        assert_ne!(counted.signing_bytes(), expiring.signing_bytes());
+        assert_ne!(counted.signing_bytes(), base.signing_bytes());
+        assert_ne!(counted.get_hash(), expiring.get_hash());
+    }
+
     #[test]
     fn test_serialize() {
         let transaction = Transaction::new(0, 1, vec![2], 3, 4, 5, 0, TransactionData::Genesis);
@@ -552,7 +882,9 @@ mod tests {
             "root": 0,
             "address": "",
             "signature": base64::encode_config(&vec![0; 8192], base64::URL_SAFE),
-            "data": TransactionData::Genesis
+            "data": TransactionData::Genesis,
+            "counter": null,
+            "expires_at": null
         });
         assert_eq!(json_value, serde_json::to_value(transaction).unwrap());
     }
@@ -592,5 +924,24 @@ mod tests {
             signed_transaction,
             serde_json::from_value(signed_json_value).unwrap()
         );
+
+        // Check the counter is kept after serializing and deserializing
+        let counter_transaction =
+            Transaction::new(0, 1, vec![2], 3, 4, 5, 0, TransactionData::Genesis).with_counter(9);
+        let counter_json_value = serde_json::to_value(counter_transaction.clone()).unwrap();
+        let deserialized: Transaction = serde_json::from_value(counter_json_value).unwrap();
+        assert_eq!(deserialized.get_counter(), Some(9));
+        assert_eq!(counter_transaction, deserialized);
+
+        // Check the expiration is kept after serializing and deserializing
+        let expiring_transaction =
+            Transaction::new(0, 1, vec![2], 3, 4, 5, 0, TransactionData::Genesis)
+                .with_expiration(100);
+        let expiring_json_value = serde_json::to_value(expiring_transaction.clone()).unwrap();
+        let deserialized: Transaction = serde_json::from_value(expiring_json_value).unwrap();
+        assert_eq!(deserialized.get_expiration(), Some(100));
+        assert_eq!(expiring_transaction, deserialized);
+        assert!(deserialized.is_expired(100));
+        assert!(!deserialized.is_expired(99));
     }
 }
@@ -1,4 +1,4 @@
-use dag::contract::{Contract, ContractValue};
+use dag::contract::{state::ContractEvent, Contract, ContractValue};
 use dag::storage::mpt::NodeUpdates;
 
 #[derive(Debug, PartialEq)]
@@ -6,6 +6,7 @@ pub struct TransactionUpdates {
     pub contract: Option<Contract>,
     pub node_updates: Option<NodeUpdates<ContractValue>>,
     pub referenced: Vec<u64>,
+    pub events: Vec<ContractEvent>,
 }
 
 impl TransactionUpdates {
@@ -14,6 +15,7 @@ impl TransactionUpdates {
             contract: None,
             node_updates: None,
             referenced,
+            events: Vec::new(),
         }
     }
 
@@ -25,6 +27,10 @@ impl TransactionUpdates {
         self.node_updates = Some(node_updates);
     }
 
+    pub fn add_events(&mut self, events: Vec<ContractEvent>) {
+        self.events.extend(events);
+    }
+
     pub fn get_storage_root(&self) -> Option<u64> {
         if let Some(ref updates) = self.node_updates {
             Some(updates.get_root_hash())
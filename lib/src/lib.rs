@@ -9,11 +9,15 @@ extern crate serde_derive;
 extern crate serde_json;
 
 extern crate base64;
+extern crate crossbeam_utils;
 extern crate flate2;
+#[macro_use]
+extern crate log;
 extern crate ordered_float;
 extern crate rand;
 extern crate replace_with;
 
+extern crate parity_wasm;
 extern crate wasmi;
 
 pub mod dag;
@@ -1,9 +1,11 @@
 use std::hash::Hasher;
 
-use std::mem::transmute;
-
-use security::hash::sha3::{Digest, Sha3_512};
+use security::hash::sha3::{Digest, Sha3_256, Sha3_512};
 
+/// Hasher producing SHA3-512 digests
+///
+/// Integers are written in big-endian byte order, and usize and isize are
+/// written as 64 bit integers, so hashes are the same on every platform
 pub struct Sha3Hasher {
     hasher: Sha3_512,
 }
@@ -23,6 +25,46 @@ impl Hasher for Sha3Hasher {
         let result = self.hasher.clone().result();
         _bytes_to_u64(result.as_slice())
     }
+
+    fn write_u16(&mut self, i: u16) {
+        self.write(&i.to_be_bytes());
+    }
+
+    fn write_u32(&mut self, i: u32) {
+        self.write(&i.to_be_bytes());
+    }
+
+    fn write_u64(&mut self, i: u64) {
+        self.write(&i.to_be_bytes());
+    }
+
+    fn write_u128(&mut self, i: u128) {
+        self.write(&i.to_be_bytes());
+    }
+
+    fn write_usize(&mut self, i: usize) {
+        self.write_u64(i as u64);
+    }
+
+    fn write_i16(&mut self, i: i16) {
+        self.write_u16(i as u16);
+    }
+
+    fn write_i32(&mut self, i: i32) {
+        self.write_u32(i as u32);
+    }
+
+    fn write_i64(&mut self, i: i64) {
+        self.write_u64(i as u64);
+    }
+
+    fn write_i128(&mut self, i: i128) {
+        self.write_u128(i as u128);
+    }
+
+    fn write_isize(&mut self, i: isize) {
+        self.write_i64(i as i64);
+    }
 }
 
 impl Sha3Hasher {
@@ -37,17 +79,19 @@ impl Sha3Hasher {
     }
 }
 
+/// Compute the 32 byte SHA3-256 digest of bytes
+pub fn sha3_256(bytes: &[u8]) -> [u8; 32] {
+    let mut hasher = Sha3_256::new();
+    hasher.input(bytes);
+    let mut digest = [0u8; 32];
+    digest.copy_from_slice(hasher.result().as_slice());
+    digest
+}
+
 fn _bytes_to_u64(bytes: &[u8]) -> u64 {
     let mut buffer = [0u8; 8];
-    buffer[0] = bytes[7];
-    buffer[1] = bytes[6];
-    buffer[2] = bytes[5];
-    buffer[3] = bytes[4];
-    buffer[4] = bytes[3];
-    buffer[5] = bytes[2];
-    buffer[6] = bytes[1];
-    buffer[7] = bytes[0];
-    unsafe { transmute::<[u8; 8], u64>(buffer) }
+    buffer.copy_from_slice(&bytes[..8]);
+    u64::from_be_bytes(buffer)
 }
 
 #[cfg(test)]
@@ -66,4 +110,19 @@ mod tests {
             _bytes_to_u64(&[0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff])
         );
     }
+
+    #[test]
+    fn test_integer_byte_order() {
+        let mut hasher = Sha3Hasher::new();
+        hasher.write_u64(0x0102_0304_0506_0708);
+        let mut expected = Sha3Hasher::new();
+        expected.write(&[0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08]);
+        assert_eq!(hasher.finish_bytes(), expected.finish_bytes());
+
+        let mut hasher = Sha3Hasher::new();
+        hasher.write_usize(1);
+        let mut expected = Sha3Hasher::new();
+        expected.write_u64(1);
+        assert_eq!(hasher.finish_bytes(), expected.finish_bytes());
+    }
 }
@@ -1,3 +1,5 @@
+use rand::{thread_rng, Rng};
+
 use security::hash::sha3::{Digest, Sha3_512};
 
 #[cfg(not(test))]
@@ -6,20 +8,122 @@ const MIN_WEIGHT_MAGNITUDE: usize = 2;
 #[cfg(test)]
 const MIN_WEIGHT_MAGNITUDE: usize = 1;
 
-pub fn proof_of_work(trunk_nonce: u32, branch_nonce: u32) -> u32 {
-    (0u32..)
-        .find(|nonce| valid_proof(trunk_nonce, branch_nonce, *nonce))
-        .expect("No valid proof of work was found")
+/// Time milestones should be issued apart, in milliseconds
+pub const TARGET_MILESTONE_INTERVAL: u64 = 60_000;
+
+/// Number of recent milestone intervals the suggested difficulty averages over
+pub const MILESTONE_INTERVAL_SPAN: usize = 16;
+
+/// Number of zero bytes the hash of a transaction's nonces must end with
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Difficulty(pub usize);
+
+impl Difficulty {
+    /// Check if nonce is a valid proof of work for the parents' nonces
+    pub fn valid_proof(self, trunk_nonce: u32, branch_nonce: u32, nonce: u32) -> bool {
+        meets_weight(trunk_nonce, branch_nonce, nonce, self.0)
+    }
+
+    /// Suggest a difficulty from the timestamps of recent milestones, oldest
+    /// first
+    ///
+    /// The times between milestones are averaged with an exponential moving
+    /// average, so recent intervals count the most. Milestones arriving much
+    /// faster than the target suggest a higher difficulty, and milestones
+    /// arriving much slower suggest a lower one, but never below one byte
+    pub fn suggest(timestamps: &[u64]) -> Difficulty {
+        let default = Difficulty::default();
+        let mut intervals = timestamps
+            .windows(2)
+            .map(|pair| pair[1].saturating_sub(pair[0]) as f64);
+        let first = match intervals.next() {
+            Some(interval) => interval,
+            None => return default,
+        };
+        let alpha = 2.0 / (MILESTONE_INTERVAL_SPAN as f64 + 1.0);
+        let average = intervals.fold(first, |average, interval| {
+            average + alpha * (interval - average)
+        });
+
+        let target = TARGET_MILESTONE_INTERVAL as f64;
+        if average < target / 2.0 {
+            Difficulty(default.0 + 1)
+        } else if average > target * 2.0 {
+            Difficulty((default.0 - 1).max(1))
+        } else {
+            default
+        }
+    }
+}
+
+impl Default for Difficulty {
+    /// The difficulty transactions are checked against when added to the dag
+    fn default() -> Self {
+        Difficulty(MIN_WEIGHT_MAGNITUDE)
+    }
+}
+
+/// Search for a valid nonce
+///
+/// See [proof_of_work_from](fn.proof_of_work_from.html)
+pub fn proof_of_work(trunk_nonce: u32, branch_nonce: u32) -> Option<u32> {
+    proof_of_work_from(trunk_nonce, branch_nonce, 0)
+}
+
+/// Search for a valid nonce starting from a random offset
+///
+/// Miners building on the same parents search different ranges, so they are
+/// unlikely to produce the same transaction
+pub fn random_proof_of_work(trunk_nonce: u32, branch_nonce: u32) -> Option<u32> {
+    proof_of_work_from(trunk_nonce, branch_nonce, thread_rng().gen())
+}
+
+/// Search for a valid nonce starting from start
+///
+/// The search wraps around, so every nonce is tried at most once. Returns
+/// None if no nonce is valid for the parents' nonces, in which case callers
+/// must build the transaction on different parents and retry
+pub fn proof_of_work_from(trunk_nonce: u32, branch_nonce: u32, start: u32) -> Option<u32> {
+    find_nonce(
+        trunk_nonce,
+        branch_nonce,
+        start,
+        u64::from(u32::max_value()) + 1,
+        MIN_WEIGHT_MAGNITUDE,
+    )
+}
+
+/// Try count nonces from start, wrapping around past the largest nonce
+fn find_nonce(
+    trunk_nonce: u32,
+    branch_nonce: u32,
+    start: u32,
+    count: u64,
+    weight_magnitude: usize,
+) -> Option<u32> {
+    (0..count)
+        .map(|offset| start.wrapping_add(offset as u32))
+        .find(|nonce| meets_weight(trunk_nonce, branch_nonce, *nonce, weight_magnitude))
 }
 
 pub fn valid_proof(trunk_nonce: u32, branch_nonce: u32, nonce: u32) -> bool {
+    meets_weight(trunk_nonce, branch_nonce, nonce, MIN_WEIGHT_MAGNITUDE)
+}
+
+/// Check if the hash of the nonces ends with weight_magnitude zero bytes
+fn meets_weight(trunk_nonce: u32, branch_nonce: u32, nonce: u32, weight_magnitude: usize) -> bool {
     let guess = nonces_to_bytes(trunk_nonce, branch_nonce, nonce);
 
     let mut hasher = Sha3_512::new();
     hasher.input(&guess);
     let hash = hasher.result();
 
-    for b in hash.as_slice()[hash.len() - MIN_WEIGHT_MAGNITUDE..].iter() {
+    // No hash ends with more zero bytes than it has
+    let start = match hash.len().checked_sub(weight_magnitude) {
+        Some(start) => start,
+        None => return false,
+    };
+    for b in hash.as_slice()[start..].iter() {
         if *b != 0u8 {
             return false;
         }
@@ -61,9 +165,65 @@ mod tests {
         assert!(valid_proof(0, 1, 29972));
     }
 
+    #[test]
+    fn test_proof_of_work_from() {
+        let first = proof_of_work_from(1, 0, 0).unwrap();
+        let second = proof_of_work_from(1, 0, 1 << 31).unwrap();
+        assert!(valid_proof(1, 0, first));
+        assert!(valid_proof(1, 0, second));
+        assert_ne!(first, second);
+        assert!(second >= 1 << 31);
+
+        // The search wraps around past the largest nonce
+        assert_eq!(Some(first), proof_of_work_from(1, 0, u32::max_value()));
+    }
+
+    #[test]
+    fn test_find_nonce_exhausted() {
+        // No hash ends with 64 zero bytes, so every nonce is tried, wrapping
+        // past the largest nonce without overflowing
+        assert_eq!(None, find_nonce(1, 0, u32::max_value() - 500, 1000, 64));
+        assert_eq!(
+            Some(136516),
+            find_nonce(1, 0, 136516, 1, MIN_WEIGHT_MAGNITUDE)
+        );
+    }
+
+    #[test]
+    fn test_difficulty_longer_than_hash() {
+        assert!(!Difficulty(65).valid_proof(1, 0, 136516));
+        assert!(!Difficulty(usize::max_value()).valid_proof(1, 0, 136516));
+    }
+
+    #[test]
+    fn test_suggested_difficulty() {
+        let default = Difficulty::default();
+        let timestamps =
+            |interval: u64, count: u64| (0..count).map(|i| i * interval).collect::<Vec<_>>();
+        assert_eq!(Difficulty::suggest(&[]), default);
+        assert_eq!(Difficulty::suggest(&[1000]), default);
+        assert_eq!(
+            Difficulty::suggest(&timestamps(TARGET_MILESTONE_INTERVAL, 10)),
+            default
+        );
+
+        // Fast milestones suggest a higher difficulty
+        let fast = timestamps(TARGET_MILESTONE_INTERVAL / 10, 10);
+        let fast_difficulty = Difficulty::suggest(&fast);
+        assert!(fast_difficulty.0 > default.0);
+
+        // Once milestones slow down, the suggested difficulty falls again
+        let mut slowed = fast.clone();
+        let last = fast[fast.len() - 1];
+        slowed.extend((1..20).map(|i| last + i * TARGET_MILESTONE_INTERVAL * 10));
+        let slow_difficulty = Difficulty::suggest(&slowed);
+        assert!(slow_difficulty.0 < fast_difficulty.0);
+        assert!(slow_difficulty.0 >= 1);
+    }
+
     #[bench]
     fn bench_proof_of_work(b: &mut test::Bencher) {
-        b.iter(|| assert_eq!(20, proof_of_work(1, 0)));
+        b.iter(|| assert_eq!(Some(20), proof_of_work(1, 0)));
     }
 
     #[bench]
@@ -0,0 +1,224 @@
+use serde::de::{self, Deserialize, Deserializer, Unexpected};
+use serde::ser::Serializer;
+
+use security::keys::{PrivateKey, PublicKey, ValidatorKey, VALIDATOR_KEY_LEN};
+use security::ring::digest::SHA512_256;
+
+/// Signature scheme of a stored key
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum KeyScheme {
+    /// Lamport one-time signatures over SHA-512/256, as used by transactions
+    LamportSha512_256,
+    /// Ed25519 signatures, as used by the validators of permissioned networks
+    Ed25519,
+}
+
+/// Whether a stored key is the public or the private half of a key pair
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum KeyKind {
+    Public,
+    Private,
+}
+
+impl Default for KeyKind {
+    fn default() -> Self {
+        KeyKind::Public
+    }
+}
+
+/// Signature schemes transactions can be verified with
+pub const SUPPORTED_SCHEMES: &[KeyScheme] = &[KeyScheme::LamportSha512_256];
+
+/// A key stored in the same JSON format as everything else
+///
+/// The key bytes are base64 encoded, and tagged with the scheme needed to
+/// parse them and whether they are a public or a private key. Files without
+/// a kind hold public keys
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct KeyFile {
+    scheme: KeyScheme,
+    #[serde(default)]
+    kind: KeyKind,
+    #[serde(serialize_with = "serialize_base64")]
+    #[serde(deserialize_with = "deserialize_base64")]
+    key: Vec<u8>,
+}
+
+impl KeyFile {
+    pub fn from_public_key(key: &PublicKey) -> Self {
+        KeyFile {
+            scheme: KeyScheme::LamportSha512_256,
+            kind: KeyKind::Public,
+            key: key.to_bytes(),
+        }
+    }
+
+    pub fn from_private_key(key: &PrivateKey) -> Self {
+        KeyFile {
+            scheme: KeyScheme::LamportSha512_256,
+            kind: KeyKind::Private,
+            key: key.to_bytes(),
+        }
+    }
+
+    /// Store a validator key, encoded as PKCS#8
+    pub fn from_validator_pkcs8(pkcs8: &[u8]) -> Self {
+        KeyFile {
+            scheme: KeyScheme::Ed25519,
+            kind: KeyKind::Private,
+            key: pkcs8.to_vec(),
+        }
+    }
+
+    pub fn from_validator_public_key(key: &[u8]) -> Self {
+        KeyFile {
+            scheme: KeyScheme::Ed25519,
+            kind: KeyKind::Public,
+            key: key.to_vec(),
+        }
+    }
+
+    pub fn get_scheme(&self) -> KeyScheme {
+        self.scheme
+    }
+
+    pub fn get_kind(&self) -> KeyKind {
+        self.kind
+    }
+
+    /// Parse the stored public key, or the public half of the stored private
+    /// key
+    ///
+    /// Returns None if the bytes are not a key of the scheme
+    pub fn to_public_key(&self) -> Option<PublicKey> {
+        match self.kind {
+            KeyKind::Public => match self.scheme {
+                KeyScheme::LamportSha512_256 => PublicKey::from_vec(self.key.clone(), &SHA512_256),
+                KeyScheme::Ed25519 => None,
+            },
+            KeyKind::Private => self.to_private_key().map(|key| key.public_key()),
+        }
+    }
+
+    /// Parse the stored private key
+    ///
+    /// Returns None if a public key is stored, or if the bytes are not a key
+    /// of the scheme
+    pub fn to_private_key(&self) -> Option<PrivateKey> {
+        match self.kind {
+            KeyKind::Public => None,
+            KeyKind::Private => match self.scheme {
+                KeyScheme::LamportSha512_256 => PrivateKey::from_vec(self.key.clone(), &SHA512_256),
+                KeyScheme::Ed25519 => None,
+            },
+        }
+    }
+
+    /// Parse the stored validator key
+    ///
+    /// Returns None if a public key is stored, or if the bytes are not a
+    /// PKCS#8 encoded ed25519 key
+    pub fn to_validator_key(&self) -> Option<ValidatorKey> {
+        match (self.scheme, self.kind) {
+            (KeyScheme::Ed25519, KeyKind::Private) => ValidatorKey::from_pkcs8(&self.key),
+            _ => None,
+        }
+    }
+
+    /// Get the stored validator public key, or the public half of the stored
+    /// validator key
+    pub fn to_validator_public_key(&self) -> Option<Vec<u8>> {
+        match (self.scheme, self.kind) {
+            (KeyScheme::Ed25519, KeyKind::Public) if self.key.len() == VALIDATOR_KEY_LEN => {
+                Some(self.key.clone())
+            }
+            (KeyScheme::Ed25519, KeyKind::Private) => {
+                self.to_validator_key().map(|key| key.public_key())
+            }
+            _ => None,
+        }
+    }
+}
+
+fn serialize_base64<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&base64::encode_config(bytes, base64::URL_SAFE))
+}
+
+fn deserialize_base64<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+    let encoded = String::deserialize(deserializer)?;
+    base64::decode_config(&encoded, base64::URL_SAFE)
+        .map_err(|_| de::Error::invalid_value(Unexpected::Str(&encoded), &"valid base64 string"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_key_file_round_trip() {
+        let mut private_key = PrivateKey::new(&SHA512_256);
+        let json =
+            serde_json::to_string(&KeyFile::from_public_key(&private_key.public_key())).unwrap();
+
+        let key_file: KeyFile = serde_json::from_str(&json).unwrap();
+        assert_eq!(key_file.get_scheme(), KeyScheme::LamportSha512_256);
+        let public_key = key_file.to_public_key().unwrap();
+        assert_eq!(public_key, private_key.public_key());
+
+        // The loaded key verifies signatures of the original private key
+        let data = b"persisted key";
+        let signature = private_key.sign(data).unwrap();
+        assert!(public_key.verify_signature(&signature, data));
+        assert!(!public_key.verify_signature(&signature, b"other data"));
+
+        let invalid = json!({"scheme": "LamportSha512_256", "key": "not base64!"});
+        assert!(serde_json::from_value::<KeyFile>(invalid).is_err());
+        let truncated = json!({"scheme": "LamportSha512_256", "key": "AAAA"});
+        let key_file: KeyFile = serde_json::from_value(truncated).unwrap();
+        assert!(key_file.to_public_key().is_none());
+    }
+
+    #[test]
+    fn test_private_key_file_round_trip() {
+        let private_key = PrivateKey::new(&SHA512_256);
+        let json = serde_json::to_string(&KeyFile::from_private_key(&private_key)).unwrap();
+
+        let key_file: KeyFile = serde_json::from_str(&json).unwrap();
+        assert_eq!(key_file.get_kind(), KeyKind::Private);
+        let mut loaded = key_file.to_private_key().unwrap();
+        assert_eq!(key_file.to_public_key(), Some(private_key.public_key()));
+
+        // The loaded key signs verifiably for the original public key
+        let data = b"persisted key";
+        let signature = loaded.sign(data).unwrap();
+        assert!(private_key.public_key().verify_signature(&signature, data));
+
+        // Public key files cannot be loaded as private keys
+        let public = KeyFile::from_public_key(&private_key.public_key());
+        assert_eq!(public.get_kind(), KeyKind::Public);
+        assert!(public.to_private_key().is_none());
+    }
+
+    #[test]
+    fn test_validator_key_file_round_trip() {
+        let pkcs8 = ValidatorKey::generate_pkcs8().unwrap();
+        let public_key = ValidatorKey::from_pkcs8(&pkcs8).unwrap().public_key();
+        let json = serde_json::to_string(&KeyFile::from_validator_pkcs8(&pkcs8)).unwrap();
+
+        let key_file: KeyFile = serde_json::from_str(&json).unwrap();
+        assert_eq!(key_file.get_scheme(), KeyScheme::Ed25519);
+        assert_eq!(key_file.get_kind(), KeyKind::Private);
+        assert_eq!(
+            key_file.to_validator_key().map(|key| key.public_key()),
+            Some(public_key.clone())
+        );
+        assert_eq!(key_file.to_validator_public_key(), Some(public_key.clone()));
+        assert!(key_file.to_private_key().is_none());
+
+        // Validator public keys cannot be loaded as keys, or as lamport keys
+        let public = KeyFile::from_validator_public_key(&public_key);
+        assert_eq!(public.to_validator_public_key(), Some(public_key));
+        assert!(public.to_validator_key().is_none());
+        assert!(public.to_public_key().is_none());
+    }
+}
@@ -1,3 +1,9 @@
 extern crate lamport_sigs;
+extern crate untrusted;
 
+mod keyfile;
+mod validator;
+
+pub use self::keyfile::{KeyFile, KeyKind, KeyScheme, SUPPORTED_SCHEMES};
 pub use self::lamport_sigs::{PrivateKey, PublicKey};
+pub use self::validator::{verify_validator_signature, ValidatorKey, VALIDATOR_KEY_LEN};
@@ -0,0 +1,73 @@
+use security::keys::untrusted::Input;
+use security::ring::rand::SystemRandom;
+use security::ring::signature::{self, Ed25519KeyPair};
+
+/// Length of the public key of a validator, which is the address of the
+/// transactions it signs
+pub const VALIDATOR_KEY_LEN: usize = 32;
+
+/// A key identifying a validator of a permissioned network
+///
+/// Lamport keys can only sign once, so validators sign with ed25519 keys
+/// instead, which can sign any number of transactions under the same address
+pub struct ValidatorKey {
+    key_pair: Ed25519KeyPair,
+}
+
+impl ValidatorKey {
+    /// Generate a new key, encoded as PKCS#8 so it can be stored
+    pub fn generate_pkcs8() -> Option<Vec<u8>> {
+        let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new()).ok()?;
+        Some(pkcs8.as_ref().to_vec())
+    }
+
+    /// Load a key encoded as PKCS#8
+    pub fn from_pkcs8(pkcs8: &[u8]) -> Option<Self> {
+        let key_pair = Ed25519KeyPair::from_pkcs8(Input::from(pkcs8)).ok()?;
+        Some(ValidatorKey { key_pair })
+    }
+
+    pub fn public_key(&self) -> Vec<u8> {
+        self.key_pair.public_key_bytes().to_vec()
+    }
+
+    pub fn sign(&self, data: &[u8]) -> Vec<u8> {
+        self.key_pair.sign(data).as_ref().to_vec()
+    }
+}
+
+/// Verify a signature made by the validator key with public_key
+pub fn verify_validator_signature(public_key: &[u8], data: &[u8], signature: &[u8]) -> bool {
+    signature::verify(
+        &signature::ED25519,
+        Input::from(public_key),
+        Input::from(data),
+        Input::from(signature),
+    )
+    .is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validator_key() {
+        let pkcs8 = ValidatorKey::generate_pkcs8().unwrap();
+        let key = ValidatorKey::from_pkcs8(&pkcs8).unwrap();
+        assert_eq!(key.public_key().len(), VALIDATOR_KEY_LEN);
+
+        // The same key signs any number of times
+        let public_key = key.public_key();
+        let first = key.sign(b"first");
+        let second = key.sign(b"second");
+        assert!(verify_validator_signature(&public_key, b"first", &first));
+        assert!(verify_validator_signature(&public_key, b"second", &second));
+        assert!(!verify_validator_signature(&public_key, b"second", &first));
+
+        // A stored key is loaded back as the same key
+        let loaded = ValidatorKey::from_pkcs8(&pkcs8).unwrap();
+        assert_eq!(loaded.public_key(), public_key);
+        assert!(ValidatorKey::from_pkcs8(&pkcs8[1..]).is_none());
+    }
+}
@@ -0,0 +1,219 @@
+use std::error::Error;
+use std::fmt;
+
+/// Human readable part of an encoded address
+pub const ADDRESS_PREFIX: &str = "rd";
+
+/// Separates the prefix from the data and checksum
+const SEPARATOR: char = '1';
+
+/// Characters of the bech32 alphabet, by 5 bit value
+const CHARSET: &[u8; 32] = b"qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+
+/// Number of 5 bit groups needed to hold a u64
+const DATA_LENGTH: usize = 13;
+
+const CHECKSUM_LENGTH: usize = 6;
+
+const GENERATOR: [u32; 5] = [
+    0x3b6a_57b2,
+    0x2650_8e6d,
+    0x1ea1_19fa,
+    0x3d42_33dd,
+    0x2a14_62b3,
+];
+
+#[derive(PartialEq, Debug)]
+pub enum AddressError {
+    /// The address does not start with the address prefix
+    InvalidPrefix,
+    /// The address has the wrong number of characters
+    InvalidLength,
+    /// The address contains a character outside of the bech32 alphabet, or
+    /// mixes upper and lower case
+    InvalidCharacter(char),
+    /// The checksum does not match, so the address was mistyped
+    InvalidChecksum,
+    /// The encoded value does not fit in a u64
+    Overflow,
+}
+
+impl fmt::Display for AddressError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AddressError::InvalidPrefix => write!(f, "Address must start with {}1", ADDRESS_PREFIX),
+            AddressError::InvalidLength => write!(f, "Address has the wrong length"),
+            AddressError::InvalidCharacter(c) => write!(f, "Invalid character '{}' in address", c),
+            AddressError::InvalidChecksum => write!(f, "Address checksum does not match"),
+            AddressError::Overflow => write!(f, "Address value is too large"),
+        }
+    }
+}
+
+impl Error for AddressError {}
+
+/// Encode a contract id or address as a checksummed, human readable string
+///
+/// Uses the bech32 format, so any single mistyped character is detected when
+/// decoding
+pub fn encode_address(value: u64) -> String {
+    let data: Vec<u8> = (0..DATA_LENGTH)
+        .rev()
+        .map(|group| ((value >> (5 * group)) & 0x1f) as u8)
+        .collect();
+    let checksum = create_checksum(&data);
+
+    let mut address =
+        String::with_capacity(ADDRESS_PREFIX.len() + 1 + DATA_LENGTH + CHECKSUM_LENGTH);
+    address.push_str(ADDRESS_PREFIX);
+    address.push(SEPARATOR);
+    address.extend(
+        data.iter()
+            .chain(checksum.iter())
+            .map(|value| CHARSET[*value as usize] as char),
+    );
+    address
+}
+
+/// Decode an address created by encode_address
+///
+/// Upper case addresses are accepted, but not addresses mixing cases
+pub fn decode_address(address: &str) -> Result<u64, AddressError> {
+    let lower = address.to_lowercase();
+    if lower != address && address.to_uppercase() != address {
+        let mixed = address.chars().find(|c| c.is_uppercase()).unwrap();
+        return Err(AddressError::InvalidCharacter(mixed));
+    }
+
+    let prefix = format!("{}{}", ADDRESS_PREFIX, SEPARATOR);
+    if !lower.starts_with(&prefix) {
+        return Err(AddressError::InvalidPrefix);
+    }
+    let encoded = &lower[prefix.len()..];
+    if encoded.chars().count() != DATA_LENGTH + CHECKSUM_LENGTH {
+        return Err(AddressError::InvalidLength);
+    }
+
+    let values = encoded
+        .chars()
+        .map(|c| {
+            CHARSET
+                .iter()
+                .position(|x| *x as char == c)
+                .map(|value| value as u8)
+                .ok_or(AddressError::InvalidCharacter(c))
+        })
+        .collect::<Result<Vec<u8>, _>>()?;
+    let mut checked = expand_prefix();
+    checked.extend_from_slice(&values);
+    if polymod(&checked) != 1 {
+        return Err(AddressError::InvalidChecksum);
+    }
+
+    let data = &values[..DATA_LENGTH];
+    // The first group only holds the top 4 bits of the value
+    if data[0] > 0xf {
+        return Err(AddressError::Overflow);
+    }
+    Ok(data
+        .iter()
+        .fold(0, |value, group| (value << 5) | u64::from(*group)))
+}
+
+/// Compute the bech32 checksum of the address prefix and data
+fn create_checksum(data: &[u8]) -> Vec<u8> {
+    let mut values = expand_prefix();
+    values.extend_from_slice(data);
+    values.extend_from_slice(&[0; CHECKSUM_LENGTH]);
+    let checksum = polymod(&values) ^ 1;
+    (0..CHECKSUM_LENGTH)
+        .map(|i| ((checksum >> (5 * (CHECKSUM_LENGTH - 1 - i))) & 0x1f) as u8)
+        .collect()
+}
+
+/// Split the prefix characters into high and low bits, so the prefix is
+/// covered by the checksum
+fn expand_prefix() -> Vec<u8> {
+    let bytes = ADDRESS_PREFIX.as_bytes();
+    let mut expanded: Vec<u8> = bytes.iter().map(|c| c >> 5).collect();
+    expanded.push(0);
+    expanded.extend(bytes.iter().map(|c| c & 0x1f));
+    expanded
+}
+
+fn polymod(values: &[u8]) -> u32 {
+    values.iter().fold(1, |checksum, value| {
+        let top = checksum >> 25;
+        let mut checksum = ((checksum & 0x01ff_ffff) << 5) ^ u32::from(*value);
+        for (i, generator) in GENERATOR.iter().enumerate() {
+            if (top >> i) & 1 == 1 {
+                checksum ^= generator;
+            }
+        }
+        checksum
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_address_round_trip() {
+        for value in &[0, 1, 16, 6043537212972274484, u64::max_value()] {
+            let address = encode_address(*value);
+            assert!(address.starts_with("rd1"));
+            assert_eq!(address.len(), 3 + DATA_LENGTH + CHECKSUM_LENGTH);
+            assert_eq!(decode_address(&address), Ok(*value));
+            assert_eq!(decode_address(&address.to_uppercase()), Ok(*value));
+        }
+    }
+
+    #[test]
+    fn test_address_typo() {
+        let address = encode_address(6043537212972274484);
+        for (i, original) in address.char_indices().skip(3) {
+            for replacement in CHARSET.iter().map(|c| *c as char) {
+                if replacement == original {
+                    continue;
+                }
+                let mut typo = address.clone();
+                typo.replace_range(i..=i, &replacement.to_string());
+                assert_eq!(decode_address(&typo), Err(AddressError::InvalidChecksum));
+            }
+        }
+    }
+
+    #[test]
+    fn test_decode_invalid_address() {
+        let address = encode_address(16);
+        assert_eq!(
+            decode_address(&address[1..]),
+            Err(AddressError::InvalidPrefix)
+        );
+        assert_eq!(
+            decode_address(&address[..address.len() - 1]),
+            Err(AddressError::InvalidLength)
+        );
+        assert_eq!(
+            decode_address(&address.replace("rd1", "rd1b")[..address.len()]),
+            Err(AddressError::InvalidCharacter('b'))
+        );
+        assert_eq!(
+            decode_address(&address.replace("rd1", "Rd1")),
+            Err(AddressError::InvalidCharacter('R'))
+        );
+
+        // A correctly checksummed value wider than 64 bits
+        let data = vec![0x1f; DATA_LENGTH];
+        let overflow: String = data
+            .iter()
+            .chain(create_checksum(&data).iter())
+            .map(|value| CHARSET[*value as usize] as char)
+            .collect();
+        assert_eq!(
+            decode_address(&format!("rd1{}", overflow)),
+            Err(AddressError::Overflow)
+        );
+    }
+}
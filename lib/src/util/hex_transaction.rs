@@ -1,5 +1,4 @@
 use std::fmt;
-use std::num::ParseIntError;
 use std::{u32, u64};
 
 use serde::{
@@ -9,7 +8,7 @@ use serde::{
 
 use super::{u32_as_hex_string, u64_as_hex_string};
 
-use dag::transaction::{data::TransactionData, Transaction};
+use dag::transaction::{data::TransactionData, header::TransactionHeader, Transaction};
 
 #[derive(Clone, PartialEq, Debug)]
 pub struct HexEncodedTransaction {
@@ -23,6 +22,8 @@ pub struct HexEncodedTransaction {
     address: Vec<u8>,
     signature: Vec<u8>,
     data: TransactionData,
+    counter: Option<u64>,
+    expires_at: Option<u64>,
 }
 
 impl From<Transaction> for HexEncodedTransaction {
@@ -38,13 +39,15 @@ impl From<Transaction> for HexEncodedTransaction {
             address: transaction.get_address().to_vec(),
             signature: transaction.get_signature().to_vec(),
             data: transaction.get_data().clone(),
+            counter: transaction.get_counter(),
+            expires_at: transaction.get_expiration(),
         }
     }
 }
 
 impl From<HexEncodedTransaction> for Transaction {
     fn from(hex: HexEncodedTransaction) -> Transaction {
-        Transaction::raw(
+        let transaction = Transaction::raw(
             hex.branch_transaction,
             hex.trunk_transaction,
             hex.ref_transactions,
@@ -55,7 +58,15 @@ impl From<HexEncodedTransaction> for Transaction {
             hex.address,
             hex.signature,
             hex.data,
-        )
+        );
+        let transaction = match hex.counter {
+            Some(counter) => transaction.with_counter(counter),
+            None => transaction,
+        };
+        match hex.expires_at {
+            Some(expires_at) => transaction.with_expiration(expires_at),
+            None => transaction,
+        }
     }
 }
 
@@ -64,8 +75,8 @@ impl Serialize for HexEncodedTransaction {
     where
         S: Serializer,
     {
-        // 9 fields in the struct
-        let mut state = serializer.serialize_struct("HexEncodedTransaction", 9)?;
+        // 12 fields in the struct
+        let mut state = serializer.serialize_struct("HexEncodedTransaction", 12)?;
         // Serialize fields
         // Convert integer fields to hex strings
         state.serialize_field(
@@ -95,10 +106,55 @@ impl Serialize for HexEncodedTransaction {
             &base64::encode_config(&self.signature, base64::URL_SAFE),
         )?;
         state.serialize_field("data", &self.data)?;
+        // Unset optional fields are written as null, so every field keeps its
+        // position in formats without field names
+        state.serialize_field("counter", &self.counter.map(u64_as_hex_string))?;
+        state.serialize_field("expires_at", &self.expires_at.map(u64_as_hex_string))?;
         state.end()
     }
 }
 
+/// Check value is exactly length hex digits
+///
+/// from_str_radix alone accepts a leading sign and values of any length, so
+/// malformed values are rejected here with an error naming the field
+fn check_hex<E: de::Error>(value: &str, length: usize, field: &str) -> Result<(), E> {
+    if value.len() != length {
+        return Err(E::custom(format!(
+            "{} must be {} hex characters, got {}",
+            field,
+            length,
+            value.len()
+        )));
+    }
+    match value.chars().find(|c| !c.is_ascii_hexdigit()) {
+        Some(c) => Err(E::custom(format!(
+            "{} contains invalid hex character {:?}",
+            field, c
+        ))),
+        None => Ok(()),
+    }
+}
+
+/// Parse a u64 written by u64_as_hex_string
+fn parse_hex_u64<E: de::Error>(value: &str, field: &str) -> Result<u64, E> {
+    check_hex(value, 16, field)?;
+    u64::from_str_radix(value, 16).map_err(E::custom)
+}
+
+/// Parse a u32 written by u32_as_hex_string
+fn parse_hex_u32<E: de::Error>(value: &str, field: &str) -> Result<u32, E> {
+    check_hex(value, 8, field)?;
+    u32::from_str_radix(value, 16).map_err(E::custom)
+}
+
+fn parse_hex_refs<E: de::Error>(values: &[String]) -> Result<Vec<u64>, E> {
+    values
+        .iter()
+        .map(|value| parse_hex_u64(value, "ref_transactions"))
+        .collect()
+}
+
 impl<'de> Deserialize<'de> for HexEncodedTransaction {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
     where
@@ -118,6 +174,8 @@ impl<'de> Deserialize<'de> for HexEncodedTransaction {
             Address,
             Signature,
             Data,
+            Counter,
+            Expires_At,
         }
 
         struct TransactionVisitor;
@@ -133,85 +191,40 @@ impl<'de> Deserialize<'de> for HexEncodedTransaction {
             where
                 V: SeqAccess<'de>,
             {
-                let branch_transaction = u64::from_str_radix(
+                let branch_transaction = parse_hex_u64(
                     &seq.next_element::<String>()?
-                        .ok_or_else(|| de::Error::invalid_length(4, &self))?,
-                    16,
-                )
-                .map_err(|_| {
-                    de::Error::invalid_value(
-                        Unexpected::Str(&"branch_transaction"),
-                        &"valid hex string",
-                    )
-                })?;
-
-                let trunk_transaction = u64::from_str_radix(
+                        .ok_or_else(|| de::Error::invalid_length(0, &self))?,
+                    "branch_transaction",
+                )?;
+                let trunk_transaction = parse_hex_u64(
                     &seq.next_element::<String>()?
-                        .ok_or_else(|| de::Error::invalid_length(4, &self))?,
-                    16,
-                )
-                .map_err(|_| {
-                    de::Error::invalid_value(
-                        Unexpected::Str(&"trunk_transaction"),
-                        &"valid hex string",
-                    )
-                })?;
-
-                let ref_transaction_results: Vec<Result<u64, ParseIntError>> = seq
-                    .next_element::<Vec<String>>()?
-                    .ok_or_else(|| de::Error::invalid_length(2, &self))?
-                    .iter()
-                    .map(|val| u64::from_str_radix(&val, 16))
-                    .collect();
-                let mut ref_transactions: Vec<u64> =
-                    Vec::with_capacity(ref_transaction_results.len());
-                for item in ref_transaction_results.into_iter() {
-                    match item {
-                        Err(_) => {
-                            return Err(de::Error::invalid_value(
-                                Unexpected::Str("ref_transactions"),
-                                &"valid hex string",
-                            ))
-                        }
-                        Ok(valid_item) => ref_transactions.push(valid_item),
-                    }
-                }
-
-                let contract = u64::from_str_radix(
+                        .ok_or_else(|| de::Error::invalid_length(1, &self))?,
+                    "trunk_transaction",
+                )?;
+                let ref_transactions = parse_hex_refs(
+                    &seq.next_element::<Vec<String>>()?
+                        .ok_or_else(|| de::Error::invalid_length(2, &self))?,
+                )?;
+                let contract = parse_hex_u64(
                     &seq.next_element::<String>()?
-                        .ok_or_else(|| de::Error::invalid_length(4, &self))?,
-                    16,
-                )
-                .map_err(|_| {
-                    de::Error::invalid_value(Unexpected::Str(&"contract"), &"valid hex string")
-                })?;
-
-                let timestamp = u64::from_str_radix(
+                        .ok_or_else(|| de::Error::invalid_length(3, &self))?,
+                    "contract",
+                )?;
+                let timestamp = parse_hex_u64(
                     &seq.next_element::<String>()?
                         .ok_or_else(|| de::Error::invalid_length(4, &self))?,
-                    16,
-                )
-                .map_err(|_| {
-                    de::Error::invalid_value(Unexpected::Str(&"timestamp"), &"valid hex string")
-                })?;
-
-                let nonce = u32::from_str_radix(
+                    "timestamp",
+                )?;
+                let nonce = parse_hex_u32(
                     &seq.next_element::<String>()?
                         .ok_or_else(|| de::Error::invalid_length(5, &self))?,
-                    16,
-                )
-                .map_err(|_| {
-                    de::Error::invalid_value(Unexpected::Str(&"nonce"), &"valid hex string")
-                })?;
-
-                let root = u64::from_str_radix(
+                    "nonce",
+                )?;
+                let root = parse_hex_u64(
                     &seq.next_element::<String>()?
                         .ok_or_else(|| de::Error::invalid_length(6, &self))?,
-                    16,
-                )
-                .map_err(|_| {
-                    de::Error::invalid_value(Unexpected::Str(&"root"), &"valid hex string")
-                })?;
+                    "root",
+                )?;
 
                 let address = base64::decode_config(
                     &seq.next_element::<String>()?
@@ -235,6 +248,15 @@ impl<'de> Deserialize<'de> for HexEncodedTransaction {
                     .next_element()?
                     .ok_or_else(|| de::Error::invalid_length(9, &self))?;
 
+                let counter = match seq.next_element::<Option<String>>()?.unwrap_or(None) {
+                    Some(counter) => Some(parse_hex_u64(&counter, "counter")?),
+                    None => None,
+                };
+                let expires_at = match seq.next_element::<Option<String>>()?.unwrap_or(None) {
+                    Some(expires_at) => Some(parse_hex_u64(&expires_at, "expires_at")?),
+                    None => None,
+                };
+
                 Ok(HexEncodedTransaction {
                     branch_transaction,
                     trunk_transaction,
@@ -246,6 +268,8 @@ impl<'de> Deserialize<'de> for HexEncodedTransaction {
                     address,
                     signature,
                     data,
+                    counter,
+                    expires_at,
                 })
             }
 
@@ -263,6 +287,8 @@ impl<'de> Deserialize<'de> for HexEncodedTransaction {
                 let mut address = None;
                 let mut signature = None;
                 let mut data = None;
+                let mut counter = None;
+                let mut expires_at = None;
 
                 while let Some(key) = map.next_key()? {
                     match key {
@@ -270,114 +296,52 @@ impl<'de> Deserialize<'de> for HexEncodedTransaction {
                             if branch_transaction.is_some() {
                                 return Err(de::Error::duplicate_field("branch_transaction"));
                             }
-                            branch_transaction = Some(
-                                u64::from_str_radix(&map.next_value::<String>()?, 16).map_err(
-                                    |_| {
-                                        de::Error::invalid_value(
-                                            Unexpected::Str(&"branch_transaction"),
-                                            &"valid hex string",
-                                        )
-                                    },
-                                )?,
-                            );
+                            branch_transaction = Some(parse_hex_u64(
+                                &map.next_value::<String>()?,
+                                "branch_transaction",
+                            )?);
                         }
                         Field::Trunk_Transaction => {
                             if trunk_transaction.is_some() {
                                 return Err(de::Error::duplicate_field("trunk_transaction"));
                             }
-                            trunk_transaction = Some(
-                                u64::from_str_radix(&map.next_value::<String>()?, 16).map_err(
-                                    |_| {
-                                        de::Error::invalid_value(
-                                            Unexpected::Str(&"trunk_transaction"),
-                                            &"valid hex string",
-                                        )
-                                    },
-                                )?,
-                            );
+                            trunk_transaction = Some(parse_hex_u64(
+                                &map.next_value::<String>()?,
+                                "trunk_transaction",
+                            )?);
                         }
                         Field::Ref_Transactions => {
                             if ref_transactions.is_some() {
                                 return Err(de::Error::duplicate_field("ref_transactions"));
                             }
-                            let parsed: Vec<Result<u64, ParseIntError>> = map
-                                .next_value::<Vec<String>>()?
-                                .iter()
-                                .map(|val| u64::from_str_radix(&val, 16))
-                                .collect();
-                            let mut val: Vec<u64> = Vec::with_capacity(parsed.len());
-                            for item in parsed.into_iter() {
-                                match item {
-                                    Err(_) => {
-                                        return Err(de::Error::invalid_value(
-                                            Unexpected::Str(&"ref_transactions"),
-                                            &"valid hex string",
-                                        ))
-                                    }
-                                    Ok(valid_item) => val.push(valid_item),
-                                }
-                            }
-                            ref_transactions = Some(val);
+                            ref_transactions =
+                                Some(parse_hex_refs(&map.next_value::<Vec<String>>()?)?);
                         }
                         Field::Contract => {
                             if contract.is_some() {
                                 return Err(de::Error::duplicate_field("contract"));
                             }
-                            contract = Some(
-                                u64::from_str_radix(&map.next_value::<String>()?, 16).map_err(
-                                    |_| {
-                                        de::Error::invalid_value(
-                                            Unexpected::Str(&"contract"),
-                                            &"valid hex string",
-                                        )
-                                    },
-                                )?,
-                            );
+                            contract =
+                                Some(parse_hex_u64(&map.next_value::<String>()?, "contract")?);
                         }
                         Field::Timestamp => {
                             if timestamp.is_some() {
                                 return Err(de::Error::duplicate_field("timestamp"));
                             }
-                            timestamp = Some(
-                                u64::from_str_radix(&map.next_value::<String>()?, 16).map_err(
-                                    |_| {
-                                        de::Error::invalid_value(
-                                            Unexpected::Str(&"timestamp"),
-                                            &"valid hex string",
-                                        )
-                                    },
-                                )?,
-                            );
+                            timestamp =
+                                Some(parse_hex_u64(&map.next_value::<String>()?, "timestamp")?);
                         }
                         Field::Nonce => {
                             if nonce.is_some() {
                                 return Err(de::Error::duplicate_field("nonce"));
                             }
-                            nonce = Some(
-                                u32::from_str_radix(&map.next_value::<String>()?, 16).map_err(
-                                    |_| {
-                                        de::Error::invalid_value(
-                                            Unexpected::Str(&"nonce"),
-                                            &"valid hex string",
-                                        )
-                                    },
-                                )?,
-                            );
+                            nonce = Some(parse_hex_u32(&map.next_value::<String>()?, "nonce")?);
                         }
                         Field::Root => {
                             if root.is_some() {
                                 return Err(de::Error::duplicate_field("root"));
                             }
-                            root = Some(
-                                u64::from_str_radix(&map.next_value::<String>()?, 16).map_err(
-                                    |_| {
-                                        de::Error::invalid_value(
-                                            Unexpected::Str(&"root"),
-                                            &"valid hex string",
-                                        )
-                                    },
-                                )?,
-                            );
+                            root = Some(parse_hex_u64(&map.next_value::<String>()?, "root")?);
                         }
                         Field::Address => {
                             if address.is_some() {
@@ -419,6 +383,24 @@ impl<'de> Deserialize<'de> for HexEncodedTransaction {
                             }
                             data = Some(map.next_value()?);
                         }
+                        Field::Counter => {
+                            if counter.is_some() {
+                                return Err(de::Error::duplicate_field("counter"));
+                            }
+                            counter = Some(match map.next_value::<Option<String>>()? {
+                                Some(counter) => Some(parse_hex_u64(&counter, "counter")?),
+                                None => None,
+                            });
+                        }
+                        Field::Expires_At => {
+                            if expires_at.is_some() {
+                                return Err(de::Error::duplicate_field("expires_at"));
+                            }
+                            expires_at = Some(match map.next_value::<Option<String>>()? {
+                                Some(expires_at) => Some(parse_hex_u64(&expires_at, "expires_at")?),
+                                None => None,
+                            });
+                        }
                     }
                 }
 
@@ -447,6 +429,8 @@ impl<'de> Deserialize<'de> for HexEncodedTransaction {
                     address,
                     signature,
                     data,
+                    counter: counter.unwrap_or(None),
+                    expires_at: expires_at.unwrap_or(None),
                 })
             }
         }
@@ -461,15 +445,119 @@ impl<'de> Deserialize<'de> for HexEncodedTransaction {
             "address",
             "signature",
             "data",
+            "counter",
+            "expires_at",
         ];
         deserializer.deserialize_struct("HexEncodedTransaction", FIELDS, TransactionVisitor)
     }
 }
 
+/// A TransactionHeader with its hashes and integers encoded as hex strings
+#[derive(Clone, PartialEq, Debug)]
+pub struct HexTransactionHeader(TransactionHeader);
+
+impl From<TransactionHeader> for HexTransactionHeader {
+    fn from(header: TransactionHeader) -> Self {
+        HexTransactionHeader(header)
+    }
+}
+
+impl From<HexTransactionHeader> for TransactionHeader {
+    fn from(hex: HexTransactionHeader) -> TransactionHeader {
+        hex.0
+    }
+}
+
+impl Serialize for HexTransactionHeader {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let header = &self.0;
+        let mut state = serializer.serialize_struct("HexTransactionHeader", 10)?;
+        state.serialize_field("hash", &u64_as_hex_string(header.hash))?;
+        state.serialize_field(
+            "branch_transaction",
+            &u64_as_hex_string(header.branch_transaction),
+        )?;
+        state.serialize_field(
+            "trunk_transaction",
+            &u64_as_hex_string(header.trunk_transaction),
+        )?;
+        let refs: Vec<String> = header
+            .ref_transactions
+            .iter()
+            .map(|val| u64_as_hex_string(*val))
+            .collect();
+        state.serialize_field("ref_transactions", &refs)?;
+        state.serialize_field("contract", &u64_as_hex_string(header.contract))?;
+        state.serialize_field("timestamp", &u64_as_hex_string(header.timestamp))?;
+        state.serialize_field("nonce", &u32_as_hex_string(header.nonce))?;
+        state.serialize_field("root", &u64_as_hex_string(header.root))?;
+        state.serialize_field("counter", &header.counter.map(u64_as_hex_string))?;
+        state.serialize_field("expires_at", &header.expires_at.map(u64_as_hex_string))?;
+        state.end()
+    }
+}
+
+impl<'de> Deserialize<'de> for HexTransactionHeader {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        /// The header as it appears on the wire, before parsing the hex
+        #[derive(Deserialize)]
+        struct RawHeader {
+            hash: String,
+            branch_transaction: String,
+            trunk_transaction: String,
+            ref_transactions: Vec<String>,
+            contract: String,
+            timestamp: String,
+            nonce: String,
+            root: String,
+            #[serde(default)]
+            counter: Option<String>,
+            #[serde(default)]
+            expires_at: Option<String>,
+        }
+
+        let raw = RawHeader::deserialize(deserializer)?;
+        let counter = match raw.counter {
+            Some(counter) => Some(parse_hex_u64(&counter, "counter")?),
+            None => None,
+        };
+        let expires_at = match raw.expires_at {
+            Some(expires_at) => Some(parse_hex_u64(&expires_at, "expires_at")?),
+            None => None,
+        };
+        Ok(HexTransactionHeader(TransactionHeader {
+            hash: parse_hex_u64(&raw.hash, "hash")?,
+            branch_transaction: parse_hex_u64(&raw.branch_transaction, "branch_transaction")?,
+            trunk_transaction: parse_hex_u64(&raw.trunk_transaction, "trunk_transaction")?,
+            ref_transactions: parse_hex_refs(&raw.ref_transactions)?,
+            contract: parse_hex_u64(&raw.contract, "contract")?,
+            timestamp: parse_hex_u64(&raw.timestamp, "timestamp")?,
+            nonce: parse_hex_u32(&raw.nonce, "nonce")?,
+            root: parse_hex_u64(&raw.root, "root")?,
+            counter,
+            expires_at,
+        }))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    use std::fs;
+    use std::io::Write;
+    use std::path::PathBuf;
+
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+
+    use dag::contract::source::{ContractSource, MAX_CODE_SIZE};
     use security::keys::PrivateKey;
     use security::ring::digest::SHA512_256;
 
@@ -488,6 +576,18 @@ mod tests {
         assert_eq!(transaction.get_address(), converted.get_address());
         assert_eq!(transaction.get_signature(), converted.get_signature());
         assert_eq!(transaction.get_data(), converted.get_data());
+
+        let transaction = transaction.with_counter(7);
+        let hex: HexEncodedTransaction = transaction.clone().into();
+        let converted: Transaction = hex.into();
+        assert_eq!(transaction.get_counter(), converted.get_counter());
+        assert_eq!(transaction.get_hash(), converted.get_hash());
+
+        let transaction = transaction.with_expiration(9);
+        let hex: HexEncodedTransaction = transaction.clone().into();
+        let converted: Transaction = hex.into();
+        assert_eq!(transaction.get_expiration(), converted.get_expiration());
+        assert_eq!(transaction.get_hash(), converted.get_hash());
     }
 
     #[test]
@@ -504,7 +604,9 @@ mod tests {
             "root": "0000000000000006",
             "address": "",
             "signature": base64::encode_config(&vec![0; 8192], base64::URL_SAFE),
-            "data": TransactionData::Genesis
+            "data": TransactionData::Genesis,
+            "counter": null,
+            "expires_at": null
         });
         assert_eq!(json_value, serde_json::to_value(transaction).unwrap());
     }
@@ -528,6 +630,50 @@ mod tests {
         assert_eq!(transaction, serde_json::from_value(json_value).unwrap());
     }
 
+    #[test]
+    fn test_deserialize_malformed() {
+        let valid = json!({
+            "branch_transaction": "0000000000000000",
+            "trunk_transaction": "0000000000000001",
+            "ref_transactions": vec!["0000000000000002"],
+            "contract": "0000000000000003",
+            "timestamp": "0000000000000004",
+            "nonce": "00000005",
+            "root": "0000000000000006",
+            "address": "",
+            "signature": "",
+            "data": TransactionData::Genesis
+        });
+        assert!(serde_json::from_value::<HexEncodedTransaction>(valid.clone()).is_ok());
+
+        let error = |field: &str, value: serde_json::Value| {
+            let mut json_value = valid.clone();
+            json_value[field] = value;
+            serde_json::from_value::<HexEncodedTransaction>(json_value)
+                .unwrap_err()
+                .to_string()
+        };
+        assert!(error("trunk_transaction", json!("00000000000001"))
+            .starts_with("trunk_transaction must be 16 hex characters, got 14"));
+        assert!(error("nonce", json!("0005")).starts_with("nonce must be 8 hex characters"));
+        assert!(error("root", json!("+000000000000006"))
+            .starts_with("root contains invalid hex character '+'"));
+        assert!(error("ref_transactions", json!(["000000000000000g"]))
+            .starts_with("ref_transactions contains invalid hex character 'g'"));
+        assert!(error("counter", json!("")).starts_with("counter must be 16 hex characters"));
+
+        // Code decompressing past the size limit
+        let code = vec![0; MAX_CODE_SIZE + 1];
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::best());
+        encoder.write_all(&code).unwrap();
+        let bytes = encoder.finish().unwrap();
+        let data = json!({"GenContract": [
+            {"code": base64::encode_config(&bytes, base64::URL_SAFE)},
+            null
+        ]});
+        assert!(error("data", data).contains("larger than"));
+    }
+
     #[test]
     fn test_serialize_deserialize() {
         // Check the transaction is identical after serializing and deserializing
@@ -548,4 +694,49 @@ mod tests {
             serde_json::from_value(signed_json_value).unwrap()
         );
     }
+
+    #[test]
+    fn test_header() {
+        let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        path.push("resources/test/contracts/api_test.wasm");
+        let source = ContractSource::new(&fs::read(path).expect("Could not read test file"));
+        let mut transaction = Transaction::new(
+            0,
+            1,
+            vec![2],
+            3,
+            4,
+            5,
+            6,
+            TransactionData::GenContract(source, None),
+        )
+        .with_counter(7)
+        .with_expiration(8);
+        let mut key = PrivateKey::new(&SHA512_256);
+        transaction.sign(&mut key);
+
+        // The header matches the full transaction
+        let header = transaction.get_header();
+        assert_eq!(header.hash, transaction.get_hash());
+        assert_eq!(header.branch_transaction, transaction.get_branch_hash());
+        assert_eq!(header.trunk_transaction, transaction.get_trunk_hash());
+        assert_eq!(header.ref_transactions, transaction.get_ref_hashes());
+        assert_eq!(header.contract, transaction.get_contract());
+        assert_eq!(header.timestamp, transaction.get_timestamp());
+        assert_eq!(header.nonce, transaction.get_nonce());
+        assert_eq!(header.root, transaction.get_root());
+        assert_eq!(header.counter, Some(7));
+        assert_eq!(header.expires_at, Some(8));
+
+        // The header survives serializing and deserializing
+        let hex_header: HexTransactionHeader = header.clone().into();
+        let header_json = serde_json::to_string(&hex_header).unwrap();
+        let parsed: HexTransactionHeader = serde_json::from_str(&header_json).unwrap();
+        assert_eq!(header, parsed.into());
+
+        // The header leaves out the contract source and signature
+        let hex_transaction: HexEncodedTransaction = transaction.into();
+        let transaction_json = serde_json::to_string(&hex_transaction).unwrap();
+        assert!(header_json.len() * 20 < transaction_json.len());
+    }
 }
@@ -1,3 +1,4 @@
+use std::fmt;
 use std::time::{SystemTime, UNIX_EPOCH};
 
 /// Convert u64 to hex string
@@ -5,6 +6,16 @@ pub fn u64_as_hex_string(val: u64) -> String {
     format!("{:016x}", val)
 }
 
+/// Displays a transaction or contract id in the hex encoding used by the API
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct HashId(pub u64);
+
+impl fmt::Display for HashId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", u64_as_hex_string(self.0))
+    }
+}
+
 /// Convert u32 to hex string
 pub fn u32_as_hex_string(val: u32) -> String {
     format!("{:08x}", val)
@@ -32,6 +43,16 @@ mod tests {
         assert_eq!(u64_as_hex_string(18446744073709551615), "ffffffffffffffff");
     }
 
+    #[test]
+    fn test_hash_id_display() {
+        assert_eq!(HashId(16).to_string(), "0000000000000010");
+        for val in &[0, 16, 6043537212972274484, 18446744073709551615] {
+            let displayed = HashId(*val).to_string();
+            assert_eq!(displayed.len(), 16);
+            assert_eq!(u64::from_str_radix(&displayed, 16), Ok(*val));
+        }
+    }
+
     #[test]
     fn test_u32_as_hex_string() {
         assert_eq!(u32_as_hex_string(0), "00000000");
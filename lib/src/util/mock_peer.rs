@@ -0,0 +1,221 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+extern crate restson;
+use self::restson::Error;
+
+use dag::{
+    blockdag::BlockDAG,
+    contract::{Contract, ContractValue},
+    storage::mpt::node::Node,
+    transaction::{error::TransactionError, header::TransactionHeader, Transaction},
+};
+
+use util::peer::PeerApi;
+use util::types::{MergeBase, TransactionHashes, TransactionStatus};
+
+type LocalBlockDAG =
+    BlockDAG<HashMap<u64, Node<ContractValue>>, HashMap<u64, Transaction>, HashMap<u64, Contract>>;
+
+/// A peer backed by a dag in the same process
+///
+/// Clones of a MockPeer share the same dag, so clients can be tested without a
+/// running server
+#[derive(Clone, Default)]
+pub struct MockPeer {
+    dag: Rc<RefCell<LocalBlockDAG>>,
+}
+
+impl MockPeer {
+    pub fn new() -> Self {
+        MockPeer::default()
+    }
+}
+
+fn not_found(kind: &str) -> Error {
+    Error::HttpError(404, format!("{} not found", kind))
+}
+
+impl PeerApi for MockPeer {
+    fn get_transaction(&self, hash: u64) -> Result<Transaction, Error> {
+        self.dag
+            .borrow()
+            .get_transaction(hash)
+            .map(|transaction| transaction.clone())
+            .ok_or_else(|| not_found("Transaction"))
+    }
+
+    fn get_header(&self, hash: u64) -> Result<TransactionHeader, Error> {
+        self.dag
+            .borrow()
+            .get_transaction(hash)
+            .map(|transaction| transaction.get_header())
+            .ok_or_else(|| not_found("Transaction"))
+    }
+
+    fn post_transaction(&self, transaction: &Transaction) -> Result<TransactionStatus, Error> {
+        if let Some(known) = self.dag.borrow().get_transaction(transaction.get_hash()) {
+            if known.full_hash() == transaction.full_hash() {
+                return Ok(TransactionStatus::Known);
+            }
+        }
+        let updates = self.dag.borrow().try_add_transaction(transaction);
+        let status = match updates {
+            Ok(updates) => self
+                .dag
+                .borrow_mut()
+                .commit_transaction(transaction.clone(), updates),
+            Err(err) => Err(err),
+        };
+        Ok(match status {
+            Ok(status) => status,
+            Err(TransactionError::Rejected(reason)) => TransactionStatus::Rejected(reason),
+        })
+    }
+
+    fn get_tips(&self) -> Result<TransactionHashes, Error> {
+        Ok(self.dag.borrow().get_tips())
+    }
+
+    fn get_all_tips(&self) -> Result<Vec<Transaction>, Error> {
+        let dag = self.dag.borrow();
+        Ok(dag
+            .get_tip_hashes()
+            .into_iter()
+            .filter_map(|hash| {
+                dag.get_transaction(hash)
+                    .map(|transaction| transaction.clone())
+            })
+            .collect())
+    }
+
+    fn get_contract(&self, hash: u64) -> Result<Contract, Error> {
+        self.dag
+            .borrow()
+            .get_contract(hash)
+            .map(|contract| contract.clone())
+            .ok_or_else(|| not_found("Contract"))
+    }
+
+    fn get_mpt_node(&self, hash: u64) -> Result<Node<ContractValue>, Error> {
+        self.dag
+            .borrow()
+            .get_mpt_node(hash)
+            .map(|node| node.clone())
+            .ok_or_else(|| not_found("Node"))
+    }
+
+    fn get_merge_base(&self, trunk: u64, branch: u64) -> Result<MergeBase, Error> {
+        self.dag
+            .borrow()
+            .get_merge_base(trunk, branch)
+            .ok_or_else(|| not_found("Merge base"))
+    }
+
+    fn get_head_milestone(&self) -> Result<(u64, u64), Error> {
+        let dag = self.dag.borrow();
+        let milestone = dag.get_head_milestone();
+        Ok((milestone.get_hash(), milestone.get_timestamp()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::PathBuf;
+
+    use dag::contract::source::ContractSource;
+    use dag::transaction::data::TransactionData;
+    use security::keys::PrivateKey;
+    use security::ring::digest::SHA512_256;
+
+    #[test]
+    fn test_merge_base_matches_remote() {
+        let peer = MockPeer::new();
+        let root = peer.dag.borrow().get_mpt_default_root();
+        let mut hashes = Vec::new();
+        for _ in 0..4 {
+            let mut key = PrivateKey::new(&SHA512_256);
+            let transaction = peer
+                .build_signed_transaction(TransactionData::Empty, 0, Some(root), &mut key)
+                .unwrap();
+            assert_eq!(
+                peer.post_transaction(&transaction).unwrap(),
+                TransactionStatus::Pending
+            );
+            hashes.push(transaction.get_hash());
+        }
+
+        // The merge base computed by the peer matches the one computed from
+        // the transactions fetched from it
+        let remote = peer.clone().into_remote_blockdag();
+        for a in hashes.iter() {
+            for b in hashes.iter() {
+                let merge_base = peer.get_merge_base(*a, *b).unwrap();
+                assert_eq!(Some(merge_base.hash), remote.find_merge_base(*a, *b));
+                assert_eq!(merge_base, remote.get_merge_base(*a, *b).unwrap());
+            }
+        }
+    }
+
+    #[test]
+    fn test_build_signed_transaction_root() {
+        let peer = MockPeer::new();
+        let root = peer.dag.borrow().get_mpt_default_root();
+        let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        path.push("resources/test/contracts/api_test.wasm");
+        let source = ContractSource::new(&fs::read(path).expect("Could not read test file"));
+
+        // Change the state on one side of the dag
+        let mut key = PrivateKey::new(&SHA512_256);
+        let deploy = peer
+            .build_signed_transaction(
+                TransactionData::GenContract(source, None),
+                0,
+                Some(root),
+                &mut key,
+            )
+            .unwrap();
+        peer.post_transaction(&deploy).unwrap();
+        let deployed_root = peer.dag.borrow().compute_resulting_root(&deploy).unwrap();
+        let mut key = PrivateKey::new(&SHA512_256);
+        let exec = peer
+            .build_signed_transaction(
+                TransactionData::ExecContract(
+                    "set_u64".into(),
+                    vec![ContractValue::U32(1), ContractValue::U64(1)],
+                ),
+                deploy.get_hash(),
+                Some(deployed_root),
+                &mut key,
+            )
+            .unwrap();
+        peer.post_transaction(&exec).unwrap();
+
+        // Without a root, transactions claim the merge of their parents'
+        // roots, as the dag builds them
+        for _ in 0..4 {
+            let mut key = PrivateKey::new(&SHA512_256);
+            let transaction = peer
+                .build_signed_transaction(TransactionData::Empty, 0, None, &mut key)
+                .unwrap();
+            let built = peer
+                .dag
+                .borrow()
+                .build_transaction(
+                    transaction.get_trunk_hash(),
+                    transaction.get_branch_hash(),
+                    0,
+                    TransactionData::Empty,
+                )
+                .unwrap();
+            assert_eq!(transaction.get_root(), built.get_root());
+            assert_eq!(
+                peer.post_transaction(&transaction).unwrap(),
+                TransactionStatus::Pending
+            );
+        }
+    }
+}
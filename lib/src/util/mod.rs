@@ -1,8 +1,11 @@
+mod address;
 mod hex_transaction;
 mod misc;
 
+pub mod mock_peer;
 pub mod peer;
 pub mod types;
 
-pub use self::hex_transaction::HexEncodedTransaction;
+pub use self::address::{decode_address, encode_address, AddressError, ADDRESS_PREFIX};
+pub use self::hex_transaction::{HexEncodedTransaction, HexTransactionHeader};
 pub use self::misc::*;
@@ -1,5 +1,4 @@
-use std::cell::RefCell;
-use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
 
 extern crate restson;
 use self::restson::{Error, RestClient, RestPath};
@@ -7,12 +6,17 @@ use self::restson::{Error, RestClient, RestPath};
 use dag::{
     blockdag::BlockDAG,
     contract::{Contract, ContractValue},
+    storage::keyed::HashKeyMap,
     storage::map::{Map, MapError, MapResult, OOB},
-    storage::mpt::node::Node,
-    transaction::Transaction,
+    storage::mpt::{node::Node, MerklePatriciaTree},
+    transaction::{data::TransactionData, header::TransactionHeader, Transaction},
 };
 
-use util::types::{TransactionHashes, TransactionStatus};
+use security::hash::proof::random_proof_of_work;
+use security::keys::PrivateKey;
+
+use util::types::{MergeBase, NodeInfo, TransactionHashes, TransactionStatus};
+use util::HexTransactionHeader;
 
 impl RestPath<()> for TransactionHashes {
     fn get_path(_: ()) -> Result<String, Error> {
@@ -34,73 +38,305 @@ impl RestPath<TransactionRequest> for Transaction {
     }
 }
 
+impl RestPath<u64> for HexTransactionHeader {
+    fn get_path(hash: u64) -> Result<String, Error> {
+        Ok(format!("transaction/{}/header", hash))
+    }
+}
+
 impl RestPath<u64> for Contract {
     fn get_path(hash: u64) -> Result<String, Error> {
         Ok(format!("contract/{}", hash))
     }
 }
 
+impl RestPath<(u64, u64)> for MergeBase {
+    fn get_path(hashes: (u64, u64)) -> Result<String, Error> {
+        Ok(format!("merge-base/{}/{}", hashes.0, hashes.1))
+    }
+}
+
+impl RestPath<()> for NodeInfo {
+    fn get_path(_: ()) -> Result<String, Error> {
+        Ok(String::from("node/info"))
+    }
+}
+
+/// The transactions of every tip of a peer
+#[derive(Deserialize)]
+struct AllTips(Vec<Transaction>);
+
+impl RestPath<()> for AllTips {
+    fn get_path(_: ()) -> Result<String, Error> {
+        Ok(String::from("tips/all"))
+    }
+}
+
+/// The hash and timestamp of a peer's head milestone
+#[derive(Deserialize)]
+struct HeadMilestone(u64, u64);
+
+impl RestPath<()> for HeadMilestone {
+    fn get_path(_: ()) -> Result<String, Error> {
+        Ok(String::from("milestones/head"))
+    }
+}
+
 impl RestPath<u64> for Node<ContractValue> {
     fn get_path(hash: u64) -> Result<String, Error> {
         Ok(format!("node/{}", hash))
     }
 }
 
+/// Operations available on a remote node
+pub trait PeerApi {
+    fn get_transaction(&self, hash: u64) -> Result<Transaction, Error>;
+
+    /// Get the header of a transaction, without its data or signature
+    fn get_header(&self, hash: u64) -> Result<TransactionHeader, Error>;
+
+    fn post_transaction(&self, transaction: &Transaction) -> Result<TransactionStatus, Error>;
+
+    fn get_tips(&self) -> Result<TransactionHashes, Error>;
+
+    /// Get the transactions of every tip of the peer
+    fn get_all_tips(&self) -> Result<Vec<Transaction>, Error>;
+
+    fn get_contract(&self, hash: u64) -> Result<Contract, Error>;
+
+    fn get_mpt_node(&self, hash: u64) -> Result<Node<ContractValue>, Error>;
+
+    /// Get the merge base of the trunk and branch transactions, computed by
+    /// the peer
+    fn get_merge_base(&self, trunk: u64, branch: u64) -> Result<MergeBase, Error>;
+
+    /// Get the hash and timestamp of the peer's most recent confirmed
+    /// milestone
+    fn get_head_milestone(&self) -> Result<(u64, u64), Error>;
+
+    /// Build a transaction on the current tips of the peer, and sign it
+    ///
+    /// Without a root, the transaction claims the merge of the selected
+    /// tips' roots, as BlockDAG::build_transaction does. A given root
+    /// overrides it, and the transaction is executed against the state at
+    /// that root. Fails with Error::InvalidValue if the tips' roots conflict,
+    /// or if no nonce is valid for the selected tips
+    fn build_signed_transaction(
+        &self,
+        data: TransactionData,
+        contract: u64,
+        root: Option<u64>,
+        key: &mut PrivateKey,
+    ) -> Result<Transaction, Error>
+    where
+        Self: Sized + Clone,
+    {
+        let tips = self.get_tips()?;
+        let trunk = self.get_transaction(tips.trunk_hash)?;
+        let branch = self.get_transaction(tips.branch_hash)?;
+        let root = match root {
+            Some(root) => root,
+            None => merge_parent_roots(self, &trunk, &branch)?,
+        };
+        build_signed_transaction(&tips, &trunk, &branch, data, contract, root, key)
+            .ok_or(Error::InvalidValue)
+    }
+
+    /// Create a dag which reads its transactions, contracts and state from
+    /// the peer
+    fn into_remote_blockdag(self) -> RemoteBlockDAG<Self>
+    where
+        Self: Sized + Clone,
+    {
+        let t = TransactionPeer(self.clone());
+        let c = ContractPeer(self.clone());
+        let m = MPTNodePeer {
+            peer: self,
+            nodes: RwLock::default(),
+        };
+
+        BlockDAG::new(t, c, m)
+    }
+}
+
 #[derive(Clone, Deserialize)]
 pub struct Peer {
     client_url: String,
+    /// Check transactions against the peer's advertised policy before
+    /// posting them
+    #[serde(default)]
+    check_scheme: bool,
+    /// Policy advertised by the peer, fetched once and shared between clones
+    #[serde(skip)]
+    info: Arc<RwLock<Option<NodeInfo>>>,
 }
 
-pub struct TransactionPeer(Peer);
-pub struct ContractPeer(Peer);
-pub struct MPTNodePeer {
-    peer: Peer,
-    nodes: RefCell<HashMap<u64, Node<ContractValue>>>,
+pub struct TransactionPeer<P: PeerApi = Peer>(P);
+pub struct ContractPeer<P: PeerApi = Peer>(P);
+pub struct MPTNodePeer<P: PeerApi = Peer> {
+    peer: P,
+    /// Nodes received from the peer, shared between threads
+    nodes: RwLock<HashKeyMap<u64, Node<ContractValue>>>,
 }
 
+/// A dag backed by a peer
+pub type RemoteBlockDAG<P = Peer> = BlockDAG<MPTNodePeer<P>, TransactionPeer<P>, ContractPeer<P>>;
+
 impl Peer {
     pub fn new(client_url: String) -> Peer {
-        Peer { client_url }
+        Peer {
+            client_url,
+            check_scheme: false,
+            info: Arc::default(),
+        }
     }
 
-    pub fn into_remote_blockdag(self) -> BlockDAG<MPTNodePeer, TransactionPeer, ContractPeer> {
-        let t = TransactionPeer(self.clone());
-        let c = ContractPeer(self.clone());
-        let m = MPTNodePeer {
-            peer: self,
-            nodes: RefCell::default(),
-        };
+    /// Check transactions against the peer's policy before posting them, and
+    /// reject transactions signed with a scheme the peer does not accept
+    /// without posting them
+    ///
+    /// The policy is fetched with the first transaction, and reused after
+    pub fn with_scheme_check(self) -> Peer {
+        Peer {
+            check_scheme: true,
+            ..self
+        }
+    }
 
-        BlockDAG::new(t, c, m)
+    /// Get the transaction policy advertised by the peer
+    pub fn get_info(&self) -> Result<NodeInfo, Error> {
+        let mut client = RestClient::new(&self.client_url)?;
+        client.get(())
     }
 
-    pub fn get_transaction(&self, hash: u64) -> Result<Transaction, Error> {
+    /// Get the policy advertised by the peer, fetching it only if it has not
+    /// been fetched before
+    fn cached_info(&self) -> Result<NodeInfo, Error> {
+        if let Some(info) = self.info.read().unwrap().as_ref() {
+            return Ok(info.clone());
+        }
+        let info = self.get_info()?;
+        *self.info.write().unwrap() = Some(info.clone());
+        Ok(info)
+    }
+}
+
+impl PeerApi for Peer {
+    fn get_transaction(&self, hash: u64) -> Result<Transaction, Error> {
         let mut client = RestClient::new(&self.client_url)?;
         client.get(TransactionRequest::GET(hash))
     }
 
-    pub fn post_transaction(&self, transaction: &Transaction) -> Result<TransactionStatus, Error> {
+    fn get_header(&self, hash: u64) -> Result<TransactionHeader, Error> {
+        let mut client = RestClient::new(&self.client_url)?;
+        let header: HexTransactionHeader = client.get(hash)?;
+        Ok(header.into())
+    }
+
+    fn post_transaction(&self, transaction: &Transaction) -> Result<TransactionStatus, Error> {
+        if self.check_scheme {
+            if let Err(reason) = self.cached_info()?.check_scheme(transaction) {
+                return Ok(TransactionStatus::Rejected(reason));
+            }
+        }
         let mut client = RestClient::new(&self.client_url)?;
         client.post_capture(TransactionRequest::POST(), transaction)
     }
 
-    pub fn get_tips(&self) -> TransactionHashes {
-        let mut client = RestClient::new(&self.client_url).unwrap();
-        client.get(()).unwrap()
+    fn get_tips(&self) -> Result<TransactionHashes, Error> {
+        let mut client = RestClient::new(&self.client_url)?;
+        client.get(())
+    }
+
+    fn get_all_tips(&self) -> Result<Vec<Transaction>, Error> {
+        let mut client = RestClient::new(&self.client_url)?;
+        let AllTips(tips) = client.get(())?;
+        Ok(tips)
     }
 
-    pub fn get_contract(&self, hash: u64) -> Result<Contract, Error> {
+    fn get_contract(&self, hash: u64) -> Result<Contract, Error> {
         let mut client = RestClient::new(&self.client_url)?;
         client.get(hash)
     }
 
-    pub fn get_mpt_node(&self, hash: u64) -> Result<Node<ContractValue>, Error> {
+    fn get_mpt_node(&self, hash: u64) -> Result<Node<ContractValue>, Error> {
         let mut client = RestClient::new(&self.client_url)?;
         client.get(hash)
     }
+
+    fn get_merge_base(&self, trunk: u64, branch: u64) -> Result<MergeBase, Error> {
+        let mut client = RestClient::new(&self.client_url)?;
+        client.get((trunk, branch))
+    }
+
+    fn get_head_milestone(&self) -> Result<(u64, u64), Error> {
+        let mut client = RestClient::new(&self.client_url)?;
+        let HeadMilestone(hash, timestamp) = client.get(())?;
+        Ok((hash, timestamp))
+    }
+}
+
+/// Merge the contract state roots of trunk and branch, relative to the root
+/// of their merge base, reading the state from the peer
+///
+/// Parents claiming no root leave nothing to merge, so the merge claims no
+/// root either. Fails with Error::InvalidValue if the roots conflict, or are
+/// not stored by the peer
+fn merge_parent_roots<P: PeerApi + Clone>(
+    peer: &P,
+    trunk: &Transaction,
+    branch: &Transaction,
+) -> Result<u64, Error> {
+    let (trunk_root, branch_root) = (trunk.get_root(), branch.get_root());
+    if trunk_root == branch_root {
+        return Ok(trunk_root);
+    }
+    if trunk_root == 0 || branch_root == 0 {
+        return Ok(0);
+    }
+    let base_root = peer
+        .get_merge_base(trunk.get_hash(), branch.get_hash())?
+        .root;
+    let nodes = MPTNodePeer {
+        peer: peer.clone(),
+        nodes: RwLock::default(),
+    };
+    for root in &[trunk_root, branch_root, base_root] {
+        nodes.get(root).map_err(|_| Error::InvalidValue)?;
+    }
+    MerklePatriciaTree::new(nodes)
+        .try_merge(trunk_root, branch_root, base_root)
+        .map(|updates| updates.get_root_hash())
+        .ok_or(Error::InvalidValue)
+}
+
+/// Build a transaction referencing trunk and branch, and sign it
+///
+/// Returns None if there is no valid nonce for trunk and branch
+fn build_signed_transaction(
+    tips: &TransactionHashes,
+    trunk: &Transaction,
+    branch: &Transaction,
+    data: TransactionData,
+    contract: u64,
+    root: u64,
+    key: &mut PrivateKey,
+) -> Option<Transaction> {
+    let nonce = random_proof_of_work(trunk.get_nonce(), branch.get_nonce())?;
+    let mut transaction = Transaction::create(
+        tips.branch_hash,
+        tips.trunk_hash,
+        tips.ref_hashes.clone(),
+        contract,
+        nonce,
+        root,
+        data,
+    );
+    transaction.sign(key);
+    Some(transaction)
 }
 
-impl Map<u64, Transaction> for TransactionPeer {
+impl<P: PeerApi> Map<u64, Transaction> for TransactionPeer<P> {
     fn get(&self, k: &u64) -> MapResult<OOB<Transaction>> {
         match self.0.get_transaction(*k) {
             Ok(transaction) => Ok(OOB::Owned(transaction)),
@@ -118,7 +354,7 @@ impl Map<u64, Transaction> for TransactionPeer {
     }
 }
 
-impl Map<u64, Contract> for ContractPeer {
+impl<P: PeerApi> Map<u64, Contract> for ContractPeer<P> {
     fn get(&self, k: &u64) -> MapResult<OOB<Contract>> {
         match self.0.get_contract(*k) {
             Ok(contract) => Ok(OOB::Owned(contract)),
@@ -131,24 +367,168 @@ impl Map<u64, Contract> for ContractPeer {
     }
 }
 
-impl Map<u64, Node<ContractValue>> for MPTNodePeer {
+impl<P: PeerApi> Map<u64, Node<ContractValue>> for MPTNodePeer<P> {
     fn get(&self, k: &u64) -> MapResult<OOB<Node<ContractValue>>> {
         // Get from the local nodes
-        let nodes_borrow = self.nodes.borrow();
-        if let Some(node) = nodes_borrow.get(k) {
+        if let Some(node) = self.nodes.read().unwrap().get(k) {
             return Ok(OOB::Owned(node.clone()));
         }
         // If the node does not exist, request from peer
-        drop(nodes_borrow);
         let node = self
             .peer
             .get_mpt_node(*k)
             .map_err(|_err| MapError::LookupError)?;
-        self.nodes.borrow_mut().insert(*k, node.clone());
+        self.nodes.write().unwrap().insert(*k, node.clone());
         Ok(OOB::Owned(node))
     }
     fn set(&mut self, k: u64, v: Node<ContractValue>) -> MapResult<()> {
-        self.nodes.borrow_mut().insert(k, v);
+        self.nodes.get_mut().unwrap().insert(k, v);
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    use crossbeam_utils::thread;
+
+    use security::hash::proof::valid_proof;
+    use security::ring::digest::SHA512_256;
+
+    /// A peer serving a fixed set of contract state nodes, counting the
+    /// nodes requested from it
+    #[derive(Clone)]
+    struct NodePeer {
+        nodes: Arc<HashMap<u64, Node<ContractValue>>>,
+        requests: Arc<AtomicUsize>,
+    }
+
+    /// Fail a request NodePeer does not serve
+    fn not_served<T>() -> Result<T, Error> {
+        Err(Error::HttpError(501, "Not served by the test peer".into()))
+    }
+
+    impl PeerApi for NodePeer {
+        fn get_transaction(&self, _: u64) -> Result<Transaction, Error> {
+            not_served()
+        }
+
+        fn get_header(&self, _: u64) -> Result<TransactionHeader, Error> {
+            not_served()
+        }
+
+        fn post_transaction(&self, _: &Transaction) -> Result<TransactionStatus, Error> {
+            not_served()
+        }
+
+        fn get_tips(&self) -> Result<TransactionHashes, Error> {
+            not_served()
+        }
+
+        fn get_all_tips(&self) -> Result<Vec<Transaction>, Error> {
+            not_served()
+        }
+
+        fn get_contract(&self, _: u64) -> Result<Contract, Error> {
+            not_served()
+        }
+
+        fn get_mpt_node(&self, hash: u64) -> Result<Node<ContractValue>, Error> {
+            self.requests.fetch_add(1, Ordering::SeqCst);
+            self.nodes
+                .get(&hash)
+                .cloned()
+                .ok_or_else(|| Error::HttpError(404, "Node not found".into()))
+        }
+
+        fn get_merge_base(&self, _: u64, _: u64) -> Result<MergeBase, Error> {
+            not_served()
+        }
+
+        fn get_head_milestone(&self) -> Result<(u64, u64), Error> {
+            not_served()
+        }
+    }
+
+    fn assert_send_sync<T: Send + Sync>() {}
+
+    #[test]
+    fn test_build_signed_transaction() {
+        let dag = BlockDAG::<HashMap<_, _>, HashMap<_, _>, HashMap<_, _>>::default();
+        let tips = dag.get_tips();
+        let trunk = dag.get_transaction(tips.trunk_hash).unwrap().clone();
+        let branch = dag.get_transaction(tips.branch_hash).unwrap().clone();
+
+        let mut key = PrivateKey::new(&SHA512_256);
+        let root = dag.get_mpt_default_root();
+        let transaction = build_signed_transaction(
+            &tips,
+            &trunk,
+            &branch,
+            TransactionData::Empty,
+            0,
+            root,
+            &mut key,
+        )
+        .unwrap();
+
+        assert!(transaction.verify());
+        assert!(valid_proof(
+            trunk.get_nonce(),
+            branch.get_nonce(),
+            transaction.get_nonce()
+        ));
+        assert_eq!(transaction.get_trunk_hash(), tips.trunk_hash);
+        assert_eq!(transaction.get_branch_hash(), tips.branch_hash);
+        assert!(dag.try_add_transaction(&transaction).is_ok());
+    }
+
+    #[test]
+    fn test_mpt_node_peer_threads() {
+        assert_send_sync::<MPTNodePeer<Peer>>();
+
+        let mut source = MerklePatriciaTree::<ContractValue, _>::new(HashMap::new());
+        let mut root = source.default_root();
+        for k in 0..32 {
+            root = source.set(root, k * 31, ContractValue::U64(k)).unwrap();
+        }
+        let peer = NodePeer {
+            nodes: Arc::new(source.inner_map()),
+            requests: Arc::new(AtomicUsize::new(0)),
+        };
+        let storage = MerklePatriciaTree::new(MPTNodePeer {
+            peer: peer.clone(),
+            nodes: RwLock::default(),
+        });
+
+        thread::scope(|scope| {
+            let handles: Vec<_> = (0..2)
+                .map(|_| {
+                    let storage = &storage;
+                    scope.spawn(move |_| {
+                        for k in 0..32 {
+                            let value = storage.get(root, k * 31).unwrap();
+                            assert_eq!(*value, ContractValue::U64(k));
+                        }
+                    })
+                })
+                .collect();
+            for handle in handles {
+                handle.join().unwrap();
+            }
+        })
+        .unwrap();
+
+        // Every node is cached once fetched
+        let requests = peer.requests.load(Ordering::SeqCst);
+        assert!(requests > 0);
+        for k in 0..32 {
+            assert!(storage.get(root, k * 31).is_ok());
+        }
+        assert_eq!(requests, peer.requests.load(Ordering::SeqCst));
+    }
+}
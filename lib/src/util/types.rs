@@ -1,8 +1,27 @@
+use std::fmt;
+use std::hash::Hasher;
+
+use dag::contract::error::ContractError;
+use dag::contract::state::TraceEntry;
+use dag::contract::ContractValue;
+use dag::transaction::Transaction;
+
+use security::hash::hasher::Sha3Hasher;
+use security::keys::{
+    verify_validator_signature, KeyFile, KeyScheme, ValidatorKey, SUPPORTED_SCHEMES,
+};
+
+use util::HashId;
+
 /// Stores the hashes returned from tip selection
 #[derive(Serialize, Deserialize, Debug)]
 pub struct TransactionHashes {
     pub trunk_hash: u64,
     pub branch_hash: u64,
+    /// Evicted tips, which the new transaction should reference so they stay
+    /// connected to the dag
+    #[serde(default)]
+    pub ref_hashes: Vec<u64>,
 }
 
 impl TransactionHashes {
@@ -10,6 +29,59 @@ impl TransactionHashes {
         TransactionHashes {
             trunk_hash,
             branch_hash,
+            ref_hashes: Vec::new(),
+        }
+    }
+
+    pub fn with_ref_hashes(mut self, ref_hashes: Vec<u64>) -> Self {
+        self.ref_hashes = ref_hashes;
+        self
+    }
+}
+
+/// The closest common ancestor of two transactions, and its contract state
+/// root
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+pub struct MergeBase {
+    pub hash: u64,
+    pub root: u64,
+}
+
+impl MergeBase {
+    pub fn new(hash: u64, root: u64) -> MergeBase {
+        MergeBase { hash, root }
+    }
+}
+
+/// A contract function to execute with tracing, against the state at root
+#[derive(Serialize, Deserialize, Debug)]
+pub struct TraceRequest {
+    pub func_name: String,
+    pub args: Vec<ContractValue>,
+    pub root: u64,
+}
+
+/// Contract functions to execute read-only, all against the state at root
+#[derive(Serialize, Deserialize, Debug)]
+pub struct MulticallRequest {
+    /// Function names and their arguments
+    pub calls: Vec<(String, Vec<ContractValue>)>,
+    pub root: u64,
+}
+
+/// The result of a traced contract call
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+pub struct ContractTrace {
+    pub return_values: Vec<ContractValue>,
+    /// Api calls made by the contract, in order
+    pub trace: Vec<TraceEntry>,
+}
+
+impl ContractTrace {
+    pub fn new(return_values: Vec<ContractValue>, trace: Vec<TraceEntry>) -> ContractTrace {
+        ContractTrace {
+            return_values,
+            trace,
         }
     }
 }
@@ -18,7 +90,303 @@ impl TransactionHashes {
 #[derive(Serialize, Deserialize, PartialEq, Debug)]
 pub enum TransactionStatus {
     Accepted,
-    Rejected(String),
+    Rejected(RejectionReason),
     Pending,
     Milestone,
+    /// The transaction was already added, so it is not broadcast again
+    Known,
+}
+
+impl fmt::Display for TransactionStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TransactionStatus::Accepted => write!(f, "Accepted"),
+            TransactionStatus::Rejected(reason) => write!(f, "Rejected: {}", reason),
+            TransactionStatus::Pending => write!(f, "Pending"),
+            TransactionStatus::Milestone => write!(f, "Milestone"),
+            TransactionStatus::Known => write!(f, "Known"),
+        }
+    }
+}
+
+/// The status of a transaction, along with the state root of the milestone
+/// that confirmed it
+///
+/// Light clients can fetch merkle proofs against the anchor root without
+/// replaying the dag
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+pub struct ConfirmationStatus {
+    pub status: TransactionStatus,
+    pub anchor_root: Option<u64>,
+}
+
+impl ConfirmationStatus {
+    pub fn new(status: TransactionStatus, anchor_root: Option<u64>) -> ConfirmationStatus {
+        ConfirmationStatus {
+            status,
+            anchor_root,
+        }
+    }
+}
+
+/// A summary of the state of a node
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+pub struct NodeStats {
+    pub tip_count: usize,
+    pub mpt_node_count: usize,
+    /// Hash of the most recent milestone
+    pub head_milestone: u64,
+    /// Number of zero bytes transactions' proofs of work should end with
+    pub suggested_difficulty: usize,
+}
+
+/// A milestone deep enough that its state root is final
+///
+/// Clients can sync the state at root instead of replaying the dag from
+/// genesis
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
+pub struct Checkpoint {
+    /// Hash of the milestone transaction
+    pub hash: u64,
+    /// State root of the milestone
+    pub root: u64,
+    pub timestamp: u64,
+}
+
+/// A checkpoint signed by the node serving it
+///
+/// Nodes sign every checkpoint with the same validator key, so a client
+/// trusting a node's public key can check any checkpoint the node serves
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
+pub struct SignedCheckpoint {
+    pub checkpoint: Checkpoint,
+    /// Public key of the node which signed the checkpoint
+    pub key: KeyFile,
+    pub signature: Vec<u8>,
+}
+
+impl SignedCheckpoint {
+    pub fn sign(checkpoint: Checkpoint, key: &ValidatorKey) -> SignedCheckpoint {
+        let signature = key.sign(&Self::signing_bytes(&checkpoint));
+        SignedCheckpoint {
+            checkpoint,
+            key: KeyFile::from_validator_public_key(&key.public_key()),
+            signature,
+        }
+    }
+
+    /// Check the checkpoint was signed with trusted_key
+    ///
+    /// The key the checkpoint claims is not checked, since anyone can sign a
+    /// checkpoint with their own key
+    pub fn verify(&self, trusted_key: &[u8]) -> bool {
+        let bytes = Self::signing_bytes(&self.checkpoint);
+        verify_validator_signature(trusted_key, &bytes, &self.signature)
+    }
+
+    fn signing_bytes(checkpoint: &Checkpoint) -> Vec<u8> {
+        let mut hasher = Sha3Hasher::new();
+        hasher.write_u64(checkpoint.hash);
+        hasher.write_u64(checkpoint.root);
+        hasher.write_u64(checkpoint.timestamp);
+        hasher.finish_bytes()
+    }
+}
+
+/// The transaction policy a node advertises to its clients
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
+pub struct NodeInfo {
+    /// Signature schemes the node accepts transactions signed with
+    pub signature_schemes: Vec<KeyScheme>,
+    /// Whether transactions must carry a valid proof of work
+    pub require_pow: bool,
+}
+
+impl NodeInfo {
+    pub fn new(require_pow: bool) -> NodeInfo {
+        let mut signature_schemes = SUPPORTED_SCHEMES.to_vec();
+        // Validator keys are accepted from listed validators whether or not
+        // the proof of work is required
+        signature_schemes.push(KeyScheme::Ed25519);
+        NodeInfo {
+            signature_schemes,
+            require_pow,
+        }
+    }
+
+    /// Check if the node would accept the transaction's signature scheme
+    pub fn check_scheme(&self, transaction: &Transaction) -> Result<(), RejectionReason> {
+        let scheme = transaction.get_scheme();
+        if self.signature_schemes.contains(&scheme) {
+            Ok(())
+        } else {
+            Err(RejectionReason::UnsupportedScheme(scheme))
+        }
+    }
+}
+
+/// The reason a transaction was rejected
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
+pub enum RejectionReason {
+    /// The proof of work nonce is invalid
+    BadNonce,
+    /// The transaction's signature does not match its address
+    BadSignature,
+    /// A parent or referenced transaction, specified by hash, was not found
+    MissingParent(u64),
+    /// The contract state roots of the transaction's parents could not be merged
+    MergeRoot,
+    /// The transaction's counter is not greater than the last counter seen
+    /// from its address
+    Replayed,
+    /// The node has reached its limit of pending transactions
+    MempoolFull,
+    /// The transaction references the same parent more than once, or
+    /// references itself
+    SelfReference,
+    /// The transaction's expiration time has passed
+    Expired,
+    /// The proof of work is disabled, and the transaction is not signed by a
+    /// validator
+    NotValidator,
+    /// The transaction is signed with a scheme the node does not accept
+    UnsupportedScheme(KeyScheme),
+    /// The contract call's function name or arguments exceed the node's
+    /// limits
+    ArgsTooLarge,
+    /// The contract failed to be created or executed
+    Contract(ContractError),
+    Other(String),
+}
+
+impl From<ContractError> for RejectionReason {
+    fn from(error: ContractError) -> Self {
+        RejectionReason::Contract(error)
+    }
+}
+
+impl fmt::Display for RejectionReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RejectionReason::BadNonce => write!(f, "Invalid nonce"),
+            RejectionReason::BadSignature => write!(f, "Invalid signature"),
+            RejectionReason::MissingParent(hash) => {
+                write!(f, "Transaction not found: {}", HashId(*hash))
+            }
+            RejectionReason::MergeRoot => write!(f, "Failed to merge parent roots"),
+            RejectionReason::Replayed => write!(f, "Transaction counter already used"),
+            RejectionReason::MempoolFull => write!(f, "Too many pending transactions"),
+            RejectionReason::SelfReference => write!(f, "Transaction references itself"),
+            RejectionReason::Expired => write!(f, "Transaction expired"),
+            RejectionReason::NotValidator => write!(f, "Not signed by a validator"),
+            RejectionReason::UnsupportedScheme(scheme) => {
+                write!(f, "Unsupported signature scheme: {:?}", scheme)
+            }
+            RejectionReason::ArgsTooLarge => write!(f, "Contract call arguments too large"),
+            RejectionReason::Contract(err) => write!(f, "Contract error: {}", err),
+            RejectionReason::Other(reason) => write!(f, "{}", reason),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use dag::transaction::data::TransactionData;
+    use security::keys::PrivateKey;
+    use security::ring::digest::SHA512_256;
+
+    #[test]
+    fn test_rejection_serialization() {
+        let status = TransactionStatus::Rejected(RejectionReason::MissingParent(10));
+        let value = serde_json::to_value(&status).unwrap();
+        assert_eq!(value, json!({"Rejected": {"MissingParent": 10}}));
+        assert_eq!(
+            serde_json::from_value::<TransactionStatus>(value).unwrap(),
+            status
+        );
+    }
+
+    #[test]
+    fn test_contract_rejection_serialization() {
+        let reason = RejectionReason::from(ContractError::ContractNotFound(3));
+        let value = serde_json::to_value(&reason).unwrap();
+        assert_eq!(value, json!({"Contract": {"ContractNotFound": 3}}));
+        assert_eq!(
+            serde_json::from_value::<RejectionReason>(value).unwrap(),
+            reason
+        );
+    }
+
+    #[test]
+    fn test_signed_checkpoint() {
+        let key = ValidatorKey::from_pkcs8(&ValidatorKey::generate_pkcs8().unwrap()).unwrap();
+        let trusted_key = key.public_key();
+        let checkpoint = |hash: u64| Checkpoint {
+            hash,
+            root: 2,
+            timestamp: 3,
+        };
+
+        // The same key signs every checkpoint
+        let first = SignedCheckpoint::sign(checkpoint(1), &key);
+        let second = SignedCheckpoint::sign(checkpoint(2), &key);
+        assert!(first.verify(&trusted_key));
+        assert!(second.verify(&trusted_key));
+        assert_eq!(
+            first.key.to_validator_public_key(),
+            Some(trusted_key.clone())
+        );
+
+        // The checkpoint cannot be swapped
+        let mut tampered = first.clone();
+        tampered.checkpoint.root = 4;
+        assert!(!tampered.verify(&trusted_key));
+
+        // Checkpoints signed by other keys are not trusted, whatever key they
+        // claim
+        let other = ValidatorKey::from_pkcs8(&ValidatorKey::generate_pkcs8().unwrap()).unwrap();
+        let mut forged = SignedCheckpoint::sign(checkpoint(1), &other);
+        forged.key = first.key.clone();
+        assert!(!forged.verify(&trusted_key));
+    }
+
+    #[test]
+    fn test_node_info_scheme() {
+        let mut key = PrivateKey::new(&SHA512_256);
+        let mut transaction = Transaction::create(0, 0, vec![], 0, 0, 0, TransactionData::Empty);
+        transaction.sign(&mut key);
+
+        let info = NodeInfo::new(true);
+        assert_eq!(
+            serde_json::to_value(&info).unwrap(),
+            json!({"signature_schemes": ["LamportSha512_256", "Ed25519"], "require_pow": true})
+        );
+        assert_eq!(info.check_scheme(&transaction), Ok(()));
+
+        // Validator keys are accepted with or without the proof of work
+        let key = ValidatorKey::from_pkcs8(&ValidatorKey::generate_pkcs8().unwrap()).unwrap();
+        let mut validated = Transaction::create(0, 0, vec![], 0, 0, 0, TransactionData::Empty);
+        validated.sign_validator(&key);
+        assert_eq!(info.check_scheme(&validated), Ok(()));
+        assert_eq!(NodeInfo::new(false).check_scheme(&validated), Ok(()));
+        assert_eq!(
+            NodeInfo::new(false).signature_schemes,
+            info.signature_schemes
+        );
+
+        // A node which does not accept lamport signatures rejects the
+        // transaction before it is posted
+        let restricted = NodeInfo {
+            signature_schemes: vec![],
+            require_pow: true,
+        };
+        assert_eq!(
+            restricted.check_scheme(&transaction),
+            Err(RejectionReason::UnsupportedScheme(
+                KeyScheme::LamportSha512_256
+            ))
+        );
+    }
 }
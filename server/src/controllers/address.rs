@@ -0,0 +1,13 @@
+use rocket::{Route, State};
+use rocket_contrib::json::Json;
+
+use dagmanager::DAGManager;
+
+pub fn address_routes() -> Vec<Route> {
+    routes![get_address_transactions]
+}
+
+#[get("/<address>/transactions")]
+fn get_address_transactions(address: u64, dag: State<DAGManager>) -> Json<Vec<u64>> {
+    Json(dag.inner().get_transactions_by_address(address))
+}
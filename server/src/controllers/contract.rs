@@ -1,15 +1,100 @@
+use rocket::http::ContentType;
+use rocket::response::{content::Content, status::BadRequest};
 use rocket::{Route, State};
 use rocket_contrib::json::Json;
 
-use rustdag_lib::dag::contract::Contract;
+use rustdag_lib::dag::contract::{abi::ContractAbi, Contract, ContractValue};
+use rustdag_lib::util::types::{ContractTrace, MulticallRequest, TraceRequest};
 
 use dagmanager::DAGManager;
+use negotiate::Negotiated;
+
+/// Number of contracts listed when no limit is given
+const DEFAULT_LIST_LIMIT: usize = 100;
 
 pub fn contract_routes() -> Vec<Route> {
-    routes![get_contract]
+    routes![
+        get_contract,
+        get_contract_abi,
+        get_contract_source,
+        list_contracts,
+        trace_contract,
+        multicall_contract
+    ]
 }
 
 #[get("/<hash>")]
-fn get_contract(hash: u64, dag: State<DAGManager>) -> Option<Json<Contract>> {
-    dag.inner().get_contract(hash).and_then(|x| Some(Json(x)))
+fn get_contract(hash: u64, dag: State<DAGManager>) -> Option<Negotiated<Contract>> {
+    dag.inner().get_contract(hash).map(Negotiated)
+}
+
+/// Get the abi the contract was deployed with, if it has one
+#[get("/<hash>/abi")]
+fn get_contract_abi(hash: u64, dag: State<DAGManager>) -> Option<Json<ContractAbi>> {
+    dag.inner()
+        .get_contract(hash)
+        .and_then(|contract| contract.get_abi().cloned())
+        .map(Json)
+}
+
+/// Get the uncompressed wasm code of the contract, base64 encoded if
+/// requested
+#[get("/<hash>/source?<base64>")]
+fn get_contract_source(
+    hash: u64,
+    base64: Option<bool>,
+    dag: State<DAGManager>,
+) -> Option<Content<Vec<u8>>> {
+    let code = dag.inner().get_contract_source(hash)?;
+    Some(if base64.unwrap_or(false) {
+        Content(ContentType::Plain, base64::encode(&code).into_bytes())
+    } else {
+        Content(ContentType::new("application", "wasm"), code)
+    })
+}
+
+#[get("/list?<offset>&<limit>")]
+fn list_contracts(
+    offset: Option<usize>,
+    limit: Option<usize>,
+    dag: State<DAGManager>,
+) -> Json<Vec<u64>> {
+    Json(
+        dag.inner()
+            .list_contracts(offset.unwrap_or(0), limit.unwrap_or(DEFAULT_LIST_LIMIT)),
+    )
+}
+
+/// Debug route executing a contract function and listing the api calls it
+/// makes. The resulting state is discarded
+#[post("/<hash>/trace", data = "<request>")]
+fn trace_contract(
+    hash: u64,
+    request: Json<TraceRequest>,
+    dag: State<DAGManager>,
+) -> Option<Result<Json<ContractTrace>, BadRequest<String>>> {
+    dag.inner()
+        .trace_contract(hash, request.into_inner())
+        .map(|result| {
+            result
+                .map(Json)
+                .map_err(|err| BadRequest(Some(err.to_string())))
+        })
+}
+
+/// Execute several contract functions read-only against the same root, so
+/// their results are consistent
+#[post("/<hash>/multicall", data = "<request>")]
+fn multicall_contract(
+    hash: u64,
+    request: Json<MulticallRequest>,
+    dag: State<DAGManager>,
+) -> Option<Result<Json<Vec<Vec<ContractValue>>>, BadRequest<String>>> {
+    dag.inner()
+        .multicall_contract(hash, request.into_inner())
+        .map(|result| {
+            result
+                .map(Json)
+                .map_err(|err| BadRequest(Some(err.to_string())))
+        })
 }
@@ -0,0 +1,13 @@
+use rocket::{Route, State};
+use rocket_contrib::json::Json;
+
+use dagmanager::DAGManager;
+
+pub fn event_routes() -> Vec<Route> {
+    routes![get_events]
+}
+
+#[get("/<topic>")]
+fn get_events(topic: u64, dag: State<DAGManager>) -> Json<Vec<(u64, Vec<u8>)>> {
+    Json(dag.inner().get_events(topic))
+}
@@ -0,0 +1,13 @@
+use rocket::response::content::Plain;
+use rocket::{Route, State};
+
+use dagmanager::DAGManager;
+
+pub fn metrics_routes() -> Vec<Route> {
+    routes![get_metrics]
+}
+
+#[get("/metrics")]
+fn get_metrics(dag: State<DAGManager>) -> Plain<String> {
+    Plain(dag.inner().render_metrics())
+}
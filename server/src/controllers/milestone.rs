@@ -0,0 +1,40 @@
+use rocket::response::status::BadRequest;
+use rocket::{Route, State};
+use rocket_contrib::json::Json;
+
+use rustdag_lib::util::types::SignedCheckpoint;
+
+use dagmanager::DAGManager;
+
+pub fn milestone_routes() -> Vec<Route> {
+    routes![get_head_milestone, get_transactions_between]
+}
+
+pub fn checkpoint_routes() -> Vec<Route> {
+    routes![get_checkpoint]
+}
+
+#[get("/head")]
+fn get_head_milestone(dag: State<DAGManager>) -> Json<(u64, u64)> {
+    Json(dag.inner().get_head_milestone())
+}
+
+/// List the transactions confirmed after milestone older, up to and
+/// including milestone newer
+#[get("/<older>/<newer>/transactions")]
+fn get_transactions_between(
+    older: u64,
+    newer: u64,
+    dag: State<DAGManager>,
+) -> Result<Json<Vec<u64>>, BadRequest<String>> {
+    dag.inner()
+        .transactions_between(older, newer)
+        .map(Json)
+        .map_err(|err| BadRequest(Some(err.to_string())))
+}
+
+/// Get the newest finalized checkpoint, signed by this node
+#[get("/checkpoint")]
+fn get_checkpoint(dag: State<DAGManager>) -> Option<Json<SignedCheckpoint>> {
+    dag.inner().get_checkpoint().map(Json)
+}
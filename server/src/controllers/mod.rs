@@ -1,3 +1,7 @@
+pub mod address;
 pub mod contract;
+pub mod event;
+pub mod metrics;
+pub mod milestone;
 pub mod node;
 pub mod transaction;
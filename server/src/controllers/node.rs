@@ -2,14 +2,30 @@ use rocket::{Route, State};
 use rocket_contrib::json::Json;
 
 use rustdag_lib::dag::{contract::ContractValue, storage::mpt::node::Node};
+use rustdag_lib::util::types::{NodeInfo, NodeStats};
 
 use dagmanager::DAGManager;
 
 pub fn node_routes() -> Vec<Route> {
-    routes![get_mpt_node]
+    routes![get_mpt_node, get_stats, get_info, get_orphans]
 }
 
 #[get("/<hash>")]
 fn get_mpt_node(hash: u64, dag: State<DAGManager>) -> Option<Json<Node<ContractValue>>> {
     dag.inner().get_mpt_node(hash).and_then(|x| Some(Json(x)))
 }
+
+#[get("/stats")]
+fn get_stats(dag: State<DAGManager>) -> Json<NodeStats> {
+    Json(dag.inner().get_stats())
+}
+
+#[get("/info")]
+fn get_info(dag: State<DAGManager>) -> Json<NodeInfo> {
+    Json(dag.inner().get_info())
+}
+
+#[get("/orphans")]
+fn get_orphans(dag: State<DAGManager>) -> Json<Vec<u64>> {
+    Json(dag.inner().get_orphans())
+}
@@ -2,29 +2,32 @@ use rocket::{Route, State};
 use rocket_contrib::json::Json;
 
 use rustdag_lib::dag::transaction::Transaction;
-use rustdag_lib::util::{types::TransactionStatus, HexEncodedTransaction};
+use rustdag_lib::util::{
+    types::{ConfirmationStatus, TransactionStatus},
+    HexEncodedTransaction, HexTransactionHeader,
+};
 
 use dagmanager::DAGManager;
+use negotiate::Negotiated;
 
 pub fn transaction_routes() -> Vec<Route> {
     routes![
         get_transaction,
         get_transaction_status,
         get_transaction_hex,
+        get_transaction_header,
         post_transaction,
         post_hex_transaction
     ]
 }
 
 #[get("/<hash>")]
-fn get_transaction(hash: u64, dag: State<DAGManager>) -> Option<Json<Transaction>> {
-    dag.inner()
-        .get_transaction(hash)
-        .and_then(|x| Some(Json(x)))
+fn get_transaction(hash: u64, dag: State<DAGManager>) -> Option<Negotiated<Transaction>> {
+    dag.inner().get_transaction(hash).map(Negotiated)
 }
 
 #[get("/<hash>/status")]
-fn get_transaction_status(hash: u64, dag: State<DAGManager>) -> Json<TransactionStatus> {
+fn get_transaction_status(hash: u64, dag: State<DAGManager>) -> Json<ConfirmationStatus> {
     Json(dag.inner().get_transaction_status(hash))
 }
 
@@ -35,6 +38,13 @@ fn get_transaction_hex(hash: u64, dag: State<DAGManager>) -> Option<Json<HexEnco
         .and_then(|x| Some(Json(x.into())))
 }
 
+#[get("/<hash>/header")]
+fn get_transaction_header(hash: u64, dag: State<DAGManager>) -> Option<Json<HexTransactionHeader>> {
+    dag.inner()
+        .get_transaction_header(hash)
+        .map(|header| Json(header.into()))
+}
+
 #[post("/", data = "<transaction>")]
 fn post_transaction(
     transaction: Json<Transaction>,
@@ -1,41 +1,237 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::io::{Read, Write};
 use std::marker::{Send, Sync};
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender};
 use std::sync::Arc;
-use std::sync::RwLock;
-use std::thread;
+use std::sync::{Mutex, RwLock};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use restson::Error;
 
 use dag::{
     blockdag::{BlockDAG, ContractStorage, TransactionStorage},
-    contract::{state::ContractStateStorage, Contract, ContractValue},
+    contract::{error::ContractError, state::ContractStateStorage, Contract, ContractValue},
+    error::BlockDAGError,
     milestone::pending::MilestoneSignature,
-    storage::mpt::node::Node,
-    transaction::{error::TransactionError, Transaction},
+    storage::{keyed::HashKeyMap, mpt::node::Node},
+    transaction::{
+        data::TransactionData, error::TransactionError, header::TransactionHeader, Transaction,
+    },
 };
+use metrics::Metrics;
 use peermanager::PeerManager;
-use util::peer::Peer;
-use util::types::{TransactionHashes, TransactionStatus};
+use rustdag_lib::security::keys::ValidatorKey;
+use util::peer::{Peer, PeerApi};
+use util::types::{
+    ConfirmationStatus, ContractTrace, MergeBase, MulticallRequest, NodeInfo, NodeStats,
+    RejectionReason, SignedCheckpoint, TraceRequest, TransactionHashes, TransactionStatus,
+};
+use util::HashId;
 
 pub type DAGManager = GenericDAGManager<
-    HashMap<u64, Node<ContractValue>>,
-    HashMap<u64, Transaction>,
+    HashKeyMap<u64, Node<ContractValue>>,
+    HashKeyMap<u64, Transaction>,
     HashMap<u64, Contract>,
 >;
 
+/// Maximum number of milestones waiting to be verified
+///
+/// Adding a milestone transaction blocks while the queue is full
+const MILESTONE_QUEUE_SIZE: usize = 16;
+
+/// Maximum number of transactions committed at once while syncing from a peer
+const SYNC_BATCH_SIZE: usize = 64;
+
+/// Maximum number of missing transactions downloaded while syncing from a
+/// peer
+const MAX_SYNC_TRANSACTIONS: usize = 16_384;
+
+/// Failure to sync the dag from a peer
+#[derive(Debug)]
+pub enum SyncError {
+    /// The peer could not be reached, or did not have a transaction
+    Peer(Error),
+    /// A transaction received from the peer was rejected
+    Rejected(RejectionReason),
+    /// Committing the transactions received from the peer failed
+    Dag(BlockDAGError),
+    /// The peer answered a request for a transaction, specified by hash, with
+    /// a different transaction
+    HashMismatch(u64),
+    /// More transactions than the limit are missing
+    TooManyMissing(usize),
+}
+
+/// Failure to sync the dag from a peer, after committing some of the missing
+/// transactions
+#[derive(Debug)]
+pub struct SyncFailure {
+    /// Number of transactions committed before the failure
+    pub committed: usize,
+    pub error: SyncError,
+}
+
+impl SyncFailure {
+    fn new<E: Into<SyncError>>(committed: usize, error: E) -> Self {
+        SyncFailure {
+            committed,
+            error: error.into(),
+        }
+    }
+}
+
+impl From<Error> for SyncError {
+    fn from(error: Error) -> Self {
+        SyncError::Peer(error)
+    }
+}
+
+impl From<BlockDAGError> for SyncError {
+    fn from(error: BlockDAGError) -> Self {
+        match error {
+            BlockDAGError::Rejected(_, reason) => SyncError::Rejected(reason),
+            error => SyncError::Dag(error),
+        }
+    }
+}
+
+/// The milestone queue, and the thread processing it
+struct MilestoneWorker {
+    sender: Option<SyncSender<Transaction>>,
+    worker: Option<JoinHandle<()>>,
+}
+
+impl MilestoneWorker {
+    /// Wait for all queued milestones to be processed
+    fn stop(&mut self) {
+        // Closing the channel stops the worker once the queue is empty
+        self.sender.take();
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
+
 pub struct GenericDAGManager<M: ContractStateStorage, T: TransactionStorage, C: ContractStorage> {
     dag: Arc<RwLock<BlockDAG<M, T, C>>>,
     peers: RwLock<PeerManager>,
+    metrics: Arc<Metrics>,
+    milestone_worker: Arc<Mutex<MilestoneWorker>>,
+    /// Key the node signs checkpoints with
+    node_key: RwLock<Option<ValidatorKey>>,
+}
+
+/// Stops a dag manager from outside of it, such as from a signal handler
+pub struct ShutdownHandle<M: ContractStateStorage, T: TransactionStorage> {
+    dag: Arc<RwLock<BlockDAG<M, T, HashMap<u64, Contract>>>>,
+    milestone_worker: Arc<Mutex<MilestoneWorker>>,
+}
+
+impl<M: ContractStateStorage, T: TransactionStorage> ShutdownHandle<M, T> {
+    /// Stop the milestone worker and write the pending transactions to writer
+    ///
+    /// Queued milestones are processed first, so transactions they confirm
+    /// are not written. The transactions are written as a JSON array, which
+    /// GenericDAGManager::restore_pending reads, and the writer is flushed.
+    /// Returns the number of transactions written
+    pub fn shutdown<W: Write>(&self, mut writer: W) -> serde_json::Result<usize> {
+        self.milestone_worker.lock().unwrap().stop();
+        let pending = self.dag.read().unwrap().get_pending_transactions();
+        serde_json::to_writer(&mut writer, &pending)?;
+        writer.flush().map_err(serde_json::Error::io)?;
+        Ok(pending.len())
+    }
 }
 
 impl<
-        M: ContractStateStorage + Default,
-        T: TransactionStorage + Default,
-        C: ContractStorage + Default,
-    > Default for GenericDAGManager<M, T, C>
+        M: 'static + ContractStateStorage + Default + Send + Sync,
+        T: 'static + TransactionStorage + Default + Send + Sync,
+    > Default for GenericDAGManager<M, T, HashMap<u64, Contract>>
 {
     fn default() -> Self {
+        let dag = Arc::new(RwLock::from(BlockDAG::default()));
+        let (milestone_sender, milestone_receiver) = sync_channel(MILESTONE_QUEUE_SIZE);
+        let metrics = Arc::new(Metrics::default());
+        let worker_dag = Arc::clone(&dag);
+        let worker_metrics = Arc::clone(&metrics);
+        let milestone_worker = thread::spawn(move || {
+            process_milestones(&worker_dag, &worker_metrics, milestone_receiver)
+        });
         GenericDAGManager {
-            dag: Arc::new(RwLock::from(BlockDAG::default())),
+            dag,
             peers: RwLock::from(PeerManager::new()),
+            metrics,
+            milestone_worker: Arc::new(Mutex::new(MilestoneWorker {
+                sender: Some(milestone_sender),
+                worker: Some(milestone_worker),
+            })),
+            node_key: RwLock::new(None),
+        }
+    }
+}
+
+impl<M: ContractStateStorage, T: TransactionStorage, C: ContractStorage> Drop
+    for GenericDAGManager<M, T, C>
+{
+    /// Wait for all queued milestones to be processed
+    fn drop(&mut self) {
+        self.milestone_worker.lock().unwrap().stop();
+    }
+}
+
+/// Verify and sign milestones received from the milestone queue, until the
+/// queue is closed
+fn process_milestones<M: ContractStateStorage, T: TransactionStorage>(
+    dag: &RwLock<BlockDAG<M, T, HashMap<u64, Contract>>>,
+    metrics: &Metrics,
+    milestones: Receiver<Transaction>,
+) {
+    for transaction in milestones {
+        let milestone_hash = transaction.get_hash();
+        let mut chain: Vec<Transaction>;
+        {
+            // Verify milestone
+            match dag.read().unwrap().verify_milestone(transaction) {
+                Ok(_chain) => {
+                    chain = _chain;
+                }
+                Err(err) => {
+                    // TODO fetch missing transactions from peers
+                    warn!(
+                        "Skipping milestone {} with missing transactions: {}",
+                        HashId(milestone_hash),
+                        err
+                    );
+                    continue;
+                }
+            }
+            // Reverse the chain so that the elements closest to the
+            // milestone are in front
+            chain = chain.into_iter().rev().collect();
+        }
+        {
+            // Add chain
+            let mut dag = dag.write().unwrap();
+            if !dag.process_chain(milestone_hash, chain) {
+                error!(
+                    "Could not process the chain of milestone {}",
+                    HashId(milestone_hash)
+                );
+                continue;
+            }
+            if true {
+                // Sign all existing contracts, in id order
+                // TODO Proper signing
+                dag.add_pending_signature(MilestoneSignature::new(milestone_hash, 0, 0));
+                for contract in dag.get_contracts() {
+                    dag.add_pending_signature(MilestoneSignature::new(milestone_hash, contract, 0));
+                }
+            }
+            // The milestone is only confirmed once its signatures approve it
+            if dag.get_head_milestone().get_hash() == milestone_hash {
+                metrics.milestone_confirmed();
+            }
         }
     }
 }
@@ -49,6 +245,22 @@ impl<
         self.dag.read().unwrap().get_tips()
     }
 
+    /// Select tips, avoiding the transactions in exclude where possible
+    pub fn get_tips_excluding(&self, exclude: &[u64]) -> TransactionHashes {
+        self.dag.read().unwrap().get_tips_excluding(exclude)
+    }
+
+    /// Iterate over the transactions of every tip
+    ///
+    /// The tip hashes are read when called, and each transaction is fetched
+    /// as the iterator advances
+    pub fn tip_transactions(&self) -> impl Iterator<Item = Transaction> + '_ {
+        let hashes = self.dag.read().unwrap().get_tip_hashes();
+        hashes
+            .into_iter()
+            .filter_map(move |hash| self.get_transaction(hash))
+    }
+
     pub fn get_transaction(&self, hash: u64) -> Option<Transaction> {
         self.dag
             .read()
@@ -57,6 +269,15 @@ impl<
             .and_then(|t| Some(t.clone()))
     }
 
+    /// Get the header of a transaction, without cloning its data
+    pub fn get_transaction_header(&self, hash: u64) -> Option<TransactionHeader> {
+        self.dag
+            .read()
+            .unwrap()
+            .get_transaction(hash)
+            .map(|t| t.get_header())
+    }
+
     pub fn get_contract(&self, hash: u64) -> Option<Contract> {
         self.dag
             .read()
@@ -65,6 +286,39 @@ impl<
             .and_then(|c| Some(c.clone()))
     }
 
+    /// Get the uncompressed wasm code of a contract
+    pub fn get_contract_source(&self, hash: u64) -> Option<Vec<u8>> {
+        self.dag.read().unwrap().get_contract_source(hash)
+    }
+
+    pub fn list_contracts(&self, offset: usize, limit: usize) -> Vec<u64> {
+        self.dag.read().unwrap().list_contracts(offset, limit)
+    }
+
+    /// Execute a contract function, recording the api calls it makes
+    pub fn trace_contract(
+        &self,
+        id: u64,
+        request: TraceRequest,
+    ) -> Option<Result<ContractTrace, ContractError>> {
+        self.dag
+            .read()
+            .unwrap()
+            .trace_contract(id, &request.func_name, &request.args, request.root)
+    }
+
+    /// Execute several contract functions read-only against a single root
+    pub fn multicall_contract(
+        &self,
+        id: u64,
+        request: MulticallRequest,
+    ) -> Option<Result<Vec<Vec<ContractValue>>, ContractError>> {
+        self.dag
+            .read()
+            .unwrap()
+            .multicall_contract(id, &request.calls, request.root)
+    }
+
     pub fn get_mpt_node(&self, hash: u64) -> Option<Node<ContractValue>> {
         self.dag
             .read()
@@ -73,20 +327,63 @@ impl<
             .and_then(|n| Some(n.clone()))
     }
 
-    pub fn get_transaction_status(&self, hash: u64) -> TransactionStatus {
-        self.dag.read().unwrap().get_confirmation_status(hash)
+    /// Get the hash and timestamp of the most recent confirmed milestone
+    pub fn get_head_milestone(&self) -> (u64, u64) {
+        let dag = self.dag.read().unwrap();
+        let milestone = dag.get_head_milestone();
+        (milestone.get_hash(), milestone.get_timestamp())
+    }
+
+    /// List the transactions confirmed between two milestones
+    pub fn transactions_between(&self, older: u64, newer: u64) -> Result<Vec<u64>, BlockDAGError> {
+        self.dag.read().unwrap().transactions_between(older, newer)
+    }
+
+    /// Get the closest common ancestor of trunk and branch, and its root
+    pub fn get_merge_base(&self, trunk: u64, branch: u64) -> Option<MergeBase> {
+        self.dag.read().unwrap().get_merge_base(trunk, branch)
+    }
+
+    /// Get the hashes of the transactions signed by the address with the id
+    /// address
+    pub fn get_transactions_by_address(&self, address: u64) -> Vec<u64> {
+        self.dag
+            .read()
+            .unwrap()
+            .get_transactions_by_address(address)
+    }
+
+    /// Get the events emitted by contracts under topic, with the hash of the
+    /// transaction emitting each one
+    pub fn get_events(&self, topic: u64) -> Vec<(u64, Vec<u8>)> {
+        self.dag.read().unwrap().get_events(topic)
+    }
+
+    /// Get the status of a transaction, and the root of the milestone that
+    /// confirmed it
+    pub fn get_transaction_status(&self, hash: u64) -> ConfirmationStatus {
+        let dag = self.dag.read().unwrap();
+        ConfirmationStatus::new(dag.get_confirmation_status(hash), dag.get_anchor_root(hash))
     }
 
     pub fn add_transaction(&self, transaction: Transaction) -> TransactionStatus {
         let hash = transaction.get_hash();
         {
-            // Ignore any already known transactions
+            // Ignore any already known transactions. Only new transactions
+            // are broadcast, so known transactions stop spreading here
             let current_status = self.dag.read().unwrap().get_confirmation_status(hash);
             if current_status == TransactionStatus::Accepted
                 || current_status == TransactionStatus::Pending
                 || current_status == TransactionStatus::Milestone
             {
-                return current_status;
+                let tampered = self
+                    .get_transaction(hash)
+                    .map_or(false, |known| known.full_hash() != transaction.full_hash());
+                if tampered {
+                    self.metrics.transaction_rejected();
+                    return TransactionStatus::Rejected(RejectionReason::BadSignature);
+                }
+                return TransactionStatus::Known;
             }
         }
 
@@ -94,63 +391,662 @@ impl<
         match dag_read.try_add_transaction(&transaction) {
             Ok(updates) => {
                 drop(dag_read);
-                let mut dag_write = self.dag.write().unwrap();
-                match dag_write.commit_transaction(transaction.clone(), updates) {
+                let status = self
+                    .dag
+                    .write()
+                    .unwrap()
+                    .commit_transaction(transaction.clone(), updates);
+                match status {
                     Ok(status) => {
-                        self.peers
-                            .read()
-                            .unwrap()
-                            .map_peers(|peer| peer.post_transaction(&transaction));
+                        self.metrics.transaction_accepted();
+                        match transaction.get_data() {
+                            TransactionData::GenContract(..)
+                            | TransactionData::ExecContract(..) => self.metrics.contract_executed(),
+                            _ => {}
+                        }
+                        self.broadcast_transaction(&transaction);
                         if status == TransactionStatus::Milestone {
-                            let dag = Arc::clone(&self.dag);
-                            thread::spawn(move || {
-                                let mut chain: Vec<Transaction>;
-                                let milestone_hash = transaction.get_hash();
-                                {
-                                    // Verify milestone
-                                    match dag.read().unwrap().verify_milestone(transaction) {
-                                        Ok(_chain) => {
-                                            chain = _chain;
-                                        }
-                                        Err(_err) => {
-                                            // TODO missing transactions
-                                            panic!("Missing Transactions: {:?}", _err);
-                                        }
-                                    }
-                                    // Reverse the chain so that the elements closest to the
-                                    // milestone are in front
-                                    chain = chain.into_iter().rev().collect();
-                                }
-                                {
-                                    // Add chain
-                                    let mut dag = dag.write().unwrap();
-                                    dag.process_chain(milestone_hash, chain);
-                                    if true {
-                                        // Sign all existing contracts
-                                        // TODO Proper signing
-                                        dag.add_pending_signature(MilestoneSignature::new(
-                                            hash, 0, 0,
-                                        ));
-                                        for contract in dag.get_contracts() {
-                                            dag.add_pending_signature(MilestoneSignature::new(
-                                                hash, contract, 0,
-                                            ));
-                                        }
-                                    }
-                                }
-                            });
+                            if let Err(reason) = self.queue_milestone(transaction) {
+                                return TransactionStatus::Rejected(reason);
+                            }
                         }
                         status
                     }
-                    Err(TransactionError::Rejected(msg)) => TransactionStatus::Rejected(msg),
+                    Err(TransactionError::Rejected(msg)) => {
+                        self.metrics.transaction_rejected();
+                        TransactionStatus::Rejected(msg)
+                    }
                 }
             }
-            Err(TransactionError::Rejected(msg)) => TransactionStatus::Rejected(msg),
+            Err(TransactionError::Rejected(msg)) => {
+                self.metrics.transaction_rejected();
+                TransactionStatus::Rejected(msg)
+            }
+        }
+    }
+
+    /// Render the node's metrics in the Prometheus text format
+    pub fn render_metrics(&self) -> String {
+        let dag = self.dag.read().unwrap();
+        self.metrics
+            .render(dag.get_tip_count(), dag.get_mpt_node_count())
+    }
+
+    /// Get the transaction policy advertised to clients
+    pub fn get_info(&self) -> NodeInfo {
+        NodeInfo::new(self.dag.read().unwrap().requires_pow())
+    }
+
+    /// Get the hashes of parents referenced by committed transactions, but
+    /// never received
+    pub fn get_orphans(&self) -> Vec<u64> {
+        self.dag.read().unwrap().orphans()
+    }
+
+    /// Set the key the node signs checkpoints with
+    pub fn set_node_key(&self, key: ValidatorKey) {
+        *self.node_key.write().unwrap() = Some(key);
+    }
+
+    /// Get the newest finalized checkpoint, signed with the node key
+    ///
+    /// Returns None until a milestone is deep enough to be final, or if the
+    /// node has no key
+    pub fn get_checkpoint(&self) -> Option<SignedCheckpoint> {
+        let checkpoint = self.dag.read().unwrap().finalized_checkpoint()?;
+        let node_key = self.node_key.read().unwrap();
+        Some(SignedCheckpoint::sign(checkpoint, node_key.as_ref()?))
+    }
+
+    /// Summarize the state of the node
+    pub fn get_stats(&self) -> NodeStats {
+        let dag = self.dag.read().unwrap();
+        NodeStats {
+            tip_count: dag.get_tip_count(),
+            mpt_node_count: dag.get_mpt_node_count(),
+            head_milestone: dag.get_head_milestone().get_hash(),
+            suggested_difficulty: dag.suggested_difficulty().0,
         }
     }
 
+    /// Post a transaction to every peer, and rate the peers by whether it was
+    /// accepted
+    ///
+    /// The peers are only locked to be listed and rated, so slow peers do not
+    /// block other requests
+    fn broadcast_transaction(&self, transaction: &Transaction) {
+        let peers = self.peers.read().unwrap().peers();
+        let results: Vec<_> = peers
+            .into_iter()
+            .map(|peer| {
+                let succeeded = peer.post_transaction(transaction).is_ok();
+                (peer, succeeded)
+            })
+            .collect();
+        self.peers.write().unwrap().rate(&results);
+    }
+
+    /// Queue a milestone to be verified and signed by the milestone worker
+    ///
+    /// Blocks while the milestone queue is full. Fails if the milestone
+    /// worker has stopped
+    fn queue_milestone(&self, transaction: Transaction) -> Result<(), RejectionReason> {
+        // Clone the sender, so the lock is not held while the queue is full
+        let sender = self.milestone_worker.lock().unwrap().sender.clone();
+        if let Some(sender) = sender {
+            // The worker only stops once the sender is dropped, or if it
+            // panicked
+            if let Err(err) = sender.send(transaction) {
+                error!(
+                    "Could not queue milestone {}: milestone worker stopped",
+                    err.0.get_hash()
+                );
+                return Err(RejectionReason::Other("Milestone worker stopped".into()));
+            }
+        }
+        Ok(())
+    }
+
+    /// Spawn a thread dropping expired pending transactions every interval
+    ///
+    /// The thread runs for as long as the process does
+    pub fn spawn_expiry_sweeper(&self, interval: Duration) -> JoinHandle<()> {
+        let dag = Arc::clone(&self.dag);
+        thread::spawn(move || loop {
+            thread::sleep(interval);
+            match dag.write().unwrap().sweep_expired() {
+                Ok(swept) => {
+                    if !swept.is_empty() {
+                        info!("Dropped {} expired transactions", swept.len());
+                    }
+                }
+                Err(err) => error!("Could not drop expired transactions: {}", err),
+            }
+        })
+    }
+
+    /// Get a handle which can stop the manager and save its pending
+    /// transactions
+    pub fn shutdown_handle(&self) -> ShutdownHandle<M, T> {
+        ShutdownHandle {
+            dag: Arc::clone(&self.dag),
+            milestone_worker: Arc::clone(&self.milestone_worker),
+        }
+    }
+
+    /// Add the pending transactions saved by ShutdownHandle::shutdown
+    ///
+    /// Transactions are added parents first. Already known or rejected
+    /// transactions are skipped. Returns the number of transactions added
+    pub fn restore_pending<R: Read>(&self, reader: R) -> serde_json::Result<usize> {
+        let transactions: Vec<Transaction> = serde_json::from_reader(reader)?;
+        let transactions = transactions
+            .into_iter()
+            .map(|transaction| (transaction.get_hash(), transaction))
+            .collect();
+        Ok(parents_first(transactions)
+            .into_iter()
+            .map(|transaction| self.add_transaction(transaction))
+            .filter(|status| {
+                *status == TransactionStatus::Pending || *status == TransactionStatus::Milestone
+            })
+            .count())
+    }
+
     // Peer functions
     pub fn add_peer(&self, peer: Peer) {
         self.peers.write().unwrap().add_peer(peer);
     }
+
+    /// Download the transactions known to peer which are missing locally
+    ///
+    /// Walks backward from the peer's head milestone and every tip until
+    /// reaching locally known transactions, then commits the missing
+    /// transactions in batches, parents first. Returns the number of
+    /// transactions added
+    ///
+    /// # Errors
+    ///
+    /// Fails with SyncError::TooManyMissing if more than
+    /// MAX_SYNC_TRANSACTIONS are missing. The batches committed before a
+    /// failure are kept, and counted in the SyncFailure
+    pub fn sync_from<P: PeerApi>(&self, peer: &P) -> Result<usize, SyncFailure> {
+        self.sync_from_within(peer, MAX_SYNC_TRANSACTIONS)
+    }
+
+    /// Download the transactions known to peer which are missing locally,
+    /// failing if more than max_missing are missing
+    pub fn sync_from_within<P: PeerApi>(
+        &self,
+        peer: &P,
+        max_missing: usize,
+    ) -> Result<usize, SyncFailure> {
+        let missing = self
+            .find_missing(peer, max_missing)
+            .map_err(|err| SyncFailure::new(0, err))?;
+
+        let transactions = parents_first(missing);
+        let mut committed = 0;
+        for batch in transactions.chunks(SYNC_BATCH_SIZE) {
+            let statuses = self
+                .dag
+                .write()
+                .unwrap()
+                .apply_block(batch.to_vec())
+                .map_err(|err| SyncFailure::new(committed, err))?;
+            committed += batch.len();
+            for (transaction, (_, status)) in batch.iter().zip(statuses) {
+                self.metrics.transaction_accepted();
+                if status == TransactionStatus::Milestone {
+                    self.queue_milestone(transaction.clone())
+                        .map_err(|reason| {
+                            SyncFailure::new(committed, SyncError::Rejected(reason))
+                        })?;
+                }
+            }
+        }
+        Ok(committed)
+    }
+
+    /// Walk backward from the peer's head milestone and every tip, collecting
+    /// the transactions which are missing locally
+    fn find_missing<P: PeerApi>(
+        &self,
+        peer: &P,
+        max_missing: usize,
+    ) -> Result<HashMap<u64, Transaction>, SyncError> {
+        let (head_milestone, _) = peer.get_head_milestone()?;
+        let mut frontier = vec![head_milestone];
+        let mut visited = HashSet::new();
+        let mut missing = HashMap::new();
+
+        // Tips are received whole, so their hashes need no checking
+        for tip in peer.get_all_tips()? {
+            let hash = tip.get_hash();
+            if !visited.insert(hash) || self.get_transaction(hash).is_some() {
+                continue;
+            }
+            if missing.len() >= max_missing {
+                return Err(SyncError::TooManyMissing(max_missing));
+            }
+            frontier.extend(tip.get_all_refs());
+            missing.insert(hash, tip);
+        }
+
+        while let Some(hash) = frontier.pop() {
+            if !visited.insert(hash) || self.get_transaction(hash).is_some() {
+                continue;
+            }
+            if missing.len() >= max_missing {
+                return Err(SyncError::TooManyMissing(max_missing));
+            }
+            let transaction = peer.get_transaction(hash)?;
+            if transaction.get_hash() != hash {
+                return Err(SyncError::HashMismatch(hash));
+            }
+            frontier.extend(transaction.get_all_refs());
+            missing.insert(hash, transaction);
+        }
+        Ok(missing)
+    }
+}
+
+/// Order transactions so that every transaction follows the transactions it
+/// references
+fn parents_first(mut transactions: HashMap<u64, Transaction>) -> Vec<Transaction> {
+    let mut parent_counts = HashMap::with_capacity(transactions.len());
+    let mut children: HashMap<u64, Vec<u64>> = HashMap::new();
+    for (hash, transaction) in &transactions {
+        let parents: HashSet<u64> = transaction
+            .get_all_refs()
+            .into_iter()
+            .filter(|parent| transactions.contains_key(parent))
+            .collect();
+        for parent in &parents {
+            children.entry(*parent).or_default().push(*hash);
+        }
+        parent_counts.insert(*hash, parents.len());
+    }
+
+    let mut ready: Vec<u64> = parent_counts
+        .iter()
+        .filter(|(_, count)| **count == 0)
+        .map(|(hash, _)| *hash)
+        .collect();
+    let mut ordered = Vec::with_capacity(transactions.len());
+    while let Some(hash) = ready.pop() {
+        for child in children.remove(&hash).unwrap_or_default() {
+            let count = parent_counts.get_mut(&child).unwrap();
+            *count -= 1;
+            if *count == 0 {
+                ready.push(child);
+            }
+        }
+        ordered.extend(transactions.remove(&hash));
+    }
+    ordered
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::PathBuf;
+
+    use dag::contract::source::ContractSource;
+    use dag::storage::mpt::MerklePatriciaTree;
+    use dag::transaction::updates::TransactionUpdates;
+    use rustdag_lib::security::hash::proof::{proof_of_work, proof_of_work_from};
+    use rustdag_lib::security::{keys::PrivateKey, ring::digest::SHA512_256};
+
+    fn thread_count() -> usize {
+        fs::read_dir("/proc/self/task").unwrap().count()
+    }
+
+    /// Find a nonce above the milestone range, so the transaction stays
+    /// pending
+    fn pending_nonce(trunk_nonce: u32, branch_nonce: u32) -> u32 {
+        proof_of_work_from(trunk_nonce, branch_nonce, 200_000).unwrap()
+    }
+
+    /// A peer backed by a manager in the same process
+    struct ManagerPeer<'a>(&'a DAGManager);
+
+    fn not_found(kind: &str) -> Error {
+        Error::HttpError(404, format!("{} not found", kind))
+    }
+
+    impl<'a> PeerApi for ManagerPeer<'a> {
+        fn get_transaction(&self, hash: u64) -> Result<Transaction, Error> {
+            self.0
+                .get_transaction(hash)
+                .ok_or_else(|| not_found("Transaction"))
+        }
+
+        fn get_header(&self, hash: u64) -> Result<TransactionHeader, Error> {
+            self.0
+                .get_transaction_header(hash)
+                .ok_or_else(|| not_found("Transaction"))
+        }
+
+        fn post_transaction(&self, transaction: &Transaction) -> Result<TransactionStatus, Error> {
+            Ok(self.0.add_transaction(transaction.clone()))
+        }
+
+        fn get_tips(&self) -> Result<TransactionHashes, Error> {
+            Ok(self.0.get_tips())
+        }
+
+        fn get_all_tips(&self) -> Result<Vec<Transaction>, Error> {
+            Ok(self.0.tip_transactions().collect())
+        }
+
+        fn get_contract(&self, hash: u64) -> Result<Contract, Error> {
+            self.0
+                .get_contract(hash)
+                .ok_or_else(|| not_found("Contract"))
+        }
+
+        fn get_mpt_node(&self, hash: u64) -> Result<Node<ContractValue>, Error> {
+            self.0.get_mpt_node(hash).ok_or_else(|| not_found("Node"))
+        }
+
+        fn get_merge_base(&self, trunk: u64, branch: u64) -> Result<MergeBase, Error> {
+            self.0
+                .get_merge_base(trunk, branch)
+                .ok_or_else(|| not_found("Merge base"))
+        }
+
+        fn get_head_milestone(&self) -> Result<(u64, u64), Error> {
+            Ok(self.0.get_head_milestone())
+        }
+    }
+
+    #[test]
+    fn test_milestone_queue_bounded() {
+        let manager = DAGManager::default();
+        let threads = thread_count();
+        let genesis = manager.get_head_milestone();
+        let tips = manager.get_tips();
+
+        for timestamp in 0..(MILESTONE_QUEUE_SIZE as u64 * 4) {
+            let milestone = Transaction::new(
+                tips.branch_hash,
+                tips.trunk_hash,
+                vec![],
+                0,
+                timestamp,
+                150_000,
+                0,
+                TransactionData::Empty,
+            );
+            let status = manager
+                .dag
+                .write()
+                .unwrap()
+                .commit_transaction(milestone.clone(), TransactionUpdates::new(vec![]));
+            assert_eq!(status, Ok(TransactionStatus::Milestone));
+            manager.queue_milestone(milestone).unwrap();
+        }
+        assert!(thread_count() <= threads);
+
+        // Dropping the manager waits for every queued milestone
+        let dag = Arc::clone(&manager.dag);
+        drop(manager);
+        assert_eq!(Arc::strong_count(&dag), 1);
+        assert_ne!(
+            dag.read().unwrap().get_head_milestone().get_hash(),
+            genesis.0
+        );
+    }
+
+    #[test]
+    fn test_render_metrics() {
+        let manager = DAGManager::default();
+        let metrics = manager.render_metrics();
+        for name in &[
+            "rustdag_transactions_accepted_total",
+            "rustdag_transactions_rejected_total",
+            "rustdag_milestones_confirmed_total",
+            "rustdag_contract_executions_total",
+            "rustdag_tips",
+            "rustdag_mpt_nodes",
+        ] {
+            assert!(metrics.contains(&format!("# TYPE {} ", name)));
+        }
+        assert!(metrics.contains("rustdag_transactions_accepted_total 0\n"));
+        assert!(metrics.contains("rustdag_tips 2\n"));
+
+        let tips = manager.get_tips();
+        let trunk = manager.get_transaction(tips.trunk_hash).unwrap();
+        let branch = manager.get_transaction(tips.branch_hash).unwrap();
+        let nonce = proof_of_work(trunk.get_nonce(), branch.get_nonce()).unwrap();
+        let mut transaction = Transaction::create(
+            tips.branch_hash,
+            tips.trunk_hash,
+            vec![],
+            0,
+            nonce,
+            0,
+            TransactionData::Empty,
+        );
+        transaction.sign(&mut PrivateKey::new(&SHA512_256));
+        assert_eq!(
+            manager.add_transaction(transaction),
+            TransactionStatus::Pending
+        );
+
+        let metrics = manager.render_metrics();
+        assert!(metrics.contains("rustdag_transactions_accepted_total 1\n"));
+        assert!(metrics.contains("rustdag_tips 1\n"));
+    }
+
+    #[test]
+    fn test_add_known_transaction() {
+        let manager = DAGManager::default();
+        let tips = manager.get_tips();
+        let trunk = manager.get_transaction(tips.trunk_hash).unwrap();
+        let branch = manager.get_transaction(tips.branch_hash).unwrap();
+        let mut transaction = Transaction::create(
+            tips.branch_hash,
+            tips.trunk_hash,
+            vec![],
+            0,
+            pending_nonce(trunk.get_nonce(), branch.get_nonce()),
+            0,
+            TransactionData::Empty,
+        );
+        transaction.sign(&mut PrivateKey::new(&SHA512_256));
+
+        assert_eq!(
+            manager.add_transaction(transaction.clone()),
+            TransactionStatus::Pending
+        );
+        assert_eq!(
+            manager.add_transaction(transaction),
+            TransactionStatus::Known
+        );
+        assert!(manager
+            .render_metrics()
+            .contains("rustdag_transactions_accepted_total 1\n"));
+    }
+
+    #[test]
+    fn test_shutdown_restore() {
+        let manager = DAGManager::default();
+        let tips = manager.get_tips();
+        let trunk = manager.get_transaction(tips.trunk_hash).unwrap();
+        let branch = manager.get_transaction(tips.branch_hash).unwrap();
+
+        let mut pending = Vec::new();
+        let (mut last_hash, mut last_nonce) = (tips.trunk_hash, trunk.get_nonce());
+        for _ in 0..3 {
+            let mut transaction = Transaction::create(
+                tips.branch_hash,
+                last_hash,
+                vec![],
+                0,
+                pending_nonce(last_nonce, branch.get_nonce()),
+                0,
+                TransactionData::Empty,
+            );
+            transaction.sign(&mut PrivateKey::new(&SHA512_256));
+            assert_eq!(
+                manager.add_transaction(transaction.clone()),
+                TransactionStatus::Pending
+            );
+            last_hash = transaction.get_hash();
+            last_nonce = transaction.get_nonce();
+            pending.push(transaction);
+        }
+
+        let mut snapshot = Vec::new();
+        let saved = manager.shutdown_handle().shutdown(&mut snapshot).unwrap();
+        // The genesis branch is pending as well
+        assert_eq!(saved, pending.len() + 1);
+        drop(manager);
+
+        let restored = DAGManager::default();
+        assert_eq!(
+            restored.restore_pending(snapshot.as_slice()).unwrap(),
+            pending.len()
+        );
+        for transaction in &pending {
+            assert_eq!(
+                restored
+                    .get_transaction_status(transaction.get_hash())
+                    .status,
+                TransactionStatus::Pending
+            );
+            assert_eq!(
+                restored
+                    .get_transaction(transaction.get_hash())
+                    .unwrap()
+                    .full_hash(),
+                transaction.full_hash()
+            );
+        }
+        assert_eq!(restored.get_tips().trunk_hash, last_hash);
+    }
+
+    #[test]
+    fn test_sync_from() {
+        let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        path.push("../lib/resources/test/contracts/api_test.wasm");
+        let src = ContractSource::new(&fs::read(path).expect("Could not read test file"));
+
+        let source = DAGManager::default();
+        let tips = source.get_tips();
+        let trunk = source.get_transaction(tips.trunk_hash).unwrap();
+        let branch = source.get_transaction(tips.branch_hash).unwrap();
+
+        let mut storage = MerklePatriciaTree::<ContractValue, _>::new(HashMap::new());
+        let mut root = storage.default_root();
+        let mut deploy = Transaction::create(
+            tips.branch_hash,
+            tips.trunk_hash,
+            vec![],
+            0,
+            pending_nonce(trunk.get_nonce(), branch.get_nonce()),
+            root,
+            TransactionData::GenContract(src.clone(), None),
+        );
+        deploy.sign(&mut PrivateKey::new(&SHA512_256));
+        assert_eq!(
+            source.add_transaction(deploy.clone()),
+            TransactionStatus::Pending
+        );
+        let contract_id = deploy.get_hash();
+        let (contract, updates) = Contract::new(src, contract_id, &storage, root).unwrap();
+        root = updates.get_root_hash();
+        storage.commit_set(updates).unwrap();
+
+        let mut last = deploy;
+        for value in 5..9 {
+            let args = vec![ContractValue::U32(0), ContractValue::U32(value)];
+            let nonce = pending_nonce(last.get_nonce(), branch.get_nonce());
+            let mut transaction = Transaction::create(
+                tips.branch_hash,
+                last.get_hash(),
+                vec![],
+                contract_id,
+                nonce,
+                root,
+                TransactionData::ExecContract("set_u32".into(), args.clone()),
+            );
+            transaction.sign(&mut PrivateKey::new(&SHA512_256));
+            assert_eq!(
+                source.add_transaction(transaction.clone()),
+                TransactionStatus::Pending
+            );
+            let (_, updates) = contract.exec("set_u32", &args, &storage, root).unwrap();
+            root = updates.get_root_hash();
+            storage.commit_set(updates).unwrap();
+            last = transaction;
+        }
+
+        // A tip off the chain is only found by walking from every tip
+        let mut side = Transaction::create(
+            tips.branch_hash,
+            tips.trunk_hash,
+            vec![],
+            0,
+            pending_nonce(trunk.get_nonce(), branch.get_nonce()),
+            0,
+            TransactionData::Empty,
+        );
+        side.sign(&mut PrivateKey::new(&SHA512_256));
+        let side_hash = side.get_hash();
+        assert_eq!(source.add_transaction(side), TransactionStatus::Pending);
+
+        // Nothing is committed if too many transactions are missing
+        let target = DAGManager::default();
+        match target.sync_from_within(&ManagerPeer(&source), 2) {
+            Err(SyncFailure {
+                committed: 0,
+                error: SyncError::TooManyMissing(2),
+            }) => {}
+            result => panic!("Expected TooManyMissing, got {:?}", result),
+        }
+        assert_eq!(target.dag.read().unwrap().get_tip_count(), 2);
+
+        assert_eq!(target.sync_from(&ManagerPeer(&source)).unwrap(), 6);
+
+        assert!(target.get_contract(contract_id).is_some());
+        assert!(target.get_mpt_node(root).is_some());
+        {
+            let (source_dag, target_dag) = (source.dag.read().unwrap(), target.dag.read().unwrap());
+            let mut expected = vec![last.get_hash(), side_hash];
+            expected.sort();
+            let mut target_tips = target_dag.get_tip_hashes();
+            target_tips.sort();
+            assert_eq!(target_tips, expected);
+            assert_eq!(source_dag.get_tip_count(), target_dag.get_tip_count());
+            assert_eq!(
+                source_dag.get_mpt_node_count(),
+                target_dag.get_mpt_node_count()
+            );
+        }
+
+        // Syncing again finds nothing missing
+        assert_eq!(target.sync_from(&ManagerPeer(&source)).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_checkpoint_node_key() {
+        let manager = DAGManager::default();
+        manager.dag.write().unwrap().set_finality_depth(0);
+
+        // Checkpoints are only served once the node has a key to sign them
+        assert_eq!(manager.get_checkpoint(), None);
+        let pkcs8 = ValidatorKey::generate_pkcs8().unwrap();
+        manager.set_node_key(ValidatorKey::from_pkcs8(&pkcs8).unwrap());
+        let trusted_key = ValidatorKey::from_pkcs8(&pkcs8).unwrap().public_key();
+        let checkpoint = manager.get_checkpoint().unwrap();
+        assert_eq!(checkpoint.checkpoint.hash, manager.get_head_milestone().0);
+        assert!(checkpoint.verify(&trusted_key));
+
+        // The same key keeps signing later requests
+        assert!(manager.get_checkpoint().unwrap().verify(&trusted_key));
+    }
 }
@@ -0,0 +1,166 @@
+//! End to end tests driving the server over HTTP with a real Peer
+
+use std::fs;
+use std::net::{TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use rocket::config::{Config, Environment, LoggingLevel};
+
+use rustdag_lib::dag::contract::{source::ContractSource, ContractValue};
+use rustdag_lib::dag::transaction::{data::TransactionData, Transaction};
+use rustdag_lib::security::{
+    hash::proof::proof_of_work_from,
+    keys::{PrivateKey, ValidatorKey},
+    ring::digest::SHA512_256,
+};
+use rustdag_lib::util::peer::{Peer, PeerApi};
+use rustdag_lib::util::types::{RejectionReason, TransactionStatus};
+
+use build_rocket;
+use dagmanager::DAGManager;
+
+/// Time to wait for the server to start accepting connections
+const STARTUP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A server running on an ephemeral port, for the rest of the test process
+struct TestServer {
+    port: u16,
+}
+
+impl TestServer {
+    /// Launch a server for manager, and wait until it accepts connections
+    fn start(manager: DAGManager) -> TestServer {
+        // Rocket cannot report the port it bound, so reserve a free port
+        // first
+        let port = TcpListener::bind("127.0.0.1:0")
+            .and_then(|listener| listener.local_addr())
+            .expect("Could not reserve a port")
+            .port();
+        let config = Config::build(Environment::Development)
+            .address("127.0.0.1")
+            .port(port)
+            .log_level(LoggingLevel::Off)
+            .finalize()
+            .expect("Invalid test server config");
+        thread::spawn(move || {
+            build_rocket(rocket::custom(config), manager).launch();
+        });
+
+        let started = Instant::now();
+        while TcpStream::connect(("127.0.0.1", port)).is_err() {
+            assert!(
+                started.elapsed() < STARTUP_TIMEOUT,
+                "Test server did not start"
+            );
+            thread::sleep(Duration::from_millis(10));
+        }
+        TestServer { port }
+    }
+
+    fn peer(&self) -> Peer {
+        Peer::new(format!("http://127.0.0.1:{}", self.port))
+    }
+}
+
+/// Build a transaction on the peer's current tips, with a nonce outside the
+/// milestone range
+fn build_transaction(peer: &Peer, data: TransactionData, contract: u64, root: u64) -> Transaction {
+    let tips = peer.get_tips().unwrap();
+    let trunk = peer.get_transaction(tips.trunk_hash).unwrap();
+    let branch = peer.get_transaction(tips.branch_hash).unwrap();
+    let nonce = proof_of_work_from(trunk.get_nonce(), branch.get_nonce(), 200_000).unwrap();
+    let mut transaction = Transaction::create(
+        tips.branch_hash,
+        tips.trunk_hash,
+        vec![],
+        contract,
+        nonce,
+        root,
+        data,
+    );
+    transaction.sign(&mut PrivateKey::new(&SHA512_256));
+    transaction
+}
+
+#[test]
+fn test_deploy_and_read_over_http() {
+    let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    path.push("../lib/resources/test/contracts/api_test.wasm");
+    let src = ContractSource::new(&fs::read(path).expect("Could not read test file"));
+
+    let server = TestServer::start(DAGManager::default());
+    let peer = server.peer();
+    // Executes transactions locally, reading the server's state over HTTP
+    let remote = peer.clone().into_remote_blockdag();
+
+    // Deploy on the state of the genesis transactions
+    let tips = peer.get_tips().unwrap();
+    let root = peer.get_transaction(tips.trunk_hash).unwrap().get_root();
+    let deploy = build_transaction(&peer, TransactionData::GenContract(src, None), 0, root);
+    let contract_id = deploy.get_hash();
+    let root = remote
+        .try_add_transaction(&deploy)
+        .unwrap()
+        .get_storage_root()
+        .unwrap();
+    assert_eq!(
+        peer.post_transaction(&deploy).unwrap(),
+        TransactionStatus::Pending
+    );
+    assert_eq!(
+        peer.get_transaction(contract_id).unwrap().full_hash(),
+        deploy.full_hash()
+    );
+
+    let args = vec![ContractValue::U32(0), ContractValue::U32(7)];
+    let exec = build_transaction(
+        &peer,
+        TransactionData::ExecContract("set_u32".into(), args),
+        contract_id,
+        root,
+    );
+    let root = remote
+        .try_add_transaction(&exec)
+        .unwrap()
+        .get_storage_root()
+        .unwrap();
+    assert_eq!(
+        peer.post_transaction(&exec).unwrap(),
+        TransactionStatus::Pending
+    );
+
+    // The state set by the transaction is read back from the server
+    let calls = vec![("get_u32".to_string(), vec![ContractValue::U32(0)])];
+    assert_eq!(
+        remote.multicall_contract(contract_id, &calls, root),
+        Some(Ok(vec![vec![ContractValue::U32(7)]]))
+    );
+}
+
+#[test]
+fn test_scheme_check_over_http() {
+    let server = TestServer::start(DAGManager::default());
+    let peer = server.peer().with_scheme_check();
+    let tips = peer.get_tips().unwrap();
+    let root = peer.get_transaction(tips.trunk_hash).unwrap().get_root();
+
+    // Lamport signatures are accepted by a node requiring the proof of work
+    let transaction = build_transaction(&peer, TransactionData::Empty, 0, root);
+    assert_eq!(
+        peer.post_transaction(&transaction).unwrap(),
+        TransactionStatus::Pending
+    );
+
+    // So are validator keys, so the transaction is posted, and rejected by
+    // the node since the key is not a listed validator
+    let key = ValidatorKey::from_pkcs8(&ValidatorKey::generate_pkcs8().unwrap()).unwrap();
+    let mut transaction = build_transaction(&peer, TransactionData::Empty, 0, root);
+    transaction.sign_validator(&key);
+    assert_eq!(
+        peer.post_transaction(&transaction).unwrap(),
+        TransactionStatus::Rejected(RejectionReason::NotValidator)
+    );
+    assert!(peer.get_transaction(transaction.get_hash()).is_err());
+}
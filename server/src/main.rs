@@ -2,25 +2,93 @@
 
 #[macro_use]
 extern crate rocket;
+extern crate base64;
+extern crate ctrlc;
+#[macro_use]
+extern crate log;
+extern crate restson;
+#[cfg(test)]
+extern crate rmp_serde;
 extern crate rocket_contrib;
+extern crate serde;
+extern crate serde_json;
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufReader, BufWriter};
+use std::os::unix::fs::OpenOptionsExt;
+use std::process;
+use std::time::Duration;
 
-use rocket::State;
+use rocket::response::{content, status::BadRequest, Stream};
+use rocket::{Rocket, State};
 use rocket_contrib::json::Json;
 
 extern crate rustdag_lib;
 
 use rustdag_lib::dag;
-use rustdag_lib::util::{self, peer::Peer, types::TransactionHashes};
+use rustdag_lib::security::keys::{KeyFile, ValidatorKey};
+use rustdag_lib::util::{
+    self,
+    peer::Peer,
+    types::{MergeBase, TransactionHashes},
+};
 
 mod controllers;
 mod dagmanager;
+#[cfg(test)]
+mod integration;
+mod metrics;
+mod negotiate;
 mod peermanager;
+mod stream;
 
+use dag::transaction::Transaction;
 use dagmanager::DAGManager;
+use negotiate::Negotiated;
+use stream::JsonArrayReader;
+
+/// File the pending transactions are saved to on shutdown, and restored from
+/// on startup
+const PENDING_SNAPSHOT_PATH: &str = "pending_transactions.json";
+
+/// File the key the node signs checkpoints with is stored in, generated on
+/// first startup
+const NODE_KEY_PATH: &str = "node_key.json";
+
+/// Time between sweeps of expired pending transactions
+const EXPIRY_SWEEP_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Select tips, avoiding the comma separated transaction hashes in exclude
+#[get("/tips?<exclude>")]
+fn get_tips(
+    exclude: Option<String>,
+    dag: State<DAGManager>,
+) -> Result<Negotiated<TransactionHashes>, BadRequest<String>> {
+    let exclude = match exclude {
+        Some(exclude) => exclude
+            .split(',')
+            .filter(|hash| !hash.is_empty())
+            .map(|hash| hash.parse())
+            .collect::<Result<Vec<u64>, _>>()
+            .map_err(|err| BadRequest(Some(format!("Invalid exclude hash: {}", err))))?,
+        None => Vec::new(),
+    };
+    Ok(Negotiated(dag.inner().get_tips_excluding(&exclude)))
+}
+
+/// Stream the transactions of every tip as a JSON array
+#[get("/tips/all")]
+fn get_all_tips<'r>(
+    dag: State<'r, DAGManager>,
+) -> content::Json<Stream<JsonArrayReader<impl Iterator<Item = Transaction> + 'r>>> {
+    content::Json(Stream::from(JsonArrayReader::new(
+        dag.inner().tip_transactions(),
+    )))
+}
 
-#[get("/tips")]
-fn get_tips(dag: State<DAGManager>) -> Json<TransactionHashes> {
-    Json(dag.inner().get_tips())
+#[get("/merge-base/<trunk>/<branch>")]
+fn get_merge_base(trunk: u64, branch: u64, dag: State<DAGManager>) -> Option<Json<MergeBase>> {
+    dag.inner().get_merge_base(trunk, branch).map(Json)
 }
 
 #[post("/peer/register", data = "<peer>")]
@@ -29,14 +97,89 @@ fn new_peer(peer: Json<Peer>, chain: State<DAGManager>) {
 }
 
 fn main() {
-    rocket::ignite()
-        .mount("/", routes![get_tips, new_peer])
+    // Igniting rocket sets up logging
+    let rocket = rocket::ignite();
+    let manager = DAGManager::default();
+    if let Ok(file) = File::open(PENDING_SNAPSHOT_PATH) {
+        match manager.restore_pending(BufReader::new(file)) {
+            Ok(count) => info!("Restored {} pending transactions", count),
+            Err(err) => error!("Could not restore pending transactions: {}", err),
+        }
+    }
+
+    match load_node_key(NODE_KEY_PATH) {
+        Ok(key) => {
+            info!(
+                "Signing checkpoints with {}",
+                base64::encode_config(&key.public_key(), base64::URL_SAFE)
+            );
+            manager.set_node_key(key);
+        }
+        Err(err) => error!("Could not load the node key: {}", err),
+    }
+
+    manager.spawn_expiry_sweeper(EXPIRY_SWEEP_INTERVAL);
+
+    let shutdown = manager.shutdown_handle();
+    ctrlc::set_handler(move || {
+        let saved = File::create(PENDING_SNAPSHOT_PATH)
+            .map_err(|err| err.to_string())
+            .and_then(|file| {
+                shutdown
+                    .shutdown(BufWriter::new(file))
+                    .map_err(|err| err.to_string())
+            });
+        match saved {
+            Ok(count) => info!("Saved {} pending transactions", count),
+            Err(err) => error!("Could not save pending transactions: {}", err),
+        }
+        process::exit(0);
+    })
+    .expect("Could not set shutdown handler");
+
+    build_rocket(rocket, manager).launch();
+}
+
+/// Load the node key from path, generating and storing a new key if there is
+/// none yet
+fn load_node_key(path: &str) -> Result<ValidatorKey, String> {
+    let key_file: KeyFile = match File::open(path) {
+        Ok(file) => serde_json::from_reader(BufReader::new(file)).map_err(|err| err.to_string())?,
+        Err(ref err) if err.kind() == io::ErrorKind::NotFound => {
+            let pkcs8 = ValidatorKey::generate_pkcs8().ok_or("Could not generate a key")?;
+            let key_file = KeyFile::from_validator_pkcs8(&pkcs8);
+            // Only readable by the node, and never replacing an existing key
+            let file = OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .mode(0o600)
+                .open(path)
+                .map_err(|err| err.to_string())?;
+            serde_json::to_writer(BufWriter::new(file), &key_file)
+                .map_err(|err| err.to_string())?;
+            key_file
+        }
+        Err(err) => return Err(format!("Could not open {}: {}", path, err)),
+    };
+    key_file
+        .to_validator_key()
+        .ok_or_else(|| format!("{} does not hold a validator key", path))
+}
+
+/// Mount every route on rocket, serving the dag of manager
+fn build_rocket(rocket: Rocket, manager: DAGManager) -> Rocket {
+    rocket
+        .mount("/", routes![get_tips, get_all_tips, get_merge_base, new_peer])
+        .mount("/", controllers::metrics::metrics_routes())
         .mount(
             "/transaction",
             controllers::transaction::transaction_routes(),
         )
         .mount("/contract", controllers::contract::contract_routes())
+        .mount("/address", controllers::address::address_routes())
+        .mount("/events", controllers::event::event_routes())
+        .mount("/milestones", controllers::milestone::milestone_routes())
+        .mount("/", controllers::milestone::checkpoint_routes())
         .mount("/node", controllers::node::node_routes())
-        .manage(DAGManager::default())
-        .launch();
+        .manage(manager)
 }
@@ -0,0 +1,80 @@
+use std::fmt::Write;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Counters of the activity of the node
+#[derive(Default, Debug)]
+pub struct Metrics {
+    transactions_accepted: AtomicUsize,
+    transactions_rejected: AtomicUsize,
+    milestones_confirmed: AtomicUsize,
+    contract_executions: AtomicUsize,
+}
+
+impl Metrics {
+    pub fn transaction_accepted(&self) {
+        self.transactions_accepted.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn transaction_rejected(&self) {
+        self.transactions_rejected.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn milestone_confirmed(&self) {
+        self.milestones_confirmed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn contract_executed(&self) {
+        self.contract_executions.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Render the counters, along with the current tip and MPT node counts, in
+    /// the Prometheus text format
+    pub fn render(&self, tip_count: usize, mpt_node_count: usize) -> String {
+        let mut out = String::new();
+        let metrics = [
+            (
+                "rustdag_transactions_accepted_total",
+                "counter",
+                "Transactions added to the dag",
+                self.transactions_accepted.load(Ordering::Relaxed),
+            ),
+            (
+                "rustdag_transactions_rejected_total",
+                "counter",
+                "Transactions rejected by the dag",
+                self.transactions_rejected.load(Ordering::Relaxed),
+            ),
+            (
+                "rustdag_milestones_confirmed_total",
+                "counter",
+                "Milestones verified and added to the dag",
+                self.milestones_confirmed.load(Ordering::Relaxed),
+            ),
+            (
+                "rustdag_contract_executions_total",
+                "counter",
+                "Contracts created or executed by accepted transactions",
+                self.contract_executions.load(Ordering::Relaxed),
+            ),
+            (
+                "rustdag_tips",
+                "gauge",
+                "Transactions not yet referenced by another transaction",
+                tip_count,
+            ),
+            (
+                "rustdag_mpt_nodes",
+                "gauge",
+                "Nodes in the contract state tree",
+                mpt_node_count,
+            ),
+        ];
+        for (name, kind, help, value) in metrics.iter() {
+            // Writing to a String cannot fail
+            let _ = writeln!(out, "# HELP {} {}", name, help);
+            let _ = writeln!(out, "# TYPE {} {}", name, kind);
+            let _ = writeln!(out, "{} {}", name, value);
+        }
+        out
+    }
+}
@@ -0,0 +1,71 @@
+use rocket::http::MediaType;
+use rocket::request::Request;
+use rocket::response::{self, Responder};
+use rocket_contrib::json::Json;
+use rocket_contrib::msgpack::MsgPack;
+
+use serde::Serialize;
+
+/// Responds with JSON, or with MessagePack if the request prefers
+/// `application/msgpack`
+///
+/// MessagePack bodies are smaller, for clients short on bandwidth
+#[derive(Debug)]
+pub struct Negotiated<T>(pub T);
+
+impl<'r, T: Serialize> Responder<'r> for Negotiated<T> {
+    fn respond_to(self, request: &Request) -> response::Result<'r> {
+        let msgpack = request.accept().map_or(false, |accept| {
+            *accept.preferred().media_type() == MediaType::MsgPack
+        });
+        if msgpack {
+            MsgPack(self.0).respond_to(request)
+        } else {
+            Json(self.0).respond_to(request)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rocket::http::{ContentType, Header, Status};
+    use rocket::local::Client;
+
+    use dag::transaction::Transaction;
+
+    use controllers::transaction::transaction_routes;
+    use dagmanager::DAGManager;
+
+    #[test]
+    fn test_negotiated_transaction() {
+        let manager = DAGManager::default();
+        let hash = manager.get_tips().trunk_hash;
+        let rocket = rocket::ignite()
+            .mount("/transaction", transaction_routes())
+            .manage(manager);
+        let client = Client::new(rocket).expect("Invalid rocket instance");
+        let get = |accept: &'static str| {
+            let mut response = client
+                .get(format!("/transaction/{}", hash))
+                .header(Header::new("Accept", accept))
+                .dispatch();
+            assert_eq!(response.status(), Status::Ok);
+            (response.content_type(), response.body_bytes().unwrap())
+        };
+
+        let (content_type, body) = get("application/json");
+        assert_eq!(content_type, Some(ContentType::JSON));
+        let json: Transaction = serde_json::from_slice(&body).unwrap();
+
+        let (content_type, body) = get("application/msgpack");
+        assert_eq!(content_type, Some(ContentType::MsgPack));
+        let msgpack: Transaction = rmp_serde::from_slice(&body).unwrap();
+
+        // The genesis transaction leaves its optional fields unset, which
+        // MessagePack only decodes if they keep their positions
+        assert_eq!(json.get_hash(), hash);
+        assert_eq!(json.get_counter(), None);
+        assert_eq!(json.get_expiration(), None);
+        assert_eq!(json.full_hash(), msgpack.full_hash());
+    }
+}
@@ -1,23 +1,149 @@
+use std::sync::Arc;
+
 use util::peer::Peer;
 
-pub struct PeerManager {
-    peers: Vec<Peer>,
+/// Reputation a newly added peer starts with
+const INITIAL_REPUTATION: i32 = 0;
+/// Highest reputation a peer can reach, so a long reliable history does not
+/// hide a peer which has started failing
+const MAX_REPUTATION: i32 = 10;
+/// Peers whose reputation falls below this are dropped
+const REPUTATION_FLOOR: i32 = -5;
+
+struct RatedPeer<P> {
+    peer: Arc<P>,
+    reputation: i32,
 }
 
-impl PeerManager {
+pub struct PeerManager<P = Peer> {
+    /// Peers ordered by reputation, highest first
+    peers: Vec<RatedPeer<P>>,
+}
+
+impl<P> PeerManager<P> {
     #[allow(clippy::new_without_default)]
-    pub fn new() -> PeerManager {
+    pub fn new() -> PeerManager<P> {
         PeerManager { peers: Vec::new() }
     }
 
-    pub fn add_peer(&mut self, peer: Peer) {
-        self.peers.push(peer);
+    pub fn add_peer(&mut self, peer: P) {
+        self.peers.push(RatedPeer {
+            peer: Arc::new(peer),
+            reputation: INITIAL_REPUTATION,
+        });
+        self.sort_peers();
+    }
+
+    /// Get the peers to broadcast to, highest reputation first
+    ///
+    /// The peers are shared, so they can be contacted without holding the
+    /// manager, and rated afterwards with rate
+    pub fn peers(&self) -> Vec<Arc<P>> {
+        self.peers.iter().map(|rated| rated.peer.clone()).collect()
+    }
+
+    /// Rate each peer by whether contacting it succeeded
+    ///
+    /// Peers dropped since they were returned by peers are ignored. Peers
+    /// whose reputation falls below the floor are dropped
+    pub fn rate(&mut self, results: &[(Arc<P>, bool)]) {
+        for (peer, succeeded) in results {
+            let rated = self
+                .peers
+                .iter_mut()
+                .find(|rated| Arc::ptr_eq(&rated.peer, peer));
+            if let Some(rated) = rated {
+                rated.reputation = if *succeeded {
+                    (rated.reputation + 1).min(MAX_REPUTATION)
+                } else {
+                    rated.reputation - 1
+                };
+            }
+        }
+        self.peers
+            .retain(|rated| rated.reputation >= REPUTATION_FLOOR);
+        self.sort_peers();
+    }
+
+    /// Get the reputation of each peer, highest first
+    #[cfg(test)]
+    pub fn reputations(&self) -> Vec<(&P, i32)> {
+        self.peers
+            .iter()
+            .map(|rated| (&*rated.peer, rated.reputation))
+            .collect()
     }
 
-    pub fn map_peers<U, F>(&self, f: F) -> Vec<U>
-    where
-        F: Fn(&Peer) -> U,
-    {
-        self.peers.iter().map(f).collect()
+    fn sort_peers(&mut self) {
+        // The sort is stable, so equally rated peers keep the order they were
+        // added in
+        self.peers.sort_by(|a, b| b.reputation.cmp(&a.reputation));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reputation() {
+        let mut manager = PeerManager::new();
+        manager.add_peer("failing");
+        manager.add_peer("reliable");
+        let broadcast = |manager: &mut PeerManager<&str>| {
+            let results: Vec<_> = manager
+                .peers()
+                .into_iter()
+                .map(|peer| {
+                    let succeeded = *peer == "reliable";
+                    (peer, succeeded)
+                })
+                .collect();
+            manager.rate(&results);
+            results
+                .into_iter()
+                .map(|(_, succeeded)| succeeded)
+                .collect::<Vec<_>>()
+        };
+
+        // The reliable peer is broadcast to first after a single broadcast
+        broadcast(&mut manager);
+        assert_eq!(
+            manager.reputations(),
+            vec![(&"reliable", 1), (&"failing", -1)]
+        );
+
+        // The failing peer is kept down to the floor
+        for _ in 1..(-REPUTATION_FLOOR) {
+            broadcast(&mut manager);
+        }
+        assert_eq!(
+            manager.reputations(),
+            vec![
+                (&"reliable", -REPUTATION_FLOOR),
+                (&"failing", REPUTATION_FLOOR)
+            ]
+        );
+
+        // Once below the floor, the failing peer is dropped
+        let results = broadcast(&mut manager);
+        assert_eq!(results, vec![true, false]);
+        assert_eq!(
+            manager.reputations(),
+            vec![(&"reliable", 1 - REPUTATION_FLOOR)]
+        );
+    }
+
+    #[test]
+    fn test_rate_changed_peers() {
+        let mut manager = PeerManager::new();
+        manager.add_peer("first");
+        let peers = manager.peers();
+
+        // Peers added while the others were contacted keep their reputation
+        manager.add_peer("second");
+        let results: Vec<_> = peers.into_iter().map(|peer| (peer, true)).collect();
+        manager.rate(&results);
+        assert_eq!(manager.reputations(), vec![(&"first", 1), (&"second", 0)]);
     }
 }
@@ -0,0 +1,119 @@
+use std::cmp;
+use std::io::{self, Read};
+
+use serde::Serialize;
+
+/// Reads the items of an iterator as a JSON array
+///
+/// Items are serialized one at a time as the array is read, so the whole
+/// array is never held in memory
+pub struct JsonArrayReader<I: Iterator> {
+    items: I,
+    /// Serialized bytes not yet read
+    buffer: Vec<u8>,
+    position: usize,
+    started: bool,
+    finished: bool,
+}
+
+impl<I: Iterator> JsonArrayReader<I> {
+    pub fn new(items: I) -> Self {
+        JsonArrayReader {
+            items,
+            buffer: Vec::new(),
+            position: 0,
+            started: false,
+            finished: false,
+        }
+    }
+}
+
+impl<I> JsonArrayReader<I>
+where
+    I: Iterator,
+    I::Item: Serialize,
+{
+    /// Serialize the next item, or the end of the array, into the buffer
+    fn fill_buffer(&mut self) -> io::Result<()> {
+        self.buffer.clear();
+        self.position = 0;
+        if !self.started {
+            self.buffer.push(b'[');
+        }
+        match self.items.next() {
+            Some(item) => {
+                if self.started {
+                    self.buffer.push(b',');
+                }
+                serde_json::to_writer(&mut self.buffer, &item)?;
+            }
+            None => {
+                self.buffer.push(b']');
+                self.finished = true;
+            }
+        }
+        self.started = true;
+        Ok(())
+    }
+}
+
+impl<I> Read for JsonArrayReader<I>
+where
+    I: Iterator,
+    I::Item: Serialize,
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.position == self.buffer.len() {
+            if self.finished {
+                return Ok(0);
+            }
+            self.fill_buffer()?;
+        }
+        let len = cmp::min(buf.len(), self.buffer.len() - self.position);
+        buf[..len].copy_from_slice(&self.buffer[self.position..self.position + len]);
+        self.position += len;
+        Ok(len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use dag::transaction::Transaction;
+    use dagmanager::DAGManager;
+
+    /// Read everything from reader, a few bytes at a time
+    fn read_in_chunks<R: Read>(mut reader: R) -> Vec<u8> {
+        let mut body = Vec::new();
+        let mut chunk = [0; 7];
+        loop {
+            let len = reader.read(&mut chunk).unwrap();
+            if len == 0 {
+                return body;
+            }
+            body.extend_from_slice(&chunk[..len]);
+        }
+    }
+
+    #[test]
+    fn test_json_array_reader() {
+        let empty = read_in_chunks(JsonArrayReader::new(Vec::<u64>::new().into_iter()));
+        assert_eq!(empty, b"[]");
+
+        let manager = DAGManager::default();
+        let expected: Vec<Transaction> = manager.tip_transactions().collect();
+        assert_eq!(expected.len(), 2);
+        let body = serde_json::to_vec(&expected).unwrap();
+
+        let streamed = read_in_chunks(JsonArrayReader::new(manager.tip_transactions()));
+        let streamed: Vec<Transaction> = serde_json::from_slice(&streamed).unwrap();
+        let body: Vec<Transaction> = serde_json::from_slice(&body).unwrap();
+
+        let mut streamed: Vec<_> = streamed.iter().map(Transaction::full_hash).collect();
+        let mut body: Vec<_> = body.iter().map(Transaction::full_hash).collect();
+        streamed.sort();
+        body.sort();
+        assert_eq!(streamed, body);
+    }
+}
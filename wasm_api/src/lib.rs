@@ -36,4 +36,12 @@ externs! {
     fn api_set_f32(index: u32, value: f32) -> ();
     fn api_set_f64(index: u32, value: f64) -> ();
     fn api_set_mapping(index: u32, key: u64, value: u64) -> ();
+
+    fn __ofc__return_tuple(ptr: u32, count: u32) -> ();
+    fn __ofc__get_mapping_wide(index: u32, key_ptr: u32, key_len: u32) -> u64;
+    fn __ofc__set_mapping_wide(index: u32, key_ptr: u32, key_len: u32, value: u64) -> ();
+    fn __ofc__get_u128(index: u32, out_ptr: u32) -> ();
+    fn __ofc__set_u128(index: u32, lo: u64, hi: u64) -> ();
+    fn __ofc__emit_event(topic: u64, ptr: u32, len: u32) -> ();
+    fn __ofc__sha3(data_ptr: u32, data_len: u32, out_ptr: u32) -> ();
 }